@@ -1,10 +1,10 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, fmt::Display};
 
-use candid::CandidType;
+use candid::{CandidType, Nat};
 use serde::Deserialize;
-use sns_treasury_manager::Balance;
+use sns_treasury_manager::{Balance, Operation, Transfer};
 
-use crate::validation::ValidatedAsset;
+use crate::validation::{decode_nat_to_u64, ValidatedAsset};
 
 // aterga icrc_account1, 10 icp, Treasuryowner
 // icp => TreasuryOwner => Balance { 10, account: Some(aterga) }
@@ -26,6 +26,32 @@ pub(crate) struct ValidatedBalancesForAsset {
     pub fee_collector: Balance,
 }
 
+impl ValidatedBalancesForAsset {
+    fn empty(account_treasury_owner: Option<sns_treasury_manager::Account>) -> Self {
+        let zero = |account| Balance {
+            amount_decimals: Nat::from(0_u8),
+            account,
+        };
+
+        Self {
+            treasury_owner: zero(account_treasury_owner),
+            treasury_manager: zero(None),
+            external: zero(None),
+            fee_collector: zero(None),
+        }
+    }
+
+    /// Sums up the four parties tracked for this asset. This total must only ever move by an
+    /// externally-initiated deposit or withdrawal amount; any other drift indicates that tokens
+    /// were created or destroyed by a bookkeeping bug.
+    fn total_decimals(&self) -> Nat {
+        self.treasury_owner.amount_decimals.clone()
+            + self.treasury_manager.amount_decimals.clone()
+            + self.external.amount_decimals.clone()
+            + self.fee_collector.amount_decimals.clone()
+    }
+}
+
 #[derive(CandidType, Deserialize, Clone)]
 pub(crate) struct ValidatedBalances {
     pub timestamp_ns: u64,
@@ -33,10 +59,215 @@ pub(crate) struct ValidatedBalances {
 }
 
 impl ValidatedBalances {
-    pub(crate) fn new() -> Self {
-        todo!()
+    pub(crate) fn new(timestamp_ns: u64) -> Self {
+        Self {
+            timestamp_ns,
+            asset_to_balances: BTreeMap::new(),
+        }
+    }
+
+    /// Registers `asset` if it is not already tracked, seeding all four parties at zero.
+    /// Calling this on an already-registered asset is a no-op.
+    pub(crate) fn refresh_asset(
+        &mut self,
+        asset: ValidatedAsset,
+        account_treasury_owner: Option<sns_treasury_manager::Account>,
+        timestamp_ns: u64,
+    ) {
+        self.timestamp_ns = timestamp_ns;
+
+        self.asset_to_balances
+            .entry(asset)
+            .or_insert_with(|| ValidatedBalancesForAsset::empty(account_treasury_owner));
+    }
+
+    /// Replaces the per-party balances observed for `asset` (e.g. after querying
+    /// `icrc1_balance_of`, `icrc2_allowance`, and the KongSwap LP position), enforcing the
+    /// conservation invariant that the total held across all four parties may only move by
+    /// `expected_total_change_decimals` — the net effect of deposits/withdrawals applied during
+    /// this refresh. Any other drift is rejected rather than silently absorbed into one party.
+    pub(crate) fn refresh_party_balances(
+        &mut self,
+        asset: ValidatedAsset,
+        treasury_owner: Balance,
+        treasury_manager: Balance,
+        external: Balance,
+        fee_collector: Balance,
+        expected_total_change_decimals: Nat,
+        timestamp_ns: u64,
+    ) -> Result<(), String> {
+        let updated = ValidatedBalancesForAsset {
+            treasury_owner,
+            treasury_manager,
+            external,
+            fee_collector,
+        };
+
+        let new_total = updated.total_decimals();
+
+        if let Some(previous) = self.asset_to_balances.get(&asset) {
+            let previous_total = previous.total_decimals();
+            let expected_total = previous_total.clone() + expected_total_change_decimals.clone();
+
+            if new_total != expected_total {
+                return Err(format!(
+                    "Conservation invariant violated while refreshing {}: expected total {} \
+                     (previous total {} plus {} externally-initiated change), observed {}.",
+                    asset.symbol(),
+                    expected_total,
+                    previous_total,
+                    expected_total_change_decimals,
+                    new_total,
+                ));
+            }
+        }
+
+        self.timestamp_ns = timestamp_ns;
+        self.asset_to_balances.insert(asset, updated);
+
+        Ok(())
+    }
+}
+
+/// The four parties [`reconcile_from_audit_trail`] folds every replayed ledger transfer into.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub(crate) enum Party {
+    Sns,
+    External,
+    FeeCollector,
+    TreasuryOwner,
+}
+
+impl Display for Party {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Party::Sns => write!(f, "Sns"),
+            Party::External => write!(f, "External"),
+            Party::FeeCollector => write!(f, "FeeCollector"),
+            Party::TreasuryOwner => write!(f, "TreasuryOwner"),
+        }
+    }
+}
+
+/// Which two parties a transaction's witnessed ledger transfer(s) move value between, given only
+/// the [`Operation`] kind that produced them -- a [`Transfer`] witness carries just the ledger and
+/// the amount moved, not a `to`/`from` account, so the direction has to come from what that
+/// operation kind is known to do. `None` for `Balances`, which never moves funds.
+fn parties_for_operation(operation: Operation) -> Option<(Party, Party)> {
+    match operation {
+        Operation::Deposit => Some((Party::External, Party::Sns)),
+        Operation::Withdraw | Operation::IssueReward => Some((Party::Sns, Party::TreasuryOwner)),
+        Operation::Balances => None,
+    }
+}
+
+/// Folds every witnessed transfer on `asset`'s ledger, across every fund-moving operation in
+/// `audit_trail`, into a per-party ledger: [`parties_for_operation`]'s `to` party is credited with
+/// the transfer net of `asset`'s ledger fee, `from` is debited the full amount, and
+/// [`Party::FeeCollector`] is credited the fee. By construction each entry's debit and credit
+/// always net to zero; the `debug_assert` below only exists to catch a regression in this
+/// function itself, not to validate external input.
+fn fold_asset_ledger(
+    audit_trail: &[(Operation, Vec<Transfer>)],
+    asset: ValidatedAsset,
+) -> BTreeMap<Party, i128> {
+    let ledger_canister_id = asset.ledger_canister_id().to_string();
+    let fee_decimals = i128::from(asset.ledger_fee_decimals());
+    let mut ledger: BTreeMap<Party, i128> = BTreeMap::new();
+
+    let total = |ledger: &BTreeMap<Party, i128>| -> i128 { ledger.values().sum() };
+
+    for (operation, transfers) in audit_trail {
+        let Some((from, to)) = parties_for_operation(*operation) else {
+            continue;
+        };
+
+        for transfer in transfers {
+            if transfer.ledger_canister_id != ledger_canister_id {
+                continue;
+            }
+            let Ok(amount_decimals) = decode_nat_to_u64(transfer.amount_decimals.clone()) else {
+                continue;
+            };
+            let amount_decimals = i128::from(amount_decimals);
+            let fee_decimals = fee_decimals.min(amount_decimals);
+            let net_decimals = amount_decimals - fee_decimals;
+
+            let total_before = total(&ledger);
+
+            *ledger.entry(from).or_default() -= amount_decimals;
+            *ledger.entry(to).or_default() += net_decimals;
+            *ledger.entry(Party::FeeCollector).or_default() += fee_decimals;
+
+            debug_assert_eq!(
+                total_before,
+                total(&ledger),
+                "replayed {} transfer did not net to zero across parties",
+                asset.symbol(),
+            );
+        }
+    }
+
+    ledger
+}
+
+/// Surfaced by [`reconcile_from_audit_trail`] when an asset's replayed party total, reconstructed
+/// purely from the audit trail, diverges from what's currently live.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct AuditReconciliationDiscrepancy {
+    pub asset: ValidatedAsset,
+    pub party: String,
+    pub replayed_decimals: Nat,
+    pub live_decimals: Nat,
+}
+
+/// Reconstructs each managed asset's [`Party::FeeCollector`] total purely by replaying
+/// `audit_trail` (see [`fold_asset_ledger`]) and compares it against the live total
+/// [`crate::balances::ValidatedBalanceBook::fee_collector`] reports, surfacing any asset where
+/// they diverge.
+///
+/// `FeeCollector` is the only party this reconciliation can faithfully replay from the audit trail
+/// alone: a witnessed transfer's amount mixes newly-deposited principal with recirculated
+/// DEX-custodial balance, which the audit trail doesn't distinguish, so `Sns`/`External`/
+/// `TreasuryOwner` can't be reconstructed to live fidelity without decoding the transfers' own
+/// ICRC-3 blocks (see [`crate::reconciliation`]). This is a coarser, audit-trail-only check meant
+/// to flag silent drift after a stuck deposit/withdraw for a DAO to investigate further -- it does
+/// not replace [`crate::balances::ValidatedBalances::reconcile`]'s own live conservation check.
+///
+/// Takes `assets` as a slice (rather than the pool-pair-shaped `(ValidatedAsset, ValidatedAsset)`
+/// most call sites use) so this already works unchanged against however many assets
+/// [`crate::balances::ValidatedBalances::registered_assets`] reports, not just a fixed two.
+pub(crate) fn reconcile_from_audit_trail(
+    audit_trail: &[(Operation, Vec<Transfer>)],
+    assets: &[ValidatedAsset],
+    live: &crate::balances::ValidatedBalances,
+) -> Vec<AuditReconciliationDiscrepancy> {
+    let mut discrepancies = Vec::new();
+
+    for asset in assets.iter().copied() {
+        let ledger = fold_asset_ledger(audit_trail, asset);
+        let replayed_fee_decimals = ledger
+            .get(&Party::FeeCollector)
+            .copied()
+            .unwrap_or(0)
+            .max(0);
+        let replayed_fee_decimals = u64::try_from(replayed_fee_decimals).unwrap_or(u64::MAX);
+
+        let live_fee_decimals = live
+            .asset_to_balances
+            .get(&asset)
+            .map(|book| book.fee_collector)
+            .unwrap_or_default();
+
+        if replayed_fee_decimals != live_fee_decimals {
+            discrepancies.push(AuditReconciliationDiscrepancy {
+                asset,
+                party: Party::FeeCollector.to_string(),
+                replayed_decimals: Nat::from(replayed_fee_decimals),
+                live_decimals: Nat::from(live_fee_decimals),
+            });
+        }
     }
-    pub(crate) fn refresh_asset(&mut self) {}
 
-    pub(crate) fn refresh_party_balances(&mut self) {}
+    discrepancies
 }
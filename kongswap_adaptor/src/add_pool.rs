@@ -48,6 +48,10 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
                 // Not needed for the ICRC2 flow.
                 tx_id_0: None,
                 tx_id_1: None,
+
+                // Lets a later reconciliation pass tie the resulting transfers back to this
+                // deposit, the same way `memo` already does for direct ledger transfers.
+                memo: Some(Vec::<u8>::from(TreasuryManagerOperation::Deposit)),
             },
             TreasuryManagerOperation::Deposit,
             "Calling KongSwapBackend.add_pool to add a new pool.".to_string(),
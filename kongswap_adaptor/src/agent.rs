@@ -1,12 +1,19 @@
 use candid::{CandidType, Principal};
 use serde::de::DeserializeOwned;
+use simulated_agent::Overlay;
 use sns_treasury_manager::TransactionWitness;
 use std::{error::Error, fmt::Display, future::Future};
 
 pub mod ic_cdk_agent;
+pub mod icrc3_requests;
 pub mod icrc_requests;
+pub mod legacy_ledger_requests;
+pub mod matcher_agent;
+pub mod mock_agent;
+pub mod retrying_agent;
+pub mod simulated_agent;
 
-pub trait Request: Send {
+pub trait Request: Send + Clone {
     fn method(&self) -> &'static str;
     fn payload(&self) -> Result<Vec<u8>, candid::Error>;
 
@@ -22,10 +29,46 @@ pub trait Request: Send {
         canister_id: Principal,
         response: Self::Response,
     ) -> Result<(TransactionWitness, Self::Ok), String>;
+
+    /// Returns the deduplication key already carried by this request (e.g. the `memo` and
+    /// `created_at_time` pair on an ICRC-1/ICRC-2 transfer-style call), if any.
+    ///
+    /// IC update calls are not idempotent by default: re-submitting one after a transient
+    /// failure can execute it twice. A deduplication key lets the destination canister recognize
+    /// a resubmission and no-op it instead, which is what makes it safe for
+    /// [`retrying_agent::RetryingAgent`] to retry this request. Requests without one (the
+    /// default) are only ever attempted once, regardless of how transient the failure looks.
+    fn dedup_key(&self) -> Option<String> {
+        None
+    }
+
+    /// Models this request's effect against a [`simulated_agent::SimulatedAgent`]'s
+    /// [`Overlay`] instead of submitting it, returning the modeled response.
+    ///
+    /// The default (`None`) means this request has no simulated model yet, so
+    /// [`simulated_agent::SimulatedAgent`] forwards it to its (expected read-only) inner agent
+    /// instead. `caller` is the principal initiating the call, since requests like
+    /// [`icrc_requests::ApproveArgs`](crate::agent::icrc_requests::ApproveArgs) don't otherwise
+    /// carry their own account.
+    fn simulate(
+        &self,
+        _canister_id: Principal,
+        _caller: Principal,
+        _overlay: &mut Overlay,
+    ) -> Option<Self::Response> {
+        None
+    }
 }
 
 pub trait AbstractAgent: Clone + Send + Sync {
-    type Error: Display + Send + Error + 'static;
+    type Error: Display + Send + Error + ErrorClassification + 'static;
+
+    /// Whether this agent simulates calls instead of submitting them (true for
+    /// [`simulated_agent::SimulatedAgent`], false for every live or retry-wrapping agent). Lets
+    /// [`crate::state::KongSwapAdaptor::finalize_audit_trail_transaction`] skip the durable
+    /// audit-trail write for a simulated run, so dry-running a deposit or rebalance leaves no
+    /// trace in stable storage.
+    const IS_SIMULATED: bool = false;
 
     fn call<R: Request>(
         &self,
@@ -33,3 +76,10 @@ pub trait AbstractAgent: Clone + Send + Sync {
         request: R,
     ) -> impl Future<Output = Result<R::Response, Self::Error>> + Send;
 }
+
+/// Lets [`retrying_agent::RetryingAgent`] tell a transient failure (a system- or
+/// transport-level reject, safe to retry) apart from a permanent one (an application-level
+/// error, where retrying would just reproduce the same failure).
+pub trait ErrorClassification {
+    fn is_transient(&self) -> bool;
+}
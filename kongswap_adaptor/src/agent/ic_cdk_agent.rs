@@ -1,4 +1,4 @@
-use super::{AbstractAgent, Request};
+use super::{AbstractAgent, ErrorClassification, Request};
 use candid::Principal;
 use thiserror::Error;
 
@@ -20,6 +20,23 @@ pub enum CdkAgentError {
     CandidDecode(candid::Error),
 }
 
+impl ErrorClassification for CdkAgentError {
+    /// Classifies an error by the IC's reject code (see the Internet Computer interface
+    /// specification's `reject_code` values): `SYS_FATAL` (1), `DESTINATION_INVALID` (3),
+    /// `CANISTER_REJECT` (4) and `CANISTER_ERROR` (5) indicate the call was rejected or trapped
+    /// on its own terms and would fail identically on retry, whereas `SYS_TRANSIENT` (2) and
+    /// `SYS_UNKNOWN` (6) indicate a transport- or queueing-level problem (e.g. the destination
+    /// was temporarily unreachable, or the outcome of the call is genuinely unknown) that a
+    /// later retry may no longer hit. A candid encode/decode failure is a bug in this canister or
+    /// its counterpart, not a transport issue, so it is never treated as transient.
+    fn is_transient(&self) -> bool {
+        match self {
+            CdkAgentError::IcCdk(reject_code, _) => matches!(reject_code, 2 | 6),
+            CdkAgentError::CandidEncode(_) | CdkAgentError::CandidDecode(_) => false,
+        }
+    }
+}
+
 impl AbstractAgent for CdkAgent {
     type Error = CdkAgentError;
 
@@ -0,0 +1,55 @@
+//! A `Request` implementation for ICRC-3 (`icrc3_get_blocks`), used to reconcile the adaptor's
+//! bookkeeping against a ledger's own block history instead of diffing `icrc1_balance_of`
+//! snapshots taken before and after a call. See https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-3
+
+use super::Request;
+use candid::{CandidType, Error, Nat};
+use icrc_ledger_types::icrc3::blocks::{GetBlocksRequest, GetBlocksResult};
+use serde::Serialize;
+use sns_treasury_manager::TransactionWitness;
+
+/// Requests the half-open block range `[start, start + length)` from a ledger's `icrc3_get_blocks`
+/// endpoint. The response additionally reports the ledger's current `log_length`, so callers can
+/// first issue a zero-length request to discover how many blocks exist.
+#[derive(CandidType, Serialize, Clone, Debug)]
+pub struct Icrc3GetBlocksRequest(pub Vec<GetBlocksRequest>);
+
+impl Icrc3GetBlocksRequest {
+    pub fn new(start: u64, length: u64) -> Self {
+        Self(vec![GetBlocksRequest {
+            start: Nat::from(start),
+            length: Nat::from(length),
+        }])
+    }
+}
+
+impl Request for Icrc3GetBlocksRequest {
+    fn method(&self) -> &'static str {
+        "icrc3_get_blocks"
+    }
+
+    fn update(&self) -> bool {
+        false
+    }
+
+    fn payload(&self) -> Result<Vec<u8>, Error> {
+        candid::encode_one(&self.0)
+    }
+
+    type Response = GetBlocksResult;
+
+    type Ok = Self::Response;
+
+    fn transaction_witness(
+        &self,
+        _canister_id: candid::Principal,
+        response: Self::Response,
+    ) -> Result<(TransactionWitness, Self::Ok), String> {
+        let response_str = format!(
+            "GetBlocksResult {{ log_length: {}, blocks: {} }}",
+            response.log_length,
+            response.blocks.len()
+        );
+        Ok((TransactionWitness::NonLedger(response_str), response))
+    }
+}
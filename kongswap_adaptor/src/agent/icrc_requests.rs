@@ -1,9 +1,10 @@
 //! This module contains implementations of the `Request` trait for some ICRC-1 and ICRC-2
 //! functions used in the KongSwap Adaptor canister. See https://github.com/dfinity/ICRC-1
 
-use super::Request;
+use super::{simulated_agent::Overlay, Request};
 use candid::{CandidType, Error, Nat, Principal};
 use icrc_ledger_types::icrc::generic_metadata_value::MetadataValue;
+use icrc_ledger_types::icrc1::transfer::{TransferArg, TransferError};
 use icrc_ledger_types::icrc2::approve::{ApproveArgs, ApproveError};
 use serde::Serialize;
 use sns_treasury_manager::{TransactionWitness, Transfer};
@@ -21,6 +22,27 @@ impl Request for ApproveArgs {
         candid::encode_one(self)
     }
 
+    /// An ICRC-2 approve is only dedup-protected once it carries a `created_at_time`; the ledger
+    /// uses that, together with `memo` and the rest of the approve's fields, to recognize and
+    /// reject a resubmitted duplicate.
+    fn dedup_key(&self) -> Option<String> {
+        let created_at_time = self.created_at_time?;
+        Some(format!("{:?}:{}", self.memo, created_at_time))
+    }
+
+    /// An approve doesn't move the approving account's spendable balance by itself -- it only
+    /// records an allowance, which [`Overlay`] doesn't track -- so this only hands back a
+    /// synthetic block index. The approval fee, and whatever a matching `transfer_from` later
+    /// moves, are accounted for when that transfer's own `Request` is simulated.
+    fn simulate(
+        &self,
+        _canister_id: Principal,
+        _caller: Principal,
+        overlay: &mut Overlay,
+    ) -> Option<Self::Response> {
+        Some(Ok(Nat::from(overlay.next_synthetic_index())))
+    }
+
     type Response = Result<Nat, ApproveError>;
 
     type Ok = Nat;
@@ -45,6 +67,62 @@ impl Request for ApproveArgs {
     }
 }
 
+impl Request for TransferArg {
+    fn method(&self) -> &'static str {
+        "icrc1_transfer"
+    }
+
+    fn update(&self) -> bool {
+        true
+    }
+
+    fn payload(&self) -> Result<Vec<u8>, Error> {
+        candid::encode_one(self)
+    }
+
+    /// Mirrors [`ApproveArgs::dedup_key`]: an `icrc1_transfer` is only dedup-protected once it
+    /// carries a `created_at_time`, which the ledger uses together with `memo` to recognize and
+    /// reject a resubmitted duplicate.
+    fn dedup_key(&self) -> Option<String> {
+        let created_at_time = self.created_at_time?;
+        Some(format!("{:?}:{}", self.memo, created_at_time))
+    }
+
+    type Response = Result<Nat, TransferError>;
+
+    type Ok = Nat;
+
+    fn transaction_witness(
+        &self,
+        canister_id: Principal,
+        response: Self::Response,
+    ) -> Result<(TransactionWitness, Self::Ok), String> {
+        // `Duplicate` means the ledger recognized this call as a resubmission of a transfer it
+        // already applied (matching `memo` + `created_at_time`), and is reporting the original
+        // transfer's block index rather than rejecting it outright. Treating it as a hard
+        // failure here would turn the ledger's own dedup protection into a poison pill: a
+        // canister that traps right after a transfer settles, then retries the same logical
+        // step on resume, would see every retry fail forever instead of recognizing it already
+        // went through.
+        let block_index = match response {
+            Ok(block_index) => block_index,
+            Err(TransferError::Duplicate { duplicate_of }) => duplicate_of,
+            Err(err) => return Err(err.to_string()),
+        };
+
+        let ledger_canister_id = canister_id.to_string();
+        let amount_decimals = self.amount.clone();
+
+        let witness = TransactionWitness::Ledger(vec![Transfer {
+            ledger_canister_id,
+            amount_decimals,
+            block_index: block_index.clone(),
+        }]);
+
+        Ok((witness, block_index))
+    }
+}
+
 #[derive(CandidType, Serialize, Clone, Debug, PartialEq, Eq)]
 pub struct Icrc1MetadataRequest {}
 
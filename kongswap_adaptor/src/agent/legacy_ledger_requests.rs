@@ -0,0 +1,91 @@
+//! `Request` implementations for the classic (pre-ICRC) ICP ledger interface: `transfer` and
+//! `account_balance`, addressed by `AccountIdentifier` rather than an ICRC-1 `Account`. See
+//! [`crate::validation::LedgerProtocol::Legacy`].
+
+use super::Request;
+use candid::{Error, Principal};
+use ic_ledger_types::{AccountBalanceArgs, Tokens, TransferArgs, TransferError};
+use sns_treasury_manager::{TransactionWitness, Transfer};
+
+impl Request for TransferArgs {
+    fn method(&self) -> &'static str {
+        "transfer"
+    }
+
+    fn update(&self) -> bool {
+        true
+    }
+
+    fn payload(&self) -> Result<Vec<u8>, Error> {
+        candid::encode_one(self)
+    }
+
+    /// Mirrors [`super::icrc_requests::ApproveArgs::dedup_key`]: a `transfer` is only
+    /// dedup-protected once it carries a `created_at_time`, which the ledger uses together with
+    /// `memo` to recognize and reject a resubmitted duplicate.
+    fn dedup_key(&self) -> Option<String> {
+        let created_at_time = self.created_at_time?;
+        Some(format!("{:?}:{:?}", self.memo, created_at_time))
+    }
+
+    type Response = Result<u64, TransferError>;
+
+    type Ok = u64;
+
+    fn transaction_witness(
+        &self,
+        canister_id: Principal,
+        response: Self::Response,
+    ) -> Result<(TransactionWitness, Self::Ok), String> {
+        // `TxDuplicate` means the ledger itself recognized this call as a resubmission of a
+        // transfer it already applied (matching `memo` + `created_at_time`), and is reporting
+        // the original transfer's block index rather than rejecting it outright. Treating it as
+        // a hard failure here would turn the ledger's own dedup protection into a poison pill: a
+        // canister that traps right after a transfer settles, then retries the same logical
+        // step on resume, would see every retry fail forever instead of recognizing it already
+        // went through.
+        let block_index = match response {
+            Ok(block_index) => block_index,
+            Err(TransferError::TxDuplicate { duplicate_of }) => duplicate_of,
+            Err(err) => return Err(err.to_string()),
+        };
+
+        let ledger_canister_id = canister_id.to_string();
+        let amount_decimals = candid::Nat::from(self.amount.e8s());
+
+        let witness = TransactionWitness::Ledger(vec![Transfer {
+            ledger_canister_id,
+            amount_decimals,
+            block_index: candid::Nat::from(block_index),
+        }]);
+
+        Ok((witness, block_index))
+    }
+}
+
+impl Request for AccountBalanceArgs {
+    fn method(&self) -> &'static str {
+        "account_balance"
+    }
+
+    fn update(&self) -> bool {
+        false
+    }
+
+    fn payload(&self) -> Result<Vec<u8>, Error> {
+        candid::encode_one(self)
+    }
+
+    type Response = Tokens;
+
+    type Ok = Self::Response;
+
+    fn transaction_witness(
+        &self,
+        _canister_id: Principal,
+        response: Self::Response,
+    ) -> Result<(TransactionWitness, Self::Ok), String> {
+        let response_str = format!("{:?}", response);
+        Ok((TransactionWitness::NonLedger(response_str), response))
+    }
+}
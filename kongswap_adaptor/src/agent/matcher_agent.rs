@@ -0,0 +1,234 @@
+//! A predicate-matching alternative to [`super::mock_agent::MockAgent`]'s strict byte-for-byte
+//! FIFO replay -- see the module-level comment there for when each mock is the better fit.
+//!
+//! Expectations are registered per `(Principal, request type)` via [`MatcherAgent::expect_call`],
+//! each carrying an optional field-level predicate over the decoded request
+//! ([`ExpectationBuilder::matching`]), a response ([`ExpectationBuilder::returns`]/
+//! [`ExpectationBuilder::returns_with`]), and an optional call count
+//! ([`ExpectationBuilder::expect_times`] -- omitted, an expectation is an unlimited fallback).
+//! Matching considers every still-eligible expectation regardless of registration order, so
+//! independent calls the adaptor happens to issue back-to-back don't need re-ordering the way
+//! [`super::mock_agent::MockAgent`]'s strict groups do. Every call that arrives is appended to
+//! [`MatcherAgent::call_trace`], so a test can assert ordering only where it actually cares. An
+//! unmatched call panics with the method name and, for every expectation registered against the
+//! same canister, its decoded request -- not a raw byte vector -- so a mismatch is readable at a
+//! glance.
+//!
+//! `commit_state`, the self-call [`crate::emit_transaction`] issues after every real call, is
+//! pre-registered as an unlimited fallback in [`MatcherAgent::new`], so tests don't have to
+//! re-register it after every `expect_call`.
+
+use super::{mock_agent::MockError, AbstractAgent, Request};
+use crate::requests::CommitStateRequest;
+use candid::Principal;
+use std::{
+    any::type_name,
+    fmt::Debug,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+};
+
+struct Expectation {
+    canister_id: Principal,
+    type_name: &'static str,
+    /// `None` means an unlimited fallback; `Some(n)` is decremented on every match and the
+    /// expectation stops being eligible once it reaches zero.
+    remaining: Option<usize>,
+    matches: Box<dyn Fn(&[u8]) -> bool + Send>,
+    respond: Box<dyn Fn(&[u8]) -> Vec<u8> + Send>,
+    describe: Box<dyn Fn(&[u8]) -> String + Send>,
+}
+
+/// See the module-level comment.
+#[derive(Clone)]
+pub struct MatcherAgent {
+    expectations: Arc<Mutex<Vec<Expectation>>>,
+    trace: Arc<Mutex<Vec<(Principal, &'static str)>>>,
+}
+
+impl MatcherAgent {
+    /// `self_canister_id` is the adaptor's own principal, against which `commit_state` is
+    /// pre-registered as an unlimited fallback -- see the module-level comment.
+    pub fn new(self_canister_id: Principal) -> Self {
+        let agent = Self {
+            expectations: Arc::new(Mutex::new(Vec::new())),
+            trace: Arc::new(Mutex::new(Vec::new())),
+        };
+
+        agent
+            .expect_call::<CommitStateRequest>(self_canister_id)
+            .returns(())
+    }
+
+    /// Starts registering an expectation for calls of type `Req` to `canister_id`. Chain
+    /// [`ExpectationBuilder::matching`]/[`ExpectationBuilder::expect_times`] and finish with
+    /// [`ExpectationBuilder::returns`]/[`ExpectationBuilder::returns_with`], which hands the agent
+    /// back so further `expect_call`s can be chained.
+    pub fn expect_call<Req>(self, canister_id: Principal) -> ExpectationBuilder<Req>
+    where
+        Req: Request + Debug + 'static,
+    {
+        ExpectationBuilder {
+            agent: self,
+            canister_id,
+            predicate: None,
+            times: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The `(canister_id, method)` of every call received so far, in the order they arrived --
+    /// for asserting ordering in the one test that cares about it, without forcing every other
+    /// test to hand-script incidental orderings.
+    pub fn call_trace(&self) -> Vec<(Principal, &'static str)> {
+        self.trace.lock().unwrap().clone()
+    }
+
+    /// Whether every expectation registered with an explicit [`ExpectationBuilder::expect_times`]
+    /// has been fully matched -- assert this at the end of a test so a call the adaptor was
+    /// supposed to make, but didn't, doesn't pass silently. Unlimited fallbacks never block this.
+    pub fn finished_calls(&self) -> bool {
+        self.expectations
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|expectation| expectation.remaining.map_or(true, |remaining| remaining == 0))
+    }
+}
+
+impl AbstractAgent for MatcherAgent {
+    type Error = MockError;
+
+    async fn call<R: Request>(
+        &self,
+        canister_id: impl Into<Principal> + Send,
+        request: R,
+    ) -> Result<R::Response, Self::Error> {
+        let canister_id = canister_id.into();
+        let raw_request = request.payload().expect("Request is not encodable");
+        let method = request.method();
+
+        self.trace.lock().unwrap().push((canister_id, method));
+
+        let mut expectations = self.expectations.lock().unwrap();
+
+        let position = expectations.iter().position(|expectation| {
+            expectation.canister_id == canister_id
+                && expectation.remaining != Some(0)
+                && (expectation.matches)(&raw_request)
+        });
+
+        let Some(position) = position else {
+            let candidates: Vec<String> = expectations
+                .iter()
+                .filter(|expectation| expectation.canister_id == canister_id)
+                .map(|expectation| {
+                    format!(
+                        "{} (remaining: {:?}, decoded as this request: {})",
+                        expectation.type_name,
+                        expectation.remaining,
+                        (expectation.describe)(&raw_request),
+                    )
+                })
+                .collect();
+
+            panic!(
+                "No expectation matched {canister_id}.{method}. Expectations registered for \
+                 this canister: {candidates:#?}",
+            );
+        };
+
+        let raw_response = (expectations[position].respond)(&raw_request);
+        if let Some(remaining) = expectations[position].remaining.as_mut() {
+            *remaining -= 1;
+        }
+        drop(expectations);
+
+        Ok(candid::decode_one(&raw_response).expect("Unable to decode the matched response"))
+    }
+}
+
+/// Builds one [`Expectation`] for [`MatcherAgent`] -- see [`MatcherAgent::expect_call`].
+pub struct ExpectationBuilder<Req> {
+    agent: MatcherAgent,
+    canister_id: Principal,
+    predicate: Option<Box<dyn Fn(&Req) -> bool + Send>>,
+    times: Option<usize>,
+    _marker: PhantomData<Req>,
+}
+
+impl<Req> ExpectationBuilder<Req>
+where
+    Req: Request + Debug + 'static,
+{
+    /// Restricts this expectation to requests whose decoded fields satisfy `predicate`, e.g.
+    /// matching on `amount` or `to` rather than requiring byte-for-byte equality with a canned
+    /// request as [`super::mock_agent::MockAgent::add_call`] does.
+    pub fn matching(mut self, predicate: impl Fn(&Req) -> bool + Send + 'static) -> Self {
+        self.predicate = Some(Box::new(predicate));
+        self
+    }
+
+    /// Caps this expectation at `times` matches, after which it stops being eligible and
+    /// [`MatcherAgent::finished_calls`] requires it to have been reached exactly. Omit this to
+    /// register an unlimited fallback instead.
+    pub fn expect_times(mut self, times: usize) -> Self {
+        self.times = Some(times);
+        self
+    }
+
+    /// Finishes this expectation, returning `response.clone()` to every matching call, and hands
+    /// the agent back so further `expect_call`s can be chained.
+    pub fn returns(self, response: Req::Response) -> MatcherAgent
+    where
+        Req::Response: Clone + Send + 'static,
+    {
+        self.returns_with(move |_| response.clone())
+    }
+
+    /// Like [`Self::returns`], but computes the response from the decoded request instead of
+    /// returning the same canned value every time.
+    pub fn returns_with(
+        self,
+        respond: impl Fn(&Req) -> Req::Response + Send + 'static,
+    ) -> MatcherAgent {
+        let Self {
+            agent,
+            canister_id,
+            predicate,
+            times,
+            ..
+        } = self;
+
+        let matches: Box<dyn Fn(&[u8]) -> bool + Send> = Box::new(move |raw: &[u8]| {
+            candid::decode_one::<Req>(raw)
+                .ok()
+                .is_some_and(|req| predicate.as_ref().map_or(true, |predicate| predicate(&req)))
+        });
+
+        let respond: Box<dyn Fn(&[u8]) -> Vec<u8> + Send> = Box::new(move |raw: &[u8]| {
+            let req: Req =
+                candid::decode_one(raw).expect("request should decode: it was already matched");
+            let response = respond(&req);
+            candid::encode_one(response).expect("Response is not encodable")
+        });
+
+        let describe: Box<dyn Fn(&[u8]) -> String + Send> = Box::new(|raw: &[u8]| {
+            match candid::decode_one::<Req>(raw) {
+                Ok(req) => format!("{:?}", req),
+                Err(_) => "<does not decode as this request type>".to_string(),
+            }
+        });
+
+        agent.expectations.lock().unwrap().push(Expectation {
+            canister_id,
+            type_name: type_name::<Req>(),
+            remaining: times,
+            matches,
+            respond,
+            describe,
+        });
+
+        agent
+    }
+}
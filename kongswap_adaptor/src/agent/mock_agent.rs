@@ -0,0 +1,905 @@
+//! Test-only [`AbstractAgent`] implementations used by the deposit/withdraw test suites in place
+//! of a real ledger or the KongSwap backend.
+//!
+//! [`MockAgent`] replays a pre-scripted sequence of exact raw request/response pairs -- the
+//! simplest and most explicit mock, but brittle: every call the adaptor makes (including the
+//! `commit_state` self-call [`crate::emit_transaction`] issues after each one) has to be
+//! hand-listed. By default calls are matched strictly in order, but [`MockAgent::unordered`] opts
+//! a run of `add_call`s into set-based matching instead, so a test only has to encode the orderings
+//! that actually matter rather than every incidental one the adaptor happens to produce.
+//! [`MockLedgerAgent`] is an alternative for ledger-facing calls that keeps an in-memory
+//! `balances`/`allowances` ledger per canister and decodes/applies
+//! `icrc2_approve`/`icrc1_transfer`/`icrc2_transfer_from`/`icrc1_balance_of`/`icrc1_metadata`
+//! against it automatically, synthesizing the response a real ledger would give -- so a test only
+//! has to script the calls it doesn't model (KongSwap backend calls, via
+//! [`MockLedgerAgent::add_scripted_call`]), and can assert accounting invariants
+//! ([`MockLedgerAgent::assert_invariants`]) instead of just replaying canned bytes. A scripted
+//! KongSwap backend call that itself moves funds by calling the ledger directly (e.g. pulling
+//! liquidity into a pool) carries no such call this mock can see; script its balance effect
+//! alongside it with [`MockLedgerAgent::add_scripted_call_with_balances`] instead.
+
+use super::{AbstractAgent, Request};
+use crate::ledger_api::account_to_account_identifier;
+use crate::requests::CommitStateRequest;
+use candid::{Nat, Principal};
+use ic_ledger_types::{
+    AccountBalanceArgs, AccountIdentifier, Tokens, TransferArgs,
+    TransferError as LegacyTransferError,
+};
+use icrc_ledger_types::icrc::generic_metadata_value::MetadataValue;
+use icrc_ledger_types::icrc1::account::Account;
+use icrc_ledger_types::icrc1::transfer::{TransferArg, TransferError};
+use icrc_ledger_types::icrc2::approve::{ApproveArgs, ApproveError};
+use icrc_ledger_types::icrc2::transfer_from::{TransferFromArgs, TransferFromError};
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt::Display,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::Notify;
+
+#[derive(Clone, Debug)]
+pub struct MockError {
+    pub message: String,
+}
+
+impl From<String> for MockError {
+    fn from(message: String) -> Self {
+        MockError { message }
+    }
+}
+
+impl From<&str> for MockError {
+    fn from(message: &str) -> Self {
+        MockError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl Display for MockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for MockError {}
+
+impl super::ErrorClassification for MockError {
+    fn is_transient(&self) -> bool {
+        false
+    }
+}
+
+struct CallSpec {
+    raw_request: Vec<u8>,
+    raw_response: Vec<u8>,
+    canister_id: Principal,
+    /// Only ever set on calls scripted via [`MockLedgerAgent::add_scripted_call_with_balances`];
+    /// applied to [`MockLedgerState`] the instant this call is consumed.
+    effects: Vec<LedgerEffect>,
+}
+
+impl CallSpec {
+    fn new<Req>(canister_id: Principal, request: Req, response: Req::Response) -> Self
+    where
+        Req: Request,
+    {
+        let raw_request = request.payload().expect("Request is not encodable");
+        let raw_response = candid::encode_one(response).expect("Response is not encodable");
+
+        Self {
+            raw_request,
+            raw_response,
+            canister_id,
+            effects: Vec::new(),
+        }
+    }
+
+    fn with_effects(mut self, effects: Vec<LedgerEffect>) -> Self {
+        self.effects = effects;
+        self
+    }
+}
+
+/// A balance mutation applied to [`MockLedgerState`] the moment the scripted call it's attached
+/// to is consumed -- simulates a fund movement a real ledger would reflect because it happened
+/// there directly (e.g. KongSwap pulling/returning liquidity via its own calls to the ledger),
+/// even though this mock only sees the KongSwap call, never the ledger side of it.
+struct LedgerEffect {
+    ledger: Principal,
+    account: Account,
+    amount: u64,
+}
+
+/// Whether a [`CallGroup`] consumes its calls strictly in the order they were scripted, or lets
+/// them arrive in any order.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MatchMode {
+    Strict,
+    Unordered,
+}
+
+/// One contiguous run of `add_call`-scripted calls, all matched the same way. [`MockAgent`] keeps
+/// these as a queue of groups: a group is only fully consumed (and the next one exposed) once
+/// every call inside it has been matched, so ordering is still enforced *between* groups even
+/// when it's relaxed *within* one.
+enum CallGroup {
+    Strict(VecDeque<CallSpec>),
+    Unordered(Vec<CallSpec>),
+}
+
+impl CallGroup {
+    fn new(mode: MatchMode) -> Self {
+        match mode {
+            MatchMode::Strict => CallGroup::Strict(VecDeque::new()),
+            MatchMode::Unordered => CallGroup::Unordered(Vec::new()),
+        }
+    }
+
+    fn push(&mut self, call: CallSpec) {
+        match self {
+            CallGroup::Strict(queue) => queue.push_back(call),
+            CallGroup::Unordered(calls) => calls.push(call),
+        }
+    }
+
+    fn mode(&self) -> MatchMode {
+        match self {
+            CallGroup::Strict(_) => MatchMode::Strict,
+            CallGroup::Unordered(_) => MatchMode::Unordered,
+        }
+    }
+
+    /// Consumes the one entry in this group matching `canister_id`/`raw_request`, in [`Strict`]
+    /// mode only ever looking at the front, panicking if that's not a match; in [`Unordered`]
+    /// mode searching the whole remaining group. Returns the matched response and whether this
+    /// was the group's last remaining call.
+    ///
+    /// [`Strict`]: MatchMode::Strict
+    /// [`Unordered`]: MatchMode::Unordered
+    fn consume(&mut self, canister_id: Principal, raw_request: &[u8]) -> (Vec<u8>, bool) {
+        match self {
+            CallGroup::Strict(queue) => {
+                let expected_call = queue
+                    .pop_front()
+                    .expect("Strict call group should not be empty while still queued");
+                if canister_id != expected_call.canister_id {
+                    panic!(
+                        "Canister IDs don't match: expected {}, got {}.",
+                        expected_call.canister_id, canister_id
+                    );
+                }
+                if raw_request != expected_call.raw_request {
+                    panic!("Request doesn't match the next expected call.");
+                }
+                (expected_call.raw_response, queue.is_empty())
+            }
+            CallGroup::Unordered(calls) => {
+                let position = calls
+                    .iter()
+                    .position(|call| call.canister_id == canister_id && call.raw_request == raw_request)
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "Request to {} doesn't match any call still pending in the current \
+                             unordered group.",
+                            canister_id
+                        )
+                    });
+                let expected_call = calls.remove(position);
+                (expected_call.raw_response, calls.is_empty())
+            }
+        }
+    }
+}
+
+/// Replays a pre-scripted sequence of raw request/response pairs -- see the module-level comment
+/// for when [`MockLedgerAgent`] is the better fit, and for how [`Self::unordered`] relaxes
+/// ordering within a group of calls.
+#[derive(Clone)]
+pub struct MockAgent {
+    self_canister_id: Principal,
+    expected_calls: Arc<Mutex<VecDeque<CallGroup>>>,
+    mode: MatchMode,
+}
+
+impl MockAgent {
+    /// `self_canister_id` is the adaptor's own principal, i.e. the target of the `commit_state`
+    /// self-call [`add_call`](Self::add_call) automatically appends after every scripted call.
+    pub fn new(self_canister_id: Principal) -> Self {
+        Self {
+            self_canister_id,
+            expected_calls: Arc::new(Mutex::new(VecDeque::default())),
+            mode: MatchMode::Strict,
+        }
+    }
+
+    /// Queues `request`/`response` as the next expected call to `canister_id`, followed by the
+    /// `commit_state` self-call every real `emit_transaction` issues afterward. Both are added to
+    /// whichever matching mode is currently active -- see [`Self::unordered`]/[`Self::ordered`].
+    pub fn add_call<Req>(
+        self,
+        canister_id: Principal,
+        request: Req,
+        response: Req::Response,
+    ) -> Self
+    where
+        Req: Request,
+    {
+        self.push(CallSpec::new(canister_id, request, response));
+        self.push(CallSpec::new(self.self_canister_id, CommitStateRequest {}, ()));
+        self
+    }
+
+    /// Switches subsequent `add_call`s into unordered (set-based) matching: any of them may be
+    /// consumed as soon as a matching request for it arrives, regardless of which was scripted
+    /// first, as long as the group as a whole is exhausted before the next strict-mode call
+    /// becomes eligible. Useful for independent calls the adaptor happens to issue back-to-back
+    /// (e.g. two ledger `approve`s for unrelated assets) where only *that* ordering is incidental.
+    pub fn unordered(mut self) -> Self {
+        self.mode = MatchMode::Unordered;
+        self
+    }
+
+    /// Switches subsequent `add_call`s back to strict, in-order matching -- the default.
+    pub fn ordered(mut self) -> Self {
+        self.mode = MatchMode::Strict;
+        self
+    }
+
+    /// Appends `call` to the current group, starting a new one first if there isn't one yet or
+    /// the active one doesn't match [`Self::mode`].
+    fn push(&self, call: CallSpec) {
+        let mut expected_calls = self.expected_calls.lock().unwrap();
+        let needs_new_group = !matches!(expected_calls.back(), Some(group) if group.mode() == self.mode);
+        if needs_new_group {
+            expected_calls.push_back(CallGroup::new(self.mode));
+        }
+        expected_calls
+            .back_mut()
+            .expect("a group was just pushed if none matched")
+            .push(call);
+    }
+
+    /// Whether every scripted call has been consumed -- assert this at the end of a test so a
+    /// call the adaptor was supposed to make, but didn't, doesn't pass silently.
+    pub fn finished_calls(&self) -> bool {
+        self.expected_calls.lock().unwrap().is_empty()
+    }
+}
+
+impl AbstractAgent for MockAgent {
+    type Error = MockError;
+
+    async fn call<R: Request>(
+        &self,
+        canister_id: impl Into<Principal> + Send,
+        request: R,
+    ) -> Result<R::Response, Self::Error> {
+        let canister_id = canister_id.into();
+        let raw_request = request.payload().expect("Request is not encodable");
+
+        let mut expected_calls = self.expected_calls.lock().unwrap();
+        let group = expected_calls
+            .front_mut()
+            .expect("Consumed all expected calls");
+        let (raw_response, group_exhausted) = group.consume(canister_id, &raw_request);
+        if group_exhausted {
+            expected_calls.pop_front();
+        }
+        drop(expected_calls);
+
+        Ok(candid::decode_one(&raw_response).expect("Unable to decode the scripted response"))
+    }
+}
+
+/// A stateful alternative to [`MockAgent`] for ledger-facing calls -- see the module-level
+/// comment.
+#[derive(Clone)]
+pub struct MockLedgerAgent {
+    /// The adaptor's own principal, i.e. the implicit `from`/`spender` of a call that doesn't
+    /// carry its own account (`icrc1_transfer`, `icrc2_approve`).
+    caller: Principal,
+    state: Arc<Mutex<MockLedgerState>>,
+}
+
+#[derive(Default)]
+struct MockLedgerState {
+    balances: HashMap<Principal, HashMap<Account, Nat>>,
+    // Every `Account` this mock has ever tracked a balance for, indexed by its derived
+    // `AccountIdentifier` -- so a legacy `transfer`'s `to` field (just an `AccountIdentifier`, with
+    // no way back to the `Account` it came from) can still land in `balances` and be asserted on
+    // through `MockLedgerAgent::balance_of` like any ICRC-1 destination, the way a real ledger's
+    // own bookkeeping sees both protocols' withdrawals of the same underlying account as one
+    // balance. Populated alongside every `set_balance`.
+    legacy_accounts: HashMap<Principal, HashMap<AccountIdentifier, Account>>,
+    // Legacy-protocol destinations that don't correspond to any `Account` this mock has tracked a
+    // balance for -- e.g. an ICP-native custodian addressed only by a bare `AccountIdentifier`,
+    // never by a principal/subaccount pair. See [`MockLedgerAgent::legacy_balance_of`].
+    legacy_balances: HashMap<Principal, HashMap<AccountIdentifier, Nat>>,
+    allowances: HashMap<Principal, HashMap<(Account, Account), (Nat, Option<u64>)>>,
+    fees: HashMap<Principal, Nat>,
+    metadata: HashMap<Principal, Vec<(String, MetadataValue)>>,
+    next_block_index: u64,
+    // KongSwap backend calls aren't modeled here; scripted the same way `MockAgent` is.
+    scripted_calls: VecDeque<CallSpec>,
+}
+
+impl MockLedgerAgent {
+    pub fn new(caller: Principal) -> Self {
+        Self {
+            caller,
+            state: Arc::new(Mutex::new(MockLedgerState::default())),
+        }
+    }
+
+    /// A handle onto this same in-memory ledger state, but acting as `caller` instead -- e.g. to
+    /// have one account `icrc2_approve` a second, then call as that second account to exercise
+    /// `icrc2_transfer_from`.
+    pub fn as_caller(&self, caller: Principal) -> Self {
+        Self {
+            caller,
+            state: self.state.clone(),
+        }
+    }
+
+    /// Seeds `ledger`'s fee and ICRC-1 metadata reply, both of which this mock needs in order to
+    /// compute transfer/approve deductions and answer `icrc1_metadata` itself.
+    pub fn with_ledger(self, ledger: Principal, fee: u64, name: &str, symbol: &str) -> Self {
+        let mut state = self.state.lock().unwrap();
+        state.fees.insert(ledger, Nat::from(fee));
+        state
+            .metadata
+            .insert(ledger, make_metadata_reply(name, symbol, fee));
+        drop(state);
+        self
+    }
+
+    /// Credits `account` on `ledger` with `amount`, as if it had arrived from outside this mock
+    /// (e.g. the SNS treasury owner funding the adaptor's account before a deposit).
+    pub fn with_balance(self, ledger: Principal, account: Account, amount: u64) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .set_balance(ledger, account, Nat::from(amount));
+        self
+    }
+
+    /// KongSwap backend calls aren't modeled by this mock; queue a canned response the same way
+    /// [`MockAgent::add_call`] does (without the `commit_state` auto-append, since that's handled
+    /// by the `"commit_state"` branch in [`Self::call`] instead).
+    pub fn add_scripted_call<Req>(
+        self,
+        canister_id: Principal,
+        request: Req,
+        response: Req::Response,
+    ) -> Self
+    where
+        Req: Request,
+    {
+        self.state
+            .lock()
+            .unwrap()
+            .scripted_calls
+            .push_back(CallSpec::new(canister_id, request, response));
+        self
+    }
+
+    /// Like [`Self::add_scripted_call`], but also sets each `(ledger, account)`'s balance to the
+    /// paired `new_balance` the instant this call is consumed -- for KongSwap backend calls (e.g.
+    /// `add_pool`, `remove_liquidity`) that move funds by calling the ledger directly rather than
+    /// through this adaptor's agent, so this mock would otherwise never see the effect.
+    pub fn add_scripted_call_with_balances<Req>(
+        self,
+        canister_id: Principal,
+        request: Req,
+        response: Req::Response,
+        balance_changes: Vec<(Principal, Account, u64)>,
+    ) -> Self
+    where
+        Req: Request,
+    {
+        let effects = balance_changes
+            .into_iter()
+            .map(|(ledger, account, amount)| LedgerEffect {
+                ledger,
+                account,
+                amount,
+            })
+            .collect();
+
+        self.state
+            .lock()
+            .unwrap()
+            .scripted_calls
+            .push_back(CallSpec::new(canister_id, request, response).with_effects(effects));
+        self
+    }
+
+    /// Whether every scripted KongSwap backend call has been consumed -- assert this at the end
+    /// of a test, the same way [`MockAgent::finished_calls`] is used, so a call the adaptor was
+    /// supposed to make, but didn't, doesn't pass silently. Ledger calls aren't scripted in the
+    /// first place, so they're not part of this check.
+    pub fn finished_calls(&self) -> bool {
+        self.state.lock().unwrap().scripted_calls.is_empty()
+    }
+
+    /// The current balance of `account` on `ledger`, for a test to assert against after a
+    /// deposit/withdraw completes.
+    pub fn balance_of(&self, ledger: Principal, account: &Account) -> Nat {
+        self.state.lock().unwrap().balance_of(ledger, account)
+    }
+
+    /// Like [`Self::balance_of`], but for a legacy-protocol destination addressed only by its
+    /// `AccountIdentifier` -- e.g. an ICP-native custodian this mock never saw an `Account` for.
+    /// Falls back to [`Self::balance_of`]'s own tracked balance if `identifier` happens to be one
+    /// this mock can still derive from a known `Account` (see
+    /// [`MockLedgerState::legacy_accounts`]).
+    pub fn legacy_balance_of(&self, ledger: Principal, identifier: &AccountIdentifier) -> Nat {
+        self.state.lock().unwrap().legacy_balance_of(ledger, identifier)
+    }
+
+    /// The sum of every tracked balance on `ledger`, across both the ICRC-1 and legacy-protocol
+    /// views of it -- see [`Self::assert_invariants`].
+    pub fn total_supply(&self, ledger: Principal) -> Nat {
+        self.state.lock().unwrap().total_supply(ledger)
+    }
+
+    /// Panics unless `ledger`'s total tracked supply equals `expected_total_supply` -- every debit
+    /// this mock applies either lands as a matching credit on another tracked account, or is
+    /// accounted for by the caller in `expected_total_supply` as a burned fee, so a real
+    /// conservation bug (a transfer crediting the wrong account, a fee deducted twice) shows up as
+    /// a mismatch here instead of passing silently. A balance going negative can't happen in the
+    /// first place: every debit below is preceded by a sufficient-funds check that rejects the
+    /// call instead of applying it.
+    pub fn assert_invariants(&self, ledger: Principal, expected_total_supply: &Nat) {
+        let total_supply = self.state.lock().unwrap().total_supply(ledger);
+        assert_eq!(
+            &total_supply, expected_total_supply,
+            "total supply on {} drifted: transfers must conserve balances modulo burned fees",
+            ledger,
+        );
+    }
+}
+
+impl MockLedgerState {
+    fn balance_of(&self, ledger: Principal, account: &Account) -> Nat {
+        self.balances
+            .get(&ledger)
+            .and_then(|balances| balances.get(account))
+            .cloned()
+            .unwrap_or_else(|| Nat::from(0u8))
+    }
+
+    fn set_balance(&mut self, ledger: Principal, account: Account, amount: Nat) {
+        self.legacy_accounts
+            .entry(ledger)
+            .or_default()
+            .insert(account_to_account_identifier(&account), account.clone());
+        self.balances.entry(ledger).or_default().insert(account, amount);
+    }
+
+    /// See [`MockLedgerAgent::legacy_balance_of`].
+    fn legacy_balance_of(&self, ledger: Principal, identifier: &AccountIdentifier) -> Nat {
+        if let Some(account) = self
+            .legacy_accounts
+            .get(&ledger)
+            .and_then(|accounts| accounts.get(identifier))
+        {
+            return self.balance_of(ledger, account);
+        }
+
+        self.legacy_balances
+            .get(&ledger)
+            .and_then(|balances| balances.get(identifier))
+            .cloned()
+            .unwrap_or_else(|| Nat::from(0u8))
+    }
+
+    /// Credits `amount` to a legacy-protocol destination `identifier`, routing it through
+    /// `balances` instead if `identifier` is one this mock already derived from a known `Account`
+    /// -- see [`Self::legacy_accounts`].
+    fn credit_legacy(&mut self, ledger: Principal, identifier: AccountIdentifier, amount: Nat) {
+        if let Some(account) = self
+            .legacy_accounts
+            .get(&ledger)
+            .and_then(|accounts| accounts.get(&identifier))
+            .cloned()
+        {
+            let balance = self.balance_of(ledger, &account);
+            self.set_balance(ledger, account, balance + amount);
+            return;
+        }
+
+        let balances = self.legacy_balances.entry(ledger).or_default();
+        let balance = balances.get(&identifier).cloned().unwrap_or_else(|| Nat::from(0u8));
+        balances.insert(identifier, balance + amount);
+    }
+
+    fn allowance(&self, ledger: Principal, owner: &Account, spender: &Account) -> Nat {
+        self.allowances
+            .get(&ledger)
+            .and_then(|allowances| allowances.get(&(owner.clone(), spender.clone())))
+            .map(|(amount, _expires_at)| amount.clone())
+            .unwrap_or_else(|| Nat::from(0u8))
+    }
+
+    fn fee(&self, ledger: Principal) -> Nat {
+        self.fees.get(&ledger).cloned().unwrap_or_else(|| Nat::from(0u8))
+    }
+
+    fn next_block_index(&mut self) -> Nat {
+        Nat::from(self.next_block_index_u64())
+    }
+
+    fn next_block_index_u64(&mut self) -> u64 {
+        let index = self.next_block_index;
+        self.next_block_index += 1;
+        index
+    }
+
+    fn total_supply(&self, ledger: Principal) -> Nat {
+        let tracked = self
+            .balances
+            .get(&ledger)
+            .map(|balances| {
+                balances
+                    .values()
+                    .fold(Nat::from(0u8), |total, balance| total + balance.clone())
+            })
+            .unwrap_or_else(|| Nat::from(0u8));
+
+        // `credit_legacy` only ever adds to `legacy_balances` for a destination it couldn't
+        // resolve to a known `Account` -- anything it could resolve landed in `balances` above
+        // instead, via `set_balance`, so this can't double-count.
+        let legacy_only = self
+            .legacy_balances
+            .get(&ledger)
+            .map(|balances| {
+                balances
+                    .values()
+                    .fold(Nat::from(0u8), |total, balance| total + balance.clone())
+            })
+            .unwrap_or_else(|| Nat::from(0u8));
+
+        tracked + legacy_only
+    }
+
+    fn handle_approve(
+        &mut self,
+        ledger: Principal,
+        caller: Principal,
+        request: &ApproveArgs,
+    ) -> Result<Nat, ApproveError> {
+        let owner = Account {
+            owner: caller,
+            subaccount: request.from_subaccount,
+        };
+        let fee = request.fee.clone().unwrap_or_else(|| self.fee(ledger));
+        let balance = self.balance_of(ledger, &owner);
+        if balance < fee {
+            return Err(ApproveError::InsufficientFunds { balance });
+        }
+
+        let current_allowance = self.allowance(ledger, &owner, &request.spender);
+        if let Some(expected_allowance) = &request.expected_allowance {
+            if *expected_allowance != current_allowance {
+                return Err(ApproveError::AllowanceChanged { current_allowance });
+            }
+        }
+
+        self.set_balance(
+            ledger,
+            owner.clone(),
+            crate::validation::saturating_sub(balance, fee),
+        );
+        self.allowances.entry(ledger).or_default().insert(
+            (owner, request.spender.clone()),
+            (request.amount.clone(), request.expires_at),
+        );
+
+        Ok(self.next_block_index())
+    }
+
+    fn handle_transfer(
+        &mut self,
+        ledger: Principal,
+        caller: Principal,
+        request: &TransferArg,
+    ) -> Result<Nat, TransferError> {
+        let from = Account {
+            owner: caller,
+            subaccount: request.from_subaccount,
+        };
+        let fee = request.fee.clone().unwrap_or_else(|| self.fee(ledger));
+        let total_debit = request.amount.clone() + fee;
+        let balance = self.balance_of(ledger, &from);
+        if balance < total_debit {
+            return Err(TransferError::InsufficientFunds { balance });
+        }
+
+        self.set_balance(ledger, from, crate::validation::saturating_sub(balance, total_debit));
+        let to_balance = self.balance_of(ledger, &request.to);
+        self.set_balance(ledger, request.to, to_balance + request.amount.clone());
+
+        Ok(self.next_block_index())
+    }
+
+    fn handle_transfer_from(
+        &mut self,
+        ledger: Principal,
+        caller: Principal,
+        request: &TransferFromArgs,
+    ) -> Result<Nat, TransferFromError> {
+        let spender = Account {
+            owner: caller,
+            subaccount: request.spender_subaccount,
+        };
+        let fee = request.fee.clone().unwrap_or_else(|| self.fee(ledger));
+        let total_debit = request.amount.clone() + fee;
+
+        let allowance = self.allowance(ledger, &request.from, &spender);
+        if allowance < total_debit {
+            return Err(TransferFromError::InsufficientAllowance { allowance });
+        }
+
+        let balance = self.balance_of(ledger, &request.from);
+        if balance < total_debit {
+            return Err(TransferFromError::InsufficientFunds { balance });
+        }
+
+        self.allowances.entry(ledger).or_default().insert(
+            (request.from.clone(), spender),
+            (
+                crate::validation::saturating_sub(allowance, total_debit.clone()),
+                request.expires_at,
+            ),
+        );
+        self.set_balance(
+            ledger,
+            request.from.clone(),
+            crate::validation::saturating_sub(balance, total_debit),
+        );
+        let to_balance = self.balance_of(ledger, &request.to);
+        self.set_balance(ledger, request.to.clone(), to_balance + request.amount.clone());
+
+        Ok(self.next_block_index())
+    }
+
+    fn handle_legacy_transfer(
+        &mut self,
+        ledger: Principal,
+        caller: Principal,
+        request: &TransferArgs,
+    ) -> Result<u64, LegacyTransferError> {
+        let from = Account {
+            owner: caller,
+            subaccount: request.from_subaccount.map(|subaccount| subaccount.0),
+        };
+        let fee = Nat::from(request.fee.e8s());
+        let amount = Nat::from(request.amount.e8s());
+        let total_debit = amount.clone() + fee;
+        let balance = self.balance_of(ledger, &from);
+        if balance < total_debit {
+            return Err(LegacyTransferError::InsufficientFunds {
+                balance: Tokens::from_e8s(
+                    crate::validation::decode_nat_to_u64(balance)
+                        .expect("mock balance should always fit in a u64"),
+                ),
+            });
+        }
+
+        self.set_balance(ledger, from, crate::validation::saturating_sub(balance, total_debit));
+        self.credit_legacy(ledger, request.to, amount);
+
+        Ok(self.next_block_index_u64())
+    }
+}
+
+fn make_metadata_reply(name: &str, symbol: &str, fee: u64) -> Vec<(String, MetadataValue)> {
+    vec![
+        (
+            "icrc1:decimals".to_string(),
+            MetadataValue::Nat(Nat::from(8_u64)),
+        ),
+        ("icrc1:name".to_string(), MetadataValue::Text(name.to_string())),
+        (
+            "icrc1:symbol".to_string(),
+            MetadataValue::Text(symbol.to_string()),
+        ),
+        ("icrc1:fee".to_string(), MetadataValue::Nat(Nat::from(fee))),
+    ]
+}
+
+impl AbstractAgent for MockLedgerAgent {
+    type Error = MockError;
+
+    async fn call<R: Request>(
+        &self,
+        canister_id: impl Into<Principal> + Send,
+        request: R,
+    ) -> Result<R::Response, Self::Error> {
+        let canister_id = canister_id.into();
+        let raw_request = request.payload().expect("Request is not encodable");
+        let mut state = self.state.lock().unwrap();
+
+        let raw_response = match request.method() {
+            "icrc2_approve" => {
+                let args: ApproveArgs =
+                    candid::decode_one(&raw_request).expect("Unable to decode icrc2_approve args");
+                let result = state.handle_approve(canister_id, self.caller, &args);
+                candid::encode_one(result).expect("Unable to encode icrc2_approve response")
+            }
+            "icrc1_transfer" => {
+                let args: TransferArg = candid::decode_one(&raw_request)
+                    .expect("Unable to decode icrc1_transfer args");
+                let result = state.handle_transfer(canister_id, self.caller, &args);
+                candid::encode_one(result).expect("Unable to encode icrc1_transfer response")
+            }
+            "icrc2_transfer_from" => {
+                let args: TransferFromArgs = candid::decode_one(&raw_request)
+                    .expect("Unable to decode icrc2_transfer_from args");
+                let result = state.handle_transfer_from(canister_id, self.caller, &args);
+                candid::encode_one(result).expect("Unable to encode icrc2_transfer_from response")
+            }
+            "icrc1_balance_of" => {
+                let account: Account = candid::decode_one(&raw_request)
+                    .expect("Unable to decode icrc1_balance_of args");
+                candid::encode_one(state.balance_of(canister_id, &account))
+                    .expect("Unable to encode icrc1_balance_of response")
+            }
+            "icrc1_metadata" => {
+                let metadata = state.metadata.get(&canister_id).cloned().unwrap_or_default();
+                candid::encode_one(metadata).expect("Unable to encode icrc1_metadata response")
+            }
+            // The classic/legacy ICP ledger interface, used instead of the ICRC-1 methods above
+            // whenever `LedgerProtocol::Legacy` applies (see `ledger_api.rs`) -- in practice, every
+            // ICP-denominated transfer this adaptor makes.
+            "transfer" => {
+                let args: TransferArgs =
+                    candid::decode_one(&raw_request).expect("Unable to decode transfer args");
+                let result = state.handle_legacy_transfer(canister_id, self.caller, &args);
+                candid::encode_one(result).expect("Unable to encode transfer response")
+            }
+            "account_balance" => {
+                let args: AccountBalanceArgs = candid::decode_one(&raw_request)
+                    .expect("Unable to decode account_balance args");
+                let tokens = Tokens::from_e8s(
+                    crate::validation::decode_nat_to_u64(
+                        state.legacy_balance_of(canister_id, &args.account),
+                    )
+                    .expect("mock balance should always fit in a u64"),
+                );
+                candid::encode_one(tokens).expect("Unable to encode account_balance response")
+            }
+            // `commit_state` is `emit_transaction`'s self-call to flush state before the next
+            // await point; it always succeeds and carries no ledger semantics, so it doesn't need
+            // to be scripted the way KongSwap backend calls below do.
+            "commit_state" => {
+                candid::encode_one(()).expect("Unable to encode commit_state response")
+            }
+            _ => {
+                let expected_call = state
+                    .scripted_calls
+                    .pop_front()
+                    .expect("Consumed all scripted calls");
+                if canister_id != expected_call.canister_id {
+                    panic!(
+                        "Canister IDs don't match: expected {}, got {}.",
+                        expected_call.canister_id, canister_id
+                    );
+                }
+                if raw_request != expected_call.raw_request {
+                    panic!("Request doesn't match the next scripted call.");
+                }
+                for effect in expected_call.effects {
+                    state.set_balance(effect.ledger, effect.account, Nat::from(effect.amount));
+                }
+                expected_call.raw_response
+            }
+        };
+
+        drop(state);
+
+        Ok(candid::decode_one(&raw_response).expect("Unable to decode the synthesized response"))
+    }
+}
+
+/// A deterministic cooperative scheduler for driving several concurrent call stacks (e.g. two
+/// interleaved `deposit`/`withdraw` invocations sharing a [`crate::state::KongSwapAdaptor`])
+/// through an explicit, reproducible interleaving instead of leaving it to the async runtime.
+///
+/// `schedule` names, for each checkpoint in turn, which stack is allowed to proceed past it.
+/// Every stack -- including the synchronous prefix of the top-level call it's driving, not just
+/// its [`AbstractAgent::call`]s -- must pass through [`Self::wait_turn`] at every point where it
+/// would otherwise race another stack, or the schedule has no way to hold it back. This
+/// generalizes the single hard-coded switch a context-switching test would otherwise hand-roll:
+/// any number of stacks, any number of switch points, driven by one ordered list.
+pub struct CooperativeScheduler {
+    schedule: Vec<usize>,
+    cursor: Mutex<usize>,
+    notify: Notify,
+    /// The order stacks actually passed their checkpoints in, so a run can be narrated or a
+    /// failure reported precisely -- it always equals `schedule` itself, since `wait_turn` blocks
+    /// until the schedule allows it, but recording it as it happens (rather than just trusting
+    /// `schedule`) is what would surface a scheduler bug.
+    realized_order: Mutex<Vec<usize>>,
+}
+
+impl CooperativeScheduler {
+    /// `schedule[i]` is the stack id let through the `i`-th checkpoint. A stack id may appear any
+    /// number of times; stacks not mentioned simply never reach that many checkpoints.
+    pub fn new(schedule: Vec<usize>) -> Arc<Self> {
+        Arc::new(Self {
+            schedule,
+            cursor: Mutex::new(0),
+            notify: Notify::new(),
+            realized_order: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Blocks until `stack_id` is the next one named by the schedule, then advances past this
+    /// checkpoint and wakes every other stack waiting here so they can re-check.
+    ///
+    /// Subscribes to [`Notify`] *before* re-checking the cursor, so a wakeup fired between the
+    /// check and the `.await` below is never missed.
+    pub async fn wait_turn(&self, stack_id: usize) {
+        loop {
+            let notified = self.notify.notified();
+            {
+                let mut cursor = self.cursor.lock().unwrap();
+                if self.schedule.get(*cursor) == Some(&stack_id) {
+                    *cursor += 1;
+                    self.realized_order.lock().unwrap().push(stack_id);
+                    self.notify.notify_waiters();
+                    return;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// The checkpoints passed so far, in the order stacks actually reached them -- for replaying
+    /// or narrating a specific seed's failure.
+    pub fn realized_order(&self) -> Vec<usize> {
+        self.realized_order.lock().unwrap().clone()
+    }
+}
+
+/// Wraps an inner [`AbstractAgent`] so every call made on behalf of `stack_id` first waits its
+/// turn on a shared [`CooperativeScheduler`] -- turning the runtime's own (nondeterministic)
+/// interleaving of concurrent `.await`s into whatever interleaving the scheduler's schedule
+/// dictates.
+#[derive(Clone)]
+pub struct SteppedAgent<A: AbstractAgent> {
+    inner: A,
+    scheduler: Arc<CooperativeScheduler>,
+    stack_id: usize,
+}
+
+impl<A: AbstractAgent> SteppedAgent<A> {
+    pub fn new(inner: A, scheduler: Arc<CooperativeScheduler>, stack_id: usize) -> Self {
+        Self {
+            inner,
+            scheduler,
+            stack_id,
+        }
+    }
+}
+
+impl<A: AbstractAgent> AbstractAgent for SteppedAgent<A> {
+    type Error = A::Error;
+
+    async fn call<R: Request>(
+        &self,
+        canister_id: impl Into<Principal> + Send,
+        request: R,
+    ) -> Result<R::Response, Self::Error> {
+        self.scheduler.wait_turn(self.stack_id).await;
+        self.inner.call(canister_id, request).await
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,348 @@
+//! A property-based conservation check for [`super::MockLedgerAgent`] itself: generates random
+//! sequences of `icrc2_approve`/`icrc1_transfer`/`icrc2_transfer_from` calls across a handful of
+//! accounts and asserts that total supply is conserved (modulo burned fees) after every single
+//! call, not just at the end.
+//!
+//! This only exercises the ledger-level mock, not full `DepositRequest`/`WithdrawRequest`/
+//! `issue_rewards` sequences -- doing that would additionally require a model of the KongSwap
+//! pool's own amount-out math (`add_pool`/`remove_liquidity` quotes), which
+//! [`super::MockLedgerAgent`] deliberately leaves scripted rather than simulated. What's here
+//! still catches the class of bug `test_withdraw_success`'s single hand-written path cannot:
+//! repeated partial transfers, zero-amount approvals, and fees larger than the transferred amount
+//! all fall out of the random amount/account choices below for free.
+
+use super::MockLedgerAgent;
+use crate::agent::AbstractAgent;
+use candid::{Nat, Principal};
+use ic_ledger_types::{
+    AccountBalanceArgs, AccountIdentifier, Subaccount, Tokens, TransferArgs,
+    TransferError as LegacyTransferError,
+};
+use icrc_ledger_types::icrc1::account::Account;
+use icrc_ledger_types::icrc1::transfer::{TransferArg, TransferError};
+use icrc_ledger_types::icrc2::approve::{ApproveArgs, ApproveError};
+use icrc_ledger_types::icrc2::transfer_from::{TransferFromArgs, TransferFromError};
+
+/// A small, deterministic, dependency-free PRNG -- this crate has no randomness source available
+/// outside of a canister (see [`crate::agent::retrying_agent`]'s jitter comment), and a property
+/// test needs its draws to be exactly reproducible from a seed anyway, so pulling in an external
+/// `rand` dependency would buy nothing a fixed splitmix64 doesn't already give us.
+struct Prng {
+    state: u64,
+}
+
+impl Prng {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// A value in `[0, bound)`.
+    fn next_below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+const LEDGER_FEE: u64 = 10_000;
+const NUM_ACCOUNTS: u64 = 4;
+const NUM_CALLS_PER_SEED: u32 = 200;
+const SEEDS: [u64; 8] = [1, 2, 3, 4, 5, 42, 1_000_003, 0xC0FFEE];
+
+fn account(principal_index: u64) -> Account {
+    // Small, low-numbered principals so they're easy to read back out of a failing assertion.
+    Account {
+        owner: Principal::from_slice(&[principal_index as u8]),
+        subaccount: None,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+    Approve { owner: u64, spender: u64, amount: u64 },
+    Transfer { from: u64, to: u64, amount: u64 },
+    TransferFrom { owner: u64, spender: u64, to: u64, amount: u64 },
+}
+
+fn generate_op(rng: &mut Prng) -> Op {
+    // Amounts deliberately range up to and past what any account could plausibly hold, so that
+    // insufficient-funds/insufficient-allowance rejections are exercised as often as successes.
+    let amount = rng.next_below(5 * LEDGER_FEE);
+    let a = rng.next_below(NUM_ACCOUNTS);
+    let b = rng.next_below(NUM_ACCOUNTS);
+    let c = rng.next_below(NUM_ACCOUNTS);
+
+    match rng.next_below(3) {
+        0 => Op::Approve {
+            owner: a,
+            spender: b,
+            amount,
+        },
+        1 => Op::Transfer {
+            from: a,
+            to: b,
+            amount,
+        },
+        _ => Op::TransferFrom {
+            owner: a,
+            spender: b,
+            to: c,
+            amount,
+        },
+    }
+}
+
+/// Runs `op` against `ledger` and returns whether it succeeded, i.e. whether a `LEDGER_FEE` was
+/// burned from the modeled world -- `icrc2_approve` included, since [`super::MockLedgerAgent`]
+/// deducts the fee from the owner on every successful approval, mirroring a real ICRC-2 ledger.
+async fn run_op(ledger: Principal, base_agent: &MockLedgerAgent, op: Op) -> bool {
+    match op {
+        Op::Approve {
+            owner,
+            spender,
+            amount,
+        } => {
+            let agent = base_agent.as_caller(account(owner).owner);
+            let result: Result<Nat, ApproveError> = agent
+                .call(
+                    ledger,
+                    ApproveArgs {
+                        from_subaccount: None,
+                        spender: account(spender),
+                        amount: Nat::from(amount),
+                        expected_allowance: None,
+                        expires_at: None,
+                        memo: None,
+                        created_at_time: None,
+                        fee: None,
+                    },
+                )
+                .await
+                .expect("MockLedgerAgent::call should not itself fail");
+            result.is_ok()
+        }
+        Op::Transfer { from, to, amount } => {
+            let agent = base_agent.as_caller(account(from).owner);
+            let result: Result<Nat, TransferError> = agent
+                .call(
+                    ledger,
+                    TransferArg {
+                        from_subaccount: None,
+                        to: account(to),
+                        amount: Nat::from(amount),
+                        fee: None,
+                        memo: None,
+                        created_at_time: None,
+                    },
+                )
+                .await
+                .expect("MockLedgerAgent::call should not itself fail");
+            result.is_ok()
+        }
+        Op::TransferFrom {
+            owner,
+            spender,
+            to,
+            amount,
+        } => {
+            let agent = base_agent.as_caller(account(spender).owner);
+            let result: Result<Nat, TransferFromError> = agent
+                .call(
+                    ledger,
+                    TransferFromArgs {
+                        spender_subaccount: None,
+                        from: account(owner),
+                        to: account(to),
+                        amount: Nat::from(amount),
+                        fee: None,
+                        memo: None,
+                        created_at_time: None,
+                    },
+                )
+                .await
+                .expect("MockLedgerAgent::call should not itself fail");
+            result.is_ok()
+        }
+    }
+}
+
+async fn run_seed(seed: u64) {
+    let ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let initial_balance = 100 * LEDGER_FEE;
+
+    let mut agent =
+        MockLedgerAgent::new(account(0).owner).with_ledger(ledger, LEDGER_FEE, "Test", "TST");
+    let mut expected_total_supply = Nat::from(0u8);
+    for i in 0..NUM_ACCOUNTS {
+        agent = agent.with_balance(ledger, account(i), initial_balance);
+        expected_total_supply = expected_total_supply + Nat::from(initial_balance);
+    }
+
+    let mut rng = Prng::new(seed);
+    for call_index in 0..NUM_CALLS_PER_SEED {
+        let op = generate_op(&mut rng);
+        if run_op(ledger, &agent, op).await {
+            // Every modeled operation that succeeds burns exactly one fee: `icrc1_transfer`/
+            // `icrc2_transfer_from` debit `amount + fee` but only credit `amount`, and
+            // `icrc2_approve` debits the fee without crediting anyone.
+            expected_total_supply = crate::validation::saturating_sub(
+                expected_total_supply,
+                Nat::from(LEDGER_FEE),
+            );
+        }
+
+        let total_supply_now = agent.total_supply(ledger);
+        assert_eq!(
+            total_supply_now, expected_total_supply,
+            "seed {seed}, call {call_index}: total supply on {ledger} drifted to \
+             {total_supply_now}, expected {expected_total_supply} -- replay with seed {seed}",
+        );
+    }
+}
+
+#[tokio::test]
+async fn property_test_conservation() {
+    for seed in SEEDS {
+        run_seed(seed).await;
+    }
+}
+
+/// `MockLedgerAgent` mirrors the ICRC-1/legacy split every ICP-denominated withdrawal actually
+/// goes through (see `ledger_api.rs`'s `LedgerProtocol`): a legacy `transfer` to a destination this
+/// mock has already tracked an `Account` balance for (the common case -- every withdrawal
+/// destination derived from `sns_treasury_manager::Account`) must stay reconcilable through the
+/// same `balance_of`/`total_supply` accounting the ICRC-1 side uses, not live in a separate,
+/// untracked ledger of its own.
+#[tokio::test]
+async fn legacy_transfer_to_known_account_is_reconciled_with_icrc1_balances() {
+    let ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let sender = account(1);
+    let recipient = account(2);
+    let initial_balance = 100 * LEDGER_FEE;
+
+    let agent = MockLedgerAgent::new(sender.owner)
+        .with_ledger(ledger, LEDGER_FEE, "Internet Computer", "ICP")
+        .with_balance(ledger, sender.clone(), initial_balance)
+        .with_balance(ledger, recipient.clone(), 0);
+    let total_supply_before = agent.total_supply(ledger);
+
+    let amount = 10 * LEDGER_FEE;
+    let result: Result<u64, LegacyTransferError> = agent
+        .call(
+            ledger,
+            TransferArgs {
+                memo: ic_ledger_types::Memo(0),
+                amount: Tokens::from_e8s(amount),
+                fee: Tokens::from_e8s(LEDGER_FEE),
+                from_subaccount: None,
+                to: account_identifier(&recipient),
+                created_at_time: None,
+            },
+        )
+        .await
+        .expect("MockLedgerAgent::call should not itself fail");
+    result.expect("transfer with sufficient funds should succeed");
+
+    assert_eq!(
+        agent.balance_of(ledger, &sender),
+        Nat::from(initial_balance - amount - LEDGER_FEE),
+    );
+    assert_eq!(agent.balance_of(ledger, &recipient), Nat::from(amount));
+    assert_eq!(
+        agent.legacy_balance_of(ledger, &account_identifier(&recipient)),
+        Nat::from(amount),
+        "a destination already tracked as an Account must be readable through both views",
+    );
+    assert_eq!(
+        agent.total_supply(ledger),
+        crate::validation::saturating_sub(total_supply_before, Nat::from(LEDGER_FEE)),
+    );
+}
+
+/// The other half of the split: a legacy `transfer` to a bare `AccountIdentifier` this mock never
+/// saw an `Account` for -- e.g. an ICP-native custodian addressed only by a hand-rolled identifier,
+/// which is exactly the kind of destination `sns_treasury_manager::Account` (principal +
+/// subaccount only) cannot express. `legacy_balance_of` is the only way to read such a balance
+/// back; confirms a failed transfer (insufficient funds) leaves both sides of the ledger
+/// untouched, so a caller retrying after a withdrawal failure sees its refunded balance intact.
+#[tokio::test]
+async fn legacy_transfer_to_unknown_identifier_and_failed_transfer_rollback() {
+    let ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let sender = account(1);
+    let initial_balance = 5 * LEDGER_FEE;
+    let bare_identifier = AccountIdentifier::new(
+        &Principal::from_slice(&[0xCA, 0xFE]),
+        &Subaccount([7u8; 32]),
+    );
+
+    let agent = MockLedgerAgent::new(sender.owner)
+        .with_ledger(ledger, LEDGER_FEE, "Internet Computer", "ICP")
+        .with_balance(ledger, sender.clone(), initial_balance);
+
+    assert_eq!(
+        agent.legacy_balance_of(ledger, &bare_identifier),
+        Nat::from(0u8),
+        "a never-credited identifier starts at zero",
+    );
+
+    // More than `sender` holds: the transfer must fail and leave both balances untouched.
+    let amount = 4 * LEDGER_FEE;
+    let result: Result<u64, LegacyTransferError> = agent
+        .call(
+            ledger,
+            TransferArgs {
+                memo: ic_ledger_types::Memo(0),
+                amount: Tokens::from_e8s(amount),
+                fee: Tokens::from_e8s(2 * LEDGER_FEE),
+                from_subaccount: None,
+                to: bare_identifier,
+                created_at_time: None,
+            },
+        )
+        .await
+        .expect("MockLedgerAgent::call should not itself fail");
+    assert!(
+        matches!(result, Err(LegacyTransferError::InsufficientFunds { .. })),
+        "a transfer debiting more than the sender holds must be rejected, not partially applied",
+    );
+    assert_eq!(agent.balance_of(ledger, &sender), Nat::from(initial_balance));
+    assert_eq!(agent.legacy_balance_of(ledger, &bare_identifier), Nat::from(0u8));
+
+    // A transfer that fits now succeeds and lands on the bare identifier's own balance.
+    let amount = LEDGER_FEE;
+    let result: Result<u64, LegacyTransferError> = agent
+        .call(
+            ledger,
+            TransferArgs {
+                memo: ic_ledger_types::Memo(0),
+                amount: Tokens::from_e8s(amount),
+                fee: Tokens::from_e8s(LEDGER_FEE),
+                from_subaccount: None,
+                to: bare_identifier,
+                created_at_time: None,
+            },
+        )
+        .await
+        .expect("MockLedgerAgent::call should not itself fail");
+    result.expect("transfer with sufficient funds should succeed");
+    assert_eq!(
+        agent.legacy_balance_of(ledger, &bare_identifier),
+        Nat::from(amount),
+    );
+
+    let balance: Tokens = agent
+        .call(ledger, AccountBalanceArgs { account: bare_identifier })
+        .await
+        .expect("MockLedgerAgent::call should not itself fail");
+    assert_eq!(balance, Tokens::from_e8s(amount));
+}
+
+fn account_identifier(account: &Account) -> AccountIdentifier {
+    AccountIdentifier::new(&account.owner, &Subaccount([0u8; 32]))
+}
@@ -0,0 +1,130 @@
+//! A [`RetryingAgent`] decorator that retries transient failures of an inner [`AbstractAgent`]
+//! with capped exponential backoff plus jitter, while leaving permanent (application-level)
+//! errors, and requests without a deduplication key, to fail (or succeed) after a single
+//! attempt.
+
+use super::{AbstractAgent, ErrorClassification, Request};
+use candid::Principal;
+use futures::channel::oneshot;
+use std::time::Duration;
+
+/// The number of attempts (including the first) before giving up on a transient failure.
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// The delay before the first retry. Doubles on each subsequent attempt, capped at
+/// `DEFAULT_MAX_BACKOFF`.
+const DEFAULT_BASE_BACKOFF: Duration = Duration::from_millis(200);
+
+/// The maximum delay between retries, regardless of attempt count.
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Wraps an [`AbstractAgent`] to retry transient failures (system/transport-level rejects, per
+/// [`ErrorClassification::is_transient`]) with capped exponential backoff plus jitter, up to a
+/// configurable attempt budget. Permanent (application-level) errors are returned immediately.
+///
+/// Because IC update calls are not idempotent by default, a request is only retried if
+/// [`Request::dedup_key`] returns `Some` -- i.e. the request itself carries a deduplication key
+/// (such as the `memo`/`created_at_time` pair on an ICRC-1/ICRC-2 call) that lets the
+/// destination canister recognize and no-op a resubmission. A request without one is forwarded
+/// to the inner agent exactly once, identical to calling the inner agent directly: only
+/// read-only calls and calls already carrying a dedup key are safe to wrap with retries.
+#[derive(Clone)]
+pub struct RetryingAgent<A: AbstractAgent> {
+    inner: A,
+    max_attempts: u32,
+    base_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl<A: AbstractAgent> RetryingAgent<A> {
+    /// Wraps `inner` with the default attempt budget and backoff schedule.
+    pub fn new(inner: A) -> Self {
+        Self {
+            inner,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_backoff: DEFAULT_BASE_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+        }
+    }
+
+    /// Wraps `inner` with a custom attempt budget and backoff schedule. `max_attempts` is the
+    /// total number of attempts, including the first; it is clamped to at least `1`.
+    pub fn with_attempt_budget(
+        inner: A,
+        max_attempts: u32,
+        base_backoff: Duration,
+        max_backoff: Duration,
+    ) -> Self {
+        Self {
+            inner,
+            max_attempts: max_attempts.max(1),
+            base_backoff,
+            max_backoff,
+        }
+    }
+
+    /// Returns the backoff delay before the retry following `attempt` (the number of retries
+    /// already made, i.e. `0` before the first retry), as exponential backoff capped at
+    /// `max_backoff`, with up to 50% jitter added so that concurrently-retrying operations don't
+    /// all wake up at the same time.
+    fn backoff_delay(&self, attempt: u32, jitter_source: u64) -> Duration {
+        let exponent = attempt.min(16); // Avoids overflowing the shift below.
+        let scaled = self
+            .base_backoff
+            .checked_mul(1u32 << exponent)
+            .unwrap_or(self.max_backoff);
+        let capped = scaled.min(self.max_backoff);
+
+        let jitter_permille = jitter_source % 500;
+        capped + (capped * jitter_permille as u32) / 1000
+    }
+}
+
+/// Resolves once `duration` has elapsed, implemented via a one-shot IC timer rather than a
+/// blocking sleep, so other canister work can still be scheduled while a retry is pending.
+async fn delay(duration: Duration) {
+    if duration.is_zero() {
+        return;
+    }
+
+    let (sender, receiver) = oneshot::channel();
+    ic_cdk_timers::set_timer(duration, move || {
+        let _ = sender.send(());
+    });
+    let _ = receiver.await;
+}
+
+impl<A: AbstractAgent> AbstractAgent for RetryingAgent<A> {
+    type Error = A::Error;
+
+    async fn call<R: Request>(
+        &self,
+        canister_id: impl Into<Principal> + Send,
+        request: R,
+    ) -> Result<R::Response, Self::Error> {
+        let canister_id = canister_id.into();
+
+        if request.dedup_key().is_none() {
+            return self.inner.call(canister_id, request).await;
+        }
+
+        let mut attempt = 0;
+        loop {
+            match self.inner.call(canister_id, request.clone()).await {
+                Ok(response) => return Ok(response),
+                Err(error) => {
+                    attempt += 1;
+                    if attempt >= self.max_attempts || !error.is_transient() {
+                        return Err(error);
+                    }
+
+                    // There is no randomness source available for jitter without a canister
+                    // call; the current time is unpredictable enough across concurrently
+                    // retrying operations to avoid synchronized wakeups.
+                    let jitter_source = ic_cdk::api::time() ^ u64::from(attempt);
+                    delay(self.backoff_delay(attempt - 1, jitter_source)).await;
+                }
+            }
+        }
+    }
+}
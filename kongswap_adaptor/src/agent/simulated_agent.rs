@@ -0,0 +1,177 @@
+//! [`SimulatedAgent`]: an [`AbstractAgent`] that models a request's effect against a
+//! copy-on-write [`Overlay`] instead of submitting it, so a deposit/withdraw/rebalance can be
+//! dry-run -- including the audit trail it would produce -- without anything reaching a real
+//! ledger or the KongSwap backend. Only requests that override [`Request::simulate`] are modeled
+//! this way; every other request (expected to be read-only, e.g. `icrc1_balance_of` or a KongSwap
+//! pool query) is forwarded to `inner` so a dry-run still sees realistic on-chain state.
+
+use super::{AbstractAgent, Request};
+use candid::Principal;
+use icrc_ledger_types::icrc1::account::Account;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// One ledger account, keyed the way [`Overlay`] tracks balances: by the ledger canister plus the
+/// ICRC-1 account held on it.
+type OverlayKey = (Principal, Account);
+
+/// A single simulated balance mutation, kept only so a finished dry run can be inspected (e.g.
+/// surfaced to a proposal reviewer) -- it is never applied to a real ledger.
+#[derive(Clone, Debug)]
+pub struct SimulatedTransfer {
+    pub ledger_canister_id: Principal,
+    pub from: Account,
+    pub to: Account,
+    pub amount_decimals: u64,
+}
+
+/// A copy-on-write view over ledger balances: reads start from `base` (or `0` if unseeded), a
+/// snapshot that [`Overlay::seed`] populates from the real ledger balance the first time an
+/// account is touched, and apply every transfer in `log` that debits or credits the account. Nothing here is ever written back to
+/// a ledger; if a simulated operation fails partway through, `base` is untouched by construction
+/// (it is never mutated after seeding) and `log` is simply left in place for diagnostics.
+#[derive(Clone, Debug, Default)]
+pub struct Overlay {
+    base: HashMap<OverlayKey, u64>,
+    log: Vec<SimulatedTransfer>,
+    next_block_index: u64,
+}
+
+impl Overlay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Seeds the base snapshot for `account` on `ledger_canister_id`, if it hasn't been seeded
+    /// yet. A real dry run calls this with the account's true on-ledger balance before simulating
+    /// any transfer that touches it, so reads start from reality instead of `0`.
+    pub fn seed(&mut self, ledger_canister_id: Principal, account: Account, amount_decimals: u64) {
+        self.base
+            .entry((ledger_canister_id, account))
+            .or_insert(amount_decimals);
+    }
+
+    /// The base snapshot as modified by every transfer recorded in `log` so far. Returns `0` for
+    /// an account that was never seeded and never credited, matching an ICRC-1 ledger's own
+    /// behavior for an account holding no tokens.
+    pub fn balance_of(&self, ledger_canister_id: Principal, account: &Account) -> u64 {
+        let mut balance = self
+            .base
+            .get(&(ledger_canister_id, account.clone()))
+            .copied()
+            .unwrap_or(0);
+
+        for transfer in &self.log {
+            if transfer.ledger_canister_id != ledger_canister_id {
+                continue;
+            }
+            if &transfer.from == account {
+                balance = balance.saturating_sub(transfer.amount_decimals);
+            }
+            if &transfer.to == account {
+                balance = balance.saturating_add(transfer.amount_decimals);
+            }
+        }
+
+        balance
+    }
+
+    /// Records a simulated transfer and returns the synthetic block index to hand back as the
+    /// call's modeled response. Indices are assigned sequentially starting at `0`; unlike a real
+    /// ledger's, they are only unique within this overlay.
+    pub fn record_transfer(&mut self, transfer: SimulatedTransfer) -> u64 {
+        let block_index = self.next_synthetic_index();
+        self.log.push(transfer);
+        block_index
+    }
+
+    /// Hands out the next synthetic block index without logging a transfer, for a call (such as
+    /// `icrc2_approve`) that needs to model a response but has no balance movement of its own to
+    /// record.
+    pub fn next_synthetic_index(&mut self) -> u64 {
+        let block_index = self.next_block_index;
+        self.next_block_index += 1;
+        block_index
+    }
+
+    /// The transfers simulated so far, for a caller that wants to inspect or discard the result
+    /// of a dry run.
+    pub fn log(&self) -> &[SimulatedTransfer] {
+        &self.log
+    }
+}
+
+/// An [`AbstractAgent`] that models requests overriding [`Request::simulate`] against a shared,
+/// copy-on-write [`Overlay`] instead of submitting them, and forwards every other (expected
+/// read-only) request to `inner`. See [`AbstractAgent::IS_SIMULATED`] for how
+/// [`crate::state::KongSwapAdaptor`] gates durable commits on which kind of agent is in use.
+#[derive(Clone)]
+pub struct SimulatedAgent<A: AbstractAgent> {
+    inner: A,
+    caller: Principal,
+    overlay: Arc<Mutex<Overlay>>,
+}
+
+impl<A: AbstractAgent> SimulatedAgent<A> {
+    /// Wraps `inner` (used only to forward unmodeled calls) with a fresh, empty [`Overlay`].
+    /// `caller` is the principal this simulated agent calls out as, i.e. the adaptor's own
+    /// canister id.
+    pub fn new(inner: A, caller: Principal) -> Self {
+        Self {
+            inner,
+            caller,
+            overlay: Arc::new(Mutex::new(Overlay::new())),
+        }
+    }
+
+    /// Seeds the overlay's base snapshot for `ledger_canister_id`/`account`, so a simulated call
+    /// chain starts from a realistic balance instead of `0`.
+    pub fn seed_balance(
+        &self,
+        ledger_canister_id: Principal,
+        account: Account,
+        amount_decimals: u64,
+    ) {
+        self.overlay
+            .lock()
+            .unwrap()
+            .seed(ledger_canister_id, account, amount_decimals);
+    }
+
+    /// The transfers simulated so far, for a caller that wants to inspect (e.g. surface to a
+    /// proposal reviewer) or discard the result of a dry run.
+    pub fn simulated_transfers(&self) -> Vec<SimulatedTransfer> {
+        self.overlay.lock().unwrap().log().to_vec()
+    }
+}
+
+impl<A: AbstractAgent> AbstractAgent for SimulatedAgent<A> {
+    type Error = A::Error;
+
+    const IS_SIMULATED: bool = true;
+
+    async fn call<R: Request>(
+        &self,
+        canister_id: impl Into<Principal> + Send,
+        request: R,
+    ) -> Result<R::Response, Self::Error> {
+        let canister_id = canister_id.into();
+
+        let modeled = {
+            let mut overlay = self.overlay.lock().unwrap();
+            request.simulate(canister_id, self.caller, &mut overlay)
+        };
+
+        if let Some(response) = modeled {
+            return Ok(response);
+        }
+
+        // No simulated model for this request: forward it to `inner`, which is expected to only
+        // ever be asked to make read-only calls (e.g. `icrc1_balance_of`, a KongSwap pool query)
+        // during a dry run -- every state-mutating request this canister issues is expected to
+        // override `simulate` before it is exercised here.
+        self.inner.call(canister_id, request).await
+    }
+}
@@ -1,23 +1,120 @@
-use sns_treasury_manager::{AuditTrail, Operation, Step, TreasuryManagerOperation};
+use sns_treasury_manager::{
+    Asset, AuditTrail, Operation, Step, TransactionError, TransactionWitness, Transfer,
+    TreasuryManagerOperation,
+};
 
 pub const MAX_REPLY_SIZE_BYTES: usize = 1_024;
 
+/// How an operation's sub-transactions should be retained in the audit trail, decided once per
+/// [`OperationContext`] by a [`RecordSampler`]. Adapted from Sentry's `traces_sampler`: a
+/// long-lived canister's audit trail would otherwise grow by one entry per `emit_transaction`
+/// call forever, so a DAO can trade off how much detail it keeps for routine activity against how
+/// bounded stable memory growth stays. An operation that errors is always recorded as `Full`
+/// regardless of this decision -- see [`crate::emit_transaction`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, candid::CandidType, serde::Deserialize)]
+pub enum RecordDecision {
+    /// Every sub-transaction is recorded as its own audit-trail entry. The only behavior before
+    /// this existed, and still the default (see [`OperationContext::new`]).
+    Full,
+    /// Only the operation's first sub-transaction is recorded as its own entry; later
+    /// sub-transactions are folded into it as a count when the operation finalizes.
+    Summary,
+    /// Nothing beyond the operation's first sub-transaction is recorded, and its entry carries no
+    /// detail about what the later sub-transactions were.
+    Drop,
+}
+
+/// Decides the [`RecordDecision`] for an operation about to start, given its (freshly
+/// constructed, not-yet-sampled) [`OperationContext`]. Installed on
+/// [`crate::state::KongSwapAdaptor`] the same way its `time_ns` clock is: a plain function pointer
+/// threaded in at construction time, since neither can be a literal closure captured once at
+/// `initialize` and carried across upgrades -- a function pointer from one wasm build is
+/// meaningless after the next.
+pub type RecordSampler = fn(&OperationContext) -> RecordDecision;
+
 #[must_use]
+#[derive(Debug)]
 pub struct OperationContext {
     operation: Operation,
 
     /// None indicates that there were no calls yet.
     index: Option<usize>,
+
+    record_decision: RecordDecision,
+
+    /// How many sub-transactions past the first have been folded into the anchor entry instead
+    /// of being recorded on their own, because `record_decision` is not [`RecordDecision::Full`].
+    /// Read by [`crate::state::KongSwapAdaptor::finalize_audit_trail_transaction`] to summarize
+    /// what was compacted.
+    compacted_count: usize,
+
+    /// The [`TreasuryManagerOperation`] most recently returned by [`Self::next_operation`], if
+    /// any. Lets a caller that needs to reuse that exact value afterwards (e.g. to stamp a
+    /// transfer memo that a later reconciliation pass must match) read it back instead of having
+    /// to capture it before calling `emit_transaction`, which now assigns it internally.
+    last: Option<TreasuryManagerOperation>,
+
+    /// The generation token a caller's `KongSwapAdaptor::acquire_operation_lock` call returned, if
+    /// this operation holds that in-flight guard. `None` for operation kinds that don't acquire it
+    /// (e.g. [`Operation::Balances`], [`Operation::IssueReward`]), in which case
+    /// [`crate::emit_transaction`] skips the corresponding `KongSwapAdaptor::assert_operation_lock`
+    /// check entirely.
+    lock_generation: Option<u64>,
 }
 
 impl OperationContext {
+    /// Constructs a context that records every sub-transaction in full. Used directly by tests and
+    /// by callers that don't go through a [`RecordSampler`]; production entry points should prefer
+    /// `KongSwapAdaptor::new_operation_context`, which consults the installed sampler.
     pub fn new(operation: Operation) -> Self {
         Self {
             operation,
             index: None,
+            record_decision: RecordDecision::Full,
+            compacted_count: 0,
+            last: None,
+            lock_generation: None,
         }
     }
 
+    /// Overrides the record decision a freshly-constructed context would otherwise default to.
+    #[must_use]
+    pub fn with_record_decision(mut self, record_decision: RecordDecision) -> Self {
+        self.record_decision = record_decision;
+        self
+    }
+
+    /// Installs the generation token returned by the caller's own
+    /// `KongSwapAdaptor::acquire_operation_lock` call, so every sub-transaction
+    /// [`crate::emit_transaction`] emits under this context asserts that guard is still held
+    /// before proceeding. See [`Self::lock_generation`].
+    #[must_use]
+    pub fn with_lock_generation(mut self, lock_generation: u64) -> Self {
+        self.lock_generation = Some(lock_generation);
+        self
+    }
+
+    pub fn lock_generation(&self) -> Option<u64> {
+        self.lock_generation
+    }
+
+    pub fn operation(&self) -> Operation {
+        self.operation
+    }
+
+    pub fn record_decision(&self) -> RecordDecision {
+        self.record_decision
+    }
+
+    pub fn compacted_count(&self) -> usize {
+        self.compacted_count
+    }
+
+    /// The [`TreasuryManagerOperation`] most recently assigned by [`Self::next_operation`].
+    pub fn last_operation(&self) -> Option<TreasuryManagerOperation> {
+        self.last
+    }
+
     /// Should be used for operations that are definitely not the final operation
     /// of the current operation.
     pub fn next_operation(&mut self) -> TreasuryManagerOperation {
@@ -30,7 +127,63 @@ impl OperationContext {
             index,
             is_final: false,
         };
-        TreasuryManagerOperation { operation, step }
+        let treasury_manager_operation = TreasuryManagerOperation { operation, step };
+        self.last = Some(treasury_manager_operation);
+        treasury_manager_operation
+    }
+
+    /// Records that one more sub-transaction was folded into the operation's anchor entry instead
+    /// of being given its own, because the installed [`RecordDecision`] isn't `Full`. Called by
+    /// [`crate::emit_transaction`].
+    pub fn note_compacted(&mut self) {
+        self.compacted_count = self.compacted_count.saturating_add(1);
+    }
+}
+
+/// The terminal outcome a deposit's persisted
+/// [`PendingDepositState`](crate::state::storage::PendingDepositState) machine reached, carried by
+/// [`StatusNotificationHook::on_settlement`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, candid::CandidType, serde::Deserialize)]
+pub enum SettlementOutcome {
+    /// The deposit's liquidity was accepted into the pool.
+    Settled,
+    /// The deposit failed partway through and whatever reached the manager's subaccount was
+    /// returned to the external custodian.
+    FailedRefunded,
+}
+
+/// Notified by [`crate::state::KongSwapAdaptor`] whenever a deposit's persisted
+/// [`PendingDepositState`](crate::state::storage::PendingDepositState) machine reaches a terminal
+/// outcome, so e.g. an SNS governance canister can react (alert on a refund, update its own books
+/// on a settlement) without polling the audit trail for it. Installed on construction the same way
+/// as [`RecordSampler`], but as a trait object rather than a function pointer: reacting to a
+/// settlement may need to hold state of its own (e.g. an outbound notification queue) across calls,
+/// which a bare `fn` can't capture.
+pub trait StatusNotificationHook {
+    /// `amount_decimals` is the amount of `asset` that settled or was refunded, and
+    /// `audit_trail_index` is the index (in [`crate::state::StableAuditTrail`]) of the audit-trail
+    /// entry recording the deposit operation that reached this outcome.
+    fn on_settlement(
+        &self,
+        asset: Asset,
+        amount_decimals: u64,
+        audit_trail_index: u64,
+        outcome: SettlementOutcome,
+    );
+}
+
+/// The default [`StatusNotificationHook`]: does nothing. Installed by callers (e.g. tests) that
+/// don't have a governance canister to notify wired up.
+pub struct NoopStatusNotificationHook;
+
+impl StatusNotificationHook for NoopStatusNotificationHook {
+    fn on_settlement(
+        &self,
+        _asset: Asset,
+        _amount_decimals: u64,
+        _audit_trail_index: u64,
+        _outcome: SettlementOutcome,
+    ) {
     }
 }
 
@@ -38,6 +191,18 @@ pub fn serialize_audit_trail(audit_trail: &AuditTrail) -> Result<String, String>
     serde_json::to_string(&audit_trail.transactions).map_err(|err| format!("{err:?}"))
 }
 
+/// Extracts the ledger blocks (ledger canister, amount, and block index) recorded in a settled
+/// transaction's witness, if any. Transactions that did not move tokens on a ledger directly
+/// (e.g. a DEX pool call) return an empty list. `icrc1_balance_of` alone can't explain *how* a
+/// balance changed, so keeping the block index around lets an auditor replay the adaptor's
+/// activity against the ledger's own block history.
+pub fn ledger_blocks(result: &Result<TransactionWitness, TransactionError>) -> Vec<Transfer> {
+    match result {
+        Ok(TransactionWitness::Ledger(transfers)) => transfers.clone(),
+        _ => vec![],
+    }
+}
+
 /// TAKEN FROM: ic/rs/nervous_system/string/src/lib.rs
 ///
 /// Returns a possibly modified version of `s` that fits within the specified bounds (in terms of
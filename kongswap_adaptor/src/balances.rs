@@ -1,13 +1,17 @@
-use std::fmt::Display;
+use std::{collections::BTreeMap, fmt::Display};
 
 use crate::{
+    dex_backend::{DexBackend, KongSwapBackend},
     kong_types::{RemoveLiquidityAmountsArgs, RemoveLiquidityAmountsReply, UpdateTokenArgs},
     log, log_err,
+    slippage::BPS_DENOMINATOR,
     tx_error_codes::TransactionErrorCodes,
-    validation::{decode_nat_to_u64, ValidatedAsset, ValidatedBalance, ValidatedSymbol},
+    validation::{
+        decode_nat_to_u64, ValidatedAllowance, ValidatedAsset, ValidatedBalance, ValidatedSymbol,
+    },
     KongSwapAdaptor, KONG_BACKEND_CANISTER_ID,
 };
-use candid::CandidType;
+use candid::{CandidType, Principal};
 use icrc_ledger_types::{icrc::generic_metadata_value::MetadataValue, icrc1::account::Account};
 use kongswap_adaptor::{
     agent::{icrc_requests::Icrc1MetadataRequest, AbstractAgent},
@@ -17,6 +21,7 @@ use serde::Deserialize;
 use sns_treasury_manager::{Error, ErrorKind};
 
 #[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 /// This enumeration indicates which entity in our eco-system,
 /// we are talking about. The naming Party is used to avoid confusion
 /// with the term `Account`.
@@ -42,7 +47,7 @@ impl Display for Party {
     }
 }
 
-#[derive(CandidType, Deserialize, Clone)]
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
 pub(crate) struct ValidatedBalanceBook {
     pub treasury_owner: ValidatedBalance,
     pub treasury_manager: ValidatedBalance,
@@ -51,15 +56,257 @@ pub(crate) struct ValidatedBalanceBook {
     pub spendings: u64,
     pub earnings: u64,
     pub suspense: u64,
+    /// When `treasury_manager`'s balance was last credited by a fresh DAO deposit (see
+    /// [`ValidatedBalances::add_manager_balance`]), i.e. the watermark
+    /// [`ValidatedBalances::withdrawal_timelock_remaining_ns`] measures the configured
+    /// `withdrawal_timelock_ns` against. `0` (the genesis value) is measured like any other
+    /// timestamp, so for an asset that was never credited the lock ends `withdrawal_timelock_ns`
+    /// after time zero.
+    pub last_manager_credit_timestamp_ns: u64,
+    /// The start (in nanoseconds) of the current withdrawal rate-limit window (see
+    /// [`ValidatedBalances::check_withdrawal_limit`]). `0` until the first withdrawal in this
+    /// asset is checked against a configured limit.
+    pub withdrawal_window_start_ns: u64,
+    /// How much of this asset (in its own decimals) has already left `treasury_manager` within
+    /// the current withdrawal rate-limit window, reset to `0` whenever the window elapses.
+    pub withdrawn_in_window_decimals: u64,
 }
 
-#[derive(CandidType, Deserialize, Clone)]
+/// The default maximum allowed deviation (in basis points) between a KongSwap preview amount and
+/// the amount actually realized by the call it previewed, used until a DAO sets its own risk
+/// tolerance via [`ValidatedBalances::set_max_slippage_bps`].
+pub(crate) const DEFAULT_MAX_SLIPPAGE_BPS: u16 = 50; // 0.5%
+
+/// The default liquidity provider fee (in basis points) requested when creating a new pool,
+/// matching KongSwap's own default.
+pub(crate) const DEFAULT_LP_FEE_BPS: u8 = 30; // 0.3%
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
 pub(crate) struct ValidatedBalances {
     pub timestamp_ns: u64,
     pub asset_0: ValidatedAsset,
     pub asset_1: ValidatedAsset,
-    pub asset_0_balance: ValidatedBalanceBook,
-    pub asset_1_balance: ValidatedBalanceBook,
+    /// Balance books keyed by asset, following the same map-keyed-by-asset approach as
+    /// [`crate::accounting::ValidatedBalances::asset_to_balances`]. Keying on the whole
+    /// [`ValidatedAsset`] (rather than, say, its `ledger_canister_id`) means a metadata change
+    /// (symbol/fee/decimals, see [`Self::refresh_asset`]) re-keys the entry for that asset, so
+    /// lookups with a freshly-read [`ValidatedAsset`] (e.g. from [`Self::asset_0`]/[`Self::asset_1`]
+    /// or [`crate::state::KongSwapAdaptor::assets`]) always find the right book.
+    pub asset_to_balances: BTreeMap<ValidatedAsset, ValidatedBalanceBook>,
+    /// The maximum tolerated deviation (in basis points) between a KongSwap preview amount and
+    /// the amount realized by `add_liquidity`/`remove_liquidity`, before the operation is
+    /// rejected as too much slippage. Configurable via [`Self::set_max_slippage_bps`].
+    pub max_slippage_bps: u16,
+    /// The liquidity provider fee (in basis points) requested when creating a new pool.
+    /// Configurable via [`Self::set_lp_fee_bps`].
+    pub lp_fee_bps: u8,
+    /// The maximum tolerated deviation (in basis points) between a deposit's implied price
+    /// (`amount_1 / amount_0`) and an already-existing pool's own reserve ratio (`reserve_1 /
+    /// reserve_0`), checked before `add_pool`/`add_liquidity` is called so a deposit can't be
+    /// drawn into a pool whose price has moved (or been manipulated) since the caller chose its
+    /// amounts. `0` (the default) disables the check -- there's also nothing to compare against
+    /// for a brand-new pool, since it has no reserves yet. Configurable via
+    /// [`Self::set_max_deposit_price_deviation_bps`].
+    pub max_deposit_price_deviation_bps: u16,
+    /// The minimum LP token amount (in the LP token's own decimals) a deposit must be minted by
+    /// `add_pool`/`add_liquidity`, checked once the call returns. `0` (the default) disables the
+    /// check. Configurable via [`Self::set_min_deposit_lp_decimals`].
+    pub min_deposit_lp_decimals: u64,
+    /// The maximum tolerated deviation (in basis points) between the LP balance and pool reserves
+    /// snapshotted at the start of a withdrawal and the values re-queried immediately before the
+    /// `remove_liquidity` call that commits to them, checked so the withdrawal can't commit
+    /// against reserves that shifted after the plan was formed. `0` (the default) disables the
+    /// check. Configurable via [`Self::set_max_withdraw_reserve_drift_bps`].
+    pub max_withdraw_reserve_drift_bps: u16,
+    /// Like [`Self::max_withdraw_reserve_drift_bps`], but for the LP reserves snapshotted at the
+    /// start of a deposit and re-queried immediately before `add_pool`/`add_liquidity` commits to
+    /// them -- closes the analogous window for a deposit built from a balance view that's since
+    /// gone stale (e.g. a periodic task that built its amounts before yielding, then executed
+    /// after the pool moved). `0` (the default) disables the check. Configurable via
+    /// [`Self::set_max_deposit_reserve_drift_bps`].
+    pub max_deposit_reserve_drift_bps: u16,
+    /// `asset_0`'s price in the reference denomination, fixed-point scaled by
+    /// [`RATE_DECIMALS_SCALE`] (e.g. `150_000_000` means "1.5 units of the reference denomination
+    /// per whole `asset_0`"). `None` until a controller sets it via [`Self::set_conversion_rates`].
+    pub asset_0_rate_decimals: Option<u64>,
+    /// Like [`Self::asset_0_rate_decimals`], but for `asset_1`.
+    pub asset_1_rate_decimals: Option<u64>,
+    /// The conserved total (see [`conserved_total_decimals`]) each asset's balance book is
+    /// expected to sum to, keyed the same way as [`Self::asset_to_balances`]. Checked by
+    /// [`Self::reconcile`] and by every `move_asset`/`charge_fee` call before it is allowed to take
+    /// effect.
+    pub expected_totals_decimals: BTreeMap<ValidatedAsset, u64>,
+    /// How long (in nanoseconds) a fresh DAO deposit must "rest" in `treasury_manager` before
+    /// [`Self::withdrawal_timelock_remaining_ns`] will let it be withdrawn, similar to the
+    /// `withdrawal_timelock` cooldown guarding Anchor's lockup/registry program. `0` (the default)
+    /// disables the cooldown entirely. Configurable via [`Self::set_withdrawal_timelock_ns`].
+    pub withdrawal_timelock_ns: u64,
+    /// A monotonically increasing counter, advanced only by [`Self::advance_operation_sequence`]
+    /// once a state-mutating operation (e.g. `rebalance`) has actually committed. Lets a caller
+    /// that read this value before submitting such a call assert the state hasn't moved out from
+    /// under it since -- Mango v4's "sequence check" idea -- by passing it back as an
+    /// `expected_sequence`, rejected on mismatch before any work is attempted.
+    pub operation_sequence: u64,
+    /// The LP token balance (see [`crate::kong_api::KongSwapAdaptor::lp_balance`]) last removed
+    /// from the pool, via either a full `withdraw` or an `issue_rewards` LP-fee harvest.
+    /// `issue_rewards_impl` diffs the current LP balance against this value to find the portion
+    /// accrued since, i.e. the fees earned on the remaining position, and harvests only that much
+    /// instead of disturbing the principal still earning fees in the pool.
+    pub last_harvested_lp_balance_decimals: u64,
+    /// The minimum acceptable value (in the reference denomination, same units as
+    /// [`KongSwapAdaptor::total_value_in_reference`]) that the current position must quote at via
+    /// `remove_liquidity_amounts` before a deposit/withdraw is allowed to commit -- see
+    /// [`crate::value_guard`]. `0` (the default) disables the guard entirely. Configurable via
+    /// [`Self::set_min_treasury_value_in_reference_decimals`].
+    pub min_treasury_value_in_reference_decimals: u64,
+    /// The annual management fee (in basis points) [`Self::accrue_management_fee`] charges against
+    /// each asset's `treasury_manager` balance, pro-rated by how long it has actually accrued since
+    /// [`Self::last_fee_accrual_ns`]. `0` (the default) disables accrual entirely. Configurable via
+    /// [`Self::set_management_fee_rate_bps`].
+    pub management_fee_rate_bps: u16,
+    /// The timestamp (in nanoseconds) [`Self::accrue_management_fee`] last charged up to. Seeded to
+    /// the adaptor's initialization time, so the first accrual only charges for time the position
+    /// actually existed.
+    pub last_fee_accrual_ns: u64,
+    /// The forex/crypto symbol [`crate::exchange_rate::KongSwapAdaptor::refresh_exchange_rates`]
+    /// quotes each managed asset against, e.g. `"USD"`. Configurable via
+    /// [`Self::set_valuation_quote_asset_symbol`]; `"USD"` until a controller sets it.
+    pub valuation_quote_asset_symbol: String,
+    /// The timestamp (in nanoseconds) [`Self::asset_0_rate_decimals`] was last refreshed by
+    /// [`crate::exchange_rate::KongSwapAdaptor::refresh_exchange_rates`]. `None` until the first
+    /// successful fetch.
+    pub asset_0_rate_timestamp_ns: Option<u64>,
+    /// Like [`Self::asset_0_rate_timestamp_ns`], but for `asset_1`.
+    pub asset_1_rate_timestamp_ns: Option<u64>,
+    /// How long (in nanoseconds) a rate recorded via
+    /// [`Self::record_exchange_rate_observation`] stays usable before
+    /// [`Self::exchange_rate_is_stale`] flags it as stale. `0` (the default) disables staleness
+    /// reporting entirely. Configurable via [`Self::set_rate_staleness_bound_ns`].
+    pub rate_staleness_bound_ns: u64,
+    /// The maximum amount of `asset_0`/`asset_1` (in their own decimals) [`Self::
+    /// check_withdrawal_limit`] lets leave `treasury_manager` within a single
+    /// `withdrawal_limit_window_ns` window, keyed the same way as [`Self::expected_totals_decimals`].
+    /// An asset absent from this map (the default) has no cap. Configurable via
+    /// [`Self::set_withdrawal_limit_decimals`].
+    pub withdrawal_limit_decimals: BTreeMap<ValidatedAsset, u64>,
+    /// The length (in nanoseconds) of the rolling window [`Self::withdrawal_limit_decimals`] is
+    /// measured against. `0` (the default) disables withdrawal rate limiting entirely, regardless
+    /// of `withdrawal_limit_decimals`. Configurable via [`Self::set_withdrawal_limit_window_ns`].
+    pub withdrawal_limit_window_ns: u64,
+    /// How often (in nanoseconds) the periodic claim-recovery sweep (see
+    /// [`crate::withdraw::KongSwapAdaptor::retry_claims`]) is allowed to run, measured from
+    /// [`Self::last_claims_sweep_timestamp_ns`]. `0` (the default) means the sweep runs on every
+    /// `refresh_balances` tick. Configurable via [`Self::set_claims_sweep_interval_ns`].
+    pub claims_sweep_interval_ns: u64,
+    /// The timestamp (in nanoseconds) the periodic claim-recovery sweep last ran, regardless of
+    /// whether it found anything to recover. Seeded to the adaptor's initialization time, the same
+    /// way [`Self::last_fee_accrual_ns`] is.
+    pub last_claims_sweep_timestamp_ns: u64,
+    /// The maximum amount (in `asset_in`'s own decimals, as computed by
+    /// [`crate::rebalance::KongSwapAdaptor::target_swap`]) a single `rebalance` call is allowed to
+    /// swap, clamped down to before the swap is submitted. `0` (the default) disables the cap --
+    /// the full amount [`crate::rebalance::KongSwapAdaptor::target_swap`] computes is swapped in
+    /// one call. A DAO that wants a large rebalance to land gradually over several calls instead of
+    /// moving the price in one shot configures this. Configurable via
+    /// [`Self::set_max_rebalance_amount_decimals`].
+    pub max_rebalance_amount_decimals: u64,
+    /// The cadence (in nanoseconds) `refresh_balances`'s own timer is re-armed at on success --
+    /// see [`crate::scheduler`]. Configurable via [`Self::set_refresh_balances_interval_ns`];
+    /// [`DEFAULT_TASK_INTERVAL_NS`] until a controller sets it.
+    pub refresh_balances_interval_ns: u64,
+    /// Like [`Self::refresh_balances_interval_ns`], but for `issue_rewards`. Configurable via
+    /// [`Self::set_issue_rewards_interval_ns`].
+    pub issue_rewards_interval_ns: u64,
+    /// How many `refresh_balances` attempts in a row have failed, reset to `0` the next time one
+    /// succeeds. Doubles the delay [`crate::scheduler::next_delay_ns`] computes for
+    /// `refresh_balances`'s next timer, up to [`crate::scheduler::MAX_BACKOFF_SHIFT`] doublings.
+    pub refresh_balances_consecutive_failures: u32,
+    /// Like [`Self::refresh_balances_consecutive_failures`], but for `issue_rewards`.
+    pub issue_rewards_consecutive_failures: u32,
+    /// Delegated principals [`Self::is_authorized`] lets call specific methods alongside the
+    /// canister itself and its controllers, keyed by principal and naming the exact methods (e.g.
+    /// `"deposit"`, `"withdraw"`) each one was granted -- mirrors a custodian model where a
+    /// privileged root set (controllers) can grant scoped call rights to additional principals.
+    /// Empty until a controller calls [`Self::authorize`]. Configurable via [`Self::authorize`]/
+    /// [`Self::deauthorize`].
+    pub authorized_callers: BTreeMap<Principal, Vec<String>>,
+}
+
+/// The default cadence (in nanoseconds) of each periodic task's own timer, matching the fixed
+/// one-hour interval `run_periodic_tasks` used before `refresh_balances`/`issue_rewards` were
+/// split onto independently-scheduled timers (see [`crate::scheduler`]).
+pub(crate) const DEFAULT_TASK_INTERVAL_NS: u64 = 60 * 60 * 1_000_000_000;
+
+/// The fixed-point scale used by [`ValidatedBalances::asset_0_rate_decimals`] and
+/// [`ValidatedBalances::asset_1_rate_decimals`], matching the 8 decimal places conventionally used
+/// for ICP/e8s-denominated amounts.
+pub(crate) const RATE_DECIMALS_SCALE: u64 = 100_000_000;
+
+/// Sums the parties whose combined total must stay constant across every `move_asset` /
+/// `charge_fee` call: a transfer only ever debits one party and credits another (plus routing the
+/// ledger fee to `fee_collector`), so this sum never grows or shrinks on its own. It can still be
+/// re-pegged deliberately, e.g. by [`ValidatedBalances::set_external_custodian_balance`] observing
+/// a DEX-side balance change, which re-snapshots the expected total to match.
+fn conserved_total_decimals(book: &ValidatedBalanceBook) -> Option<u64> {
+    book.treasury_owner
+        .amount_decimals
+        .checked_add(book.treasury_manager.amount_decimals)?
+        .checked_add(book.external)?
+        .checked_add(book.fee_collector)?
+        .checked_add(book.suspense)
+}
+
+/// Computes `current + delta`, turning an overflow into a `BalanceArithmeticCode` error naming
+/// `description` (typically a party and asset, e.g. `"TreasuryManager ICP balance"`) instead of
+/// silently wrapping.
+fn checked_add_decimals(description: &str, current: u64, delta: u64) -> Result<u64, Error> {
+    current.checked_add(delta).ok_or_else(|| Error {
+        code: u64::from(TransactionErrorCodes::BalanceArithmeticCode),
+        message: format!(
+            "{} would overflow: current value {}, attempted to add {}.",
+            description, current, delta
+        ),
+        kind: ErrorKind::Postcondition {},
+    })
+}
+
+/// Like [`checked_add_decimals`], but for subtraction (underflow instead of overflow).
+fn checked_sub_decimals(description: &str, current: u64, delta: u64) -> Result<u64, Error> {
+    current.checked_sub(delta).ok_or_else(|| Error {
+        code: u64::from(TransactionErrorCodes::BalanceArithmeticCode),
+        message: format!(
+            "{} would underflow: current value {}, attempted to subtract {}.",
+            description, current, delta
+        ),
+        kind: ErrorKind::Postcondition {},
+    })
+}
+
+/// A 365-day year, in nanoseconds -- the denominator [`management_fee_decimals`] annualizes
+/// [`ValidatedBalances::management_fee_rate_bps`] against.
+const YEAR_NS: u64 = 365 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Computes the management fee owed on `balance_decimals` held for `elapsed_ns` at an annualized
+/// `rate_bps`, as `balance * rate_bps * elapsed_ns / (10_000 * year_ns)`. Multiplies through in
+/// `u128` before dividing, since `balance_decimals` alone can already approach `u64::MAX`.
+fn management_fee_decimals(balance_decimals: u64, rate_bps: u16, elapsed_ns: u64) -> u64 {
+    let numerator = u128::from(balance_decimals) * u128::from(rate_bps) * u128::from(elapsed_ns);
+    let denominator = u128::from(BPS_DENOMINATOR) * u128::from(YEAR_NS);
+    u64::try_from(numerator / denominator).unwrap_or(u64::MAX)
+}
+
+/// Returns a typed error naming `asset` as not registered with this adaptor, used in place of the
+/// historical `log_err` + silent no-op whenever a caller passes an asset this adaptor doesn't
+/// manage.
+fn unknown_asset_error(asset: ValidatedAsset) -> Error {
+    Error {
+        code: u64::from(TransactionErrorCodes::UnknownAssetCode),
+        message: format!(
+            "Asset {} is not registered with this adaptor.",
+            asset.symbol()
+        ),
+        kind: ErrorKind::Precondition {},
+    }
 }
 
 impl ValidatedBalances {
@@ -97,6 +344,9 @@ impl ValidatedBalances {
             spendings,
             earnings,
             suspense,
+            last_manager_credit_timestamp_ns: 0,
+            withdrawal_window_start_ns: 0,
+            withdrawn_in_window_decimals: 0,
         };
         let asset_1_balance = ValidatedBalanceBook {
             treasury_owner: ValidatedBalance {
@@ -114,153 +364,841 @@ impl ValidatedBalances {
             spendings,
             earnings,
             suspense,
+            last_manager_credit_timestamp_ns: 0,
+            withdrawal_window_start_ns: 0,
+            withdrawn_in_window_decimals: 0,
         };
 
+        let asset_to_balances =
+            BTreeMap::from([(asset_0, asset_0_balance), (asset_1, asset_1_balance)]);
+
+        // Every party balance above starts at 0, so the conserved total starts at 0 too.
+        let expected_totals_decimals = BTreeMap::from([(asset_0, 0), (asset_1, 0)]);
+
         Self {
             timestamp_ns,
             asset_0,
             asset_1,
-            asset_0_balance,
-            asset_1_balance,
+            asset_to_balances,
+            max_slippage_bps: DEFAULT_MAX_SLIPPAGE_BPS,
+            lp_fee_bps: DEFAULT_LP_FEE_BPS,
+            max_deposit_price_deviation_bps: 0,
+            min_deposit_lp_decimals: 0,
+            max_withdraw_reserve_drift_bps: 0,
+            max_deposit_reserve_drift_bps: 0,
+            asset_0_rate_decimals: None,
+            asset_1_rate_decimals: None,
+            expected_totals_decimals,
+            withdrawal_timelock_ns: 0,
+            operation_sequence: 0,
+            last_harvested_lp_balance_decimals: 0,
+            min_treasury_value_in_reference_decimals: 0,
+            management_fee_rate_bps: 0,
+            last_fee_accrual_ns: timestamp_ns,
+            valuation_quote_asset_symbol: "USD".to_string(),
+            asset_0_rate_timestamp_ns: None,
+            asset_1_rate_timestamp_ns: None,
+            rate_staleness_bound_ns: 0,
+            withdrawal_limit_decimals: BTreeMap::new(),
+            withdrawal_limit_window_ns: 0,
+            claims_sweep_interval_ns: 0,
+            last_claims_sweep_timestamp_ns: timestamp_ns,
+            max_rebalance_amount_decimals: 0,
+            refresh_balances_interval_ns: DEFAULT_TASK_INTERVAL_NS,
+            issue_rewards_interval_ns: DEFAULT_TASK_INTERVAL_NS,
+            refresh_balances_consecutive_failures: 0,
+            issue_rewards_consecutive_failures: 0,
+            authorized_callers: BTreeMap::new(),
         }
     }
 
-    // As the metadata of an asset, e.g., its symbol and fee, might change over time,
-    // calling this function would update them.
+    /// Returns the assets currently registered with this adaptor, in the order they were added
+    /// (currently always `[asset_0, asset_1]`, since a single adaptor manages one KongSwap pool).
+    pub(crate) fn registered_assets(&self) -> Vec<ValidatedAsset> {
+        vec![self.asset_0, self.asset_1]
+    }
+
+    fn balance_book(&self, asset: ValidatedAsset) -> Result<&ValidatedBalanceBook, Error> {
+        self.asset_to_balances
+            .get(&asset)
+            .ok_or_else(|| unknown_asset_error(asset))
+    }
+
+    fn balance_book_mut(
+        &mut self,
+        asset: ValidatedAsset,
+    ) -> Result<&mut ValidatedBalanceBook, Error> {
+        self.asset_to_balances
+            .get_mut(&asset)
+            .ok_or_else(|| unknown_asset_error(asset))
+    }
+
+    fn expected_total_decimals(&self, asset: ValidatedAsset) -> Result<u64, Error> {
+        self.expected_totals_decimals
+            .get(&asset)
+            .copied()
+            .ok_or_else(|| unknown_asset_error(asset))
+    }
+
+    /// Sets the maximum tolerated slippage (in basis points) for `add_liquidity`/`remove_liquidity`
+    /// operations, letting a DAO set its own risk tolerance instead of relying on
+    /// [`DEFAULT_MAX_SLIPPAGE_BPS`].
+    pub(crate) fn set_max_slippage_bps(&mut self, max_slippage_bps: u16) {
+        self.max_slippage_bps = max_slippage_bps;
+    }
+
+    /// Sets the liquidity provider fee (in basis points) requested when creating a new pool,
+    /// instead of relying on [`DEFAULT_LP_FEE_BPS`].
+    pub(crate) fn set_lp_fee_bps(&mut self, lp_fee_bps: u8) {
+        self.lp_fee_bps = lp_fee_bps;
+    }
+
+    /// Sets the maximum tolerated deviation (in basis points) between a deposit's implied price
+    /// and an already-existing pool's reserve ratio, instead of leaving the check disabled.
+    pub(crate) fn set_max_deposit_price_deviation_bps(
+        &mut self,
+        max_deposit_price_deviation_bps: u16,
+    ) {
+        self.max_deposit_price_deviation_bps = max_deposit_price_deviation_bps;
+    }
+
+    /// Sets the minimum LP token amount a deposit must be minted, instead of leaving the check
+    /// disabled.
+    pub(crate) fn set_min_deposit_lp_decimals(&mut self, min_deposit_lp_decimals: u64) {
+        self.min_deposit_lp_decimals = min_deposit_lp_decimals;
+    }
+
+    /// Sets the maximum tolerated deviation (in basis points) between a withdrawal's start-of-
+    /// operation LP-balance/reserve snapshot and the values re-queried immediately before
+    /// `remove_liquidity`, instead of leaving the check disabled.
+    pub(crate) fn set_max_withdraw_reserve_drift_bps(
+        &mut self,
+        max_withdraw_reserve_drift_bps: u16,
+    ) {
+        self.max_withdraw_reserve_drift_bps = max_withdraw_reserve_drift_bps;
+    }
+
+    /// Sets the maximum tolerated deviation (in basis points) between a deposit's start-of-
+    /// operation pool-reserve snapshot and the reserves re-queried immediately before
+    /// `add_pool`/`add_liquidity`, instead of leaving the check disabled.
+    pub(crate) fn set_max_deposit_reserve_drift_bps(&mut self, max_deposit_reserve_drift_bps: u16) {
+        self.max_deposit_reserve_drift_bps = max_deposit_reserve_drift_bps;
+    }
+
+    /// Re-pegs [`Self::last_harvested_lp_balance_decimals`], the baseline `issue_rewards` diffs
+    /// the current LP balance against to find the portion accrued since the last harvest.
+    pub(crate) fn set_last_harvested_lp_balance_decimals(&mut self, lp_balance_decimals: u64) {
+        self.last_harvested_lp_balance_decimals = lp_balance_decimals;
+    }
+
+    /// Sets the floor [`crate::value_guard`]'s pre-commit guard enforces against the current
+    /// position's `remove_liquidity_amounts`-quoted value. `0` disables the guard.
+    pub(crate) fn set_min_treasury_value_in_reference_decimals(
+        &mut self,
+        min_treasury_value_in_reference_decimals: u64,
+    ) {
+        self.min_treasury_value_in_reference_decimals = min_treasury_value_in_reference_decimals;
+    }
+
+    /// Sets the conversion rate registry used by [`KongSwapAdaptor::total_value_in_reference`],
+    /// each rate fixed-point scaled by [`RATE_DECIMALS_SCALE`]. A DAO is expected to keep these in
+    /// sync with an external price feed; this adaptor has no built-in oracle.
+    pub(crate) fn set_conversion_rates(
+        &mut self,
+        asset_0_rate_decimals: u64,
+        asset_1_rate_decimals: u64,
+    ) {
+        self.asset_0_rate_decimals = Some(asset_0_rate_decimals);
+        self.asset_1_rate_decimals = Some(asset_1_rate_decimals);
+    }
+
+    /// Sets [`Self::valuation_quote_asset_symbol`].
+    pub(crate) fn set_valuation_quote_asset_symbol(&mut self, valuation_quote_asset_symbol: String) {
+        self.valuation_quote_asset_symbol = valuation_quote_asset_symbol;
+    }
+
+    /// Sets [`Self::rate_staleness_bound_ns`]. `0` disables staleness reporting entirely.
+    pub(crate) fn set_rate_staleness_bound_ns(&mut self, rate_staleness_bound_ns: u64) {
+        self.rate_staleness_bound_ns = rate_staleness_bound_ns;
+    }
+
+    /// Records a freshly fetched exchange rate for `asset_id` (`0` or `1`), the same way
+    /// [`Self::set_conversion_rates`] does for a manually supplied one, but also pegging the
+    /// observation's timestamp so [`Self::exchange_rate_is_stale`] can judge it later.
+    pub(crate) fn record_exchange_rate_observation(
+        &mut self,
+        asset_id: usize,
+        rate_decimals: u64,
+        timestamp_ns: u64,
+    ) {
+        match asset_id {
+            0 => {
+                self.asset_0_rate_decimals = Some(rate_decimals);
+                self.asset_0_rate_timestamp_ns = Some(timestamp_ns);
+            }
+            1 => {
+                self.asset_1_rate_decimals = Some(rate_decimals);
+                self.asset_1_rate_timestamp_ns = Some(timestamp_ns);
+            }
+            _ => log_err(&format!("Invalid asset_id {}: must be 0 or 1.", asset_id)),
+        }
+    }
+
+    /// Whether either asset's exchange rate is missing or was last refreshed more than
+    /// [`Self::rate_staleness_bound_ns`] before `now_ns`. Always `false` while
+    /// `rate_staleness_bound_ns` is `0` (the default), since that disables staleness reporting
+    /// entirely.
+    pub(crate) fn exchange_rate_is_stale(&self, now_ns: u64) -> bool {
+        if self.rate_staleness_bound_ns == 0 {
+            return false;
+        }
+
+        [self.asset_0_rate_timestamp_ns, self.asset_1_rate_timestamp_ns]
+            .into_iter()
+            .any(|rate_timestamp_ns| match rate_timestamp_ns {
+                Some(rate_timestamp_ns) => {
+                    now_ns.saturating_sub(rate_timestamp_ns) > self.rate_staleness_bound_ns
+                }
+                None => true,
+            })
+    }
+
+    /// Sets how long a fresh DAO deposit must rest in `treasury_manager` before it can be
+    /// withdrawn. A DAO that wants a governance-enforced cooldown against rapid treasury drains
+    /// configures this; `0` (the default) disables the cooldown.
+    pub(crate) fn set_withdrawal_timelock_ns(&mut self, withdrawal_timelock_ns: u64) {
+        self.withdrawal_timelock_ns = withdrawal_timelock_ns;
+    }
+
+    /// Sets the per-window withdrawal cap (in `asset`'s own decimals) that
+    /// [`Self::check_withdrawal_limit`] enforces. `0` removes any cap for this asset.
+    pub(crate) fn set_withdrawal_limit_decimals(
+        &mut self,
+        asset: ValidatedAsset,
+        limit_decimals: u64,
+    ) {
+        if limit_decimals == 0 {
+            self.withdrawal_limit_decimals.remove(&asset);
+        } else {
+            self.withdrawal_limit_decimals.insert(asset, limit_decimals);
+        }
+    }
+
+    /// Sets [`Self::withdrawal_limit_window_ns`]. `0` disables withdrawal rate limiting entirely.
+    pub(crate) fn set_withdrawal_limit_window_ns(&mut self, withdrawal_limit_window_ns: u64) {
+        self.withdrawal_limit_window_ns = withdrawal_limit_window_ns;
+    }
+
+    /// Sets [`Self::claims_sweep_interval_ns`]. `0` runs the sweep on every periodic-task tick.
+    pub(crate) fn set_claims_sweep_interval_ns(&mut self, claims_sweep_interval_ns: u64) {
+        self.claims_sweep_interval_ns = claims_sweep_interval_ns;
+    }
+
+    /// Sets [`Self::max_rebalance_amount_decimals`]. `0` removes the per-call cap.
+    pub(crate) fn set_max_rebalance_amount_decimals(&mut self, max_rebalance_amount_decimals: u64) {
+        self.max_rebalance_amount_decimals = max_rebalance_amount_decimals;
+    }
+
+    /// Sets [`Self::refresh_balances_interval_ns`] and clears any backoff already in progress, so
+    /// a controller reconfiguring the cadence gets the new interval immediately rather than
+    /// finishing out a stale doubling.
+    pub(crate) fn set_refresh_balances_interval_ns(&mut self, refresh_balances_interval_ns: u64) {
+        self.refresh_balances_interval_ns = refresh_balances_interval_ns;
+        self.refresh_balances_consecutive_failures = 0;
+    }
+
+    /// Like [`Self::set_refresh_balances_interval_ns`], but for [`Self::issue_rewards_interval_ns`].
+    pub(crate) fn set_issue_rewards_interval_ns(&mut self, issue_rewards_interval_ns: u64) {
+        self.issue_rewards_interval_ns = issue_rewards_interval_ns;
+        self.issue_rewards_consecutive_failures = 0;
+    }
+
+    /// Updates `task`'s consecutive-failure counter for `succeeded` and returns the delay (in
+    /// nanoseconds) before its timer should next be armed -- see [`crate::scheduler::next_delay_ns`].
+    pub(crate) fn record_scheduled_task_outcome(
+        &mut self,
+        task: crate::scheduler::ScheduledTask,
+        succeeded: bool,
+    ) -> u64 {
+        use crate::scheduler::ScheduledTask;
+
+        let (interval_ns, consecutive_failures) = match task {
+            ScheduledTask::RefreshBalances => (
+                self.refresh_balances_interval_ns,
+                &mut self.refresh_balances_consecutive_failures,
+            ),
+            ScheduledTask::IssueRewards => (
+                self.issue_rewards_interval_ns,
+                &mut self.issue_rewards_consecutive_failures,
+            ),
+        };
+
+        *consecutive_failures = if succeeded {
+            0
+        } else {
+            consecutive_failures.saturating_add(1)
+        };
+
+        crate::scheduler::next_delay_ns(interval_ns, *consecutive_failures)
+    }
+
+    /// Grants `principal` permission to call each method named in `methods` directly, the same
+    /// way [`crate::check_access_for`] already lets the canister itself and its controllers call
+    /// them. Replaces any method set previously granted to `principal` rather than adding to it --
+    /// call [`Self::authorizations`] first if the caller wants to extend rather than replace it.
+    pub(crate) fn authorize(&mut self, principal: Principal, methods: Vec<String>) {
+        if methods.is_empty() {
+            self.authorized_callers.remove(&principal);
+        } else {
+            self.authorized_callers.insert(principal, methods);
+        }
+    }
+
+    /// Revokes every permission [`Self::authorize`] previously granted `principal`.
+    pub(crate) fn deauthorize(&mut self, principal: Principal) {
+        self.authorized_callers.remove(&principal);
+    }
+
+    /// Whether `principal` was granted permission to call `method` via [`Self::authorize`].
+    pub(crate) fn is_authorized(&self, principal: Principal, method: &str) -> bool {
+        self.authorized_callers
+            .get(&principal)
+            .map_or(false, |methods| methods.iter().any(|m| m == method))
+    }
+
+    /// All current delegated authorizations, as `(principal, methods)` pairs -- what
+    /// [`crate::list_authorizations`] reports.
+    pub(crate) fn authorizations(&self) -> Vec<(Principal, Vec<String>)> {
+        self.authorized_callers
+            .iter()
+            .map(|(principal, methods)| (*principal, methods.clone()))
+            .collect()
+    }
+
+    /// Whether enough time has elapsed since [`Self::last_claims_sweep_timestamp_ns`] for the
+    /// periodic claim-recovery sweep to run again, per [`Self::claims_sweep_interval_ns`]. Always
+    /// advances `last_claims_sweep_timestamp_ns` to `now_ns` when due, the same way
+    /// [`Self::accrue_management_fee`] resets its own accrual clock up front regardless of whether
+    /// there turns out to be anything to accrue.
+    pub(crate) fn claims_sweep_is_due(&mut self, now_ns: u64) -> bool {
+        let elapsed_ns = now_ns.saturating_sub(self.last_claims_sweep_timestamp_ns);
+        if elapsed_ns < self.claims_sweep_interval_ns {
+            return false;
+        }
+
+        self.last_claims_sweep_timestamp_ns = now_ns;
+        true
+    }
+
+    /// Checks `requested_amount_decimals` of `asset` (in its own decimals) against the configured
+    /// per-window withdrawal cap, clamping it down to whatever headroom remains in the current
+    /// `withdrawal_limit_window_ns` window and recording the (possibly clamped) amount against
+    /// that window. Returns the amount actually allowed to proceed: `requested_amount_decimals`
+    /// unchanged whenever `asset` has no configured cap, no window is configured, or the window
+    /// still has headroom to spare.
+    ///
+    /// Uses a fixed (not sliding) window: once `now_ns` moves past `withdrawal_window_start_ns +
+    /// withdrawal_limit_window_ns`, the counter resets to empty rather than decaying gradually.
+    pub(crate) fn check_withdrawal_limit(
+        &mut self,
+        asset: ValidatedAsset,
+        requested_amount_decimals: u64,
+        now_ns: u64,
+    ) -> Result<u64, Error> {
+        if self.withdrawal_limit_window_ns == 0 {
+            return Ok(requested_amount_decimals);
+        }
+
+        let Some(limit_decimals) = self.withdrawal_limit_decimals.get(&asset).copied() else {
+            return Ok(requested_amount_decimals);
+        };
+
+        let withdrawal_limit_window_ns = self.withdrawal_limit_window_ns;
+        let balance_book = self.balance_book_mut(asset)?;
+
+        let window_elapsed = now_ns.saturating_sub(balance_book.withdrawal_window_start_ns)
+            >= withdrawal_limit_window_ns;
+
+        if window_elapsed {
+            balance_book.withdrawal_window_start_ns = now_ns;
+            balance_book.withdrawn_in_window_decimals = 0;
+        }
+
+        let remaining_decimals =
+            limit_decimals.saturating_sub(balance_book.withdrawn_in_window_decimals);
+
+        let allowed_amount_decimals = requested_amount_decimals.min(remaining_decimals);
+
+        balance_book.withdrawn_in_window_decimals = balance_book
+            .withdrawn_in_window_decimals
+            .saturating_add(allowed_amount_decimals);
+
+        Ok(allowed_amount_decimals)
+    }
+
+    /// Sets [`Self::management_fee_rate_bps`]. `0` disables management fee accrual entirely.
+    pub(crate) fn set_management_fee_rate_bps(&mut self, management_fee_rate_bps: u16) {
+        self.management_fee_rate_bps = management_fee_rate_bps;
+    }
+
+    /// Rejects `expected_sequence` if it names a value other than [`Self::operation_sequence`]'s
+    /// current one, i.e. the state has moved on since the caller last read it. `None` skips the
+    /// check entirely, for callers that don't track the sequence.
+    pub(crate) fn check_operation_sequence(
+        &self,
+        expected_sequence: Option<u64>,
+    ) -> Result<(), Error> {
+        match expected_sequence {
+            Some(expected_sequence) if expected_sequence != self.operation_sequence => {
+                Err(Error {
+                    code: u64::from(TransactionErrorCodes::PreConditionCode),
+                    message: format!(
+                        "Stale operation_sequence: expected {}, current value is {}. Re-read the \
+                         current sequence and retry.",
+                        expected_sequence, self.operation_sequence,
+                    ),
+                    kind: ErrorKind::Precondition {},
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Advances [`Self::operation_sequence`] by one, called once a state-mutating operation has
+    /// actually committed so a caller that read the sequence beforehand can detect, on its next
+    /// call, that the state has since moved on.
+    pub(crate) fn advance_operation_sequence(&mut self) {
+        self.operation_sequence = self.operation_sequence.saturating_add(1);
+    }
+
+    // As the metadata of an asset, e.g., its symbol and fee, might change over time, calling this
+    // function would update them. Since `asset_to_balances`/`expected_totals_decimals` are keyed
+    // by the full `ValidatedAsset` (metadata and all), a metadata change re-keys this asset's
+    // entries in both maps so that future lookups with the refreshed `ValidatedAsset` still find
+    // them.
     pub(crate) fn refresh_asset(&mut self, asset_id: usize, asset_new: ValidatedAsset) {
-        let asset = if asset_id == 0 {
-            &mut self.asset_0
+        let old_asset = if asset_id == 0 {
+            self.asset_0
         } else if asset_id == 1 {
-            &mut self.asset_1
+            self.asset_1
         } else {
             log_err(&format!("Invalid asset_id {}: must be 0 or 1.", asset_id));
             return;
         };
 
-        let asset_old_info = asset.clone();
+        if old_asset == asset_new {
+            return;
+        }
 
         let ValidatedAsset::Token {
             symbol: new_symbol,
             ledger_canister_id: _,
             ledger_fee_decimals: new_ledger_fee_decimals,
+            decimals: new_decimals,
+            ledger_protocol: _,
         } = asset_new;
 
-        if asset.set_symbol(new_symbol) {
+        let mut refreshed_asset = old_asset;
+
+        if refreshed_asset.set_symbol(new_symbol) {
             log(&format!(
                 "Changed asset_{} symbol from `{}` to `{}`.",
                 asset_id,
-                asset_old_info.symbol(),
+                old_asset.symbol(),
                 new_symbol,
             ));
-            return;
         }
 
-        if asset.set_ledger_fee_decimals(new_ledger_fee_decimals) {
+        if refreshed_asset.set_ledger_fee_decimals(new_ledger_fee_decimals) {
             log(&format!(
                 "Changed asset_{} ledger_fee_decimals from `{}` to `{}`.",
                 asset_id,
-                asset_old_info.ledger_fee_decimals(),
+                old_asset.ledger_fee_decimals(),
                 new_ledger_fee_decimals,
             ));
         }
+
+        if refreshed_asset.set_decimals(new_decimals) {
+            log(&format!(
+                "Changed asset_{} decimals from `{}` to `{}`.",
+                asset_id,
+                old_asset.decimals(),
+                new_decimals,
+            ));
+        }
+
+        if let Some(book) = self.asset_to_balances.remove(&old_asset) {
+            self.asset_to_balances.insert(refreshed_asset, book);
+        }
+        if let Some(expected_total) = self.expected_totals_decimals.remove(&old_asset) {
+            self.expected_totals_decimals
+                .insert(refreshed_asset, expected_total);
+        }
+
+        if asset_id == 0 {
+            self.asset_0 = refreshed_asset;
+        } else {
+            self.asset_1 = refreshed_asset;
+        }
     }
 
     // This function updates the distribution of balances for
-    // a given asset held by the external protocol.
-    pub(crate) fn set_external_custodian_balance(&mut self, asset: ValidatedAsset, balance: u64) {
-        let balance_book = if asset == self.asset_0 {
-            &mut self.asset_0_balance
-        } else if asset == self.asset_1 {
-            &mut self.asset_1_balance
-        } else {
-            log_err(&format!(
-                "Invalid asset: must be {} or {}.",
-                self.asset_0.symbol(),
-                self.asset_1.symbol()
-            ));
-            return;
-        };
+    // a given asset held by the external protocol. Since this balance is pegged directly from the
+    // DEX's own report rather than derived from a tracked transfer, it re-snapshots the expected
+    // conserved total (see `conserved_total_decimals`) to match, rather than checking it. Any
+    // growth over the previously recorded external balance (LP trading fees / yield accrued at the
+    // DEX since the last refresh) is credited to `earnings`.
+    pub(crate) fn set_external_custodian_balance(
+        &mut self,
+        asset: ValidatedAsset,
+        balance: u64,
+    ) -> Result<(), Error> {
+        let balance_book = self.balance_book_mut(asset)?;
+
+        if balance > balance_book.external {
+            let surplus = balance - balance_book.external;
+            balance_book.earnings = checked_add_decimals(
+                &format!("{} {} balance", Party::Earnings, asset.symbol()),
+                balance_book.earnings,
+                surplus,
+            )?;
+        }
 
         balance_book.external = balance;
+
+        self.resnapshot_expected_total(asset, "re-pegging the external balance");
+
+        Ok(())
     }
 
-    pub(crate) fn add_manager_balance(&mut self, asset: ValidatedAsset, amount: u64) {
-        let balance_book = if asset == self.asset_0 {
-            &mut self.asset_0_balance
-        } else if asset == self.asset_1 {
-            &mut self.asset_1_balance
-        } else {
+    /// Re-snapshots the expected conserved total for `asset` to the conserved total currently held
+    /// in its balance book. Called after a deliberate, externally-driven balance change (a DEX
+    /// balance re-peg, or a discrepancy adjustment) that isn't itself a `move_asset`/`charge_fee`
+    /// transfer, so it's exempt from the conserved-total check those go through.
+    fn resnapshot_expected_total(&mut self, asset: ValidatedAsset, reason: &str) {
+        let Some(balance_book) = self.asset_to_balances.get(&asset) else {
             log_err(&format!(
-                "Invalid asset: must be {} or {}.",
-                self.asset_0.symbol(),
-                self.asset_1.symbol()
+                "Cannot re-snapshot the expected total for unregistered asset {}.",
+                asset.symbol()
             ));
             return;
         };
 
-        balance_book.treasury_manager.amount_decimals += amount;
+        let expected_total = conserved_total_decimals(balance_book).unwrap_or_else(|| {
+            log_err(&format!(
+                "{} conserved total overflowed a u64 after {}.",
+                asset.symbol(),
+                reason
+            ));
+            u64::MAX
+        });
+
+        self.expected_totals_decimals.insert(asset, expected_total);
+    }
+
+    /// Recomputes the conserved total of each [`ValidatedBalanceBook`] and checks it against the
+    /// expected total last snapshotted in [`Self::expected_totals_decimals`]. A mismatch means some
+    /// code path mutated a balance outside of `move_asset`/`charge_fee`/
+    /// `set_external_custodian_balance`, silently creating or destroying tokens in the books.
+    pub(crate) fn reconcile(&self) -> Result<(), Error> {
+        for asset in self.registered_assets() {
+            let balance_book = self.balance_book(asset)?;
+            let expected_total_decimals = self.expected_total_decimals(asset)?;
+            Self::check_conserved_total(asset, balance_book, expected_total_decimals)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_conserved_total(
+        asset: ValidatedAsset,
+        book: &ValidatedBalanceBook,
+        expected_total_decimals: u64,
+    ) -> Result<(), Error> {
+        let actual_total_decimals = conserved_total_decimals(book).ok_or_else(|| {
+            Error::new_postcondition(format!(
+                "{} balance book overflowed a u64 while summing its conserved total.",
+                asset.symbol()
+            ))
+        })?;
+
+        if actual_total_decimals != expected_total_decimals {
+            return Err(Error::new_postcondition(format!(
+                "{} balance book is out of balance: expected total {}, got {} \
+                 (discrepancy {}).",
+                asset.symbol(),
+                expected_total_decimals,
+                actual_total_decimals,
+                actual_total_decimals.abs_diff(expected_total_decimals),
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Credits a freshly-deposited allowance to `treasury_manager`. Like
+    /// [`Self::set_external_custodian_balance`], this is a deliberate, externally-driven increase
+    /// (new funds entering the system from the DAO, not a transfer between existing parties), so
+    /// it re-snapshots the expected conserved total after applying it.
+    ///
+    /// `timestamp_ns` resets [`ValidatedBalanceBook::last_manager_credit_timestamp_ns`], the
+    /// watermark [`Self::withdrawal_timelock_remaining_ns`] measures the configured
+    /// `withdrawal_timelock_ns` against.
+    pub(crate) fn add_manager_balance(
+        &mut self,
+        asset: ValidatedAsset,
+        amount: u64,
+        timestamp_ns: u64,
+    ) -> Result<(), Error> {
+        let balance_book = self.balance_book_mut(asset)?;
+
+        balance_book.treasury_manager.amount_decimals = checked_add_decimals(
+            &format!("{} {} balance", Party::TreasuryManager, asset.symbol()),
+            balance_book.treasury_manager.amount_decimals,
+            amount,
+        )?;
+        balance_book.last_manager_credit_timestamp_ns = timestamp_ns;
+
+        self.resnapshot_expected_total(asset, "crediting a fresh manager deposit");
+
+        Ok(())
+    }
+
+    /// The remaining time (in nanoseconds) before `asset`'s `treasury_manager` balance clears the
+    /// configured [`Self::withdrawal_timelock_ns`] cooldown, or `None` if it's already withdrawable
+    /// (including when no timelock is configured). Since balances aren't tracked per-deposit, the
+    /// whole balance is gated on the most recent credit (see
+    /// [`ValidatedBalanceBook::last_manager_credit_timestamp_ns`]): a DAO that wants older funds to
+    /// stay freely withdrawable across deposits should keep `withdrawal_timelock_ns` short relative
+    /// to its deposit cadence.
+    pub(crate) fn withdrawal_timelock_remaining_ns(
+        &self,
+        asset: ValidatedAsset,
+        now_ns: u64,
+    ) -> Result<Option<u64>, Error> {
+        if self.withdrawal_timelock_ns == 0 {
+            return Ok(None);
+        }
+
+        let balance_book = self.balance_book(asset)?;
+
+        let unlock_timestamp_ns = balance_book
+            .last_manager_credit_timestamp_ns
+            .saturating_add(self.withdrawal_timelock_ns);
+
+        Ok(unlock_timestamp_ns.checked_sub(now_ns).filter(|ns| *ns > 0))
+    }
+
+    /// Reverses an [`Self::add_manager_balance`] call, debiting `treasury_manager` back down.
+    /// Used by [`KongSwapAdaptor::rollback_operation`](crate::state::KongSwapAdaptor::rollback_operation)
+    /// to unwind a deposit that was credited to the manager but never made it into the DEX, since
+    /// (unlike [`Self::move_asset`]) there's no other party to move the funds back to.
+    pub(crate) fn subtract_manager_balance(
+        &mut self,
+        asset: ValidatedAsset,
+        amount: u64,
+    ) -> Result<(), Error> {
+        let balance_book = self.balance_book_mut(asset)?;
+
+        balance_book.treasury_manager.amount_decimals = checked_sub_decimals(
+            &format!("{} {} balance", Party::TreasuryManager, asset.symbol()),
+            balance_book.treasury_manager.amount_decimals,
+            amount,
+        )?;
+
+        self.resnapshot_expected_total(asset, "rolling back a manager deposit");
+
+        Ok(())
     }
 
     // TODO[ATG]: Let's discuss this in detail.
+    //
+    // Mutates a clone of the affected `ValidatedBalanceBook` and only commits it once the move has
+    // been checked against the conserved-total invariant (see `conserved_total_decimals`), so a
+    // bug in the arm below can never silently create or destroy tokens in the books.
     pub(crate) fn move_asset(
         &mut self,
         asset: ValidatedAsset,
         from: Party,
         to: Party,
         amount: u64,
-    ) {
-        let balance_book = if asset == self.asset_0 {
-            &mut self.asset_0_balance
-        } else if asset == self.asset_1 {
-            &mut self.asset_1_balance
-        } else {
-            log_err(&format!(
-                "Invalid asset: must be {} or {}.",
-                self.asset_0.symbol(),
-                self.asset_1.symbol()
-            ));
-            return;
-        };
+    ) -> Result<(), Error> {
+        let mut new_book = self.balance_book(asset)?.clone();
+        let fee = asset.ledger_fee_decimals();
+
+        // The amount actually credited to the receiving party, net of the ledger fee routed to
+        // `fee_collector` below.
+        let net_of_fee = checked_sub_decimals(
+            &format!("Transfer amount for {}, net of its ledger fee", asset.symbol()),
+            amount,
+            fee,
+        );
 
         match (&from, &to) {
             (Party::External, Party::TreasuryManager) => {
-                balance_book.external -= amount;
-                balance_book.treasury_manager.amount_decimals +=
-                    amount - asset.ledger_fee_decimals();
+                new_book.external = checked_sub_decimals(
+                    &format!("{} {} balance", Party::External, asset.symbol()),
+                    new_book.external,
+                    amount,
+                )?;
+                new_book.treasury_manager.amount_decimals = checked_add_decimals(
+                    &format!("{} {} balance", Party::TreasuryManager, asset.symbol()),
+                    new_book.treasury_manager.amount_decimals,
+                    net_of_fee?,
+                )?;
             }
             (Party::TreasuryManager, Party::TreasuryOwner) => {
-                balance_book.treasury_manager.amount_decimals -= amount;
-                balance_book.treasury_owner.amount_decimals += amount - asset.ledger_fee_decimals();
+                new_book.treasury_manager.amount_decimals = checked_sub_decimals(
+                    &format!("{} {} balance", Party::TreasuryManager, asset.symbol()),
+                    new_book.treasury_manager.amount_decimals,
+                    amount,
+                )?;
+                new_book.treasury_owner.amount_decimals = checked_add_decimals(
+                    &format!("{} {} balance", Party::TreasuryOwner, asset.symbol()),
+                    new_book.treasury_owner.amount_decimals,
+                    net_of_fee?,
+                )?;
             }
             (Party::TreasuryManager, Party::External) => {
-                balance_book.external += amount - asset.ledger_fee_decimals();
-                balance_book.treasury_manager.amount_decimals -= amount;
+                new_book.external = checked_add_decimals(
+                    &format!("{} {} balance", Party::External, asset.symbol()),
+                    new_book.external,
+                    net_of_fee?,
+                )?;
+                new_book.treasury_manager.amount_decimals = checked_sub_decimals(
+                    &format!("{} {} balance", Party::TreasuryManager, asset.symbol()),
+                    new_book.treasury_manager.amount_decimals,
+                    amount,
+                )?;
+            }
+            // The reverse of `(TreasuryManager, TreasuryOwner)`, used to unwind a journal entry
+            // when a deposit/withdraw is rolled back after tokens were credited to the owner.
+            (Party::TreasuryOwner, Party::TreasuryManager) => {
+                new_book.treasury_owner.amount_decimals = checked_sub_decimals(
+                    &format!("{} {} balance", Party::TreasuryOwner, asset.symbol()),
+                    new_book.treasury_owner.amount_decimals,
+                    net_of_fee?,
+                )?;
+                new_book.treasury_manager.amount_decimals = checked_add_decimals(
+                    &format!("{} {} balance", Party::TreasuryManager, asset.symbol()),
+                    new_book.treasury_manager.amount_decimals,
+                    amount,
+                )?;
             }
             _ => {
                 log_err(&format!("Invalid asset movement from {} to {}", from, to));
             }
         }
 
-        balance_book.fee_collector += asset.ledger_fee_decimals();
-    }
+        new_book.fee_collector = checked_add_decimals(
+            &format!("{} {} balance", Party::FeeCollector, asset.symbol()),
+            new_book.fee_collector,
+            fee,
+        )?;
+        // Every ledger fee is a cost of operating the position; accrue it to `spendings` as well,
+        // so `earnings - spendings` (see the `pnl` module) reflects it.
+        new_book.spendings = checked_add_decimals(
+            &format!("{} {} balance", Party::Spendings, asset.symbol()),
+            new_book.spendings,
+            fee,
+        )?;
 
-    pub(crate) fn charge_fee(&mut self, asset: ValidatedAsset) {
-        let balance_book = if asset == self.asset_0 {
-            &mut self.asset_0_balance
-        } else if asset == self.asset_1 {
-            &mut self.asset_1_balance
-        } else {
-            log_err(&format!(
-                "Invalid asset: must be {} or {}.",
-                self.asset_0.symbol(),
-                self.asset_1.symbol()
-            ));
-            return;
-        };
+        let expected_total_decimals = self.expected_total_decimals(asset)?;
+        Self::check_conserved_total(asset, &new_book, expected_total_decimals)?;
+
+        self.asset_to_balances.insert(asset, new_book);
+        Ok(())
+    }
 
+    pub(crate) fn charge_fee(&mut self, asset: ValidatedAsset) -> Result<(), Error> {
+        let mut new_book = self.balance_book(asset)?.clone();
         let fee = asset.ledger_fee_decimals();
-        balance_book.fee_collector += fee;
-        balance_book.treasury_manager.amount_decimals -= fee;
+        new_book.fee_collector = checked_add_decimals(
+            &format!("{} {} balance", Party::FeeCollector, asset.symbol()),
+            new_book.fee_collector,
+            fee,
+        )?;
+        new_book.spendings = checked_add_decimals(
+            &format!("{} {} balance", Party::Spendings, asset.symbol()),
+            new_book.spendings,
+            fee,
+        )?;
+        new_book.treasury_manager.amount_decimals = checked_sub_decimals(
+            &format!("{} {} balance", Party::TreasuryManager, asset.symbol()),
+            new_book.treasury_manager.amount_decimals,
+            fee,
+        )?;
+
+        let expected_total_decimals = self.expected_total_decimals(asset)?;
+        Self::check_conserved_total(asset, &new_book, expected_total_decimals)?;
+
+        self.asset_to_balances.insert(asset, new_book);
+        Ok(())
+    }
+
+    /// Debits `fee_decimals` from `asset`'s `treasury_manager` balance and routes it to
+    /// `fee_collector`/`spendings`, exactly like [`Self::charge_fee`] but for a caller-supplied
+    /// amount instead of `asset`'s ledger fee. Used by [`Self::accrue_management_fee`].
+    fn charge_management_fee(&mut self, asset: ValidatedAsset, fee_decimals: u64) -> Result<(), Error> {
+        let mut new_book = self.balance_book(asset)?.clone();
+        new_book.fee_collector = checked_add_decimals(
+            &format!("{} {} balance", Party::FeeCollector, asset.symbol()),
+            new_book.fee_collector,
+            fee_decimals,
+        )?;
+        new_book.spendings = checked_add_decimals(
+            &format!("{} {} balance", Party::Spendings, asset.symbol()),
+            new_book.spendings,
+            fee_decimals,
+        )?;
+        new_book.treasury_manager.amount_decimals = checked_sub_decimals(
+            &format!("{} {} balance", Party::TreasuryManager, asset.symbol()),
+            new_book.treasury_manager.amount_decimals,
+            fee_decimals,
+        )?;
+
+        let expected_total_decimals = self.expected_total_decimals(asset)?;
+        Self::check_conserved_total(asset, &new_book, expected_total_decimals)?;
+
+        self.asset_to_balances.insert(asset, new_book);
+        Ok(())
+    }
+
+    /// Charges [`Self::management_fee_rate_bps`]'s pro-rated share of each registered asset's
+    /// `treasury_manager` balance accrued since [`Self::last_fee_accrual_ns`], as
+    /// `balance * rate_bps * elapsed_ns / (10_000 * year_ns)`, routing it to `fee_collector` the
+    /// same way [`Self::charge_fee`] routes ledger fees there. Returns the amount charged per asset
+    /// charged, omitting assets whose accrued fee rounded down to zero, so the caller can record it
+    /// in the audit trail.
+    ///
+    /// A `0` rate (the default) or a zero elapsed duration only re-pegs
+    /// [`Self::last_fee_accrual_ns`] to `now_ns`, without touching any balance.
+    pub(crate) fn accrue_management_fee(
+        &mut self,
+        now_ns: u64,
+    ) -> Result<Vec<(ValidatedAsset, u64)>, Error> {
+        let elapsed_ns = now_ns.saturating_sub(self.last_fee_accrual_ns);
+        self.last_fee_accrual_ns = now_ns;
+
+        if self.management_fee_rate_bps == 0 || elapsed_ns == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut charged = Vec::new();
+        for asset in self.registered_assets() {
+            let balance_decimals = self.balance_book(asset)?.treasury_manager.amount_decimals;
+            let fee_decimals =
+                management_fee_decimals(balance_decimals, self.management_fee_rate_bps, elapsed_ns);
+
+            if fee_decimals == 0 {
+                continue;
+            }
+
+            self.charge_management_fee(asset, fee_decimals)?;
+            charged.push((asset, fee_decimals));
+        }
+
+        Ok(charged)
     }
 
     pub(crate) fn find_deposit_discrepency(
@@ -269,23 +1207,18 @@ impl ValidatedBalances {
         balance_before: u64,
         balance_after: u64,
         transferred_amount: u64,
-    ) {
-        let balance_book = if asset == self.asset_0 {
-            &mut self.asset_0_balance
-        } else if asset == self.asset_1 {
-            &mut self.asset_1_balance
-        } else {
-            log_err(&format!(
-                "Invalid asset: must be {} or {}.",
-                self.asset_0.symbol(),
-                self.asset_1.symbol()
-            ));
-            return;
-        };
+    ) -> Result<(), Error> {
+        let balance_book = self.balance_book_mut(asset)?;
 
         if balance_after.abs_diff(balance_before) > transferred_amount {
             balance_book.suspense += balance_before.abs_diff(balance_after) - transferred_amount;
+            // Crediting `suspense` here is a deliberate, externally-observed adjustment (a gap
+            // between the ledger-reported balance change and what this adaptor tracked), not a
+            // `move_asset` transfer, so the conserved total must be re-snapshotted to match.
+            self.resnapshot_expected_total(asset, "recording a deposit discrepancy");
         }
+
+        Ok(())
     }
 
     pub(crate) fn find_withdraw_discrepency(
@@ -294,29 +1227,168 @@ impl ValidatedBalances {
         balance_before: u64,
         balance_after: u64,
         transferred_amount: u64,
-    ) {
-        let balance_book = if asset == self.asset_0 {
-            &mut self.asset_0_balance
-        } else if asset == self.asset_1 {
-            &mut self.asset_1_balance
-        } else {
-            log_err(&format!(
-                "Invalid asset: must be {} or {}.",
-                self.asset_0.symbol(),
-                self.asset_1.symbol()
-            ));
-            return;
-        };
+    ) -> Result<(), Error> {
+        let balance_book = self.balance_book_mut(asset)?;
 
         if balance_after.abs_diff(balance_before) < transferred_amount - asset.ledger_fee_decimals()
         {
             balance_book.suspense += balance_after
                 .abs_diff(balance_before + transferred_amount - asset.ledger_fee_decimals());
+            self.resnapshot_expected_total(asset, "recording a withdraw discrepancy");
         }
+
+        Ok(())
+    }
+
+    /// Renders this balance table as human-readable strings scaled by each asset's `decimals`
+    /// (e.g. `"1.23456789 ICP"`), so operators inspecting the adaptor's treasury state directly
+    /// don't need to mentally divide by each token's base-unit scale.
+    pub(crate) fn format_human_readable(&self) -> Vec<FormattedAssetBalances> {
+        self.registered_assets()
+            .into_iter()
+            .filter_map(|asset| {
+                let balance_book = self.asset_to_balances.get(&asset)?;
+                let fmt = |amount_decimals: u64| asset.format_amount_decimals(amount_decimals);
+
+                Some(FormattedAssetBalances {
+                    symbol: asset.symbol(),
+                    balances: vec![
+                        FormattedPartyBalance {
+                            party: Party::TreasuryOwner.to_string(),
+                            value: fmt(balance_book.treasury_owner.amount_decimals),
+                        },
+                        FormattedPartyBalance {
+                            party: Party::TreasuryManager.to_string(),
+                            value: fmt(balance_book.treasury_manager.amount_decimals),
+                        },
+                        FormattedPartyBalance {
+                            party: Party::External.to_string(),
+                            value: fmt(balance_book.external),
+                        },
+                        FormattedPartyBalance {
+                            party: Party::FeeCollector.to_string(),
+                            value: fmt(balance_book.fee_collector),
+                        },
+                        FormattedPartyBalance {
+                            party: Party::Spendings.to_string(),
+                            value: fmt(balance_book.spendings),
+                        },
+                        FormattedPartyBalance {
+                            party: Party::Earnings.to_string(),
+                            value: fmt(balance_book.earnings),
+                        },
+                    ],
+                })
+            })
+            .collect()
     }
 }
 
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FormattedPartyBalance {
+    pub party: String,
+    pub value: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FormattedAssetBalances {
+    pub symbol: String,
+    pub balances: Vec<FormattedPartyBalance>,
+}
+
 impl<A: AbstractAgent> KongSwapAdaptor<A> {
+    /// Cross-checks `allowance`'s declared `symbol` and `ledger_fee_decimals` against the live
+    /// `icrc1_metadata` reported by its ledger, rejecting the allowance if they disagree.
+    ///
+    /// Unlike [`Self::refresh_ledger_metadata_impl`] (which silently overwrites our locally
+    /// cached asset metadata once a pool is already being managed), this is a pre-acceptance
+    /// check: an allowance whose caller-supplied symbol or fee doesn't match the ledger it claims
+    /// to come from indicates a misconfigured or stale request, so it's rejected outright rather
+    /// than "corrected" on the caller's behalf.
+    pub(crate) async fn validate_allowance_against_ledger(
+        &mut self,
+        context: &mut OperationContext,
+        allowance: &ValidatedAllowance,
+    ) -> Result<(), Error> {
+        let ledger_canister_id = allowance.asset.ledger_canister_id();
+
+        let human_readable = format!(
+            "Calling {}.icrc1_metadata to cross-validate the declared allowance metadata.",
+            ledger_canister_id
+        );
+
+        let reply = self
+            .emit_transaction(
+                context,
+                ledger_canister_id,
+                Icrc1MetadataRequest {},
+                human_readable,
+            )
+            .await?;
+
+        let mut problems = vec![];
+
+        let declared_symbol = allowance.asset.symbol();
+        let ledger_symbol = reply.iter().find_map(|(key, value)| {
+            if key == "icrc1:symbol" {
+                if let MetadataValue::Text(symbol) = value {
+                    return Some(symbol.clone());
+                }
+            }
+            None
+        });
+        match ledger_symbol {
+            Some(ledger_symbol) if ledger_symbol != declared_symbol => {
+                problems.push(format!(
+                    "declared symbol `{}` does not match ledger {}'s symbol `{}`",
+                    declared_symbol, ledger_canister_id, ledger_symbol
+                ));
+            }
+            Some(_) => (),
+            None => problems.push(format!(
+                "Ledger {} icrc1_metadata response does not have an `icrc1:symbol`.",
+                ledger_canister_id
+            )),
+        }
+
+        let declared_fee_decimals = allowance.asset.ledger_fee_decimals();
+        let ledger_fee_decimals = reply.iter().find_map(|(key, value)| {
+            if key == "icrc1:fee" {
+                if let MetadataValue::Nat(fee) = value {
+                    return decode_nat_to_u64(fee.clone()).ok();
+                }
+            }
+            None
+        });
+        match ledger_fee_decimals {
+            Some(ledger_fee_decimals) if ledger_fee_decimals != declared_fee_decimals => {
+                problems.push(format!(
+                    "declared ledger_fee_decimals {} does not match ledger {}'s fee {}",
+                    declared_fee_decimals, ledger_canister_id, ledger_fee_decimals
+                ));
+            }
+            Some(_) => (),
+            None => problems.push(format!(
+                "Ledger {} icrc1_metadata response does not have an `icrc1:fee`.",
+                ledger_canister_id
+            )),
+        }
+
+        if !problems.is_empty() {
+            return Err(Error {
+                code: u64::from(TransactionErrorCodes::PreConditionCode),
+                message: format!(
+                    "Allowance for ledger {} disagrees with its live metadata:\n  - {}",
+                    ledger_canister_id,
+                    problems.join("\n  - ")
+                ),
+                kind: ErrorKind::Precondition {},
+            });
+        }
+
+        Ok(())
+    }
+
     async fn refresh_ledger_metadata_impl(
         &mut self,
         context: &mut OperationContext,
@@ -333,11 +1405,12 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
                 asset_id, ledger_canister_id,
             );
 
-            let token = format!("IC.{}", ledger_canister_id);
+            let token =
+                KongSwapBackend::new(*KONG_BACKEND_CANISTER_ID).token_name(ledger_canister_id);
 
             let result = self
                 .emit_transaction(
-                    context.next_operation(),
+                    context,
                     *KONG_BACKEND_CANISTER_ID,
                     UpdateTokenArgs { token },
                     human_readable,
@@ -360,7 +1433,7 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
 
         let reply = self
             .emit_transaction(
-                context.next_operation(),
+                context,
                 ledger_canister_id,
                 Icrc1MetadataRequest {},
                 human_readable,
@@ -401,9 +1474,9 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
         }
 
         // II.B. Refresh the ledger fee.
-        let new_fee = reply.into_iter().find_map(|(key, value)| {
+        let new_fee = reply.iter().find_map(|(key, value)| {
             if key == "icrc1:fee" {
-                Some(value)
+                Some(value.clone())
             } else {
                 None
             }
@@ -433,6 +1506,41 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
             }
         }
 
+        // II.C. Refresh the display decimals, used only for human-readable balance reporting.
+        let new_decimals = reply.into_iter().find_map(|(key, value)| {
+            if key == "icrc1:decimals" {
+                Some(value)
+            } else {
+                None
+            }
+        });
+
+        if let Some(MetadataValue::Nat(new_decimals)) = new_decimals {
+            match decode_nat_to_u64(new_decimals) {
+                Ok(new_decimals) if new_decimals <= u64::from(u8::MAX) => {
+                    asset.set_decimals(new_decimals as u8);
+                }
+                Ok(new_decimals) => {
+                    log_err(&format!(
+                        "Ledger {} reported an out-of-range `icrc1:decimals` ({}). Keeping the old value {}.",
+                        ledger_canister_id, new_decimals, old_asset.decimals()
+                    ));
+                }
+                Err(err) => {
+                    log_err(&format!(
+                        "Failed to decode `icrc1:decimals` as Nat ({}). Keeping the old value {}.",
+                        err,
+                        old_asset.decimals()
+                    ));
+                }
+            }
+        } else {
+            log_err(&format!(
+                "Ledger {} icrc1_metadata response does not have an `icrc1:decimals`. Keeping the old value {}.",
+                ledger_canister_id, old_asset.decimals()
+            ));
+        }
+
         Ok(asset)
     }
 
@@ -441,19 +1549,20 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
         &mut self,
         context: &mut OperationContext,
     ) -> Result<(), Error> {
-        let (asset_0, asset_1) = self.assets();
+        let registered_assets = self.get_cached_balances().registered_assets();
 
-        let asset_0 = self
-            .refresh_ledger_metadata_impl(context, 0, asset_0)
-            .await?;
-
-        let asset_1 = self
-            .refresh_ledger_metadata_impl(context, 1, asset_1)
-            .await?;
+        let mut refreshed_assets = vec![];
+        for (asset_id, asset) in registered_assets.into_iter().enumerate() {
+            let refreshed_asset = self
+                .refresh_ledger_metadata_impl(context, asset_id, asset)
+                .await?;
+            refreshed_assets.push((asset_id, refreshed_asset));
+        }
 
         self.with_balances_mut(|validated_balances| {
-            validated_balances.refresh_asset(0, asset_0);
-            validated_balances.refresh_asset(1, asset_1);
+            for (asset_id, refreshed_asset) in refreshed_assets {
+                validated_balances.refresh_asset(asset_id, refreshed_asset);
+            }
         });
 
         Ok(())
@@ -481,7 +1590,7 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
 
         let reply = self
             .emit_transaction(
-                context.next_operation(),
+                context,
                 *KONG_BACKEND_CANISTER_ID,
                 request,
                 human_readable,
@@ -503,11 +1612,29 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
             kind: ErrorKind::Postcondition {},
         })?;
 
-        self.with_balances_mut(|validated_balances| {
-            validated_balances.set_external_custodian_balance(asset_0, balance_0_decimals);
-            validated_balances.set_external_custodian_balance(asset_1, balance_1_decimals);
-        });
+        self.with_balances_mut_result(|validated_balances| {
+            for (asset, balance_decimals) in
+                [(asset_0, balance_0_decimals), (asset_1, balance_1_decimals)]
+            {
+                validated_balances.set_external_custodian_balance(asset, balance_decimals)?;
+            }
+            Ok(())
+        })?;
+
+        self.record_price_observation(self.time_ns(), balance_0_decimals, balance_1_decimals);
+        self.refresh_exchange_rates(context).await;
+
+        if let Err(err) = self.reconcile() {
+            log_err(&format!(
+                "Balance books failed reconciliation after refresh_balances: {}",
+                err.message
+            ));
+            self.mark_state_corrupt(&err.message);
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests;
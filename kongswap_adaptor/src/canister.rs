@@ -1,4 +1,9 @@
-use crate::state::storage::{ConfigState, StableTransaction};
+use crate::slippage::BPS_DENOMINATOR;
+use crate::state::storage::{
+    ConfigState, ContractStatus, ExchangeRateHistoryEntry, IdempotencyKey, IdempotencyRecord,
+    IntegrityStatus, OperationLock, PendingDepositState, PeriodicTask, PriceHistoryEntry,
+    StableTransaction, StableWithdrawState, TaskStatuses, TransferIntentKey, TransferIntentRecord,
+};
 use crate::validation::{
     ValidatedDepositRequest, ValidatedTreasuryManagerInit, ValidatedWithdrawRequest,
 };
@@ -6,36 +11,65 @@ use candid::Principal;
 use ic_canister_log::{declare_log_buffer, log};
 use ic_cdk::{init, post_upgrade, pre_upgrade, query, update};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{Cell as StableCell, DefaultMemoryImpl, Vec as StableVec};
+use ic_stable_structures::{
+    BTreeMap as StableBTreeMap, Cell as StableCell, DefaultMemoryImpl, Vec as StableVec,
+};
 use kongswap_adaptor::agent::ic_cdk_agent::CdkAgent;
 use kongswap_adaptor::agent::AbstractAgent;
-use kongswap_adaptor::audit::OperationContext;
+use kongswap_adaptor::audit::RecordDecision;
 use lazy_static::lazy_static;
 use sns_treasury_manager::{
     Allowance, AuditTrail, AuditTrailRequest, Balances, BalancesRequest, DepositRequest, Error,
-    Operation, TreasuryManager, TreasuryManagerArg, TreasuryManagerResult, WithdrawRequest,
+    Operation, Transaction, TreasuryManager, TreasuryManagerArg, TreasuryManagerResult, Transfer,
+    WithdrawRequest,
 };
 use state::KongSwapAdaptor;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::{cell::RefCell, time::Duration};
 
+mod accounting;
 mod balances;
 mod deposit;
+mod dex_backend;
 mod emit_transaction;
+mod exchange_rate;
+mod health_check;
+mod http;
 mod kong_api;
 mod kong_types;
 mod ledger_api;
+mod pnl;
+mod price_history;
+mod rebalance;
+mod reconciliation;
 mod rewards;
+mod scheduler;
+mod single_sided_swap;
+mod slippage;
 mod state;
+mod subaccount;
+mod token_amount;
+mod transfer_verification;
 mod tx_error_codes;
 mod validation;
+mod value_guard;
 mod withdraw;
 
-const RUN_PERIODIC_TASKS_INTERVAL: Duration = Duration::from_secs(60 * 60); // one hour
-
 pub(crate) type Memory = VirtualMemory<DefaultMemoryImpl>;
 pub(crate) type StableAuditTrail = StableVec<StableTransaction, Memory>;
 pub(crate) type StableBalances = StableCell<ConfigState, Memory>;
+pub(crate) type StableWithdrawStateCell = StableCell<StableWithdrawState, Memory>;
+pub(crate) type StablePriceHistory = StableBTreeMap<u64, PriceHistoryEntry, Memory>;
+pub(crate) type StableIdempotencyKeys = StableBTreeMap<IdempotencyKey, IdempotencyRecord, Memory>;
+pub(crate) type StableContractStatus = StableCell<ContractStatus, Memory>;
+pub(crate) type StablePendingDepositStateCell = StableCell<PendingDepositState, Memory>;
+pub(crate) type StableIntegrityStatus = StableCell<IntegrityStatus, Memory>;
+pub(crate) type StableExchangeRateHistory = StableBTreeMap<u64, ExchangeRateHistoryEntry, Memory>;
+pub(crate) type StableTaskStatusCell = StableCell<TaskStatuses, Memory>;
+pub(crate) type StableTransferIntents =
+    StableBTreeMap<TransferIntentKey, TransferIntentRecord, Memory>;
+pub(crate) type StableOperationLockCell = StableCell<OperationLock, Memory>;
 
 // Canister ID from the mainnet.
 // See https://dashboard.internetcomputer.org/canister/2ipq2-uqaaa-aaaar-qailq-cai
@@ -48,6 +82,16 @@ lazy_static! {
 
 const BALANCES_MEMORY_ID: MemoryId = MemoryId::new(0);
 const AUDIT_TRAIL_MEMORY_ID: MemoryId = MemoryId::new(1);
+const WITHDRAW_STATE_MEMORY_ID: MemoryId = MemoryId::new(2);
+const PRICE_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(3);
+const IDEMPOTENCY_KEYS_MEMORY_ID: MemoryId = MemoryId::new(4);
+const CONTRACT_STATUS_MEMORY_ID: MemoryId = MemoryId::new(5);
+const PENDING_DEPOSIT_STATE_MEMORY_ID: MemoryId = MemoryId::new(6);
+const INTEGRITY_STATUS_MEMORY_ID: MemoryId = MemoryId::new(7);
+const EXCHANGE_RATE_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(8);
+const TASK_STATUS_MEMORY_ID: MemoryId = MemoryId::new(9);
+const TRANSFER_INTENTS_MEMORY_ID: MemoryId = MemoryId::new(10);
+const OPERATION_LOCK_MEMORY_ID: MemoryId = MemoryId::new(11);
 
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
@@ -74,19 +118,144 @@ thread_local! {
             )
         );
 
+    static WITHDRAW_STATE: RefCell<StableWithdrawStateCell> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableCell::init(
+                    memory_manager.borrow().get(WITHDRAW_STATE_MEMORY_ID),
+                    StableWithdrawState::default()
+                )
+                .expect("WITHDRAW_STATE init should not cause errors")
+            )
+        );
+
+    static PRICE_HISTORY: RefCell<StablePriceHistory> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableBTreeMap::init(
+                    memory_manager.borrow().get(PRICE_HISTORY_MEMORY_ID)
+                )
+            )
+        );
+
+    static IDEMPOTENCY_KEYS: RefCell<StableIdempotencyKeys> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableBTreeMap::init(
+                    memory_manager.borrow().get(IDEMPOTENCY_KEYS_MEMORY_ID)
+                )
+            )
+        );
+
+    static CONTRACT_STATUS: RefCell<StableContractStatus> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableCell::init(
+                    memory_manager.borrow().get(CONTRACT_STATUS_MEMORY_ID),
+                    ContractStatus::default()
+                )
+                .expect("CONTRACT_STATUS init should not cause errors")
+            )
+        );
+
+    static PENDING_DEPOSIT_STATE: RefCell<StablePendingDepositStateCell> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableCell::init(
+                    memory_manager.borrow().get(PENDING_DEPOSIT_STATE_MEMORY_ID),
+                    PendingDepositState::default()
+                )
+                .expect("PENDING_DEPOSIT_STATE init should not cause errors")
+            )
+        );
+
+    static INTEGRITY_STATUS: RefCell<StableIntegrityStatus> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableCell::init(
+                    memory_manager.borrow().get(INTEGRITY_STATUS_MEMORY_ID),
+                    IntegrityStatus::default()
+                )
+                .expect("INTEGRITY_STATUS init should not cause errors")
+            )
+        );
+
+    static EXCHANGE_RATE_HISTORY: RefCell<StableExchangeRateHistory> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableBTreeMap::init(
+                    memory_manager.borrow().get(EXCHANGE_RATE_HISTORY_MEMORY_ID)
+                )
+            )
+        );
+
+    static TASK_STATUS: RefCell<StableTaskStatusCell> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableCell::init(
+                    memory_manager.borrow().get(TASK_STATUS_MEMORY_ID),
+                    TaskStatuses::default()
+                )
+                .expect("TASK_STATUS init should not cause errors")
+            )
+        );
+
+    static TRANSFER_INTENTS: RefCell<StableTransferIntents> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableBTreeMap::init(
+                    memory_manager.borrow().get(TRANSFER_INTENTS_MEMORY_ID)
+                )
+            )
+        );
+
+    static OPERATION_LOCK: RefCell<StableOperationLockCell> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableCell::init(
+                    memory_manager.borrow().get(OPERATION_LOCK_MEMORY_ID),
+                    OperationLock::default()
+                )
+                .expect("OPERATION_LOCK init should not cause errors")
+            )
+        );
+
 }
 
 fn time_ns() -> u64 {
     ic_cdk::api::time()
 }
 
+/// The installed [`RecordSampler`]: `Deposit`/`Withdraw` (the operations that move real funds)
+/// are always kept in full, while `Balances`/`IssueReward` (routine, frequent, lower-stakes
+/// polling-style operations) are compacted to a single summary entry, bounding how much of the
+/// audit trail's growth comes from them on a long-lived canister.
+fn audit_sampler(context: &kongswap_adaptor::audit::OperationContext) -> RecordDecision {
+    match context.operation() {
+        Operation::Deposit | Operation::Withdraw => RecordDecision::Full,
+        Operation::Balances | Operation::IssueReward => RecordDecision::Summary,
+    }
+}
+
 fn canister_state() -> KongSwapAdaptor<CdkAgent> {
-    KongSwapAdaptor::new(
+    KongSwapAdaptor::with_audit_sampler(
         Box::new(time_ns),
+        audit_sampler,
         Arc::new(CdkAgent::new()),
         ic_cdk::id(),
         &BALANCES,
         &AUDIT_TRAIL,
+        &WITHDRAW_STATE,
+        &PRICE_HISTORY,
+        &IDEMPOTENCY_KEYS,
+        &CONTRACT_STATUS,
+        &PENDING_DEPOSIT_STATE,
+        &INTEGRITY_STATUS,
+        &EXCHANGE_RATE_HISTORY,
+        &TASK_STATUS,
+        &TRANSFER_INTENTS,
+        &OPERATION_LOCK,
+        Box::new(kongswap_adaptor::audit::NoopStatusNotificationHook),
     )
 }
 
@@ -104,6 +273,46 @@ fn check_access() {
     ic_cdk::trap("Only a controller can call this method.");
 }
 
+/// Like [`check_access`], but also lets through a caller a controller delegated `method` to via
+/// [`authorize`] -- the custodian model [`authorize`]'s doc comment describes, letting the owning
+/// SNS hand routine `deposit`/`withdraw`/`commit_state` calls to an operations canister without
+/// making it a full controller. Used only at the entry points that model names (plus their
+/// `..._with_expected_sequence`, `deposit_with_max_price_deviation_bps`, and
+/// `deposit_with_swap_and_redeploy` siblings); every other controller-gated endpoint keeps using
+/// the plain, non-delegable [`check_access`].
+fn check_access_for(method: &str) {
+    let caller = ic_cdk::api::caller();
+
+    if caller == ic_cdk::id() {
+        return;
+    }
+
+    if ic_cdk::api::is_controller(&caller) {
+        return;
+    }
+
+    if canister_state().is_authorized(caller, method) {
+        return;
+    }
+
+    ic_cdk::trap(&format!(
+        "Only a controller, or a principal authorized for `{}`, can call this method.",
+        method
+    ));
+}
+
+/// Collapses a deposit/withdraw failure's errors into the single [`Error`] stored as the rolled
+/// back audit-trail transaction's result witness (see [`KongSwapAdaptor::rollback_operation`]).
+fn combine_errors(errors: &[Error]) -> Error {
+    let message = errors
+        .iter()
+        .map(|err| err.message.clone())
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    Error::new_backend(message)
+}
+
 declare_log_buffer!(name = LOG, capacity = 100);
 
 fn log_err(msg: &str) {
@@ -124,7 +333,12 @@ fn log(msg: &str) {
 
 impl<A: AbstractAgent> TreasuryManager for KongSwapAdaptor<A> {
     async fn withdraw(&mut self, request: WithdrawRequest) -> TreasuryManagerResult {
-        self.check_state_lock()?;
+        self.check_state_lock(&self.lock_keys(&[Operation::Withdraw]))?;
+        self.check_integrity()?;
+
+        if let Err(err) = self.accrue_management_fee() {
+            log_err(&format!("Failed to accrue management fee: {:?}", err));
+        }
 
         let (ledger_0, ledger_1) = self.ledgers();
 
@@ -143,41 +357,33 @@ impl<A: AbstractAgent> TreasuryManager for KongSwapAdaptor<A> {
             .try_into()
             .map_err(|err: String| vec![Error::new_precondition(err)])?;
 
-        let mut context = OperationContext::new(Operation::Withdraw);
+        let lock_generation = self
+            .acquire_operation_lock(Operation::Withdraw)
+            .map_err(|err| vec![err])?;
+        let mut context =
+            self.new_operation_context(Operation::Withdraw).with_lock_generation(lock_generation);
 
-        let returned_amounts = self
+        let withdraw_result = self
             .withdraw_impl(&mut context, withdraw_account_0, withdraw_account_1)
-            .await
-            .map(Balances::from)?;
-
-        self.finalize_audit_trail_transaction(context);
-
-        Ok(returned_amounts)
+            .await;
+
+        match withdraw_result {
+            Ok(balances) => {
+                self.clear_operation_journal();
+                self.finalize_audit_trail_transaction(context);
+                self.advance_operation_sequence();
+                self.release_operation_lock(Operation::Withdraw);
+                Ok(Balances::from(balances))
+            }
+            Err(errors) => {
+                self.rollback_operation(context, combine_errors(&errors));
+                Err(errors)
+            }
+        }
     }
 
     async fn deposit(&mut self, request: DepositRequest) -> TreasuryManagerResult {
-        self.check_state_lock()?;
-
-        let ValidatedDepositRequest {
-            allowance_0,
-            allowance_1,
-        } = request
-            .try_into()
-            .map_err(|err: String| vec![Error::new_precondition(err)])?;
-
-        self.validate_deposit_args(allowance_0, allowance_1)
-            .map_err(|err| vec![err])?;
-
-        let mut context = OperationContext::new(Operation::Deposit);
-
-        let deposited_amounts = self
-            .deposit_impl(&mut context, allowance_0, allowance_1)
-            .await
-            .map(Balances::from)?;
-
-        self.finalize_audit_trail_transaction(context);
-
-        Ok(deposited_amounts)
+        self.deposit_with_max_price_deviation_bps(request, None).await
     }
 
     fn audit_trail(&self, _request: AuditTrailRequest) -> AuditTrail {
@@ -189,43 +395,227 @@ impl<A: AbstractAgent> TreasuryManager for KongSwapAdaptor<A> {
     }
 
     async fn refresh_balances(&mut self) {
-        if let Err(err) = self.check_state_lock() {
+        // `Balances` doesn't hold a lock of its own (see `crate::emit_transaction`), but it must
+        // still defer to an in-flight deposit/withdraw so it doesn't read balances mid-mutation.
+        let keys = self.lock_keys(&[Operation::Deposit, Operation::Withdraw]);
+        if let Err(err) = self.check_state_lock(&keys) {
+            log_err(&format!("Cannot refresh balances: {:?}", err));
+            return;
+        }
+
+        if let Err(err) = self.check_integrity() {
             log_err(&format!("Cannot refresh balances: {:?}", err));
             return;
         }
 
-        let mut context = OperationContext::new(Operation::Balances);
+        if let Err(err) = self.accrue_management_fee() {
+            log_err(&format!("Failed to accrue management fee: {:?}", err));
+        }
+
+        let mut context = self.new_operation_context(Operation::Balances);
 
         let result = self.refresh_balances_impl(&mut context).await;
 
-        if let Err(err) = result {
+        if let Err(err) = &result {
             log_err(&format!("refresh_balances failed: {:?}", err));
         }
+        self.record_task_outcome(
+            PeriodicTask::RefreshBalances,
+            result.as_ref().err().map(|err| err.message.clone()),
+        );
 
         self.finalize_audit_trail_transaction(context);
     }
 
     async fn issue_rewards(&mut self) {
-        if let Err(err) = self.check_state_lock() {
+        // Same reasoning as `refresh_balances`: `IssueReward` doesn't hold a lock of its own, but
+        // still must not run concurrently with an in-flight deposit/withdraw.
+        let keys = self.lock_keys(&[Operation::Deposit, Operation::Withdraw]);
+        if let Err(err) = self.check_state_lock(&keys) {
             log_err(&format!("Cannot issue rewards: {:?}", err));
             return;
         }
 
-        let mut context = OperationContext::new(Operation::IssueReward);
+        if self.get_contract_status() == ContractStatus::Halted {
+            log_err("Cannot issue rewards: contract status is Halted.");
+            return;
+        }
+
+        if let Err(err) = self.check_integrity() {
+            log_err(&format!("Cannot issue rewards: {:?}", err));
+            return;
+        }
+
+        if let Err(err) = self.accrue_management_fee() {
+            log_err(&format!("Failed to accrue management fee: {:?}", err));
+        }
+
+        let mut context = self.new_operation_context(Operation::IssueReward);
 
         let result = self.issue_rewards_impl(&mut context).await;
 
-        if let Err(err) = result {
+        if let Err(err) = &result {
             log_err(&format!("issue_rewards failed: {:?}", err));
         }
+        self.record_task_outcome(
+            PeriodicTask::IssueRewards,
+            result.as_ref().err().map(|errors| combine_errors(errors).message),
+        );
 
         self.finalize_audit_trail_transaction(context);
     }
 }
 
+impl<A: AbstractAgent> KongSwapAdaptor<A> {
+    /// Like [`TreasuryManager::deposit`], but additionally bounds how far a top-up's realized
+    /// split may deviate from the ratio implied by the caller's own `allowance_0`/`allowance_1`
+    /// amounts -- see [`crate::slippage::check_deposit_intended_ratio_bps`]. `DepositRequest` is
+    /// owned by the `sns_treasury_manager` crate and fixed by the `TreasuryManager` interface, so
+    /// it can't carry this field itself; [`TreasuryManager::deposit`] calls this with
+    /// `max_price_deviation_bps: None` (the check disabled), the same way [`Self::deposit_impl`]'s
+    /// `min_holdings` defaults to empty for that entry point. Exposed directly via
+    /// [`deposit_with_max_price_deviation_bps`] for callers that want it enforced.
+    pub async fn deposit_with_max_price_deviation_bps(
+        &mut self,
+        request: DepositRequest,
+        max_price_deviation_bps: Option<u16>,
+    ) -> TreasuryManagerResult {
+        self.check_state_lock(&self.lock_keys(&[Operation::Deposit]))?;
+        self.check_integrity()?;
+
+        let contract_status = self.get_contract_status();
+        if contract_status != ContractStatus::Operational {
+            return Err(vec![Error::new_precondition(format!(
+                "Deposits are currently disabled (contract status: {:?}).",
+                contract_status
+            ))]);
+        }
+
+        if let Err(err) = self.accrue_management_fee() {
+            log_err(&format!("Failed to accrue management fee: {:?}", err));
+        }
+
+        let ValidatedDepositRequest {
+            allowance_0,
+            allowance_1,
+        } = request
+            .try_into()
+            .map_err(|err: String| vec![Error::new_precondition(err)])?;
+
+        self.validate_deposit_args(allowance_0, allowance_1)
+            .map_err(|err| vec![err])?;
+
+        let lock_generation = self
+            .acquire_operation_lock(Operation::Deposit)
+            .map_err(|err| vec![err])?;
+        let mut context =
+            self.new_operation_context(Operation::Deposit).with_lock_generation(lock_generation);
+
+        // `DepositRequest` (from the unmodifiable `sns_treasury_manager` crate) has no field for
+        // a caller-supplied min-holdings floor yet, so none is enforced via this entry point;
+        // `deposit_impl`'s `min_holdings` parameter exists for callers that can supply one
+        // directly (see `value_guard::assert_min_holdings`).
+        //
+        // `swap_and_redeploy` is similarly left disabled here -- see
+        // [`Self::deposit_with_swap_and_redeploy`] for the sibling that enables it.
+        let deposit_result = self
+            .deposit_impl(
+                allowance_0,
+                allowance_1,
+                &BTreeMap::new(),
+                max_price_deviation_bps,
+                false,
+            )
+            .await;
+
+        match deposit_result {
+            Ok(balances) => {
+                self.clear_operation_journal();
+                self.finalize_audit_trail_transaction(context);
+                self.advance_operation_sequence();
+                self.release_operation_lock(Operation::Deposit);
+                Ok(Balances::from(balances))
+            }
+            Err(errors) => {
+                self.rollback_operation(context, combine_errors(&errors));
+                Err(errors)
+            }
+        }
+    }
+
+    /// Like [`TreasuryManager::deposit`], but additionally lets a top-up fold its unproportional
+    /// remainder into additional deployed liquidity instead of leaving it for
+    /// [`crate::ledger_api::KongSwapAdaptor::return_remaining_assets_to_owner`]'s end-of-deposit
+    /// refund sweep -- see [`crate::deposit::KongSwapAdaptor::redeploy_remainder`].
+    /// `DepositRequest` has no field for this either, for the same reason
+    /// [`Self::deposit_with_max_price_deviation_bps`] exists alongside plain
+    /// [`TreasuryManager::deposit`]; `swap_and_redeploy: false` reproduces plain `deposit`'s
+    /// refund-only behavior.
+    pub async fn deposit_with_swap_and_redeploy(
+        &mut self,
+        request: DepositRequest,
+        swap_and_redeploy: bool,
+    ) -> TreasuryManagerResult {
+        self.check_state_lock(&self.lock_keys(&[Operation::Deposit]))?;
+        self.check_integrity()?;
+
+        let contract_status = self.get_contract_status();
+        if contract_status != ContractStatus::Operational {
+            return Err(vec![Error::new_precondition(format!(
+                "Deposits are currently disabled (contract status: {:?}).",
+                contract_status
+            ))]);
+        }
+
+        if let Err(err) = self.accrue_management_fee() {
+            log_err(&format!("Failed to accrue management fee: {:?}", err));
+        }
+
+        let ValidatedDepositRequest {
+            allowance_0,
+            allowance_1,
+        } = request
+            .try_into()
+            .map_err(|err: String| vec![Error::new_precondition(err)])?;
+
+        self.validate_deposit_args(allowance_0, allowance_1)
+            .map_err(|err| vec![err])?;
+
+        let lock_generation = self
+            .acquire_operation_lock(Operation::Deposit)
+            .map_err(|err| vec![err])?;
+        let mut context =
+            self.new_operation_context(Operation::Deposit).with_lock_generation(lock_generation);
+
+        let deposit_result = self
+            .deposit_impl(
+                allowance_0,
+                allowance_1,
+                &BTreeMap::new(),
+                None,
+                swap_and_redeploy,
+            )
+            .await;
+
+        match deposit_result {
+            Ok(balances) => {
+                self.clear_operation_journal();
+                self.finalize_audit_trail_transaction(context);
+                self.advance_operation_sequence();
+                self.release_operation_lock(Operation::Deposit);
+                Ok(Balances::from(balances))
+            }
+            Err(errors) => {
+                self.rollback_operation(context, combine_errors(&errors));
+                Err(errors)
+            }
+        }
+    }
+}
+
 #[update]
 async fn deposit(request: DepositRequest) -> TreasuryManagerResult {
-    check_access();
+    check_access_for("deposit");
 
     log("deposit.");
 
@@ -236,7 +626,7 @@ async fn deposit(request: DepositRequest) -> TreasuryManagerResult {
 
 #[update]
 async fn withdraw(request: WithdrawRequest) -> TreasuryManagerResult {
-    check_access();
+    check_access_for("withdraw");
 
     log("withdraw.");
 
@@ -245,6 +635,424 @@ async fn withdraw(request: WithdrawRequest) -> TreasuryManagerResult {
     Ok(result)
 }
 
+/// Adaptor-specific variant of [`deposit`] for callers that want the same "sequence check" guard
+/// [`rebalance`]'s `RebalanceRequest::expected_sequence` already gets: `expected_sequence`, if
+/// set, must match [`operation_sequence`] or the deposit is rejected before any work is attempted.
+/// `DepositRequest` is owned by the `sns_treasury_manager` crate and fixed by the `TreasuryManager`
+/// interface (see [`operation_sequence`]'s doc comment), so it can't carry this field itself -- this
+/// wraps the same upstream request in a separate, adaptor-specific entry point instead, the same
+/// way [`audit_trail_page`] sits alongside [`audit_trail`].
+#[update]
+async fn deposit_with_expected_sequence(
+    request: DepositRequest,
+    expected_sequence: Option<u64>,
+) -> TreasuryManagerResult {
+    check_access_for("deposit");
+
+    log("deposit_with_expected_sequence.");
+
+    let mut kong_adaptor = canister_state();
+
+    kong_adaptor
+        .check_operation_sequence(expected_sequence)
+        .map_err(|err| vec![err])?;
+
+    kong_adaptor.deposit(request).await
+}
+
+/// Like [`deposit_with_expected_sequence`], but for [`withdraw`].
+#[update]
+async fn withdraw_with_expected_sequence(
+    request: WithdrawRequest,
+    expected_sequence: Option<u64>,
+) -> TreasuryManagerResult {
+    check_access_for("withdraw");
+
+    log("withdraw_with_expected_sequence.");
+
+    let mut kong_adaptor = canister_state();
+
+    kong_adaptor
+        .check_operation_sequence(expected_sequence)
+        .map_err(|err| vec![err])?;
+
+    kong_adaptor.withdraw(request).await
+}
+
+/// Adaptor-specific variant of [`deposit`] that additionally bounds how far a top-up's realized
+/// split may deviate (in basis points) from the ratio implied by this deposit's own
+/// `allowance_0`/`allowance_1` amounts, aborting before `add_liquidity` is called if it's
+/// exceeded -- see [`crate::state::KongSwapAdaptor::deposit_with_max_price_deviation_bps`].
+/// `None` disables the check, same as plain [`deposit`]. Unlike [`set_deposit_guard_params`]'s
+/// `max_deposit_price_deviation_bps` (a DAO-wide tolerance checked against the pool's reserves),
+/// this is a per-call tolerance checked against what this specific caller's allowances asked for.
+#[update]
+async fn deposit_with_max_price_deviation_bps(
+    request: DepositRequest,
+    max_price_deviation_bps: Option<u16>,
+) -> TreasuryManagerResult {
+    check_access_for("deposit");
+
+    log("deposit_with_max_price_deviation_bps.");
+
+    canister_state()
+        .deposit_with_max_price_deviation_bps(request, max_price_deviation_bps)
+        .await
+}
+
+/// Adaptor-specific variant of [`deposit`] that additionally lets a top-up fold its
+/// unproportional remainder into additional deployed liquidity instead of refunding it -- see
+/// [`crate::state::KongSwapAdaptor::deposit_with_swap_and_redeploy`]. `false` reproduces plain
+/// [`deposit`]'s refund-only behavior.
+#[update]
+async fn deposit_with_swap_and_redeploy(
+    request: DepositRequest,
+    swap_and_redeploy: bool,
+) -> TreasuryManagerResult {
+    check_access_for("deposit");
+
+    log("deposit_with_swap_and_redeploy.");
+
+    canister_state()
+        .deposit_with_swap_and_redeploy(request, swap_and_redeploy)
+        .await
+}
+
+/// Lets a controller (e.g. the owning SNS) set its own risk tolerance for `add_liquidity` /
+/// `remove_liquidity` calls, instead of relying on the built-in defaults
+/// (`DEFAULT_MAX_SLIPPAGE_BPS`, `DEFAULT_LP_FEE_BPS`).
+#[update]
+fn set_pool_risk_params(max_slippage_bps: u16, lp_fee_bps: u8) {
+    check_access();
+
+    if max_slippage_bps > BPS_DENOMINATOR {
+        ic_cdk::trap(&format!(
+            "max_slippage_bps must not exceed {} (100%), got {}.",
+            BPS_DENOMINATOR, max_slippage_bps
+        ));
+    }
+
+    let mut kong_adaptor = canister_state();
+    kong_adaptor.set_max_slippage_bps(max_slippage_bps);
+    kong_adaptor.set_lp_fee_bps(lp_fee_bps);
+}
+
+/// Lets a controller (e.g. the owning SNS) set guards on the deposit pipeline: the maximum
+/// tolerated deviation (in basis points) between a deposit's implied price and an already-existing
+/// pool's reserve ratio, and the minimum LP token amount (in the LP token's own decimals) a deposit
+/// must be minted. `0` in either position disables that guard, matching the built-in default of
+/// "no extra guard beyond what `add_pool`/`add_liquidity` already enforce".
+#[update]
+fn set_deposit_guard_params(max_deposit_price_deviation_bps: u16, min_deposit_lp_decimals: u64) {
+    check_access();
+
+    if max_deposit_price_deviation_bps > BPS_DENOMINATOR {
+        ic_cdk::trap(&format!(
+            "max_deposit_price_deviation_bps must not exceed {} (100%), got {}.",
+            BPS_DENOMINATOR, max_deposit_price_deviation_bps
+        ));
+    }
+
+    let mut kong_adaptor = canister_state();
+    kong_adaptor.set_max_deposit_price_deviation_bps(max_deposit_price_deviation_bps);
+    kong_adaptor.set_min_deposit_lp_decimals(min_deposit_lp_decimals);
+}
+
+/// Lets a controller set the conversion rates used by [`total_value_in_reference`] to value the
+/// managed assets in a reference denomination of its choosing (e.g. USD or ICP), fixed-point
+/// scaled by `RATE_DECIMALS_SCALE` (1e8). This adaptor has no built-in price oracle, so the
+/// controller is responsible for keeping these in sync with an external price feed.
+#[update]
+fn set_conversion_rates(asset_0_rate_decimals: u64, asset_1_rate_decimals: u64) {
+    check_access();
+
+    canister_state().set_conversion_rates(asset_0_rate_decimals, asset_1_rate_decimals);
+}
+
+/// Lets a controller choose the forex/crypto symbol (e.g. `"USD"`) [`refresh_balances`]'s
+/// automatic Exchange Rate Canister integration (see [`crate::exchange_rate`]) quotes each managed
+/// asset against, instead of the `"USD"` default. Unlike [`set_conversion_rates`], the rates
+/// themselves don't need to be kept in sync manually once this is set.
+#[update]
+fn set_valuation_quote_asset_symbol(valuation_quote_asset_symbol: String) {
+    check_access();
+
+    canister_state().set_valuation_quote_asset_symbol(valuation_quote_asset_symbol);
+}
+
+/// Lets a controller bound how long an Exchange Rate Canister observation stays usable before
+/// [`get_balances_valuation`] flags it as stale. `0` (the default) disables staleness reporting
+/// entirely.
+#[update]
+fn set_rate_staleness_bound_ns(rate_staleness_bound_ns: u64) {
+    check_access();
+
+    canister_state().set_rate_staleness_bound_ns(rate_staleness_bound_ns);
+}
+
+/// Lets a controller set how often (in nanoseconds) [`run_refresh_balances_task`]'s
+/// piggybacked claim-recovery sweep (see [`retry_claims`]) is allowed to run, measured from when
+/// it last ran. `0` (the default) means every `refresh_balances` tick (currently
+/// [`set_periodic_task_intervals`]'s `refresh_balances_interval_ns`,
+/// [`crate::balances::DEFAULT_TASK_INTERVAL_NS`] until reconfigured).
+#[update]
+fn set_claims_sweep_interval_ns(claims_sweep_interval_ns: u64) {
+    check_access();
+
+    canister_state().set_claims_sweep_interval_ns(claims_sweep_interval_ns);
+}
+
+/// Lets a controller set the floor the value-preservation guard (see [`crate::value_guard`])
+/// enforces before a deposit/withdraw is allowed to commit: the current position, quoted via
+/// `remove_liquidity_amounts` and valued at the rates set through [`set_conversion_rates`], must
+/// not be below this amount. `0` (the default) disables the guard, e.g. before any conversion
+/// rate has been set.
+#[update]
+fn set_min_treasury_value_in_reference_decimals(min_treasury_value_in_reference_decimals: u64) {
+    check_access();
+
+    canister_state().set_min_treasury_value_in_reference_decimals(
+        min_treasury_value_in_reference_decimals,
+    );
+}
+
+/// Lets a controller require a fresh DAO deposit to "rest" in the manager for
+/// `withdrawal_timelock_ns` before it can be withdrawn, as a governance-enforced cooldown against
+/// rapid treasury drains. `0` (the default) disables the cooldown.
+#[update]
+fn set_withdrawal_timelock_ns(withdrawal_timelock_ns: u64) {
+    check_access();
+
+    canister_state().set_withdrawal_timelock_ns(withdrawal_timelock_ns);
+}
+
+/// Lets a controller cap how much of each managed asset (in its own decimals) `withdraw` is
+/// allowed to move out of `treasury_manager` within a single `withdrawal_limit_window_ns` window
+/// (see [`crate::balances::ValidatedBalances::check_withdrawal_limit`]). `0` removes the cap for
+/// that asset; a withdrawal that would exceed it is clamped down to the remaining headroom rather
+/// than rejected outright, with the clamp recorded in the audit trail.
+#[update]
+fn set_withdrawal_limit_decimals(asset_0_limit_decimals: u64, asset_1_limit_decimals: u64) {
+    check_access();
+
+    let mut kong_adaptor = canister_state();
+    let (asset_0, asset_1) = kong_adaptor.assets();
+    kong_adaptor.set_withdrawal_limit_decimals(asset_0, asset_0_limit_decimals);
+    kong_adaptor.set_withdrawal_limit_decimals(asset_1, asset_1_limit_decimals);
+}
+
+/// Sets the length (in nanoseconds) of the rolling window [`set_withdrawal_limit_decimals`] is
+/// measured against. `0` (the default) disables withdrawal rate limiting entirely, regardless of
+/// any configured per-asset cap.
+#[update]
+fn set_withdrawal_limit_window_ns(withdrawal_limit_window_ns: u64) {
+    check_access();
+
+    canister_state().set_withdrawal_limit_window_ns(withdrawal_limit_window_ns);
+}
+
+/// Lets a controller delegate `methods` (e.g. `"deposit"`, `"withdraw"`, `"commit_state"`) to
+/// `principal` without making it a full controller -- see [`check_access_for`] for which entry
+/// points actually consult this, and [`deauthorize`]/[`list_authorizations`] for revoking/
+/// inspecting it. Replaces any method set previously granted to `principal` rather than adding to
+/// it; pass the union of old and new methods (from [`list_authorizations`]) to extend one instead.
+/// An empty `methods` is equivalent to [`deauthorize`].
+#[update]
+fn authorize(principal: Principal, methods: Vec<String>) {
+    check_access();
+
+    canister_state().authorize(principal, methods);
+}
+
+/// Revokes every permission [`authorize`] previously granted `principal`.
+#[update]
+fn deauthorize(principal: Principal) {
+    check_access();
+
+    canister_state().deauthorize(principal);
+}
+
+/// Lists every principal [`authorize`] has delegated call rights to, alongside the exact methods
+/// each one was granted.
+#[query]
+fn list_authorizations() -> Vec<(Principal, Vec<String>)> {
+    canister_state().authorizations()
+}
+
+/// Lets a controller throw the emergency killswitch: `DepositsPaused` rejects fresh deposits while
+/// still allowing a DAO to withdraw, and `Halted` additionally rejects `issue_rewards`/`rebalance`,
+/// leaving only `withdraw`/`refresh_balances`/balance queries available so liquidity can still be
+/// drained during an incident. The transition itself is recorded in the audit trail by
+/// [`KongSwapAdaptor::set_contract_status`].
+#[update]
+fn set_contract_status(status: ContractStatus) {
+    check_access();
+
+    canister_state().set_contract_status(status);
+}
+
+/// Reads the current value [`set_contract_status`] last set, e.g. so an operator's tooling can
+/// confirm a pause actually took effect without scraping the audit trail for the last
+/// `set_contract_status` transaction.
+#[query]
+fn get_contract_status() -> ContractStatus {
+    canister_state().get_contract_status()
+}
+
+/// Reports the outcome (timestamp plus error message, if any) of the most recent attempt of each
+/// periodic/background task (`refresh_balances`, `issue_rewards`, `init_async`). Unlike the audit
+/// trail, this also observes a task that failed before ever calling `emit_transaction` -- see
+/// [`state::KongSwapAdaptor::record_task_outcome`].
+#[query]
+fn task_health() -> TaskStatuses {
+    canister_state().get_task_statuses()
+}
+
+/// The explicit repair entry point a controller calls after
+/// [`KongSwapAdaptor::mark_state_corrupt`] has rejected every deposit/withdraw/rebalance call:
+/// re-reconciles the balance books and, only if that now succeeds, restores
+/// [`IntegrityStatus::Sound`]. Returns the reconciliation error (leaving the state corrupt) if the
+/// underlying discrepancy hasn't actually been fixed.
+#[update]
+fn repair_state() -> Result<(), Error> {
+    check_access();
+
+    canister_state().repair_state()
+}
+
+/// Lets a controller cap how much a single [`rebalance`] call is allowed to swap (in the
+/// swapped-from asset's own decimals), so a large rebalance lands gradually over several calls
+/// instead of moving the pool's price in one shot. `0` (the default) removes the cap -- the full
+/// amount [`crate::rebalance::KongSwapAdaptor::target_swap`] computes is swapped in one call. See
+/// [`crate::balances::ValidatedBalances::max_rebalance_amount_decimals`].
+#[update]
+fn set_max_rebalance_amount_decimals(max_rebalance_amount_decimals: u64) {
+    check_access();
+
+    canister_state().set_max_rebalance_amount_decimals(max_rebalance_amount_decimals);
+}
+
+/// Rebalances the treasury manager's position toward holding `target_ratio_bps` (out of
+/// [`BPS_DENOMINATOR`]) of its total value in `asset_0`, swapping through KongSwap with a
+/// slippage guard (see [`crate::rebalance`]). The swap amount is clamped to
+/// [`set_max_rebalance_amount_decimals`] if configured, so reaching `target_ratio_bps` from a long
+/// way off may take more than one call.
+///
+/// `Operation` is owned by the `sns_treasury_manager` crate, so this reuses `Operation::Withdraw`
+/// for its audit-trail lock and [`OperationContext`](kongswap_adaptor::audit::OperationContext)
+/// rather than adding a dedicated
+/// `Operation::Rebalance` variant, which would require an upstream change to that crate first --
+/// `Withdraw` is the closest existing fit, since like a withdrawal this operation moves managed
+/// assets against the DEX rather than bringing fresh assets in.
+///
+/// `request.expected_sequence`, if set, must match [`operation_sequence`] or the call is rejected
+/// before any work is attempted -- Mango v4's "sequence check" idea, letting a caller that read
+/// the sequence before submitting detect that the state has since moved on, rather than retrying
+/// blind. This sits alongside (not instead of) the idempotency-key check below: that one
+/// recognizes a byte-identical resubmission of the *same* call, this one rejects a call that's
+/// stale relative to *any* intervening state-mutating operation.
+#[update]
+async fn rebalance(request: RebalanceRequest) -> TreasuryManagerResult {
+    check_access();
+
+    log("rebalance.");
+
+    let mut kong_adaptor = canister_state();
+
+    kong_adaptor.check_state_lock(&kong_adaptor.lock_keys(&[Operation::Withdraw]))?;
+    kong_adaptor.check_integrity()?;
+
+    let contract_status = kong_adaptor.get_contract_status();
+    if contract_status == ContractStatus::Halted {
+        return Err(vec![Error::new_precondition(format!(
+            "Rebalance is currently disabled (contract status: {:?}).",
+            contract_status
+        ))]);
+    }
+
+    kong_adaptor
+        .check_operation_sequence(request.expected_sequence)
+        .map_err(|err| vec![err])?;
+
+    let idempotency_key = kong_adaptor.idempotency_key_for(Operation::Withdraw, &request);
+    if let Some(transaction_index) = kong_adaptor.check_idempotency_key(idempotency_key) {
+        log_err(&format!(
+            "Rebalance request already processed as audit-trail transaction {}; returning \
+             current balances instead of re-applying it.",
+            transaction_index
+        ));
+        return Ok(Balances::from(kong_adaptor.get_cached_balances()));
+    }
+
+    let lock_generation = kong_adaptor
+        .acquire_operation_lock(Operation::Withdraw)
+        .map_err(|err| vec![err])?;
+    let mut context = kong_adaptor
+        .new_operation_context(Operation::Withdraw)
+        .with_lock_generation(lock_generation);
+
+    let result = kong_adaptor
+        .rebalance_to_target_ratio(&mut context, request.target_ratio_bps)
+        .await;
+
+    match result {
+        Ok(()) => {
+            kong_adaptor.clear_operation_journal();
+            kong_adaptor.finalize_audit_trail_transaction(context);
+            if let Some(transaction_index) = kong_adaptor.audit_trail_tail_index() {
+                kong_adaptor.record_idempotency_key(idempotency_key, transaction_index);
+            }
+            kong_adaptor.advance_operation_sequence();
+            kong_adaptor.release_operation_lock(Operation::Withdraw);
+            Ok(Balances::from(kong_adaptor.get_cached_balances()))
+        }
+        Err(errors) => {
+            kong_adaptor.rollback_operation(context, combine_errors(&errors));
+            Err(errors)
+        }
+    }
+}
+
+#[derive(candid::CandidType, serde::Deserialize, Clone, Debug)]
+struct RebalanceRequest {
+    target_ratio_bps: u16,
+    /// The [`operation_sequence`] the caller believed was current when it built this request.
+    /// `None` skips the check. See the guard note on [`rebalance`].
+    expected_sequence: Option<u64>,
+}
+
+/// Manual trigger for the claim-recovery sweep [`run_refresh_balances_task`] otherwise only runs
+/// once every [`set_claims_sweep_interval_ns`] -- lets an operator recover stranded claims immediately
+/// instead of waiting for the next periodic tick. See
+/// [`crate::state::KongSwapAdaptor::retry_claims`] for what it actually does and why it can't
+/// forward recovered funds to an owner account on its own.
+#[update]
+async fn retry_claims() -> Result<(), Vec<Error>> {
+    check_access();
+
+    log("retry_claims.");
+
+    canister_state().retry_claims().await
+}
+
+/// A read-only solvency check -- see [`KongSwapAdaptor::health_check`] for exactly what it
+/// previews and what it deliberately leaves out. `#[update]` rather than `#[query]` because it
+/// makes a live call to KongSwap to price the current LP balance; it never locks or mutates
+/// managed balances, so no access check is required, the same as the other balance-reporting
+/// endpoints below.
+#[update]
+async fn health_check(request: HealthCheckRequest) -> Result<Balances, Error> {
+    canister_state()
+        .health_check(request.min_amount_0_decimals, request.min_amount_1_decimals)
+        .await
+}
+
+#[derive(candid::CandidType, serde::Deserialize, Clone, Debug)]
+struct HealthCheckRequest {
+    min_amount_0_decimals: u64,
+    min_amount_1_decimals: u64,
+}
+
 #[query]
 fn balances(request: BalancesRequest) -> TreasuryManagerResult {
     canister_state().balances(request)
@@ -255,20 +1063,314 @@ fn audit_trail(request: AuditTrailRequest) -> AuditTrail {
     canister_state().audit_trail(request)
 }
 
-async fn run_periodic_tasks() {
-    log("run_periodic_tasks.");
+/// Paginated variant of [`audit_trail`], for a trail too large to return in one reply --
+/// `AuditTrailRequest`/`AuditTrail` are owned by the `sns_treasury_manager` crate and fixed by the
+/// `TreasuryManager` interface, so this is a separate, adaptor-specific query rather than an
+/// extension of that trait method. Mirrors the `start`/`length` shape of ICRC's
+/// `GetBlocksRequest`; omitting either field defaults to the full range.
+#[query]
+fn audit_trail_page(request: AuditTrailPageRequest) -> AuditTrailPage {
+    let (transactions, total_length) =
+        canister_state().get_audit_trail_page(request.start_index, request.length);
+
+    AuditTrailPage {
+        transactions,
+        total_length,
+    }
+}
+
+#[derive(candid::CandidType, serde::Deserialize, Clone, Debug)]
+struct AuditTrailPageRequest {
+    start_index: Option<u64>,
+    length: Option<u64>,
+}
 
-    let mut kong_adaptor = canister_state();
+#[derive(candid::CandidType, serde::Deserialize, Clone, Debug)]
+struct AuditTrailPage {
+    transactions: Vec<Transaction>,
+    total_length: u64,
+}
+
+/// The current value of the sequence counter a caller can pass back as [`rebalance`]'s
+/// `expected_sequence`, or [`deposit_with_expected_sequence`]'s/[`withdraw_with_expected_sequence`]'s,
+/// to guard against the state having moved on since it was read. Advanced by every state-mutating
+/// operation that actually commits (`deposit`, `withdraw`, `rebalance`), so it reflects staleness
+/// against *any* of them, not just the one a particular call happened to submit.
+///
+/// Plain `deposit`/`withdraw` don't accept an `expected_sequence` themselves: `DepositRequest`/
+/// `WithdrawRequest` are owned by the `sns_treasury_manager` crate and fixed by the
+/// `TreasuryManager` interface, so they can't be extended with a new field without an upstream
+/// change there; [`deposit_with_expected_sequence`]/[`withdraw_with_expected_sequence`] exist
+/// alongside them for callers that need this staleness guard. A resubmitted `deposit`/`withdraw`
+/// is not deduplicated by request content -- see [`PendingDepositState`](crate::state::storage::PendingDepositState)
+/// and [`StableWithdrawState`](crate::state::storage::StableWithdrawState) for how a resumed call
+/// instead picks up from its last completed sub-step, and [`TransferIntentKey`](crate::state::storage::TransferIntentKey)
+/// for how the underlying ledger transfers themselves stay safe to retry.
+#[query]
+fn operation_sequence() -> u64 {
+    canister_state().operation_sequence()
+}
+
+/// Returns, for every audit trail transaction, its timestamp and the ledger blocks (if any) it
+/// produced. This lets an auditor independently verify the adaptor's treasury activity against
+/// the block history of the underlying ICRC-1/ICRC-2 ledgers.
+#[query]
+fn ledger_block_log() -> Vec<(u64, Vec<Transfer>)> {
+    canister_state().get_ledger_block_log()
+}
+
+/// Returns the balance table for both managed assets. By default, each party's holding is
+/// formatted against the asset's decimal precision and symbol (e.g. `"1.23456789 ICP"`); pass
+/// `raw = true` to get the machine-readable candid values instead.
+#[query]
+fn human_readable_balances(raw: bool) -> HumanReadableBalancesResult {
+    let kong_adaptor = canister_state();
+
+    if raw {
+        HumanReadableBalancesResult::Raw(Balances::from(kong_adaptor.get_cached_balances()))
+    } else {
+        HumanReadableBalancesResult::Formatted(kong_adaptor.get_human_readable_balances())
+    }
+}
+
+#[derive(candid::CandidType, serde::Deserialize, Clone, Debug)]
+enum HumanReadableBalancesResult {
+    Formatted(Vec<balances::FormattedAssetBalances>),
+    Raw(Balances),
+}
+
+/// Returns the recorded pool price observations as `(timestamp_ns, price_ratio)` pairs, oldest
+/// first, where `price_ratio` is `amount_0 / amount_1` formatted as a decimal string (price ratios
+/// aren't representable in candid as a native numeric type).
+#[query]
+fn price_history() -> Vec<(u64, String)> {
+    canister_state().get_price_history()
+}
+
+/// Reports impermanent loss for the LP position relative to an `entry_price_ratio` (as recorded by
+/// a prior [`price_history`] observation), alongside the current position value denominated in
+/// `ASSET_1`. `entry_price_ratio` is a decimal string, e.g. `"1.5"`.
+#[query]
+fn impermanent_loss(entry_price_ratio: String) -> Result<price_history::ValuationSummary, String> {
+    canister_state().get_valuation_summary(&entry_price_ratio)
+}
+
+/// Returns the total portfolio value across both managed assets, converted into the reference
+/// denomination set through [`set_valuation_quote_asset_symbol`]. Each asset's rate prefers
+/// KongSwap's own pool price (when the reference symbol names the *other* managed asset) and
+/// falls back to the exchange rate [`refresh_balances`]'s `refresh_exchange_rates` keeps up to
+/// date otherwise (see [`crate::exchange_rate`]). Errs if neither source currently has a usable
+/// rate for one of the assets.
+#[query]
+fn total_value_in_reference() -> Result<String, String> {
+    canister_state().total_value_in_reference()
+}
+
+/// Like [`total_value_in_reference`], but broken down per asset and reporting which rate source
+/// backed each (see [`price_history::RateSource`]), plus whether the report as a whole should be
+/// considered stale -- `true` whenever either asset's value came back `None` rather than a
+/// fabricated zero.
+#[query]
+fn get_balances_valuation() -> price_history::BalancesValuation {
+    canister_state().get_balances_valuation()
+}
+
+/// An alias for [`get_balances_valuation`], under the name a DAO auditing the treasury's fiat
+/// exposure would look for first.
+#[query]
+fn valuation() -> price_history::BalancesValuation {
+    canister_state().get_balances_valuation()
+}
+
+/// Returns the net realized P&L (`earnings - spendings`, see [`pnl`]) for each managed asset.
+#[query]
+fn realized_pnl() -> (pnl::AssetPnl, pnl::AssetPnl) {
+    canister_state().get_realized_pnl()
+}
+
+/// Returns net realized P&L across both managed assets, combined into the reference denomination
+/// via the rates set through [`set_conversion_rates`]. Errs if a rate hasn't been set for one of
+/// the assets yet.
+#[query]
+fn realized_pnl_in_reference() -> Result<String, String> {
+    canister_state().get_realized_pnl_in_reference()
+}
+
+/// Walks the audit trail's tamper-evident hash chain (see [`state::storage::StableTransaction`])
+/// and returns the index of the first entry whose hash no longer matches what it should be, or
+/// `Ok(())` if the whole trail is intact. A DAO can call this to independently prove the recorded
+/// treasury history was never altered, reordered, or dropped.
+#[query]
+fn verify_audit_trail() -> Result<(), u64> {
+    canister_state().verify_audit_trail()
+}
+
+/// Re-sums each managed asset's [`balances::ValidatedBalanceBook`] (`treasury_owner` +
+/// `treasury_manager` + `external` + `fee_collector` + `suspense`) and compares it against the
+/// total snapshotted the last time a deliberate change to that total (a fresh deposit, its
+/// rollback, an external re-peg or a recorded discrepancy) was applied -- see
+/// [`balances::ValidatedBalances::reconcile`]. Every `move_asset`/`charge_fee` debit-and-credit
+/// pair already re-checks this invariant itself before committing, so a failure here means some other code path mutated a
+/// balance directly; callable as a query so external monitoring can poll it independently of
+/// [`repair_state`] actually tripping.
+#[query]
+fn verify_balance_invariant() -> Result<(), Error> {
+    canister_state().reconcile()
+}
+
+/// Reconstructs each asset's expected `fee_collector` balance purely by replaying the audit trail
+/// and compares it against the live balance [`balances`] reports, surfacing any asset where they
+/// diverge; the other parties are not reconstructed -- see
+/// [`accounting::reconcile_from_audit_trail`]. An empty result means no drift was detected.
+#[query]
+fn audit_trail_reconciliation() -> Vec<accounting::AuditReconciliationDiscrepancy> {
+    canister_state().reconcile_audit_trail()
+}
+
+/// Confirms every ledger transfer recorded in the audit trail still has a matching on-chain
+/// block, by re-querying each transfer's own ledger for the exact block it claims -- see
+/// [`reconciliation::AuditTrailBlockDiscrepancy`] for what's checked (amount and block existence,
+/// not counterparty, which the audit trail's own `Transfer` representation doesn't carry) and
+/// [`audit_trail_reconciliation`] for the complementary, purely in-memory check this doesn't
+/// replace. `#[update]` rather than `#[query]` because it makes a live call per recorded transfer;
+/// it never locks or mutates managed balances, so no access check is required. An empty result
+/// means no drift was detected.
+#[update]
+async fn audit_trail_block_reconciliation() -> Vec<reconciliation::AuditTrailBlockDiscrepancy> {
+    canister_state()
+        .reconcile_audit_trail_against_ledgers()
+        .await
+}
+
+/// One concise, human-readable line per audit-trail transaction, with ledger amounts scaled by
+/// each asset's own decimals instead of raw e8s -- see
+/// [`state::KongSwapAdaptor::get_human_readable_audit_summary`]. An SNS operator reading this
+/// doesn't need to decode [`audit_trail`]'s `MAX_REPLY_SIZE_BYTES`-clamped JSON by hand.
+#[query]
+fn audit_trail_human_readable() -> Vec<String> {
+    canister_state().get_human_readable_audit_summary()
+}
+
+/// Serves the adaptor's state over plain HTTP -- see [`http`] for the routes this dispatches to
+/// (`/metrics`, `/audit`, `/audit.json`, `/audit.txt`).
+#[query]
+fn http_request(request: http::HttpRequest) -> http::HttpResponse {
+    http::handle_http_request(&canister_state(), request)
+}
 
+/// Runs a `refresh_balances` tick (plus the claim-recovery sweep piggybacked onto its cadence,
+/// same as before the split) and reports whether it succeeded, for
+/// [`run_refresh_balances_task`]'s backoff decision.
+async fn refresh_balances_tick(kong_adaptor: &mut KongSwapAdaptor<CdkAgent>) -> bool {
     kong_adaptor.refresh_balances().await;
 
+    if kong_adaptor.claims_sweep_is_due() {
+        if let Err(err) = kong_adaptor.retry_claims().await {
+            log_err(&format!("Periodic claims sweep failed: {:?}", err));
+        }
+    }
+
+    kong_adaptor
+        .get_task_statuses()
+        .refresh_balances
+        .map_or(true, |status| status.error_message.is_none())
+}
+
+/// Like [`refresh_balances_tick`], but for `issue_rewards`.
+async fn issue_rewards_tick(kong_adaptor: &mut KongSwapAdaptor<CdkAgent>) -> bool {
     kong_adaptor.issue_rewards().await;
+
+    kong_adaptor
+        .get_task_statuses()
+        .issue_rewards
+        .map_or(true, |status| status.error_message.is_none())
 }
 
+thread_local! {
+    static REFRESH_BALANCES_TIMER: RefCell<Option<ic_cdk_timers::TimerId>> = const { RefCell::new(None) };
+    static ISSUE_REWARDS_TIMER: RefCell<Option<ic_cdk_timers::TimerId>> = const { RefCell::new(None) };
+}
+
+/// Arms `refresh_balances`'s next one-shot timer after `delay`, replacing (and clearing) whatever
+/// timer was previously armed for it -- see [`scheduler`] for why each task owns its own timer
+/// instead of both running back-to-back off one fixed-interval timer, as they used to.
+fn schedule_refresh_balances(delay: Duration) {
+    let timer_id =
+        ic_cdk_timers::set_timer(delay, || ic_cdk::spawn(run_refresh_balances_task()));
+
+    if let Some(old_timer_id) = REFRESH_BALANCES_TIMER.with(|cell| cell.borrow_mut().replace(timer_id))
+    {
+        ic_cdk_timers::clear_timer(old_timer_id);
+    }
+}
+
+/// Like [`schedule_refresh_balances`], but for `issue_rewards`.
+fn schedule_issue_rewards(delay: Duration) {
+    let timer_id = ic_cdk_timers::set_timer(delay, || ic_cdk::spawn(run_issue_rewards_task()));
+
+    if let Some(old_timer_id) = ISSUE_REWARDS_TIMER.with(|cell| cell.borrow_mut().replace(timer_id))
+    {
+        ic_cdk_timers::clear_timer(old_timer_id);
+    }
+}
+
+/// One `refresh_balances` tick, followed by re-arming its own timer at the delay
+/// [`state::KongSwapAdaptor::record_scheduled_task_outcome`] computes from the outcome -- longer
+/// than [`ValidatedBalances::refresh_balances_interval_ns`](crate::balances::ValidatedBalances::refresh_balances_interval_ns)
+/// while it keeps failing, back to it the moment it next succeeds.
+async fn run_refresh_balances_task() {
+    log("run_refresh_balances_task.");
+
+    let mut kong_adaptor = canister_state();
+
+    let succeeded = refresh_balances_tick(&mut kong_adaptor).await;
+
+    let delay = kong_adaptor.record_scheduled_task_outcome(
+        scheduler::ScheduledTask::RefreshBalances,
+        succeeded,
+    );
+    schedule_refresh_balances(delay);
+}
+
+/// Like [`run_refresh_balances_task`], but for `issue_rewards`.
+async fn run_issue_rewards_task() {
+    log("run_issue_rewards_task.");
+
+    let mut kong_adaptor = canister_state();
+
+    let succeeded = issue_rewards_tick(&mut kong_adaptor).await;
+
+    let delay = kong_adaptor
+        .record_scheduled_task_outcome(scheduler::ScheduledTask::IssueRewards, succeeded);
+    schedule_issue_rewards(delay);
+}
+
+/// Arms both periodic-task timers at their currently configured cadence (see
+/// [`set_periodic_task_intervals`]), resuming the schedule after an upgrade or, on a fresh
+/// install, starting it -- the immediate population of balances on install instead comes from
+/// [`init_async`] ticking both tasks directly, once, outside this schedule.
 fn init_periodic_tasks() {
-    let _new_timer_id = ic_cdk_timers::set_timer_interval(RUN_PERIODIC_TASKS_INTERVAL, || {
-        ic_cdk::spawn(run_periodic_tasks())
-    });
+    let kong_adaptor = canister_state();
+    schedule_refresh_balances(Duration::from_nanos(kong_adaptor.refresh_balances_interval_ns()));
+    schedule_issue_rewards(Duration::from_nanos(kong_adaptor.issue_rewards_interval_ns()));
+}
+
+/// Lets a controller reconfigure how often `refresh_balances`/`issue_rewards` run, re-arming each
+/// task's timer at the new cadence immediately (rather than waiting for the currently-armed timer
+/// to fire) and clearing any exponential backoff already in progress -- see [`scheduler`]. This is
+/// the operator knob the claim-recovery sweep already has via
+/// [`set_claims_sweep_interval_ns`], extended to the two tasks that used to share a single fixed
+/// interval.
+#[update]
+fn set_periodic_task_intervals(refresh_balances_interval_ns: u64, issue_rewards_interval_ns: u64) {
+    check_access();
+
+    let mut kong_adaptor = canister_state();
+    kong_adaptor.set_refresh_balances_interval_ns(refresh_balances_interval_ns);
+    kong_adaptor.set_issue_rewards_interval_ns(issue_rewards_interval_ns);
+
+    schedule_refresh_balances(Duration::from_nanos(refresh_balances_interval_ns));
+    schedule_issue_rewards(Duration::from_nanos(issue_rewards_interval_ns));
 }
 
 async fn init_async(allowance_0: Allowance, allowance_1: Allowance) {
@@ -284,22 +1386,31 @@ async fn init_async(allowance_0: Allowance, allowance_1: Allowance) {
     let result = match result {
         Ok(result) => result,
         Err((err_code, err_message)) => {
-            log_err(&format!(
+            let message = format!(
                 "Self-call failed in async initializition. Error code {}: {:?}",
                 err_code as i32, err_message,
-            ));
+            );
+            log_err(&message);
+            canister_state().record_task_outcome(PeriodicTask::InitAsync, Some(message));
             return;
         }
     };
 
     if let Err(err) = result.0 {
-        log_err(&format!("Initial deposit failed: {:?}", err));
+        let message = format!("Initial deposit failed: {:?}", err);
+        log_err(&message);
+        canister_state().record_task_outcome(PeriodicTask::InitAsync, Some(message));
         return;
     }
 
-    // Ensure the balances are available after initialization.
-    run_periodic_tasks().await;
+    // Ensure the balances are available after initialization. Ticks both tasks directly, once,
+    // rather than through `run_refresh_balances_task`/`run_issue_rewards_task`, so this doesn't
+    // also arm a redundant pair of timers alongside the ones `init_periodic_tasks` already armed.
+    let mut kong_adaptor = canister_state();
+    refresh_balances_tick(&mut kong_adaptor).await;
+    issue_rewards_tick(&mut kong_adaptor).await;
 
+    canister_state().record_task_outcome(PeriodicTask::InitAsync, None);
     log("init_async completed successfully.");
 }
 
@@ -348,6 +1459,48 @@ fn canister_post_upgrade(arg: TreasuryManagerArg) {
         ic_cdk::trap("Expected TreasuryManagerArg::Upgrade on canister upgrade.");
     };
 
+    // The withdraw state lives in stable memory, so it survives the upgrade unchanged; the next
+    // `withdraw` call picks up from it automatically. Logging it here just gives an operator
+    // visibility into whether a withdraw was left mid-flight across this upgrade.
+    let withdraw_state = canister_state().get_withdraw_state();
+    if withdraw_state != StableWithdrawState::Done {
+        log(&format!(
+            "Resuming from a withdraw left mid-flight before the upgrade: {:?}.",
+            withdraw_state
+        ));
+    }
+
+    // Same as above, but for a deposit -- see `PendingDepositState`.
+    let pending_deposit_state = canister_state().get_pending_deposit_state();
+    if pending_deposit_state != PendingDepositState::Idle {
+        log(&format!(
+            "Resuming from a deposit left mid-flight before the upgrade: {:?}.",
+            pending_deposit_state
+        ));
+    }
+
+    // Re-derive expected balances purely from the persisted audit trail and compare against the
+    // persisted `ValidatedBalances` -- the same check `audit_trail_reconciliation` exposes live,
+    // run here unconditionally so a migration bug that silently corrupts `StableBalances` (but
+    // leaves the audit trail itself intact) can't pass through an upgrade unnoticed. Mirrors the
+    // "verify ledger state between upgrades" technique from the ICRC upgrade/downgrade test suite.
+    //
+    // Traps on a mismatch rather than quarantining: a trap here rolls back the *entire* upgrade
+    // atomically (new Wasm and any state mutations made above both discarded), leaving the
+    // canister on its last-known-good version with real funds at stake -- `mark_state_corrupt`
+    // would instead let the already-proven-inconsistent new version boot and keep serving
+    // `balances`/`audit_trail` (which don't call `check_integrity`), exactly the outcome this
+    // check exists to prevent. `mark_state_corrupt`/quarantine remains the right response to a
+    // runtime (non-upgrade) integrity violation, where rollback isn't an option.
+    let discrepancies = canister_state().reconcile_audit_trail();
+    if !discrepancies.is_empty() {
+        ic_cdk::trap(&format!(
+            "Refusing to complete the upgrade: replaying the audit trail disagrees with the \
+             persisted balances: {:?}.",
+            discrepancies
+        ));
+    }
+
     init_periodic_tasks();
 }
 
@@ -356,7 +1509,7 @@ fn canister_post_upgrade(arg: TreasuryManagerArg) {
 /// See: https://internetcomputer.org/docs/building-apps/security/inter-canister-calls#journaling
 #[update(hidden = true)]
 fn commit_state() {
-    check_access();
+    check_access_for("commit_state");
 }
 
 fn candid_service() -> String {
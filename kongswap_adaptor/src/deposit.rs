@@ -1,27 +1,116 @@
 use crate::{
     balances::{Party, ValidatedBalances},
+    dex_backend::{DexBackend, KongSwapBackend},
     kong_types::{
         AddLiquidityAmountsArgs, AddLiquidityAmountsReply, AddLiquidityArgs, AddLiquidityReply,
         AddPoolArgs,
     },
+    log_err,
+    single_sided_swap::single_sided_swap_in_amount,
+    slippage::{
+        check_deposit_intended_ratio_bps, check_deposit_price_deviation_bps, check_slippage_bps,
+        BPS_DENOMINATOR,
+    },
+    state::storage::PendingDepositState,
     tx_error_codes::TransactionErrorCodes,
     validation::{decode_nat_to_u64, saturating_sub, ValidatedAllowance},
     KongSwapAdaptor, KONG_BACKEND_CANISTER_ID,
 };
 use candid::Nat;
 use icrc_ledger_types::{icrc1::account::Account, icrc2::approve::ApproveArgs};
-use kongswap_adaptor::agent::AbstractAgent;
-use sns_treasury_manager::{Error, ErrorKind, TreasuryManager, TreasuryManagerOperation};
+use kongswap_adaptor::{
+    agent::AbstractAgent,
+    audit::{OperationContext, SettlementOutcome},
+};
+use sns_treasury_manager::{
+    Asset, Error, ErrorKind, Operation, TreasuryManager, TreasuryManagerOperation,
+};
+use std::collections::BTreeMap;
 
 /// How many ledger transaction that incur fees are required for a deposit operation (per token).
 /// This is an implementation detail of KongSwap and ICRC1 ledgers.
 const DEPOSIT_LEDGER_FEES_PER_TOKEN: u64 = 2;
 
 impl<A: AbstractAgent> KongSwapAdaptor<A> {
+    /// Rejects a deposit before any ledger `approve` call is dispatched, so a malformed or
+    /// pointless request fails fast instead of burning approval fees on a deposit that was
+    /// always going to go nowhere.
+    ///
+    /// Checks the same ledger pairing [`Self::deposit_into_dex`]'s Step 0 re-checks once it
+    /// starts talking to the ledgers, plus that each allowance leaves enough after
+    /// `DEPOSIT_LEDGER_FEES_PER_TOKEN` ledger fees to actually contribute a positive `add_pool`
+    /// amount -- a request that fails either check would otherwise only surface as an error
+    /// partway through `deposit_into_dex`, after the approvals (and their fees) already landed.
+    pub(crate) fn validate_deposit_args(
+        &self,
+        allowance_0: ValidatedAllowance,
+        allowance_1: ValidatedAllowance,
+    ) -> Result<(), Error> {
+        let new_ledger_0 = allowance_0.asset.ledger_canister_id();
+        let new_ledger_1 = allowance_1.asset.ledger_canister_id();
+
+        let (old_asset_0, old_asset_1) = self.assets();
+
+        if new_ledger_0 != old_asset_0.ledger_canister_id()
+            || new_ledger_1 != old_asset_1.ledger_canister_id()
+        {
+            return Err(Error {
+                code: u64::from(TransactionErrorCodes::PreConditionCode),
+                message: format!(
+                    "This KongSwapAdaptor only supports {}:{} as token_{{0,1}} (got ledger_0 {}, \
+                     ledger_1 {}).",
+                    old_asset_0.symbol(),
+                    old_asset_1.symbol(),
+                    new_ledger_0,
+                    new_ledger_1,
+                ),
+                kind: ErrorKind::Precondition {},
+            });
+        }
+
+        for (allowance, label) in [(&allowance_0, "allowance_0"), (&allowance_1, "allowance_1")] {
+            let required_decimals =
+                DEPOSIT_LEDGER_FEES_PER_TOKEN * allowance.asset.ledger_fee_decimals();
+
+            if allowance.amount_decimals.get() <= required_decimals {
+                return Err(Error {
+                    code: u64::from(TransactionErrorCodes::PreConditionCode),
+                    message: format!(
+                        "{} amount ({}) must exceed {} ledger fees ({}) for {}, or the deposit \
+                         would contribute nothing to the pool.",
+                        label,
+                        allowance.amount_decimals.get(),
+                        DEPOSIT_LEDGER_FEES_PER_TOKEN,
+                        required_decimals,
+                        allowance.asset.symbol(),
+                    ),
+                    kind: ErrorKind::Precondition {},
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drives the deposit sequence (approve both ledgers -> register/add the pool) from the step
+    /// last persisted in [`PendingDepositState`], rather than always restarting at the beginning.
+    /// This matters because a trap or upgrade between the two sub-steps (e.g. after both
+    /// approvals settled but before `add_pool` was called) would otherwise redo an `approve` whose
+    /// allowance was already fully consumed by KongSwap, or double-approve a spender that already
+    /// drew it down.
+    ///
+    /// Advances the persisted state once a sub-step has returned successfully, so a trap always
+    /// leaves the state pointing at the sub-step that still needs to run. A failure here leaves
+    /// the state short of [`PendingDepositState::Settled`]; [`Self::deposit_impl`] is responsible
+    /// for driving it to [`PendingDepositState::FailedRefunded`] once the stranded funds have
+    /// actually been returned to the external custodian.
     async fn deposit_into_dex(
         &mut self,
         allowance_0: ValidatedAllowance,
         allowance_1: ValidatedAllowance,
+        min_holdings: &BTreeMap<Asset, Nat>,
+        max_price_deviation_bps: Option<u16>,
+        swap_and_redeploy: bool,
     ) -> Result<(), Vec<Error>> {
         let operation = TreasuryManagerOperation::new(sns_treasury_manager::Operation::Deposit);
 
@@ -50,46 +139,93 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
             }
         }
 
-        // Step 1. Set up the allowances for the KongSwapBackend canister.
-        for ValidatedAllowance {
-            asset,
-            amount_decimals,
-            owner_account: _,
-        } in [&allowance_0, &allowance_1]
+        // Step 0.5. Cross-validate the declared allowance metadata against the live ledgers,
+        // rejecting the deposit outright rather than silently trusting caller-supplied values
+        // that could desync fee accounting and display.
         {
-            let human_readable = format!(
-                "Calling ICRC2 approve to set KongSwapBackend as spender for {}.",
-                asset.symbol()
-            );
-            let canister_id = asset.ledger_canister_id();
-            let fee_decimals = Nat::from(asset.ledger_fee_decimals());
-            let fee = Some(fee_decimals.clone());
-            let amount = Nat::from(amount_decimals.clone()) - fee_decimals;
+            let mut context = OperationContext::new(Operation::Deposit);
 
-            let request = ApproveArgs {
-                from_subaccount: None,
-                spender: Account {
-                    owner: *KONG_BACKEND_CANISTER_ID,
-                    subaccount: None,
-                },
+            self.validate_allowance_against_ledger(&mut context, &allowance_0)
+                .await
+                .map_err(|err| vec![err])?;
+            self.validate_allowance_against_ledger(&mut context, &allowance_1)
+                .await
+                .map_err(|err| vec![err])?;
+        }
 
-                // All approved tokens should be fully used up before the next deposit.
-                amount,
-                expected_allowance: Some(Nat::from(0u8)),
+        // Step 0.6. Abort before touching any funds if the existing position (if any) already
+        // quotes below the DAO-configured value-preservation floor -- see `value_guard`. This
+        // protects the deposit from adding to a pool that's already manipulated or broken.
+        {
+            let mut context = OperationContext::new(Operation::Deposit);
 
-                // TODO: Choose a more concervative expiration date.
-                expires_at: Some(u64::MAX),
-                memo: None,
-                created_at_time: None,
-                fee,
-            };
+            self.assert_value_preserved(&mut context).await?;
+        }
 
-            // Charge the approval fee.
-            self.charge_fee(asset);
+        // Step 0.65. Snapshot the pool reserves and operation_sequence now, at the start of the
+        // deposit, so they can be re-queried and compared immediately before the `add_pool` call
+        // below that commits to this deposit's plan -- see
+        // `assert_deposit_reserve_sequence_unchanged`. Only bother querying reserves if the
+        // drift check is actually enabled, the same way Step 4.5's price-deviation guard skips
+        // its own `pool_reserves` call when disabled.
+        let max_deposit_reserve_drift_bps = self.max_deposit_reserve_drift_bps();
+        let deposit_reserve_sequence_snapshot = if max_deposit_reserve_drift_bps > 0 {
+            let mut context = OperationContext::new(Operation::Deposit);
+            Some(self.pool_reserves(&mut context).await)
+        } else {
+            None
+        };
+        let deposit_snapshot_operation_sequence = self.operation_sequence();
 
-            self.emit_transaction(canister_id, request, operation, human_readable)
-                .await
-                .map_err(|err| vec![err])?;
+        // Step 1. Set up the allowances for the KongSwapBackend canister. Skipped on a resume that
+        // already got past this step (`PendingPoolAdd`), since both allowances were already
+        // consumed (or are being consumed) by KongSwap and re-approving would either double-spend
+        // the fee or race the in-flight `add_pool`/`add_liquidity` call.
+        if self.get_pending_deposit_state() != PendingDepositState::PendingPoolAdd {
+            self.set_pending_deposit_state(PendingDepositState::PendingApproval);
+
+            for ValidatedAllowance {
+                asset,
+                amount_decimals,
+                owner_account: _,
+            } in [&allowance_0, &allowance_1]
+            {
+                let human_readable = format!(
+                    "Calling ICRC2 approve to set KongSwapBackend as spender for {}.",
+                    asset.symbol()
+                );
+                let canister_id = asset.ledger_canister_id();
+                let fee_decimals = Nat::from(asset.ledger_fee_decimals());
+                let fee = Some(fee_decimals.clone());
+                let amount = Nat::from(amount_decimals.clone()) - fee_decimals;
+
+                let request = ApproveArgs {
+                    from_subaccount: None,
+                    spender: Account {
+                        owner: *KONG_BACKEND_CANISTER_ID,
+                        subaccount: None,
+                    },
+
+                    // All approved tokens should be fully used up before the next deposit.
+                    amount,
+                    expected_allowance: Some(Nat::from(0u8)),
+
+                    // TODO: Choose a more concervative expiration date.
+                    expires_at: Some(u64::MAX),
+                    memo: None,
+                    created_at_time: None,
+                    fee,
+                };
+
+                // Charge the approval fee.
+                self.charge_fee(asset).map_err(|err| vec![err])?;
+
+                self.emit_transaction(canister_id, request, operation, human_readable)
+                    .await
+                    .map_err(|err| vec![err])?;
+            }
+
+            self.set_pending_deposit_state(PendingDepositState::PendingPoolAdd);
         }
 
         let ledger_0 = allowance_0.asset.ledger_canister_id();
@@ -127,11 +263,84 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
             .map_err(|err| vec![err])?;
 
         // Step 4. Ensure the pool exists.
-        let token_0 = format!("IC.{}", ledger_0);
-        let token_1 = format!("IC.{}", ledger_1);
+        let dex_backend = KongSwapBackend::new(*KONG_BACKEND_CANISTER_ID);
+        let token_0 = dex_backend.token_name(ledger_0);
+        let token_1 = dex_backend.token_name(ledger_1);
 
         let balances_before = self.get_ledger_balances(operation).await?;
 
+        let (max_slippage_bps, lp_fee_bps) = self.pool_risk_params();
+
+        // What we're asking the DEX to accept, kept around so the amounts it reports back as
+        // actually accepted (`AddPoolReply::amount_{0,1}`) can be checked for slippage below --
+        // `add_pool` is free to only partially fill a brand-new pool at a different ratio than
+        // requested (e.g. if someone else created it first at a different price).
+        let requested_amount_0 = decode_nat_to_u64(amount_0.clone()).unwrap_or_default();
+        let requested_amount_1 = decode_nat_to_u64(amount_1.clone()).unwrap_or_default();
+
+        // Step 4.5. Abort before calling the DEX if this deposit's implied price deviates from an
+        // already-existing pool's current reserve ratio by more than the DAO-configured tolerance
+        // -- see `assert_deposit_price_within_reserve_tolerance`.
+        let (max_deposit_price_deviation_bps, min_deposit_lp_decimals) =
+            self.deposit_guard_params();
+        {
+            let mut context = OperationContext::new(Operation::Deposit);
+            self.assert_deposit_price_within_reserve_tolerance(
+                &mut context,
+                requested_amount_0,
+                requested_amount_1,
+            )
+            .await
+            .map_err(|errors| {
+                // Nothing was submitted to the DEX, so there's no in-flight transaction for
+                // `emit_transaction` to have recorded this error against -- record it directly
+                // instead, so the rejection isn't invisible to governance.
+                for error in &errors {
+                    self.record_rejected_operation(Operation::Deposit, error.message.clone());
+                }
+                errors
+            })?;
+        }
+
+        // Step 4.55. Abort before calling the DEX if this deposit disagrees with the DAO's own
+        // conversion-rate oracle by more than the configured tolerance -- see
+        // `assert_deposit_price_within_oracle_bounds`. Unlike Step 4.5 above, this doesn't trust
+        // the pool's own reserves at all, so it still catches a pool that's itself been
+        // manipulated to a bad price (or a near-empty one reporting a degenerate ratio).
+        self.assert_deposit_price_within_oracle_bounds(requested_amount_0, requested_amount_1)
+            .map_err(|errors| {
+                for error in &errors {
+                    self.record_rejected_operation(Operation::Deposit, error.message.clone());
+                }
+                errors
+            })?;
+
+        // Step 4.6. Abort before calling the DEX if the resulting holdings for any
+        // caller-specified floor asset (see `value_guard::assert_min_holdings`) are already
+        // below that floor -- protects the treasury from a mis-specified deposit that would
+        // move more value into the pool than intended.
+        self.assert_min_holdings(min_holdings)?;
+
+        // Step 4.65. Abort before calling the DEX if the pool reserves have drifted from the
+        // snapshot taken at the start of this deposit by more than tolerated, or if another
+        // operation has committed in the meantime -- see
+        // `assert_deposit_reserve_sequence_unchanged`.
+        {
+            let mut context = OperationContext::new(Operation::Deposit);
+            self.assert_deposit_reserve_sequence_unchanged(
+                &mut context,
+                deposit_reserve_sequence_snapshot.flatten(),
+                deposit_snapshot_operation_sequence,
+            )
+            .await
+            .map_err(|errors| {
+                for error in &errors {
+                    self.record_rejected_operation(Operation::Deposit, error.message.clone());
+                }
+                errors
+            })?;
+        }
+
         let result = self
             .emit_transaction(
                 *KONG_BACKEND_CANISTER_ID,
@@ -141,12 +350,19 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
                     token_1: token_1.clone(),
                     amount_1,
 
-                    // Liquidity provider fee in basis points 30=0.3%.
-                    lp_fee_bps: Some(30),
+                    // Liquidity provider fee in basis points, DAO-configurable via
+                    // `set_lp_fee_bps` (defaults to `DEFAULT_LP_FEE_BPS`).
+                    lp_fee_bps: Some(lp_fee_bps),
 
                     // Not needed for the ICRC2 flow.
                     tx_id_0: None,
                     tx_id_1: None,
+
+                    // Lets a later reconciliation pass tie the resulting transfers back to this
+                    // deposit, the same way `memo` already does for direct ledger transfers.
+                    memo: Some(Vec::<u8>::from(TreasuryManagerOperation::new(
+                        sns_treasury_manager::Operation::Deposit,
+                    ))),
                 },
                 TreasuryManagerOperation::new(sns_treasury_manager::Operation::Deposit),
                 "Calling KongSwapBackend.add_pool to add a new pool.".to_string(),
@@ -163,23 +379,68 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
         match result {
             // All used up, since the pool is brand new.
             Ok(add_pool_reply) => {
-                // Transferring the assets to DEX was successful.
-                // Charge the transfer fee.
+                // `add_pool` above already transferred both sides into the new pool -- that
+                // can't be undone from here. Record what it actually accepted via `move_asset`
+                // right away, before checking whether the accepted ratio or the minted LP amount
+                // are within tolerance: a failure in either check below can now only flag the
+                // state as corrupt (via `mark_state_corrupt`) rather than return an error that
+                // would leave the books unaware the transfer happened.
                 // TODO unwrapping
                 let amount_0 = decode_nat_to_u64(add_pool_reply.balance_0).unwrap();
                 let amount_1 = decode_nat_to_u64(add_pool_reply.balance_1).unwrap();
+                self.record_price_observation(self.time_ns(), amount_0, amount_1);
                 self.move_asset(
                     &allowance_0.asset,
                     amount_0,
                     Party::TreasuryManager,
                     Party::External,
-                );
+                )
+                .map_err(|err| vec![err])?;
                 self.move_asset(
                     &allowance_1.asset,
                     amount_1,
                     Party::TreasuryManager,
                     Party::External,
-                );
+                )
+                .map_err(|err| vec![err])?;
+
+                // Abort rather than silently over-contributing one side if the pool only
+                // accepted `add_pool`'s amounts at a ratio that deviates from what we requested
+                // by more than the configured tolerance -- see `requested_amount_{0,1}` above.
+                let accepted_amount_0 =
+                    decode_nat_to_u64(add_pool_reply.amount_0.clone()).unwrap_or_default();
+                let accepted_amount_1 =
+                    decode_nat_to_u64(add_pool_reply.amount_1.clone()).unwrap_or_default();
+                if let Err(err) = check_slippage_bps(
+                    requested_amount_0,
+                    accepted_amount_0,
+                    max_slippage_bps,
+                    &format!("add_pool amount_0 ({})", allowance_0.asset.symbol()),
+                ) {
+                    self.mark_state_corrupt(&err.message);
+                }
+                if let Err(err) = check_slippage_bps(
+                    requested_amount_1,
+                    accepted_amount_1,
+                    max_slippage_bps,
+                    &format!("add_pool amount_1 ({})", allowance_1.asset.symbol()),
+                ) {
+                    self.mark_state_corrupt(&err.message);
+                }
+
+                // Abort if the pool minted fewer LP tokens than the DAO-configured floor --
+                // `min_deposit_lp_decimals` of 0 (the default) disables this check.
+                if min_deposit_lp_decimals > 0 {
+                    let minted_lp_decimals =
+                        decode_nat_to_u64(add_pool_reply.add_lp_token_amount.clone())
+                            .unwrap_or_default();
+                    if minted_lp_decimals < min_deposit_lp_decimals {
+                        self.mark_state_corrupt(&format!(
+                            "add_pool minted {} LP tokens, below the configured minimum of {}.",
+                            minted_lp_decimals, min_deposit_lp_decimals,
+                        ));
+                    }
+                }
 
                 let balances_after = self.get_ledger_balances(operation).await?;
                 self.find_discrepency(
@@ -188,14 +449,18 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
                     balances_after.0,
                     amount_0,
                     true,
-                );
+                )
+                .map_err(|err| vec![err])?;
                 self.find_discrepency(
                     &allowance_1.asset,
                     balances_before.1,
                     balances_after.1,
                     amount_1,
                     true,
-                );
+                )
+                .map_err(|err| vec![err])?;
+
+                self.settle_deposit(&allowance_0, amount_0, &allowance_1, amount_1);
 
                 return Ok(());
             }
@@ -233,6 +498,73 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
             .map_err(|err| vec![err])?
         };
 
+        let expected_amount_1 = decode_nat_to_u64(amount_1.clone()).unwrap_or_default();
+
+        // Step 4.5b. Re-run Step 4.5's price-deviation guard, but against reserves queried right
+        // before this top-up's `add_liquidity` call. Step 4.5's snapshot was taken before the
+        // `add_pool` attempt above, whose round trip (tolerated here as "pool already exists")
+        // gives the pool's price a window to have moved adversarially in the meantime.
+        if max_deposit_price_deviation_bps > 0 {
+            let mut context = OperationContext::new(Operation::Deposit);
+            if let Some((reserve_0, reserve_1)) = self.pool_reserves(&mut context).await {
+                let reserve_0 = decode_nat_to_u64(reserve_0).unwrap_or_default();
+                let reserve_1 = decode_nat_to_u64(reserve_1).unwrap_or_default();
+
+                if reserve_0 > 0 && reserve_1 > 0 {
+                    check_deposit_price_deviation_bps(
+                        reserve_0,
+                        reserve_1,
+                        requested_amount_0,
+                        expected_amount_1,
+                        max_deposit_price_deviation_bps,
+                        &format!("add_liquidity top-up for pool {}", self.lp_token()),
+                    )
+                    .map_err(|err| {
+                        // Nothing has been submitted to `add_liquidity` yet, so there's no
+                        // in-flight transaction for `emit_transaction` to have recorded this
+                        // error against -- record it directly, as Step 4.5 does.
+                        self.record_rejected_operation(Operation::Deposit, err.message.clone());
+                        vec![err]
+                    })?;
+                }
+            }
+        }
+
+        // Step 4.55b. Oracle counterpart of Step 4.5b, for the same reason Step 4.55 runs
+        // alongside Step 4.5.
+        self.assert_deposit_price_within_oracle_bounds(requested_amount_0, expected_amount_1)
+            .map_err(|errors| {
+                for error in &errors {
+                    self.record_rejected_operation(Operation::Deposit, error.message.clone());
+                }
+                errors
+            })?;
+
+        // Step 4.56b. Abort before calling the DEX if this top-up's realized split disagrees with
+        // the ratio the caller's own allowances imply -- see `check_deposit_intended_ratio_bps`.
+        // Unlike Steps 4.5b/4.55b above (which compare against the pool's reserves and the DAO's
+        // oracle), this protects the immediate caller: even a split both of those would tolerate
+        // can still leave this specific deposit contributing at a worse price than it asked for.
+        if let Some(max_price_deviation_bps) = max_price_deviation_bps {
+            if max_price_deviation_bps > 0 {
+                check_deposit_intended_ratio_bps(
+                    u64::from(allowance_0.amount_decimals),
+                    u64::from(allowance_1.amount_decimals),
+                    requested_amount_0,
+                    expected_amount_1,
+                    max_price_deviation_bps,
+                    &format!("add_liquidity top-up for pool {}", self.lp_token()),
+                )
+                .map_err(|err| {
+                    // Nothing has been submitted to `add_liquidity` yet, so there's no in-flight
+                    // transaction for `emit_transaction` to have recorded this error against --
+                    // record it directly, as Step 4.5b does.
+                    self.record_rejected_operation(Operation::Deposit, err.message.clone());
+                    vec![err]
+                })?;
+            }
+        }
+
         let reply = {
             let human_readable = format!(
                 "Calling KongSwapBackend.add_liquidity to top up liquidity for \
@@ -249,6 +581,10 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
                 // Not needed for the ICRC2 flow.
                 tx_id_0: None,
                 tx_id_1: None,
+
+                // Lets a later reconciliation pass tie the resulting transfers back to this
+                // deposit, the same way `memo` already does for direct ledger transfers.
+                memo: Some(Vec::<u8>::from(operation)),
             };
 
             self.emit_transaction(
@@ -263,21 +599,56 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
 
         // Topping-up the DEX with asset_0 and asset_1 was successful.
         // Charge the transfer fee.
-        let AddLiquidityReply { amount_1, .. } = reply;
+        let AddLiquidityReply {
+            amount_1,
+            add_lp_token_amount,
+            ..
+        } = reply;
         let amount_0 = decode_nat_to_u64(amount_0).unwrap();
         let amount_1 = decode_nat_to_u64(amount_1).unwrap();
+
+        // `add_liquidity` above already topped up the pool -- that can't be undone from here.
+        // Record what it actually accepted via `move_asset` right away, before checking the
+        // minted LP amount or realized slippage: a failure in either check below can now only
+        // flag the state as corrupt (via `mark_state_corrupt`) rather than return an error that
+        // would leave the books unaware the top-up happened.
+        self.record_price_observation(self.time_ns(), amount_0, amount_1);
+
         self.move_asset(
             &allowance_0.asset,
             amount_0,
             Party::TreasuryManager,
             Party::External,
-        );
+        )
+        .map_err(|err| vec![err])?;
         self.move_asset(
             &allowance_1.asset,
             amount_1,
             Party::TreasuryManager,
             Party::External,
-        );
+        )
+        .map_err(|err| vec![err])?;
+
+        // Abort if the top-up minted fewer LP tokens than the DAO-configured floor --
+        // `min_deposit_lp_decimals` of 0 (the default) disables this check.
+        if min_deposit_lp_decimals > 0 {
+            let minted_lp_decimals = decode_nat_to_u64(add_lp_token_amount).unwrap_or_default();
+            if minted_lp_decimals < min_deposit_lp_decimals {
+                self.mark_state_corrupt(&format!(
+                    "add_liquidity minted {} LP tokens, below the configured minimum of {}.",
+                    minted_lp_decimals, min_deposit_lp_decimals,
+                ));
+            }
+        }
+
+        if let Err(err) = check_slippage_bps(
+            expected_amount_1,
+            amount_1,
+            max_slippage_bps,
+            &format!("add_liquidity amount_1 ({})", allowance_1.asset.symbol()),
+        ) {
+            self.mark_state_corrupt(&err.message);
+        }
 
         let balances_after = self.get_ledger_balances(operation).await?;
         self.find_discrepency(
@@ -286,15 +657,339 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
             balances_after.0,
             amount_0,
             true,
-        );
+        )
+        .map_err(|err| vec![err])?;
         self.find_discrepency(
             &allowance_1.asset,
             balances_before.1,
             balances_after.1,
             amount_1,
             true,
+        )
+        .map_err(|err| vec![err])?;
+
+        self.settle_deposit(&allowance_0, amount_0, &allowance_1, amount_1);
+
+        // Step 5b. If enabled, fold this top-up's unproportional remainder -- the part of
+        // `allowance_1` the pool's ratio didn't call for, known since Step 4's
+        // `add_liquidity_amounts` preview -- into additional deployed liquidity instead of
+        // leaving it for `return_remaining_assets_to_owner`'s end-of-deposit refund sweep. The
+        // deposit above has already settled, so a failure here is logged and swallowed rather
+        // than propagated: it just means the remainder falls through to that refund instead, the
+        // same as if `swap_and_redeploy` had been disabled.
+        if swap_and_redeploy {
+            let excess_amount_1 = requested_amount_1.saturating_sub(expected_amount_1);
+            if excess_amount_1 > 0 {
+                if let Err(errors) = self
+                    .redeploy_remainder(&allowance_0, &allowance_1, excess_amount_1)
+                    .await
+                {
+                    for error in &errors {
+                        log_err(&format!(
+                            "Swap-and-redeploy of the top-up's remainder failed, falling back \
+                             to a refund: {}",
+                            error.message
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Converts `excess_amount_1` of `allowance_1`'s asset -- the part of a just-settled top-up's
+    /// remainder that [`Self::deposit_into_dex`]'s `swap_and_redeploy` mode asked to be deployed
+    /// rather than refunded -- into additional liquidity: swaps
+    /// [`single_sided_swap_in_amount`]'s share of it for `allowance_0`'s asset via [`Self::swap`],
+    /// then contributes the swapped-out amount and the untouched residual as a second, smaller
+    /// `add_liquidity` call. Queries the pool's reserves fresh (rather than reusing whatever was
+    /// read earlier in `deposit_into_dex`), since the just-settled top-up above moved them.
+    async fn redeploy_remainder(
+        &mut self,
+        allowance_0: &ValidatedAllowance,
+        allowance_1: &ValidatedAllowance,
+        excess_amount_1: u64,
+    ) -> Result<(), Vec<Error>> {
+        let mut context = OperationContext::new(Operation::Deposit);
+
+        let Some((reserve_0, reserve_1)) = self.pool_reserves(&mut context).await else {
+            return Err(vec![Error::new_postcondition(
+                "Could not look up the pool's reserves to redeploy the top-up's remainder."
+                    .to_string(),
+            )]);
+        };
+        let reserve_0 = decode_nat_to_u64(reserve_0).unwrap_or_default();
+        let reserve_1 = decode_nat_to_u64(reserve_1).unwrap_or_default();
+        if reserve_0 == 0 || reserve_1 == 0 {
+            return Ok(());
+        }
+
+        let (max_slippage_bps, lp_fee_bps) = self.pool_risk_params();
+
+        let Some(swap_in_amount) =
+            single_sided_swap_in_amount(reserve_1, excess_amount_1, lp_fee_bps)
+        else {
+            return Ok(());
+        };
+        if swap_in_amount == 0 || swap_in_amount >= excess_amount_1 {
+            return Ok(());
+        }
+
+        // The swap's spot-price-implied output, before the DAO-configured slippage tolerance is
+        // subtracted to get a safe floor -- the same derivation `rebalance_to_target_ratio` uses
+        // for its own `min_amount_out`, since `Self::swap`'s own preview is what actually
+        // protects the trade.
+        let spot_amount_out =
+            (swap_in_amount as u128 * reserve_0 as u128 / reserve_1.max(1) as u128) as u64;
+        let slippage_allowance =
+            spot_amount_out * u64::from(max_slippage_bps) / u64::from(BPS_DENOMINATOR);
+        let min_amount_out = spot_amount_out.saturating_sub(slippage_allowance);
+
+        let swapped_amount_0 = self
+            .swap(
+                &mut context,
+                allowance_1.asset,
+                allowance_0.asset,
+                swap_in_amount,
+                min_amount_out,
+            )
+            .await?;
+
+        let residual_amount_1 = excess_amount_1 - swap_in_amount;
+
+        let dex_backend = KongSwapBackend::new(*KONG_BACKEND_CANISTER_ID);
+        let token_0 = dex_backend.token_name(allowance_0.asset.ledger_canister_id());
+        let token_1 = dex_backend.token_name(allowance_1.asset.ledger_canister_id());
+
+        let human_readable = format!(
+            "Calling KongSwapBackend.add_liquidity to redeploy a top-up's unproportional \
+             remainder: token_0 = {}, amount_0 = {}, token_1 = {}, amount_1 = {}.",
+            token_0, swapped_amount_0, token_1, residual_amount_1,
         );
 
+        let request = AddLiquidityArgs {
+            token_0,
+            amount_0: Nat::from(swapped_amount_0),
+            token_1,
+            amount_1: Nat::from(residual_amount_1),
+
+            // Not needed for the ICRC2 flow.
+            tx_id_0: None,
+            tx_id_1: None,
+
+            // Lets a later reconciliation pass tie the resulting transfers back to this deposit,
+            // the same way `memo` already does for direct ledger transfers.
+            memo: Some(Vec::<u8>::from(TreasuryManagerOperation::new(
+                sns_treasury_manager::Operation::Deposit,
+            ))),
+        };
+
+        // Reconcile against the ledgers' own view of this add_liquidity, the same way every
+        // sibling call site does (e.g. `deposit_into_dex`'s own `add_liquidity` above) -- without
+        // this, a settlement that silently diverges from what KongSwap was asked to accept would
+        // drift the balance book from on-chain reality undetected.
+        let operation = TreasuryManagerOperation::new(sns_treasury_manager::Operation::Deposit);
+        let balances_before = self.get_ledger_balances(operation).await?;
+
+        let AddLiquidityReply {
+            amount_1: settled_amount_1,
+            ..
+        } = self
+            .emit_transaction(
+                &mut context,
+                *KONG_BACKEND_CANISTER_ID,
+                request,
+                human_readable,
+            )
+            .await
+            .map_err(|err| vec![err])?;
+        let settled_amount_1 = decode_nat_to_u64(settled_amount_1).unwrap_or(residual_amount_1);
+
+        // `add_liquidity` above already redeployed the remainder -- that can't be undone from
+        // here. Record what it actually accepted via `move_asset` right away, before checking
+        // realized slippage: a failure there can now only flag the state as corrupt (via
+        // `mark_state_corrupt`) rather than return an error that would leave the books unaware
+        // the redeploy happened.
+        self.record_price_observation(self.time_ns(), swapped_amount_0, settled_amount_1);
+
+        self.move_asset(
+            allowance_0.asset,
+            swapped_amount_0,
+            Party::TreasuryManager,
+            Party::External,
+        )
+        .map_err(|err| vec![err])?;
+        self.move_asset(
+            allowance_1.asset,
+            settled_amount_1,
+            Party::TreasuryManager,
+            Party::External,
+        )
+        .map_err(|err| vec![err])?;
+
+        if let Err(err) = check_slippage_bps(
+            residual_amount_1,
+            settled_amount_1,
+            max_slippage_bps,
+            &format!(
+                "redeploy add_liquidity amount_1 ({})",
+                allowance_1.asset.symbol()
+            ),
+        ) {
+            self.mark_state_corrupt(&err.message);
+        }
+
+        let balances_after = self.get_ledger_balances(operation).await?;
+        self.find_discrepency(
+            &allowance_0.asset,
+            balances_before.0,
+            balances_after.0,
+            swapped_amount_0,
+            true,
+        )
+        .map_err(|err| vec![err])?;
+        self.find_discrepency(
+            &allowance_1.asset,
+            balances_before.1,
+            balances_after.1,
+            settled_amount_1,
+            true,
+        )
+        .map_err(|err| vec![err])?;
+
+        Ok(())
+    }
+
+    /// Advances [`PendingDepositState`] to [`PendingDepositState::Settled`] and fires
+    /// [`StatusNotificationHook::on_settlement`](kongswap_adaptor::audit::StatusNotificationHook::on_settlement)
+    /// for both assets, once [`Self::deposit_into_dex`] has durably moved `amount_{0,1}` into the
+    /// pool.
+    fn settle_deposit(
+        &self,
+        allowance_0: &ValidatedAllowance,
+        amount_0: u64,
+        allowance_1: &ValidatedAllowance,
+        amount_1: u64,
+    ) {
+        self.set_pending_deposit_state(PendingDepositState::Settled);
+
+        let audit_trail_index = self.audit_trail_len().saturating_sub(1);
+
+        self.notify_settlement(
+            allowance_0.asset,
+            amount_0,
+            audit_trail_index,
+            SettlementOutcome::Settled,
+        );
+        self.notify_settlement(
+            allowance_1.asset,
+            amount_1,
+            audit_trail_index,
+            SettlementOutcome::Settled,
+        );
+    }
+
+    /// Advances [`PendingDepositState`] to [`PendingDepositState::FailedRefunded`] and fires
+    /// [`StatusNotificationHook::on_settlement`](kongswap_adaptor::audit::StatusNotificationHook::on_settlement)
+    /// for both assets, once a failed [`Self::deposit_into_dex`] call has had whatever reached the
+    /// manager's subaccount returned to the external custodian. A no-op if the deposit already
+    /// reached a terminal state (`Settled`, or an earlier `FailedRefunded`), so a retried deposit
+    /// on top of an already-refunded one doesn't notify twice.
+    fn fail_deposit_refunded(
+        &self,
+        allowance_0: &ValidatedAllowance,
+        allowance_1: &ValidatedAllowance,
+    ) {
+        if !matches!(
+            self.get_pending_deposit_state(),
+            PendingDepositState::PendingApproval | PendingDepositState::PendingPoolAdd
+        ) {
+            return;
+        }
+
+        self.set_pending_deposit_state(PendingDepositState::FailedRefunded);
+
+        let audit_trail_index = self.audit_trail_len().saturating_sub(1);
+
+        self.notify_settlement(
+            allowance_0.asset,
+            allowance_0.amount_decimals.into(),
+            audit_trail_index,
+            SettlementOutcome::FailedRefunded,
+        );
+        self.notify_settlement(
+            allowance_1.asset,
+            allowance_1.amount_decimals.into(),
+            audit_trail_index,
+            SettlementOutcome::FailedRefunded,
+        );
+    }
+
+    /// Zeroes out the ICRC2 approval granted to KongSwapBackend for both assets in Step 1, once a
+    /// deposit has failed, so a stale approval left over from this deposit can't later be drawn on
+    /// by the backend for something unrelated. A no-op once the deposit has moved past
+    /// `PendingApproval`/`PendingPoolAdd` (nothing was approved yet, or the pool call already
+    /// consumed the approval), mirroring the same state check [`Self::fail_deposit_refunded`] uses.
+    async fn revoke_deposit_approvals(
+        &mut self,
+        allowance_0: &ValidatedAllowance,
+        allowance_1: &ValidatedAllowance,
+    ) -> Result<(), Vec<Error>> {
+        if !matches!(
+            self.get_pending_deposit_state(),
+            PendingDepositState::PendingApproval | PendingDepositState::PendingPoolAdd
+        ) {
+            return Ok(());
+        }
+
+        let operation = TreasuryManagerOperation::new(sns_treasury_manager::Operation::Deposit);
+
+        for ValidatedAllowance {
+            asset,
+            amount_decimals,
+            ..
+        } in [allowance_0, allowance_1]
+        {
+            let canister_id = asset.ledger_canister_id();
+            let human_readable = format!(
+                "Calling ICRC2 approve to revoke KongSwapBackend's allowance for {} after a \
+                 failed deposit.",
+                asset.symbol()
+            );
+
+            // The exact amount Step 1 approved, net of its own fee -- the same computation Step 1
+            // itself used -- passed as `expected_allowance` so this compensating revoke only
+            // commits if nothing has drawn on the allowance in between, the same "nothing moved
+            // underneath us" guarantee Step 1's own `expected_allowance: Some(0)` gives its approve.
+            let approved_amount =
+                Nat::from(amount_decimals.clone()) - Nat::from(asset.ledger_fee_decimals());
+
+            let request = ApproveArgs {
+                from_subaccount: None,
+                spender: Account {
+                    owner: *KONG_BACKEND_CANISTER_ID,
+                    subaccount: None,
+                },
+                amount: Nat::from(0u8),
+                expected_allowance: Some(approved_amount),
+                expires_at: None,
+                memo: None,
+                created_at_time: None,
+                fee: Some(Nat::from(asset.ledger_fee_decimals())),
+            };
+
+            // This compensating revoke is itself a ledger call that incurs its own fee, debited
+            // from this adaptor's `treasury_manager` balance -- charge it the same way Step 1's
+            // approve does, so the balance book stays consistent with what actually left the
+            // ledger instead of drifting by one more fee than the books record.
+            self.charge_fee(asset).map_err(|err| vec![err])?;
+
+            self.emit_transaction(canister_id, request, operation, human_readable)
+                .await
+                .map_err(|err| vec![err])?;
+        }
+
         Ok(())
     }
 
@@ -303,12 +998,36 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
         &mut self,
         allowance_0: ValidatedAllowance,
         allowance_1: ValidatedAllowance,
+        min_holdings: &BTreeMap<Asset, Nat>,
+        max_price_deviation_bps: Option<u16>,
+        swap_and_redeploy: bool,
     ) -> Result<ValidatedBalances, Vec<Error>> {
         {
-            self.add_manager_balance(&allowance_0.asset, allowance_0.amount_decimals);
-            self.add_manager_balance(&allowance_1.asset, allowance_1.amount_decimals);
+            self.add_manager_balance(&allowance_0.asset, allowance_0.amount_decimals.into())
+                .map_err(|err| vec![err])?;
+            self.add_manager_balance(&allowance_1.asset, allowance_1.amount_decimals.into())
+                .map_err(|err| vec![err])?;
         }
-        let deposit_into_dex_result = self.deposit_into_dex(allowance_0, allowance_1).await;
+        let deposit_into_dex_result = self
+            .deposit_into_dex(
+                allowance_0,
+                allowance_1,
+                min_holdings,
+                max_price_deviation_bps,
+                swap_and_redeploy,
+            )
+            .await;
+
+        // Close the approval window opened by Step 1 before anything is transferred back, so a
+        // stranded approval can't be raced by a caller trying to draw on it between now and the
+        // refund below. Folded into `deposit_into_dex_result` below rather than surfaced
+        // separately, since it's secondary to reporting why the deposit itself failed.
+        let revoke_approvals_result = if deposit_into_dex_result.is_err() {
+            self.revoke_deposit_approvals(&allowance_0, &allowance_1)
+                .await
+        } else {
+            Ok(())
+        };
 
         let returned_amounts_result = self
             .return_remaining_assets_to_owner(
@@ -318,8 +1037,27 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
             )
             .await;
 
+        // A deposit that failed partway through (state is still `PendingApproval` or
+        // `PendingPoolAdd`, i.e. it never reached `Settled` above) has just had whatever reached
+        // the manager's subaccount returned to the external custodian by the call above. Record
+        // that outcome and notify once -- not on every retry of an already-`FailedRefunded`
+        // deposit.
+        if deposit_into_dex_result.is_err() && returned_amounts_result.is_ok() {
+            self.fail_deposit_refunded(&allowance_0, &allowance_1);
+        }
+
         self.refresh_balances().await;
 
+        let deposit_into_dex_result = match (deposit_into_dex_result, revoke_approvals_result) {
+            (Ok(ok), Ok(())) => Ok(ok),
+            (Ok(_), Err(errs)) => Err(errs),
+            (Err(errs), Ok(())) => Err(errs),
+            (Err(mut errs), Err(revoke_errs)) => {
+                errs.extend(revoke_errs);
+                Err(errs)
+            }
+        };
+
         match (deposit_into_dex_result, returned_amounts_result) {
             (Ok(_), Ok(_)) => Ok(self.get_cached_balances()),
             (Ok(_), Err(errs)) => Err(errs),
@@ -332,3 +1070,6 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
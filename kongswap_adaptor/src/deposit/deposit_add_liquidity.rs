@@ -1,12 +1,16 @@
 use super::*;
 use crate::kong_types::{AddLiquidityReply, AddTokenArgs, AddTokenReply, ICReply};
 use crate::{
-    state::storage::ConfigState, validation::ValidatedTreasuryManagerInit, StableAuditTrail,
-    StableBalances, AUDIT_TRAIL_MEMORY_ID, BALANCES_MEMORY_ID,
+    state::storage::{ConfigState, StableWithdrawState},
+    validation::ValidatedTreasuryManagerInit,
+    StableAuditTrail, StableBalances, StablePriceHistory, StableWithdrawStateCell,
+    AUDIT_TRAIL_MEMORY_ID, BALANCES_MEMORY_ID, PRICE_HISTORY_MEMORY_ID, WITHDRAW_STATE_MEMORY_ID,
 };
 use candid::Principal;
 use ic_stable_structures::memory_manager::MemoryManager;
-use ic_stable_structures::{Cell as StableCell, DefaultMemoryImpl, Vec as StableVec};
+use ic_stable_structures::{
+    BTreeMap as StableBTreeMap, Cell as StableCell, DefaultMemoryImpl, Vec as StableVec,
+};
 use icrc_ledger_types::icrc1::transfer::{Memo, TransferArg};
 use kongswap_adaptor::agent::mock_agent::MockAgent;
 use maplit::btreemap;
@@ -105,6 +109,9 @@ fn make_add_pool_request(
         amount_1: Nat::from(amount_1),
         tx_id_1: None,
         lp_fee_bps: Some(30),
+        memo: Some(Vec::<u8>::from(TreasuryManagerOperation::new(
+            sns_treasury_manager::Operation::Deposit,
+        ))),
     }
 }
 
@@ -155,6 +162,9 @@ fn make_add_liquidity_request(
         token_1: token_1.to_string(),
         amount_1: Nat::from(amount_1),
         tx_id_1: None,
+        memo: Some(Vec::<u8>::from(TreasuryManagerOperation::new(
+            sns_treasury_manager::Operation::Deposit,
+        ))),
     }
 }
 
@@ -310,6 +320,26 @@ async fn run_add_liquidity_test(
                     .expect("AUDIT_TRAIL init should not cause errors")
                 )
             );
+
+        static WITHDRAW_STATE: RefCell<StableWithdrawStateCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(WITHDRAW_STATE_MEMORY_ID),
+                        StableWithdrawState::default()
+                    )
+                    .expect("WITHDRAW_STATE init should not cause errors")
+                )
+            );
+
+        static PRICE_HISTORY: RefCell<StablePriceHistory> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(PRICE_HISTORY_MEMORY_ID)
+                    )
+                )
+            );
     }
 
     let allowances = vec![
@@ -453,6 +483,8 @@ async fn run_add_liquidity_test(
         *SELF_CANISTER_ID,
         &BALANCES,
         &AUDIT_TRAIL,
+        &WITHDRAW_STATE,
+        &PRICE_HISTORY,
     );
 
     let init = TreasuryManagerInit {
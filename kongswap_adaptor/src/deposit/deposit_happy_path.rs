@@ -1,18 +1,22 @@
 use super::*;
 use crate::kong_types::{AddPoolReply, AddTokenArgs, AddTokenReply, ICReply};
 use crate::{
-    state::storage::ConfigState, validation::ValidatedTreasuryManagerInit, StableAuditTrail,
-    StableBalances, AUDIT_TRAIL_MEMORY_ID, BALANCES_MEMORY_ID,
+    state::storage::{ConfigState, StableWithdrawState},
+    validation::ValidatedTreasuryManagerInit,
+    StableAuditTrail, StableBalances, StablePriceHistory, StableWithdrawStateCell,
+    AUDIT_TRAIL_MEMORY_ID, BALANCES_MEMORY_ID, PRICE_HISTORY_MEMORY_ID, WITHDRAW_STATE_MEMORY_ID,
 };
 use candid::Principal;
 use ic_stable_structures::memory_manager::MemoryManager;
-use ic_stable_structures::{Cell as StableCell, DefaultMemoryImpl, Vec as StableVec};
+use ic_stable_structures::{
+    BTreeMap as StableBTreeMap, Cell as StableCell, DefaultMemoryImpl, Vec as StableVec,
+};
 use kongswap_adaptor::agent::mock_agent::MockAgent;
 use maplit::btreemap;
 use pretty_assertions::assert_eq;
 use sns_treasury_manager::{
     Allowance, Asset, Balance, BalanceBook, Balances, DepositRequest, TreasuryManager,
-    TreasuryManagerInit,
+    TreasuryManagerInit, TreasuryManagerOperation,
 };
 use std::cell::RefCell;
 
@@ -90,6 +94,7 @@ fn make_add_pool_request(
         amount_1: Nat::from(amount_1),
         tx_id_1: None,
         lp_fee_bps: Some(30),
+        memo: Some(Vec::<u8>::from(TreasuryManagerOperation::Deposit)),
     }
 }
 
@@ -144,6 +149,26 @@ async fn test_deposit_success() {
                     .expect("AUDIT_TRAIL init should not cause errors")
                 )
             );
+
+        static WITHDRAW_STATE: RefCell<StableWithdrawStateCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(WITHDRAW_STATE_MEMORY_ID),
+                        StableWithdrawState::default()
+                    )
+                    .expect("WITHDRAW_STATE init should not cause errors")
+                )
+            );
+
+        static PRICE_HISTORY: RefCell<StablePriceHistory> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(PRICE_HISTORY_MEMORY_ID)
+                    )
+                )
+            );
     }
 
     let amount_0_decimals = 500 * E8;
@@ -233,6 +258,8 @@ async fn test_deposit_success() {
         *SELF_CANISTER_ID,
         &BALANCES,
         &AUDIT_TRAIL,
+        &WITHDRAW_STATE,
+        &PRICE_HISTORY,
     );
 
     let init = TreasuryManagerInit {
@@ -1,12 +1,16 @@
 use crate::state::KongSwapAdaptor;
 use crate::{
-    state::storage::ConfigState, validation::ValidatedTreasuryManagerInit, StableAuditTrail,
-    StableBalances, AUDIT_TRAIL_MEMORY_ID, BALANCES_MEMORY_ID,
+    state::storage::{ConfigState, StableWithdrawState},
+    validation::ValidatedTreasuryManagerInit,
+    StableAuditTrail, StableBalances, StablePriceHistory, StableWithdrawStateCell,
+    AUDIT_TRAIL_MEMORY_ID, BALANCES_MEMORY_ID, PRICE_HISTORY_MEMORY_ID, WITHDRAW_STATE_MEMORY_ID,
 };
 use crate::{test_helpers::*, KONG_BACKEND_CANISTER_ID};
 use candid::Nat;
 use ic_stable_structures::memory_manager::MemoryManager;
-use ic_stable_structures::{Cell as StableCell, DefaultMemoryImpl, Vec as StableVec};
+use ic_stable_structures::{
+    BTreeMap as StableBTreeMap, Cell as StableCell, DefaultMemoryImpl, Vec as StableVec,
+};
 use icrc_ledger_types::icrc1::account::Account;
 use kongswap_adaptor::agent::mock_agent::MockAgent;
 use maplit::btreemap;
@@ -46,6 +50,26 @@ async fn test_deposit_success() {
                     .expect("AUDIT_TRAIL init should not cause errors")
                 )
             );
+
+        static WITHDRAW_STATE: RefCell<StableWithdrawStateCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(WITHDRAW_STATE_MEMORY_ID),
+                        StableWithdrawState::default()
+                    )
+                    .expect("WITHDRAW_STATE init should not cause errors")
+                )
+            );
+
+        static PRICE_HISTORY: RefCell<StablePriceHistory> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(PRICE_HISTORY_MEMORY_ID)
+                    )
+                )
+            );
     }
 
     let allowances = vec![
@@ -175,6 +199,8 @@ async fn test_deposit_success() {
         *SELF_CANISTER_ID,
         &BALANCES,
         &AUDIT_TRAIL,
+        &WITHDRAW_STATE,
+        &PRICE_HISTORY,
     );
 
     let init = TreasuryManagerInit {
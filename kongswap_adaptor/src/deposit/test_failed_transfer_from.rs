@@ -2,12 +2,16 @@ use super::*;
 use crate::kong_types::{AddTokenArgs, AddTokenReply, ICReply};
 use crate::tx_error_codes::TransactionErrorCodes;
 use crate::{
-    state::storage::ConfigState, validation::ValidatedTreasuryManagerInit, StableAuditTrail,
-    StableBalances, AUDIT_TRAIL_MEMORY_ID, BALANCES_MEMORY_ID,
+    state::storage::{ConfigState, StableWithdrawState},
+    validation::ValidatedTreasuryManagerInit,
+    StableAuditTrail, StableBalances, StablePriceHistory, StableWithdrawStateCell,
+    AUDIT_TRAIL_MEMORY_ID, BALANCES_MEMORY_ID, PRICE_HISTORY_MEMORY_ID, WITHDRAW_STATE_MEMORY_ID,
 };
 use candid::Principal;
 use ic_stable_structures::memory_manager::MemoryManager;
-use ic_stable_structures::{Cell as StableCell, DefaultMemoryImpl, Vec as StableVec};
+use ic_stable_structures::{
+    BTreeMap as StableBTreeMap, Cell as StableCell, DefaultMemoryImpl, Vec as StableVec,
+};
 use icrc_ledger_types::icrc1::transfer::{Memo, TransferArg};
 use kongswap_adaptor::agent::mock_agent::MockAgent;
 use maplit::btreemap;
@@ -58,6 +62,24 @@ fn make_approve_request(amount: u64, fee: u64) -> ApproveArgs {
     }
 }
 
+/// The ICRC2 approve a failed deposit issues to zero out the allowance it granted KongSwapBackend
+/// in Step 1 -- see `KongSwapAdaptor::revoke_deposit_approvals`.
+fn make_revoke_approve_request(amount: u64, fee: u64) -> ApproveArgs {
+    ApproveArgs {
+        from_subaccount: None,
+        spender: Account {
+            owner: *KONG_BACKEND_CANISTER_ID,
+            subaccount: None,
+        },
+        amount: Nat::from(0u8),
+        expected_allowance: Some(Nat::from(amount - fee)),
+        expires_at: None,
+        memo: None,
+        created_at_time: None,
+        fee: Some(fee.into()),
+    }
+}
+
 fn make_balance_request() -> Account {
     Account {
         owner: *SELF_CANISTER_ID,
@@ -106,6 +128,7 @@ fn make_add_pool_request(
         amount_1: Nat::from(amount_1),
         tx_id_1: None,
         lp_fee_bps: Some(30),
+        memo: Some(Vec::<u8>::from(TreasuryManagerOperation::Deposit)),
     }
 }
 
@@ -236,6 +259,26 @@ async fn run_failed_transfer_from_test(
                     .expect("AUDIT_TRAIL init should not cause errors")
                 )
             );
+
+        static WITHDRAW_STATE: RefCell<StableWithdrawStateCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(WITHDRAW_STATE_MEMORY_ID),
+                        StableWithdrawState::default()
+                    )
+                    .expect("WITHDRAW_STATE init should not cause errors")
+                )
+            );
+
+        static PRICE_HISTORY: RefCell<StablePriceHistory> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(PRICE_HISTORY_MEMORY_ID)
+                    )
+                )
+            );
     }
 
     let allowances = vec![
@@ -325,6 +368,16 @@ async fn run_failed_transfer_from_test(
             ),
             Err(error_message.clone()),
         )
+        .add_call(
+            sns_ledger,
+            make_revoke_approve_request(amount_0_decimals, FEE_SNS),
+            Ok(Nat::from(0u8)),
+        )
+        .add_call(
+            icp_ledger,
+            make_revoke_approve_request(amount_1_decimals, FEE_ICP),
+            Ok(Nat::from(0u8)),
+        )
         .add_call(
             sns_ledger,
             make_balance_request(),
@@ -390,6 +443,8 @@ async fn run_failed_transfer_from_test(
         *SELF_CANISTER_ID,
         &BALANCES,
         &AUDIT_TRAIL,
+        &WITHDRAW_STATE,
+        &PRICE_HISTORY,
     );
 
     let init = TreasuryManagerInit {
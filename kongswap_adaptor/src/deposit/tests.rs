@@ -1,572 +1,486 @@
-use candid::{CandidType, Principal};
-use ic_stable_structures::memory_manager::MemoryManager;
-use ic_stable_structures::{Cell as StableCell, DefaultMemoryImpl, Vec as StableVec};
-use icrc_ledger_types::icrc::generic_metadata_value::MetadataValue;
-use kongswap_adaptor::agent::icrc_requests::Icrc1MetadataRequest;
-use kongswap_adaptor::{agent::Request, requests::CommitStateRequest};
-use pretty_assertions::assert_eq;
-use serde::de::DeserializeOwned;
-use sns_treasury_manager::{
-    Allowance, Asset, Balances, DepositRequest, TreasuryManager, TreasuryManagerInit,
-};
-use std::{cell::RefCell, collections::VecDeque, error::Error, fmt::Display};
-
 use super::*;
-use crate::kong_types::{
-    AddPoolReply, AddTokenArgs, AddTokenReply, ICReply, RemoveLiquidityAmountsArgs,
-    RemoveLiquidityAmountsReply, UpdateTokenArgs, UpdateTokenReply, UserBalanceLPReply,
-    UserBalancesArgs, UserBalancesReply,
-};
 use crate::{
-    state::storage::ConfigState, validation::ValidatedTreasuryManagerInit, StableAuditTrail,
-    StableBalances, AUDIT_TRAIL_MEMORY_ID, BALANCES_MEMORY_ID,
+    kong_types::{ICReply, TokensArgs, TokensReply},
+    state::storage::{
+        ConfigState, ContractStatus, IntegrityStatus, OperationLock, StableWithdrawState,
+        TaskStatuses,
+    },
+    validation::{LedgerProtocol, ValidatedAsset, ValidatedSymbol},
+    StableAuditTrail, StableBalances, StableContractStatus, StableExchangeRateHistory,
+    StableIdempotencyKeys, StableIntegrityStatus, StableOperationLockCell,
+    StablePendingDepositStateCell, StableTaskStatusCell, StableTransferIntents,
+    StableWithdrawStateCell, AUDIT_TRAIL_MEMORY_ID, BALANCES_MEMORY_ID, CONTRACT_STATUS_MEMORY_ID,
+    EXCHANGE_RATE_HISTORY_MEMORY_ID, IDEMPOTENCY_KEYS_MEMORY_ID, INTEGRITY_STATUS_MEMORY_ID,
+    OPERATION_LOCK_MEMORY_ID, PENDING_DEPOSIT_STATE_MEMORY_ID, TASK_STATUS_MEMORY_ID,
+    TRANSFER_INTENTS_MEMORY_ID, WITHDRAW_STATE_MEMORY_ID,
+};
+use candid::{Nat, Principal};
+use ic_stable_structures::memory_manager::MemoryManager;
+use ic_stable_structures::{
+    BTreeMap as StableBTreeMap, Cell as StableCell, DefaultMemoryImpl, Vec as StableVec,
+};
+use kongswap_adaptor::agent::mock_agent::MockAgent;
+use kongswap_adaptor::audit::{OperationContext, RecordDecision, StatusNotificationHook};
+use pretty_assertions::assert_eq;
+use sns_treasury_manager::{Asset, Operation};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
 };
-use std::fmt::Debug;
-
-const E8: u64 = 10_000_000;
 
-#[derive(Clone, Debug)]
-pub struct MockError {
-    pub message: String,
+/// Records every [`StatusNotificationHook::on_settlement`] call it receives, so a test can assert
+/// on exactly which assets/amounts/outcomes a settlement or refund reported, instead of only on
+/// the persisted [`PendingDepositState`].
+#[derive(Clone, Default)]
+struct RecordingHook {
+    calls: Arc<Mutex<Vec<(Asset, u64, u64, SettlementOutcome)>>>,
 }
 
-impl From<String> for MockError {
-    fn from(message: String) -> Self {
-        MockError { message }
+impl StatusNotificationHook for RecordingHook {
+    fn on_settlement(
+        &self,
+        asset: Asset,
+        amount_decimals: u64,
+        audit_trail_index: u64,
+        outcome: SettlementOutcome,
+    ) {
+        self.calls
+            .lock()
+            .unwrap()
+            .push((asset, amount_decimals, audit_trail_index, outcome));
     }
 }
 
-impl From<&str> for MockError {
-    fn from(message: &str) -> Self {
-        MockError {
-            message: message.to_string(),
-        }
-    }
+/// Always records in full, so the test adaptor's audit trail isn't affected by sampling.
+fn always_full(_context: &OperationContext) -> RecordDecision {
+    RecordDecision::Full
 }
 
-impl Display for MockError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.message)
+fn make_allowance(
+    ledger_canister_id: Principal,
+    symbol: &str,
+    fee_decimals: u64,
+    amount_decimals: u64,
+) -> ValidatedAllowance {
+    ValidatedAllowance {
+        asset: ValidatedAsset::Token {
+            symbol: ValidatedSymbol::try_from(symbol).unwrap(),
+            ledger_canister_id,
+            ledger_fee_decimals: fee_decimals,
+            decimals: 8,
+            ledger_protocol: LedgerProtocol::Icrc,
+        },
+        amount_decimals: amount_decimals.try_into().unwrap(),
+        owner_account: Account {
+            owner: Principal::anonymous(),
+            subaccount: None,
+        },
     }
 }
 
-impl Error for MockError {}
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
 
-// TODO use Result to store reply and failure
-struct CallSpec {
-    raw_request: Vec<u8>,
-    raw_response: Vec<u8>,
-    canister_id: Principal,
-}
+    static BALANCES: RefCell<StableBalances> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableCell::init(
+                    memory_manager.borrow().get(BALANCES_MEMORY_ID),
+                    ConfigState::default()
+                )
+                .expect("BALANCES init should not cause errors")
+            )
+        );
 
-impl CallSpec {
-    fn new<Req>(canister_id: Principal, request: Req, response: Req::Response) -> Result<Self, ()>
-    where
-        Req: Request,
-    {
-        let raw_request = request.payload().expect("Request is not encodable");
-        let raw_response = candid::encode_one(response).expect("Response is not encodable");
-
-        Ok(Self {
-            raw_request,
-            raw_response,
-            canister_id,
-        })
-    }
-}
+    static AUDIT_TRAIL: RefCell<StableAuditTrail> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableVec::init(
+                    memory_manager.borrow().get(AUDIT_TRAIL_MEMORY_ID)
+                )
+                .expect("AUDIT_TRAIL init should not cause errors")
+            )
+        );
 
-struct MockAgent {
-    // Add fields to control mock behavior
-    expected_calls: VecDeque<CallSpec>,
-}
+    static WITHDRAW_STATE: RefCell<StableWithdrawStateCell> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableCell::init(
+                    memory_manager.borrow().get(WITHDRAW_STATE_MEMORY_ID),
+                    StableWithdrawState::default()
+                )
+                .expect("WITHDRAW_STATE init should not cause errors")
+            )
+        );
 
-impl MockAgent {
-    fn new() -> Self {
-        Self {
-            expected_calls: VecDeque::default(),
-        }
-    }
+    static PRICE_HISTORY: RefCell<StablePriceHistory> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableBTreeMap::init(
+                    memory_manager.borrow().get(PRICE_HISTORY_MEMORY_ID)
+                )
+            )
+        );
 
-    fn add_call<Req>(
-        mut self,
-        canister_id: Principal,
-        request: Req,
-        response: Req::Response,
-    ) -> Self
-    where
-        Req: Request,
-    {
-        let call = CallSpec::new(canister_id, request, response)
-            .expect("Creating a new call specification failed");
-        self.expected_calls.push_back(call);
-        let commit_state = CallSpec::new(*KONG_BACKEND_CANISTER_ID, CommitStateRequest {}, ())
-            .expect("CommittState call creation failed");
-        self.expected_calls.push_back(commit_state);
-        self
-    }
+    static IDEMPOTENCY_KEYS: RefCell<StableIdempotencyKeys> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableBTreeMap::init(
+                    memory_manager.borrow().get(IDEMPOTENCY_KEYS_MEMORY_ID)
+                )
+            )
+        );
 
-    fn finished_calls(&self) -> bool {
-        self.expected_calls.is_empty()
-    }
-}
+    static CONTRACT_STATUS: RefCell<StableContractStatus> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableCell::init(
+                    memory_manager.borrow().get(CONTRACT_STATUS_MEMORY_ID),
+                    ContractStatus::default()
+                )
+                .expect("CONTRACT_STATUS init should not cause errors")
+            )
+        );
 
-impl AbstractAgent for MockAgent {
-    type Error = MockError;
-    // Infallable !
-    async fn call<R: kongswap_adaptor::agent::Request + Debug + CandidType>(
-        &mut self,
-        canister_id: impl Into<Principal> + Send,
-        request: R,
-    ) -> Result<R::Response, Self::Error> {
-        println!("started call...");
-        let Ok(raw_request) = request.payload() else {
-            panic!("Cannot encode the request");
-        };
-
-        let expected_call = self
-            .expected_calls
-            .pop_front()
-            .expect("Consumed all expected requests");
-
-        if raw_request != expected_call.raw_request {
-            println!("request: {:#?}", request);
-            println!("{:?}\n{:?}", raw_request, expected_call.raw_request);
-            panic!("Request doesn't match");
-        }
-        let canister_id = canister_id.into();
-
-        if canister_id != expected_call.canister_id {
-            println!("request canister id: {}", canister_id);
-            panic!("Canister IDs doesn't match");
-        }
-
-        let reply = candid::decode_one::<R::Response>(&expected_call.raw_response)
-            .expect("Unable to decode the response");
-
-        println!("successfully called canister ID: {}", canister_id);
-        return Ok(reply);
-    }
-}
+    static PENDING_DEPOSIT_STATE: RefCell<StablePendingDepositStateCell> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableCell::init(
+                    memory_manager.borrow().get(PENDING_DEPOSIT_STATE_MEMORY_ID),
+                    PendingDepositState::default()
+                )
+                .expect("PENDING_DEPOSIT_STATE init should not cause errors")
+            )
+        );
 
-fn make_approve_request(amount: u64, fee: u64) -> ApproveArgs {
-    ApproveArgs {
-        from_subaccount: None,
-        spender: Account {
-            owner: *KONG_BACKEND_CANISTER_ID,
-            subaccount: None,
-        },
-        // All approved tokens should be fully used up before the next deposit.
-        amount: Nat::from(amount - fee),
-        expected_allowance: Some(Nat::from(0u8)),
-        expires_at: Some(u64::MAX),
-        memo: None,
-        created_at_time: None,
-        fee: Some(fee.into()),
-    }
-}
+    static INTEGRITY_STATUS: RefCell<StableIntegrityStatus> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableCell::init(
+                    memory_manager.borrow().get(INTEGRITY_STATUS_MEMORY_ID),
+                    IntegrityStatus::default()
+                )
+                .expect("INTEGRITY_STATUS init should not cause errors")
+            )
+        );
 
-fn make_balance_request(self_id: Principal) -> Account {
-    Account {
-        owner: self_id,
-        subaccount: None,
-    }
-}
+    static EXCHANGE_RATE_HISTORY: RefCell<StableExchangeRateHistory> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableBTreeMap::init(
+                    memory_manager.borrow().get(EXCHANGE_RATE_HISTORY_MEMORY_ID)
+                )
+            )
+        );
 
-fn make_add_token_request(token: String) -> AddTokenArgs {
-    AddTokenArgs { token }
-}
+    static TASK_STATUS: RefCell<StableTaskStatusCell> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableCell::init(
+                    memory_manager.borrow().get(TASK_STATUS_MEMORY_ID),
+                    TaskStatuses::default()
+                )
+                .expect("TASK_STATUS init should not cause errors")
+            )
+        );
 
-fn make_add_token_reply(
-    token_id: u32,
-    chain: String,
-    canister_id: Principal,
-    name: String,
-    symbol: String,
-    fee: u64,
-) -> AddTokenReply {
-    AddTokenReply::IC(ICReply {
-        token_id,
-        chain,
-        canister_id: canister_id.to_string(),
-        name,
-        symbol,
-        decimals: 8,
-        fee: Nat::from(fee),
-        icrc1: true,
-        icrc2: true,
-        icrc3: true,
-        is_removed: false,
-    })
+    static TRANSFER_INTENTS: RefCell<StableTransferIntents> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableBTreeMap::init(
+                    memory_manager.borrow().get(TRANSFER_INTENTS_MEMORY_ID)
+                )
+            )
+        );
+    static OPERATION_LOCK: RefCell<StableOperationLockCell> =
+        MEMORY_MANAGER.with(|memory_manager|
+            RefCell::new(
+                StableCell::init(
+                    memory_manager.borrow().get(OPERATION_LOCK_MEMORY_ID),
+                    OperationLock::default()
+                )
+                .expect("OPERATION_LOCK init should not cause errors")
+            )
+        );
 }
 
-fn make_update_token_request(token: String) -> UpdateTokenArgs {
-    UpdateTokenArgs { token }
-}
+fn new_test_adaptor(notification_hook: RecordingHook) -> KongSwapAdaptor<MockAgent> {
+    let self_id = Principal::from_text("jexlm-gaaaa-aaaar-qalmq-cai").unwrap();
 
-fn make_update_token_reply(
-    token_id: u32,
-    chain: String,
-    canister_id: Principal,
-    name: String,
-    symbol: String,
-    fee: u64,
-) -> UpdateTokenReply {
-    UpdateTokenReply::IC(ICReply {
-        token_id,
-        chain,
-        canister_id: canister_id.to_string(),
-        name,
-        symbol,
-        decimals: 8,
-        fee: Nat::from(fee),
-        icrc1: true,
-        icrc2: true,
-        icrc3: true,
-        is_removed: false,
-    })
+    KongSwapAdaptor::with_audit_sampler(
+        || 0,
+        always_full,
+        MockAgent::new(self_id),
+        self_id,
+        &BALANCES,
+        &AUDIT_TRAIL,
+        &WITHDRAW_STATE,
+        &PRICE_HISTORY,
+        &IDEMPOTENCY_KEYS,
+        &CONTRACT_STATUS,
+        &PENDING_DEPOSIT_STATE,
+        &INTEGRITY_STATUS,
+        &EXCHANGE_RATE_HISTORY,
+        &TASK_STATUS,
+        &TRANSFER_INTENTS,
+        &OPERATION_LOCK,
+        Box::new(notification_hook),
+    )
 }
 
-fn make_metadata_reply(name: String, symbol: String, fee: u64) -> Vec<(String, MetadataValue)> {
-    vec![
-        (
-            "icrc1:decimals".to_string(),
-            MetadataValue::Nat(Nat::from(8_u64)),
-        ),
-        ("icrc1:name".to_string(), MetadataValue::Text(name)),
-        ("icrc1:symbol".to_string(), MetadataValue::Text(symbol)),
-        ("icrc1:fee".to_string(), MetadataValue::Nat(Nat::from(fee))),
-        (
-            "icrc1:max_memo_length".to_string(),
-            MetadataValue::Nat(Nat::from(32_u64)),
-        ),
-        (
-            "icrc103:public_allowances".to_string(),
-            MetadataValue::Text("true".to_string()),
-        ),
-        (
-            "icrc103:max_take_value".to_string(),
-            MetadataValue::Nat(Nat::from(500_u64)),
-        ),
-    ]
-}
+#[test]
+fn test_settle_deposit_advances_state_and_notifies_both_assets() {
+    let hook = RecordingHook::default();
+    let adaptor = new_test_adaptor(hook.clone());
 
-fn make_add_pool_request(
-    token_0: String,
-    amount_0: u64,
-    token_1: String,
-    amount_1: u64,
-) -> AddPoolArgs {
-    AddPoolArgs {
-        token_0,
-        amount_0: Nat::from(amount_0),
-        tx_id_0: None,
-        token_1,
-        amount_1: Nat::from(amount_1),
-        tx_id_1: None,
-        lp_fee_bps: Some(30),
-    }
-}
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
 
-fn make_user_balances_request(self_id: Principal) -> UserBalancesArgs {
-    UserBalancesArgs {
-        principal_id: self_id.to_text(),
-    }
-}
+    let allowance_0 = make_allowance(sns_ledger, "DAO", 10_000, 500 * 100_000_000);
+    let allowance_1 = make_allowance(icp_ledger, "ICP", 10_000, 400 * 100_000_000);
 
-fn make_user_balance_reply() -> UserBalancesReply {
-    UserBalancesReply::LP(UserBalanceLPReply {
-        symbol: "DAO_ICP".to_string(),
-        balance: 100.0,
-        ..Default::default()
-    })
-}
+    adaptor.settle_deposit(
+        &allowance_0,
+        499 * 100_000_000,
+        &allowance_1,
+        399 * 100_000_000,
+    );
 
-fn make_remove_liquidity_amounts_request(
-    token_0: String,
-    token_1: String,
-    remove_lp_token_amount: u64,
-) -> RemoveLiquidityAmountsArgs {
-    RemoveLiquidityAmountsArgs {
-        token_0,
-        token_1,
-        remove_lp_token_amount: Nat::from(remove_lp_token_amount),
-    }
-}
+    assert_eq!(
+        adaptor.get_pending_deposit_state(),
+        PendingDepositState::Settled
+    );
 
-fn make_remove_liquidity_amounts_reply(
-    amount_0: u64,
-    amount_1: u64,
-) -> RemoveLiquidityAmountsReply {
-    RemoveLiquidityAmountsReply {
-        amount_0: Nat::from(amount_0),
-        amount_1: Nat::from(amount_1),
-        ..Default::default()
-    }
+    let calls = hook.calls.lock().unwrap().clone();
+    assert_eq!(
+        calls,
+        vec![
+            (
+                allowance_0.asset.into(),
+                499 * 100_000_000,
+                0,
+                SettlementOutcome::Settled
+            ),
+            (
+                allowance_1.asset.into(),
+                399 * 100_000_000,
+                0,
+                SettlementOutcome::Settled
+            ),
+        ]
+    );
 }
 
-#[tokio::test]
-async fn test_deposit_success() {
-    const FEE_SNS: u64 = 10_500u64;
-    const FEE_ICP: u64 = 9_500u64;
+#[test]
+fn test_fail_deposit_refunded_from_pending_approval_notifies_refund() {
+    let hook = RecordingHook::default();
+    let adaptor = new_test_adaptor(hook.clone());
+
     let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
     let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
-    let sns_id = Principal::from_text("jg2ra-syaaa-aaaaq-aaewa-cai").unwrap();
-    let token_0 = format!("IC.{}", sns_ledger);
-    let token_1 = format!("IC.{}", icp_ledger);
-    // Create test assets and request first
-    let asset_0 = Asset::Token {
-        ledger_canister_id: sns_ledger,
-        symbol: "DAO".to_string(),
-        ledger_fee_decimals: Nat::from(FEE_SNS),
-    };
 
-    let asset_1 = Asset::Token {
-        ledger_canister_id: icp_ledger,
-        symbol: "ICP".to_string(),
-        ledger_fee_decimals: Nat::from(FEE_ICP),
-    };
+    let allowance_0 = make_allowance(sns_ledger, "DAO", 10_000, 500 * 100_000_000);
+    let allowance_1 = make_allowance(icp_ledger, "ICP", 10_000, 400 * 100_000_000);
 
-    let owner_account = sns_treasury_manager::Account {
-        owner: Principal::from_text("2vxsx-fae").unwrap(),
-        subaccount: None,
-    };
+    // Simulate a deposit that got past the first approve before the second one (or `add_pool`)
+    // failed -- the same stuck state `deposit_into_dex` would leave a mid-sequence failure in.
+    adaptor.set_pending_deposit_state(PendingDepositState::PendingApproval);
 
-    thread_local! {
-        static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
-            RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
-
-        static BALANCES: RefCell<StableBalances> =
-            MEMORY_MANAGER.with(|memory_manager|
-                RefCell::new(
-                    StableCell::init(
-                        memory_manager.borrow().get(BALANCES_MEMORY_ID),
-                        ConfigState::default()
-                    )
-                    .expect("BALANCES init should not cause errors")
-                )
-            );
-
-        static AUDIT_TRAIL: RefCell<StableAuditTrail> =
-            MEMORY_MANAGER.with(|memory_manager|
-                RefCell::new(
-                    StableVec::init(
-                        memory_manager.borrow().get(AUDIT_TRAIL_MEMORY_ID)
-                    )
-                    .expect("AUDIT_TRAIL init should not cause errors")
-                )
-            );
-    }
+    adaptor.fail_deposit_refunded(&allowance_0, &allowance_1);
 
-    let amount_0_decimals = 500 * E8;
-    let amount_1_decimals = 400 * E8;
-    let allowances = vec![
-        // SNS
-        Allowance {
-            asset: asset_0,
-            owner_account,
-            amount_decimals: Nat::from(amount_0_decimals),
-        },
-        // ICP
-        Allowance {
-            asset: asset_1,
-            owner_account,
-            amount_decimals: Nat::from(amount_1_decimals),
-        },
-    ];
+    assert_eq!(
+        adaptor.get_pending_deposit_state(),
+        PendingDepositState::FailedRefunded
+    );
 
-    let mock_agent = MockAgent::new()
-        .add_call(
-            sns_ledger,
-            make_approve_request(amount_0_decimals, FEE_SNS),
-            Ok(Nat::from(amount_0_decimals)),
-        )
-        .add_call(
-            icp_ledger,
-            make_approve_request(amount_1_decimals, FEE_ICP),
-            Ok(Nat::from(amount_1_decimals)),
-        )
-        .add_call(
-            sns_ledger,
-            make_balance_request(*KONG_BACKEND_CANISTER_ID),
-            Nat::from(amount_0_decimals - FEE_SNS),
-        )
-        .add_call(
-            icp_ledger,
-            make_balance_request(*KONG_BACKEND_CANISTER_ID),
-            Nat::from(amount_1_decimals - FEE_ICP),
-        )
-        .add_call(
-            *KONG_BACKEND_CANISTER_ID,
-            make_add_token_request(token_0.clone()),
-            Ok(make_add_token_reply(
-                1,
-                "IC".to_string(),
-                sns_id,
-                "My DAO Token".to_string(),
-                "DAO".to_string(),
-                FEE_SNS,
-            )),
-        )
-        .add_call(
-            *KONG_BACKEND_CANISTER_ID,
-            make_add_token_request(token_1.clone()),
-            Ok(make_add_token_reply(
-                2,
-                "IC".to_string(),
-                icp_ledger,
-                "Internet Computer".to_string(),
-                "ICP".to_string(),
-                FEE_ICP,
-            )),
-        )
-        .add_call(
-            *KONG_BACKEND_CANISTER_ID,
-            make_update_token_request(token_0.clone()),
-            Ok(make_update_token_reply(
-                1,
-                "IC".to_string(),
-                sns_id,
-                "My DAO Token".to_string(),
-                "DAO".to_string(),
-                FEE_SNS,
-            )),
-        )
-        .add_call(
-            sns_ledger,
-            Icrc1MetadataRequest {},
-            make_metadata_reply("My DAO Token".to_string(), "DAO".to_string(), FEE_SNS),
-        )
-        .add_call(
-            *KONG_BACKEND_CANISTER_ID,
-            make_update_token_request(token_1.clone()),
-            Ok(make_update_token_reply(
-                2,
-                "IC".to_string(),
-                icp_ledger,
-                "Internet Computer".to_string(),
-                "ICP".to_string(),
-                FEE_ICP,
-            )),
-        )
-        .add_call(
-            icp_ledger,
-            Icrc1MetadataRequest {},
-            make_metadata_reply("Internet Computer".to_string(), "ICP".to_string(), FEE_ICP),
-        )
-        .add_call(
-            *KONG_BACKEND_CANISTER_ID,
-            make_add_pool_request(
-                token_0.clone(),
-                amount_0_decimals - 2 * FEE_SNS,
-                token_1.clone(),
-                amount_1_decimals - 2 * FEE_ICP,
+    let calls = hook.calls.lock().unwrap().clone();
+    assert_eq!(
+        calls,
+        vec![
+            (
+                allowance_0.asset.into(),
+                allowance_0.amount_decimals.get(),
+                0,
+                SettlementOutcome::FailedRefunded
             ),
-            Ok(AddPoolReply::default()),
-        )
-        .add_call(
-            sns_ledger,
-            make_balance_request(*KONG_BACKEND_CANISTER_ID),
-            Nat::from(0_u64),
-        )
-        .add_call(
-            icp_ledger,
-            make_balance_request(*KONG_BACKEND_CANISTER_ID),
-            Nat::from(0_u64),
-        )
-        .add_call(
-            sns_ledger,
-            make_balance_request(*KONG_BACKEND_CANISTER_ID),
-            Nat::from(0_u64),
-        )
-        .add_call(
-            icp_ledger,
-            make_balance_request(*KONG_BACKEND_CANISTER_ID),
-            Nat::from(0_u64),
-        )
-        .add_call(
-            *KONG_BACKEND_CANISTER_ID,
-            make_update_token_request(token_0.clone()),
-            Ok(make_update_token_reply(
-                1,
-                "IC".to_string(),
-                sns_id,
-                "My DAO Token".to_string(),
-                "DAO".to_string(),
-                FEE_SNS,
-            )),
-        )
-        .add_call(
-            sns_ledger,
-            Icrc1MetadataRequest {},
-            make_metadata_reply("My DAO Token".to_string(), "DAO".to_string(), FEE_SNS),
-        )
-        .add_call(
-            *KONG_BACKEND_CANISTER_ID,
-            make_update_token_request(token_1.clone()),
-            Ok(make_update_token_reply(
-                2,
-                "IC".to_string(),
-                icp_ledger,
-                "Internet Computer".to_string(),
-                "ICP".to_string(),
-                FEE_ICP,
-            )),
-        )
-        .add_call(
-            icp_ledger,
-            Icrc1MetadataRequest {},
-            make_metadata_reply("Internet Computer".to_string(), "ICP".to_string(), FEE_ICP),
-        )
-        .add_call(
-            *KONG_BACKEND_CANISTER_ID,
-            make_user_balances_request(*KONG_BACKEND_CANISTER_ID),
-            Ok(vec![make_user_balance_reply()]),
-        )
-        .add_call(
-            *KONG_BACKEND_CANISTER_ID,
-            make_remove_liquidity_amounts_request(
-                "DAO".to_string(),
-                "ICP".to_string(),
-                10000000000,
+            (
+                allowance_1.asset.into(),
+                allowance_1.amount_decimals.get(),
+                0,
+                SettlementOutcome::FailedRefunded
             ),
-            Ok(make_remove_liquidity_amounts_reply(
-                amount_0_decimals - FEE_SNS,
-                amount_1_decimals - FEE_ICP,
-            )),
-        );
+        ]
+    );
+}
 
-    let mut kong_adaptor = KongSwapAdaptor::new(
-        || 0, // Mock time function
-        mock_agent,
+#[test]
+fn test_fail_deposit_refunded_is_noop_once_already_settled() {
+    let hook = RecordingHook::default();
+    let adaptor = new_test_adaptor(hook.clone());
+
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    let allowance_0 = make_allowance(sns_ledger, "DAO", 10_000, 500 * 100_000_000);
+    let allowance_1 = make_allowance(icp_ledger, "ICP", 10_000, 400 * 100_000_000);
+
+    adaptor.set_pending_deposit_state(PendingDepositState::Settled);
+
+    // A retried `deposit` that already reached `Settled` must not be reclassified as a refund,
+    // nor notify a second time, just because the retry's own attempt failed downstream.
+    adaptor.fail_deposit_refunded(&allowance_0, &allowance_1);
+
+    assert_eq!(
+        adaptor.get_pending_deposit_state(),
+        PendingDepositState::Settled
+    );
+    assert!(hook.calls.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_check_operation_sequence_rejects_a_retried_deposit_before_any_agent_call() {
+    let hook = RecordingHook::default();
+    let mut adaptor = new_test_adaptor(hook);
+
+    // No calls are seeded on the mock agent: if the sequence guard let a second, stale-sequence
+    // deposit attempt reach `deposit_impl`, the very first unscripted agent call would panic.
+    assert_eq!(adaptor.operation_sequence(), 0);
+
+    // Simulates a first `deposit_with_expected_sequence` call that read `operation_sequence() ==
+    // 0`, then actually committed -- mirroring what `TreasuryManager::deposit` does once a deposit
+    // succeeds (see `advance_operation_sequence`'s call sites in `canister.rs`).
+    adaptor.check_operation_sequence(Some(0)).unwrap();
+    adaptor.advance_operation_sequence();
+    assert_eq!(adaptor.operation_sequence(), 1);
+
+    // A second attempt built against the same, now-stale observation of `operation_sequence() ==
+    // 0` (e.g. a governance proposal retried after the first deposit already landed) must be
+    // rejected -- and therefore never reach `deposit_impl`, let alone any ledger call.
+    let err = adaptor.check_operation_sequence(Some(0)).unwrap_err();
+    assert_eq!(err.code, u64::from(TransactionErrorCodes::PreConditionCode));
+
+    assert!(adaptor.agent.finished_calls());
+}
+
+#[tokio::test]
+async fn test_maybe_add_token_skips_add_token_for_an_already_registered_ledger() {
+    let hook = RecordingHook::default();
+    let self_id = Principal::from_text("jexlm-gaaaa-aaaar-qalmq-cai").unwrap();
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+
+    // Only `tokens()` is scripted -- if `maybe_add_token` still issued `add_token` for a ledger
+    // `tokens()` already reports as registered, the very next (unscripted) agent call would panic.
+    let mock_agent = MockAgent::new(self_id).add_call(
         *KONG_BACKEND_CANISTER_ID,
+        TokensArgs { symbol: None },
+        Ok(vec![TokensReply::IC(ICReply {
+            token_id: 1,
+            chain: "IC".to_string(),
+            canister_id: sns_ledger.to_string(),
+            name: "DAO".to_string(),
+            symbol: "DAO".to_string(),
+            decimals: 8,
+            fee: Nat::from(10_000u64),
+            icrc1: true,
+            icrc2: true,
+            icrc3: true,
+            is_removed: false,
+        })]),
+    );
+
+    let mut adaptor = KongSwapAdaptor::with_audit_sampler(
+        || 0,
+        always_full,
+        mock_agent,
+        self_id,
         &BALANCES,
         &AUDIT_TRAIL,
+        &WITHDRAW_STATE,
+        &PRICE_HISTORY,
+        &IDEMPOTENCY_KEYS,
+        &CONTRACT_STATUS,
+        &PENDING_DEPOSIT_STATE,
+        &INTEGRITY_STATUS,
+        &EXCHANGE_RATE_HISTORY,
+        &TASK_STATUS,
+        &TRANSFER_INTENTS,
+        &OPERATION_LOCK,
+        Box::new(hook),
     );
 
-    let init = TreasuryManagerInit {
-        allowances: allowances.clone(),
+    let mut context = OperationContext::new(Operation::Deposit);
+
+    adaptor
+        .maybe_add_token(&mut context, sns_ledger)
+        .await
+        .unwrap();
+
+    assert!(adaptor.agent.finished_calls());
+}
+
+/// Covers `assert_min_holdings`'s contract as wired into `deposit_into_dex`: a floor set just
+/// above the DAO's current holdings trips the guard before anything is submitted, so the pool
+/// call (`add_pool`/`add_liquidity`) is never emitted -- the mock agent has no calls scripted at
+/// all, so any attempt to reach the DEX would panic.
+#[test]
+fn test_assert_min_holdings_rejects_a_deposit_whose_floor_is_not_yet_met() {
+    let hook = RecordingHook::default();
+    let mut adaptor = new_test_adaptor(hook);
+
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    let asset_0 = ValidatedAsset::Token {
+        symbol: ValidatedSymbol::try_from("DAO").unwrap(),
+        ledger_canister_id: sns_ledger,
+        ledger_fee_decimals: 10_000,
+        decimals: 8,
+        ledger_protocol: LedgerProtocol::Icrc,
+    };
+    let asset_1 = ValidatedAsset::Token {
+        symbol: ValidatedSymbol::try_from("ICP").unwrap(),
+        ledger_canister_id: icp_ledger,
+        ledger_fee_decimals: 10_000,
+        decimals: 8,
+        ledger_protocol: LedgerProtocol::Icrc,
     };
 
-    let ValidatedTreasuryManagerInit {
-        allowance_0,
-        allowance_1,
-    } = init.try_into().unwrap();
-
-    // Initialize and test
-    kong_adaptor.initialize(
-        allowance_0.asset,
-        allowance_1.asset,
-        allowance_0.owner_account,
-        allowance_1.owner_account,
-    );
+    let owner_account = Account {
+        owner: Principal::anonymous(),
+        subaccount: None,
+    };
+
+    adaptor.initialize(asset_0, asset_1, owner_account, owner_account);
+
+    // Simulate a prior deposit of 10 DAO tokens having already settled into the DAO's own
+    // account, so the guard has nonzero holdings to compare against the floor below.
+    let settled_amount_decimals = 10 * 100_000_000u64;
+    adaptor
+        .add_manager_balance(asset_0, settled_amount_decimals)
+        .unwrap();
+    adaptor
+        .move_asset(
+            asset_0,
+            settled_amount_decimals,
+            Party::TreasuryManager,
+            Party::TreasuryOwner,
+        )
+        .unwrap();
 
-    // This should now work without panicking
-    let result = kong_adaptor.deposit(DepositRequest { allowances }).await;
+    // Set the floor one decimal above what's actually held, so the guard must reject.
+    let mut min_holdings = BTreeMap::new();
+    min_holdings.insert(Asset::from(asset_0), Nat::from(settled_amount_decimals + 1));
+
+    let result = adaptor.assert_min_holdings(&min_holdings);
 
     assert!(
-        kong_adaptor.agent.finished_calls(),
-        "There are still some calls remaining"
+        result.is_err(),
+        "the guard should reject holdings that fall short of the configured floor"
+    );
+    assert!(
+        adaptor.agent.finished_calls(),
+        "no agent call should be made evaluating this guard, let alone a pool call"
     );
-
-    assert_eq!(result, Ok(Balances::default()),);
 }
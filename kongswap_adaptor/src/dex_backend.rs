@@ -0,0 +1,54 @@
+//! A seam for the DEX-specific token/pool naming conventions that [`crate::deposit`],
+//! [`crate::kong_api`], and [`crate::balances`] otherwise bake in as bare literals and format
+//! strings, so a future non-KongSwap backend would have one documented place to override them
+//! from instead of a fork of every call site.
+
+use candid::Principal;
+
+/// DEX-specific conventions for naming the tokens and pools a [`crate::KongSwapAdaptor`] talks
+/// about. `KongSwapAdaptor<A>` is not generic over this trait today -- see [`KongSwapBackend`]'s
+/// doc comment for why -- so this only abstracts naming, not the `add_pool`/`add_liquidity`
+/// call shapes themselves.
+pub(crate) trait DexBackend {
+    /// The DEX backend canister this adaptor talks to.
+    fn canister_id(&self) -> Principal;
+
+    /// The DEX's own name for a ledger-backed token, e.g. KongSwap's `"IC.{canister_id}"`.
+    fn token_name(&self, ledger_canister_id: Principal) -> String;
+
+    /// The DEX's LP-token/pool symbol for a pair of token symbols, e.g. KongSwap's
+    /// `"{symbol_0}_{symbol_1}"`.
+    fn lp_token_symbol(&self, symbol_0: &str, symbol_1: &str) -> String;
+}
+
+/// The only [`DexBackend`] implementation today. `KongSwapAdaptor<A>` isn't made generic over
+/// this trait (e.g. `KongSwapAdaptor<A, D: DexBackend>`) because doing so would mean threading a
+/// second type parameter through every stable-storage type, the canister init signature, and
+/// every inter-canister call site in `deposit.rs`, `withdraw.rs`, `kong_api.rs`, and
+/// `rebalance.rs` -- in effect a rewrite of the whole crate to support a DEX this adaptor doesn't
+/// actually talk to yet. This struct exists so the naming conventions have one documented,
+/// testable home instead of being repeated as bare literals, and so a future generic migration
+/// has a real implementation to generalize from.
+pub(crate) struct KongSwapBackend {
+    canister_id: Principal,
+}
+
+impl KongSwapBackend {
+    pub(crate) fn new(canister_id: Principal) -> Self {
+        Self { canister_id }
+    }
+}
+
+impl DexBackend for KongSwapBackend {
+    fn canister_id(&self) -> Principal {
+        self.canister_id
+    }
+
+    fn token_name(&self, ledger_canister_id: Principal) -> String {
+        format!("IC.{}", ledger_canister_id)
+    }
+
+    fn lp_token_symbol(&self, symbol_0: &str, symbol_1: &str) -> String {
+        format!("{}_{}", symbol_0, symbol_1)
+    }
+}
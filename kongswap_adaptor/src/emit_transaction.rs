@@ -1,18 +1,23 @@
 use crate::{
     log_err,
-    state::{storage::StableTransaction, KongSwapAdaptor},
+    state::{
+        storage::{StableTransaction, GENESIS_PREV_HASH},
+        KongSwapAdaptor,
+    },
 };
 use candid::{CandidType, Principal};
 use kongswap_adaptor::agent::{AbstractAgent, Request};
+use kongswap_adaptor::audit::{OperationContext, RecordDecision};
 use kongswap_adaptor::requests::CommitStateRequest;
-use sns_treasury_manager::{Error, TreasuryManagerOperation};
+use sns_treasury_manager::{Error, Operation};
 use std::fmt::Debug;
 
 impl<A: AbstractAgent> KongSwapAdaptor<A> {
-    /// Performs the request call and records the transaction in the audit trail.
+    /// Performs the request call and records the transaction in the audit trail -- unless
+    /// `context`'s installed [`RecordDecision`] says otherwise (see below).
     pub(crate) async fn emit_transaction<R>(
         &mut self,
-        operation: TreasuryManagerOperation,
+        context: &mut OperationContext,
         canister_id: Principal,
         request: R,
         human_readable: String,
@@ -20,6 +25,12 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
     where
         R: Request + Clone + CandidType + Debug,
     {
+        if let Some(lock_generation) = context.lock_generation() {
+            self.assert_operation_lock(context.operation(), lock_generation)?;
+        }
+
+        let operation = context.next_operation();
+        let is_first_sub_transaction = operation.step.index == 0;
         let call_result = unsafe {
             let agent = self.agent.0.get();
             (*agent)
@@ -44,15 +55,47 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
             }
         };
 
-        let transaction = StableTransaction {
-            timestamp_ns: self.time_ns(),
-            canister_id,
-            result,
-            human_readable,
-            operation,
+        // Deposit/withdraw are the only operation kinds `check_state_lock` locks against, and both
+        // always touch the whole pool (there's no such thing as depositing into just one side), so
+        // every transaction belonging to one of them locks both of the pool's asset ledgers.
+        let locked_ledgers = if [Operation::Deposit, Operation::Withdraw]
+            .contains(&operation.operation)
+        {
+            let (asset_0, asset_1) = self.assets();
+            vec![asset_0.ledger_canister_id(), asset_1.ledger_canister_id()]
+        } else {
+            Vec::new()
+        };
+
+        // An operation's first sub-transaction is always recorded: it's the anchor entry that
+        // holds the operation's lock (see `check_state_lock`) and that later sub-transactions get
+        // folded into if they're sampled out (see `RecordDecision`). Any error is always recorded
+        // in full, regardless of the installed decision, so a dropped/summarized entry can never
+        // be the one hiding a failure.
+        let record_decision = if result.is_err() {
+            RecordDecision::Full
+        } else {
+            context.record_decision()
         };
 
-        self.push_audit_trail_transaction(transaction);
+        if is_first_sub_transaction || record_decision == RecordDecision::Full {
+            // `prev_hash`/`hash` are placeholders here; `push_audit_trail_transaction` overwrites
+            // them by chaining off the current tail entry before storing.
+            let transaction = StableTransaction {
+                timestamp_ns: self.time_ns(),
+                canister_id,
+                result,
+                human_readable,
+                operation,
+                prev_hash: GENESIS_PREV_HASH,
+                hash: GENESIS_PREV_HASH,
+                locked_ledgers,
+            };
+
+            self.push_audit_trail_transaction(transaction);
+        } else {
+            context.note_compacted();
+        }
 
         // Self-call to ensure that the state has been committed, to prevent state roll back in case
         // of a panic that occurs before the next (meaningful) async operation. This is recommended:
@@ -0,0 +1,273 @@
+//! Exchange Rate Canister (XRC) integration, so a managed asset's value can be reported in a
+//! DAO-chosen quote denomination (e.g. `"USD"`) without requiring a controller to keep
+//! `set_conversion_rates` in sync with an external price feed by hand.
+//!
+//! Every `refresh_balances` cycle fetches a fresh rate for each managed asset from the real
+//! mainnet XRC canister and records it both as the current
+//! [`crate::balances::ValidatedBalances`] conversion rate and as an
+//! [`ExchangeRateHistoryEntry`](crate::state::storage::ExchangeRateHistoryEntry) in
+//! [`crate::StableExchangeRateHistory`], the same way [`crate::price_history`] tracks the pool's
+//! own spot price over time.
+
+use crate::{
+    balances::RATE_DECIMALS_SCALE, log_err, state::storage::ExchangeRateHistoryEntry,
+    tx_error_codes::TransactionErrorCodes, validation::ValidatedAsset, KongSwapAdaptor,
+};
+use candid::{CandidType, Principal};
+use kongswap_adaptor::{
+    agent::{AbstractAgent, Request},
+    audit::OperationContext,
+};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sns_treasury_manager::{Error, ErrorKind, TransactionWitness};
+
+/// Caps the number of exchange-rate observations retained in stable memory, same rationale and
+/// limit as [`crate::price_history`]'s own history cap.
+const MAX_EXCHANGE_RATE_HISTORY_ENTRIES: u64 = 1_000;
+
+// Canister ID from the mainnet.
+// See https://dashboard.internetcomputer.org/canister/uf6dk-hyaaa-aaaaq-qaaaq-cai
+lazy_static! {
+    static ref EXCHANGE_RATE_CANISTER_ID: Principal =
+        Principal::from_text("uf6dk-hyaaa-aaaaq-qaaaq-cai").unwrap();
+}
+
+// ----------------- begin:get_exchange_rate -----------------
+#[derive(CandidType, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum XrcAssetClass {
+    Cryptocurrency,
+    FiatCurrency,
+}
+
+#[derive(CandidType, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct XrcAsset {
+    pub symbol: String,
+    pub class: XrcAssetClass,
+}
+
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
+pub struct GetExchangeRateRequest {
+    pub base_asset: XrcAsset,
+    pub quote_asset: XrcAsset,
+    pub timestamp: Option<u64>,
+}
+
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
+pub struct ExchangeRateMetadata {
+    pub decimals: u32,
+    pub forex_timestamp: Option<u64>,
+    pub quote_asset_num_received_rates: u64,
+    pub base_asset_num_received_rates: u64,
+    pub base_asset_num_queried_sources: u64,
+    pub standard_deviation: u64,
+    pub quote_asset_num_queried_sources: u64,
+}
+
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
+pub struct ExchangeRate {
+    pub metadata: ExchangeRateMetadata,
+    pub rate: u64,
+    pub timestamp: u64,
+    pub quote_asset: XrcAsset,
+    pub base_asset: XrcAsset,
+}
+
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
+pub enum ExchangeRateError {
+    AnonymousPrincipalNotAllowed,
+    Pending,
+    CryptoBaseAssetNotFound,
+    CryptoQuoteAssetNotFound,
+    StablecoinRateNotFound,
+    StablecoinRateTooFewRates,
+    StablecoinRateZeroRate,
+    ForexInvalidTimestamp,
+    ForexBaseAssetNotFound,
+    ForexQuoteAssetNotFound,
+    ForexAssetsNotFound,
+    RateLimited,
+    NotEnoughCycles,
+    FailedToAcceptCycles,
+    InconsistentRatesReceived,
+    Other { code: u32, description: String },
+}
+
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
+pub enum GetExchangeRateResult {
+    Ok(ExchangeRate),
+    Err(ExchangeRateError),
+}
+
+/// A typed witness for an XRC `get_exchange_rate` call, following the same
+/// [`crate::kong_types::ParsedKongWitness`] discipline: JSON via [`serde_json::to_string`] instead
+/// of a raw `Debug` dump.
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ParsedExchangeRateWitness {
+    base_asset_symbol: String,
+    quote_asset_symbol: String,
+    rate: u64,
+    decimals: u32,
+    timestamp: u64,
+}
+
+impl Request for GetExchangeRateRequest {
+    fn method(&self) -> &'static str {
+        "get_exchange_rate"
+    }
+
+    fn update(&self) -> bool {
+        true
+    }
+
+    fn payload(&self) -> Result<Vec<u8>, candid::Error> {
+        candid::encode_one(self)
+    }
+
+    type Response = GetExchangeRateResult;
+
+    type Ok = ExchangeRate;
+
+    fn transaction_witness(
+        &self,
+        _canister_id: Principal,
+        response: Self::Response,
+    ) -> Result<(TransactionWitness, Self::Ok), String> {
+        let rate = match response {
+            GetExchangeRateResult::Ok(rate) => rate,
+            GetExchangeRateResult::Err(err) => {
+                return Err(format!("XRC.get_exchange_rate failed: {:?}", err));
+            }
+        };
+
+        let parsed = ParsedExchangeRateWitness {
+            base_asset_symbol: rate.base_asset.symbol.clone(),
+            quote_asset_symbol: rate.quote_asset.symbol.clone(),
+            rate: rate.rate,
+            decimals: rate.metadata.decimals,
+            timestamp: rate.timestamp,
+        };
+
+        let json = serde_json::to_string(&parsed).unwrap_or_else(|err| {
+            format!("failed to serialize ParsedExchangeRateWitness: {}", err)
+        });
+
+        Ok((TransactionWitness::NonLedger(json), rate))
+    }
+}
+// ----------------- end:get_exchange_rate -----------------
+
+/// Converts an XRC rate (`rate` scaled by `10^decimals`) into [`RATE_DECIMALS_SCALE`] (1e8)
+/// fixed-point, widening through `u128` since `rate` can already approach `u64::MAX`.
+fn rescale_rate(rate: u64, decimals: u32) -> Option<u64> {
+    let rate = u128::from(rate);
+    let scale = u128::from(RATE_DECIMALS_SCALE);
+
+    let rescaled = if decimals <= 8 {
+        rate.checked_mul(scale.checked_div(10u128.checked_pow(decimals)?)?)?
+    } else {
+        rate.checked_div(10u128.checked_pow(decimals - 8)?)?
+    };
+
+    u64::try_from(rescaled).ok()
+}
+
+impl<A: AbstractAgent> KongSwapAdaptor<A> {
+    /// Fetches and records a fresh exchange rate for `asset_id` (`0` or `1`) against
+    /// [`Self::valuation_quote_asset_symbol`], rescaling XRC's `rate`/`decimals` pair into
+    /// [`RATE_DECIMALS_SCALE`] fixed-point before storing it as this asset's conversion rate.
+    async fn refresh_exchange_rate(
+        &mut self,
+        context: &mut OperationContext,
+        asset_id: usize,
+        asset: ValidatedAsset,
+    ) -> Result<(), Error> {
+        let quote_asset_symbol = self.valuation_quote_asset_symbol();
+
+        let request = GetExchangeRateRequest {
+            base_asset: XrcAsset {
+                symbol: asset.symbol(),
+                class: XrcAssetClass::Cryptocurrency,
+            },
+            quote_asset: XrcAsset {
+                symbol: quote_asset_symbol.clone(),
+                class: XrcAssetClass::FiatCurrency,
+            },
+            timestamp: None,
+        };
+
+        let human_readable = format!(
+            "Calling ExchangeRateCanister.get_exchange_rate to price {} in {}.",
+            asset.symbol(),
+            quote_asset_symbol,
+        );
+
+        let rate = self
+            .emit_transaction(context, *EXCHANGE_RATE_CANISTER_ID, request, human_readable)
+            .await?;
+
+        let rate_decimals = rescale_rate(rate.rate, rate.metadata.decimals).ok_or_else(|| Error {
+            code: u64::from(TransactionErrorCodes::ExchangeRateCode),
+            message: format!(
+                "Overflow while rescaling exchange rate {} ({} decimals) for {}.",
+                rate.rate,
+                rate.metadata.decimals,
+                asset.symbol()
+            ),
+            kind: ErrorKind::Postcondition {},
+        })?;
+
+        let timestamp_ns = self.time_ns();
+
+        self.with_balances_mut(|validated_balances| {
+            validated_balances.record_exchange_rate_observation(
+                asset_id,
+                rate_decimals,
+                timestamp_ns,
+            )
+        });
+
+        self.with_exchange_rate_history_mut(|exchange_rate_history| {
+            // `asset_0` and `asset_1` are refreshed back-to-back and can land on the same whole-
+            // second timestamp, so the low bit of the key disambiguates them instead of one
+            // silently overwriting the other.
+            let key = (timestamp_ns << 1) | (asset_id as u64);
+            exchange_rate_history.insert(
+                key,
+                ExchangeRateHistoryEntry {
+                    timestamp_ns,
+                    asset,
+                    rate_decimals,
+                },
+            );
+
+            while exchange_rate_history.len() > MAX_EXCHANGE_RATE_HISTORY_ENTRIES {
+                let Some((oldest_key, _)) = exchange_rate_history.iter().next() else {
+                    break;
+                };
+                exchange_rate_history.remove(&oldest_key);
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Refreshes the exchange rate for both managed assets, logging (rather than failing the
+    /// caller) if one leg fails -- the same "best effort, not load-bearing" treatment
+    /// [`crate::balances::KongSwapAdaptor::refresh_balances_impl`] gives the price observation it
+    /// records alongside this.
+    pub(crate) async fn refresh_exchange_rates(&mut self, context: &mut OperationContext) {
+        let registered_assets = self.get_cached_balances().registered_assets();
+
+        for (asset_id, asset) in registered_assets.into_iter().enumerate() {
+            if let Err(err) = self.refresh_exchange_rate(context, asset_id, asset).await {
+                log_err(&format!(
+                    "Failed to refresh exchange rate for {}: {:?}",
+                    asset.symbol(),
+                    err
+                ));
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{
+    kong_types::{RemoveLiquidityAmountsArgs, RemoveLiquidityAmountsReply},
+    validation::decode_nat_to_u64,
+    KongSwapAdaptor, KONG_BACKEND_CANISTER_ID,
+};
+use kongswap_adaptor::agent::AbstractAgent;
+use sns_treasury_manager::{Balances, Error, Operation};
+
+impl<A: AbstractAgent> KongSwapAdaptor<A> {
+    /// A read-only solvency check -- Mango v4's health check, adapted to this adaptor's shape --
+    /// that previews what a withdrawal would return to the DAO right now, and errs if either
+    /// asset would come back below its caller-supplied minimum, without moving anything.
+    ///
+    /// This previews only the DEX side of a withdrawal: it queries the current LP balance, asks
+    /// KongSwap's `remove_liquidity_amounts` what that LP balance would convert to, and deducts
+    /// one ledger fee per asset -- the same deduction [`crate::ledger_api::
+    /// return_remaining_assets_to_owner`] applies to the final transfer out. It does not model
+    /// outstanding `claims`, the withdrawal timelock, or the per-window withdrawal-limit clamp
+    /// that a real `withdraw` may additionally apply, since none of those can be previewed without
+    /// either committing a real `remove_liquidity` call or re-deriving state no longer cached
+    /// here; a caller relying on this for more than an early warning should still expect `withdraw`
+    /// itself to be the source of truth.
+    ///
+    /// Like [`Self::assert_value_preserved`] and the other `value_guard` checks, the preview call
+    /// this makes is still recorded as a normal audit-trail transaction (see
+    /// `crate::emit_transaction`), but no managed balance is moved, so `StableBalances` itself is
+    /// left untouched.
+    pub async fn health_check(
+        &mut self,
+        min_amount_0_decimals: u64,
+        min_amount_1_decimals: u64,
+    ) -> Result<Balances, Error> {
+        let mut context = self.new_operation_context(Operation::Balances);
+
+        let (asset_0, asset_1) = self.assets();
+
+        let remove_lp_token_amount = self.lp_balance(&mut context).await;
+
+        let human_readable = format!(
+            "Calling KongSwapBackend.remove_liquidity_amounts to preview a withdrawal of LP \
+             token amount {} for `health_check`.",
+            remove_lp_token_amount
+        );
+
+        let preview = self
+            .emit_transaction(
+                &mut context,
+                *KONG_BACKEND_CANISTER_ID,
+                RemoveLiquidityAmountsArgs {
+                    token_0: asset_0.symbol(),
+                    token_1: asset_1.symbol(),
+                    remove_lp_token_amount,
+                },
+                human_readable,
+            )
+            .await
+            .map(
+                |RemoveLiquidityAmountsReply {
+                     amount_0, amount_1, ..
+                 }| {
+                    (
+                        decode_nat_to_u64(amount_0).unwrap_or_default(),
+                        decode_nat_to_u64(amount_1).unwrap_or_default(),
+                    )
+                },
+            );
+
+        // Unlike `deposit`/`withdraw`/`rebalance`, `health_check` never locks or mutates managed
+        // balances, so there's nothing for `Err` to roll back here -- finalizing unconditionally
+        // mirrors how `refresh_balances` (the other lock-free `Operation::Balances` caller)
+        // closes out its own audit-trail entry on both the success and failure path.
+        let result = preview.and_then(|(amount_0, amount_1)| {
+            let returnable_amount_0 = amount_0.saturating_sub(asset_0.ledger_fee_decimals());
+            let returnable_amount_1 = amount_1.saturating_sub(asset_1.ledger_fee_decimals());
+
+            if returnable_amount_0 < min_amount_0_decimals {
+                return Err(Error::new_precondition(format!(
+                    "{} withdrawable after fees ({}) would be below the required minimum ({}).",
+                    asset_0.symbol(),
+                    returnable_amount_0,
+                    min_amount_0_decimals
+                )));
+            }
+            if returnable_amount_1 < min_amount_1_decimals {
+                return Err(Error::new_precondition(format!(
+                    "{} withdrawable after fees ({}) would be below the required minimum ({}).",
+                    asset_1.symbol(),
+                    returnable_amount_1,
+                    min_amount_1_decimals
+                )));
+            }
+
+            Ok(Balances::from(self.get_cached_balances()))
+        });
+
+        self.finalize_audit_trail_transaction(context);
+
+        result
+    }
+}
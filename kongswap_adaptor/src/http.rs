@@ -0,0 +1,227 @@
+//! Serves the adaptor's state over plain HTTP, the way other NNS/SNS canisters expose an
+//! `http_request` query for operators and monitoring scrapers instead of requiring a candid call:
+//! `/metrics` (Prometheus text format, via `ic-metrics-encoder`, the nervous-system common crate
+//! for this) summarizes the audit trail and current balances; `/audit` renders the full
+//! [`AuditTrail`] as an HTML table for a human glancing at it in a browser; `/audit.json` returns
+//! the same trail as pretty-printed JSON for a script to consume; `/audit.txt` returns
+//! [`KongSwapAdaptor::get_human_readable_audit_report`]'s plain-text rendering, with ledger
+//! amounts scaled by each asset's own decimals instead of raw e8s, for an operator who just wants
+//! to read what happened.
+//!
+//! This sits on top of existing serialization helpers ([`kongswap_adaptor::audit::serialize_reply`]
+//! clamps a single transaction's result for storage; here, the whole trail is rendered for
+//! display) rather than replacing them.
+
+use crate::state::KongSwapAdaptor;
+use kongswap_adaptor::agent::AbstractAgent;
+use sns_treasury_manager::Operation;
+
+#[derive(candid::CandidType, serde::Deserialize, Clone, Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(candid::CandidType, serde::Deserialize, Clone, Debug)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn ok(content_type: &str, body: Vec<u8>) -> Self {
+        Self {
+            status_code: 200,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body,
+        }
+    }
+
+    fn not_found(path: &str) -> Self {
+        Self {
+            status_code: 404,
+            headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+            body: format!("No such path: {path}").into_bytes(),
+        }
+    }
+
+    fn internal_error(message: String) -> Self {
+        Self {
+            status_code: 500,
+            headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+            body: message.into_bytes(),
+        }
+    }
+}
+
+/// Dispatches on `request.url`'s path, ignoring any query string (none of the routes below take
+/// one).
+pub fn handle_http_request<A: AbstractAgent>(
+    kong_adaptor: &KongSwapAdaptor<A>,
+    request: HttpRequest,
+) -> HttpResponse {
+    let path = request.url.split('?').next().unwrap_or(&request.url);
+
+    match path {
+        "/metrics" => metrics_response(kong_adaptor),
+        "/audit" => audit_html_response(kong_adaptor),
+        "/audit.json" => audit_json_response(kong_adaptor),
+        "/audit.txt" => audit_text_response(kong_adaptor),
+        _ => HttpResponse::not_found(path),
+    }
+}
+
+fn metrics_response<A: AbstractAgent>(kong_adaptor: &KongSwapAdaptor<A>) -> HttpResponse {
+    let now_millis = ic_cdk::api::time() as i64 / 1_000_000;
+    let mut encoder = ic_metrics_encoder::MetricsEncoder::new(vec![], now_millis);
+
+    match encode_metrics(kong_adaptor, &mut encoder) {
+        Ok(()) => HttpResponse::ok("text/plain; version=0.0.4", encoder.into_inner()),
+        Err(err) => HttpResponse::internal_error(format!("Failed to encode metrics: {err}")),
+    }
+}
+
+fn encode_metrics<A: AbstractAgent, W: std::io::Write>(
+    kong_adaptor: &KongSwapAdaptor<A>,
+    encoder: &mut ic_metrics_encoder::MetricsEncoder<W>,
+) -> std::io::Result<()> {
+    let (counts, last_operation_timestamp_ns) = kong_adaptor.get_operation_counts();
+
+    let mut operations_total = encoder.counter_vec(
+        "kongswap_adaptor_operations_total",
+        "Number of operations recorded in the audit trail, by kind and outcome.",
+    )?;
+    let mut last_successful_operation_timestamp = encoder.gauge_vec(
+        "kongswap_adaptor_last_successful_operation_timestamp_seconds",
+        "Timestamp (seconds since the Unix epoch) of the most recent successful audit trail \
+         entry, by kind.",
+    )?;
+    let mut operation_locked = encoder.gauge_vec(
+        "kongswap_adaptor_operation_locked",
+        "Whether an unfinalized, unexpired transaction currently holds this operation's state \
+         lock (1) or not (0).",
+    )?;
+
+    for (name, operation, outcome_counts) in [
+        ("deposit", Operation::Deposit, counts.deposit),
+        ("withdraw", Operation::Withdraw, counts.withdraw),
+        ("balances", Operation::Balances, counts.balances),
+        ("issue_reward", Operation::IssueReward, counts.issue_reward),
+    ] {
+        operations_total.value(
+            &[("operation", name), ("outcome", "ok")],
+            outcome_counts.ok as f64,
+        )?;
+        operations_total.value(
+            &[("operation", name), ("outcome", "err")],
+            outcome_counts.err as f64,
+        )?;
+
+        if let Some(timestamp_ns) = outcome_counts.last_ok_timestamp_ns {
+            last_successful_operation_timestamp.value(
+                &[("operation", name)],
+                timestamp_ns as f64 / 1_000_000_000.0,
+            )?;
+        }
+
+        let locked = if kong_adaptor.is_operation_locked(operation) {
+            1.0
+        } else {
+            0.0
+        };
+        operation_locked.value(&[("operation", name)], locked)?;
+    }
+
+    if let Some(timestamp_ns) = last_operation_timestamp_ns {
+        encoder.encode_gauge(
+            "kongswap_adaptor_last_operation_timestamp_seconds",
+            timestamp_ns as f64 / 1_000_000_000.0,
+            "Timestamp (seconds since the Unix epoch) of the most recent audit trail entry.",
+        )?;
+    }
+
+    let balances = kong_adaptor.get_cached_balances();
+
+    let mut treasury_manager_balance = encoder.gauge_vec(
+        "kongswap_adaptor_treasury_manager_balance_decimals",
+        "Current treasury-manager (i.e. managed position) balance per asset, in the asset's own \
+         decimals.",
+    )?;
+    let mut external_custodian_balance = encoder.gauge_vec(
+        "kongswap_adaptor_external_custodian_balance_decimals",
+        "Current external-custodian balance per managed asset, in the asset's own decimals.",
+    )?;
+    let mut fee_collector_balance = encoder.gauge_vec(
+        "kongswap_adaptor_fee_collector_balance_decimals",
+        "Current fee-collector balance per managed asset, in the asset's own decimals.",
+    )?;
+
+    for (asset, book) in balances.asset_to_balances.iter() {
+        let symbol = asset.symbol();
+        treasury_manager_balance.value(
+            &[("asset", symbol.as_str())],
+            book.treasury_manager.amount_decimals as f64,
+        )?;
+        external_custodian_balance.value(&[("asset", symbol.as_str())], book.external as f64)?;
+        fee_collector_balance.value(&[("asset", symbol.as_str())], book.fee_collector as f64)?;
+    }
+
+    Ok(())
+}
+
+fn audit_json_response<A: AbstractAgent>(kong_adaptor: &KongSwapAdaptor<A>) -> HttpResponse {
+    let audit_trail = kong_adaptor.get_audit_trail();
+
+    match serde_json::to_string_pretty(&audit_trail.transactions) {
+        Ok(json) => HttpResponse::ok("application/json", json.into_bytes()),
+        Err(err) => HttpResponse::internal_error(format!("Failed to serialize audit trail: {err}")),
+    }
+}
+
+fn audit_text_response<A: AbstractAgent>(kong_adaptor: &KongSwapAdaptor<A>) -> HttpResponse {
+    let report = kong_adaptor.get_human_readable_audit_report();
+    HttpResponse::ok("text/plain; charset=utf-8", report.into_bytes())
+}
+
+fn audit_html_response<A: AbstractAgent>(kong_adaptor: &KongSwapAdaptor<A>) -> HttpResponse {
+    let audit_trail = kong_adaptor.get_audit_trail();
+
+    let mut rows = String::new();
+    for transaction in &audit_trail.transactions {
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            transaction.timestamp_ns,
+            transaction.treasury_manager_operation.operation.name(),
+            html_escape(&format!("{:?}", transaction.result)),
+            html_escape(&transaction.human_readable),
+        ));
+    }
+
+    let body = format!(
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head><title>KongSwapAdaptor audit trail</title></head>\n\
+         <body>\n\
+         <h1>Audit trail ({} entries)</h1>\n\
+         <table border=\"1\" cellpadding=\"4\" cellspacing=\"0\">\n\
+         <tr><th>Timestamp (ns)</th><th>Operation</th><th>Result</th><th>Description</th></tr>\n\
+         {}\
+         </table>\n\
+         </body>\n\
+         </html>\n",
+        audit_trail.transactions.len(),
+        rows,
+    );
+
+    HttpResponse::ok("text/html; charset=utf-8", body.into_bytes())
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
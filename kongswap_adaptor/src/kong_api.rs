@@ -1,7 +1,8 @@
 use crate::{
+    dex_backend::{DexBackend, KongSwapBackend},
     kong_types::{
-        kong_lp_balance_to_decimals, AddTokenArgs, UserBalanceLPReply, UserBalancesArgs,
-        UserBalancesReply,
+        kong_lp_balance_to_decimals, AddTokenArgs, ICReply, LPReply, PoolReply, PoolsArgs,
+        TokensArgs, TokensReply, UserBalanceLPReply, UserBalancesArgs, UserBalancesReply,
     },
     log_err, KongSwapAdaptor, KONG_BACKEND_CANISTER_ID,
 };
@@ -9,10 +10,155 @@ use candid::{Nat, Principal};
 use kongswap_adaptor::{agent::AbstractAgent, audit::OperationContext};
 use sns_treasury_manager::Error;
 
+/// Fallback LP token precision used only if `tokens()` fails to report the real value, matching
+/// Kong's historical LP token decimals.
+const DEFAULT_LP_TOKEN_DECIMALS: u8 = 8;
+
 impl<A: AbstractAgent> KongSwapAdaptor<A> {
     pub fn lp_token(&self) -> String {
         let (asset_0, asset_1) = self.assets();
-        format!("{}_{}", asset_0.symbol(), asset_1.symbol())
+        KongSwapBackend::new(*KONG_BACKEND_CANISTER_ID)
+            .lp_token_symbol(&asset_0.symbol(), &asset_1.symbol())
+    }
+
+    /// Looks up the LP token's real `decimals` via `tokens()`, falling back to
+    /// [`DEFAULT_LP_TOKEN_DECIMALS`] if the call fails or the LP token isn't found, so a balance
+    /// conversion never hard-codes a guessed precision.
+    async fn lp_decimals(&mut self, context: &mut OperationContext) -> u8 {
+        let request = TokensArgs {
+            symbol: Some(self.lp_token()),
+        };
+
+        let human_readable = format!(
+            "Calling KongSwapBackend.tokens to look up decimals for LP token {}.",
+            self.lp_token()
+        );
+
+        let result = self
+            .emit_transaction(context, *KONG_BACKEND_CANISTER_ID, request, human_readable)
+            .await;
+
+        let replies = match result {
+            Ok(replies) => replies,
+            Err(err) => {
+                log_err(&format!(
+                    "Failed to call KongSwapBackend.tokens to look up decimals for LP token {}: \
+                     {}. Defaulting to {}.",
+                    self.lp_token(),
+                    err.message,
+                    DEFAULT_LP_TOKEN_DECIMALS,
+                ));
+                return DEFAULT_LP_TOKEN_DECIMALS;
+            }
+        };
+
+        replies
+            .into_iter()
+            .find_map(|reply| match reply {
+                TokensReply::LP(LPReply {
+                    symbol, decimals, ..
+                }) if symbol == self.lp_token() => Some(decimals),
+                _ => None,
+            })
+            .unwrap_or_else(|| {
+                log_err(&format!(
+                    "KongSwapBackend.tokens didn't report decimals for LP token {}. Defaulting \
+                     to {}.",
+                    self.lp_token(),
+                    DEFAULT_LP_TOKEN_DECIMALS,
+                ));
+                DEFAULT_LP_TOKEN_DECIMALS
+            })
+    }
+
+    /// Looks up the current reserves (`balance_0`, `balance_1`) of this adaptor's pool via
+    /// `pools()`, returning `None` if the call fails or the pool doesn't exist yet (e.g. the very
+    /// first deposit, before `add_pool` has ever been called) -- there's nothing to compare a
+    /// deposit's price against in that case.
+    pub(crate) async fn pool_reserves(
+        &mut self,
+        context: &mut OperationContext,
+    ) -> Option<(Nat, Nat)> {
+        let request = PoolsArgs {
+            symbol: Some(self.lp_token()),
+        };
+
+        let human_readable = format!(
+            "Calling KongSwapBackend.pools to look up reserves for pool {}.",
+            self.lp_token()
+        );
+
+        let result = self
+            .emit_transaction(context, *KONG_BACKEND_CANISTER_ID, request, human_readable)
+            .await;
+
+        let replies = match result {
+            Ok(replies) => replies,
+            Err(err) => {
+                log_err(&format!(
+                    "Failed to call KongSwapBackend.pools to look up reserves for pool {}: {}.",
+                    self.lp_token(),
+                    err.message,
+                ));
+                return None;
+            }
+        };
+
+        replies.into_iter().find_map(
+            |PoolReply {
+                 symbol,
+                 balance_0,
+                 balance_1,
+                 ..
+             }| {
+                if symbol == self.lp_token() {
+                    Some((balance_0, balance_1))
+                } else {
+                    None
+                }
+            },
+        )
+    }
+
+    /// Looks up whether `ledger_canister_id` is already registered with the DEX via `tokens()`,
+    /// letting [`Self::maybe_add_token`] skip issuing `add_token` for a token that's already known
+    /// instead of relying solely on the "already exists" error it tolerates. Returns `false`
+    /// (rather than aborting the deposit) if the query itself fails -- `maybe_add_token`'s
+    /// existing tolerated-error handling is still there as a fallback in that case.
+    async fn token_is_registered(
+        &mut self,
+        context: &mut OperationContext,
+        ledger_canister_id: Principal,
+    ) -> bool {
+        let request = TokensArgs { symbol: None };
+
+        let human_readable = format!(
+            "Calling KongSwapBackend.tokens to check whether ledger {} is already registered.",
+            ledger_canister_id
+        );
+
+        let result = self
+            .emit_transaction(context, *KONG_BACKEND_CANISTER_ID, request, human_readable)
+            .await;
+
+        let replies = match result {
+            Ok(replies) => replies,
+            Err(err) => {
+                log_err(&format!(
+                    "Failed to call KongSwapBackend.tokens to check whether ledger {} is \
+                     already registered: {}.",
+                    ledger_canister_id, err.message,
+                ));
+                return false;
+            }
+        };
+
+        let ledger_canister_id = ledger_canister_id.to_string();
+
+        replies.into_iter().any(|reply| match reply {
+            TokensReply::IC(ICReply { canister_id, .. }) => canister_id == ledger_canister_id,
+            _ => false,
+        })
     }
 
     pub async fn maybe_add_token(
@@ -20,7 +166,11 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
         context: &mut OperationContext,
         ledger_canister_id: Principal,
     ) -> Result<(), Error> {
-        let token = format!("IC.{}", ledger_canister_id);
+        if self.token_is_registered(context, ledger_canister_id).await {
+            return Ok(());
+        }
+
+        let token = KongSwapBackend::new(*KONG_BACKEND_CANISTER_ID).token_name(ledger_canister_id);
 
         let human_readable = format!(
             "Calling KongSwapBackend.add_token to attempt to add {}.",
@@ -33,7 +183,7 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
 
         let response = self
             .emit_transaction(
-                context.next_operation(),
+                context,
                 *KONG_BACKEND_CANISTER_ID,
                 request,
                 human_readable,
@@ -59,7 +209,7 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
 
         let result = self
             .emit_transaction(
-                context.next_operation(),
+                context,
                 *KONG_BACKEND_CANISTER_ID,
                 request,
                 human_readable,
@@ -84,21 +234,30 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
                  symbol, balance, ..
              })| {
                 if symbol == self.lp_token() {
-                    Some(kong_lp_balance_to_decimals(balance))
+                    Some(balance)
                 } else {
                     None
                 }
             },
         );
 
-        if let Some(lp_balance) = lp_balance {
-            lp_balance
-        } else {
+        let Some(lp_balance) = lp_balance else {
             log_err(&format!(
                 "Failed to get LP balance for {}. Defaulting to 0.",
                 self.lp_token(),
             ));
+            return Nat::from(0_u8);
+        };
+
+        let decimals = self.lp_decimals(context).await;
+
+        kong_lp_balance_to_decimals(lp_balance, decimals).unwrap_or_else(|err| {
+            log_err(&format!(
+                "Failed to convert LP balance for {}: {}. Defaulting to 0.",
+                self.lp_token(),
+                err
+            ));
             Nat::from(0_u8)
-        }
+        })
     }
 }
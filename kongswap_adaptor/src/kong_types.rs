@@ -4,26 +4,250 @@ use sns_treasury_manager::{TransactionWitness, Transfer};
 
 use crate::agent::Request;
 
-const E8: u64 = 100_000_000; // 10^8, used for converting LP balances to decimals
+// ----------------- begin:parsed_kong_witness -----------------
+/// A typed, versioned witness for a Kong backend call whose [`TransactionWitness`] isn't a
+/// ledger transfer, serialized to JSON via [`serde_json::to_string`] instead of `format!("{:?}",
+/// ...)`. Mirrors the pattern Solana's transaction-status crate uses for `UiInstruction` /
+/// `ParsedInstruction`: one variant per Kong method, tagged by method name, carrying the fields
+/// worth recording in the audit trail rather than the raw reply. Downstream auditing tools get a
+/// stable schema to parse instead of Rust `Debug` output, which can change between compiler
+/// versions.
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum ParsedKongWitness {
+    #[serde(rename_all = "camelCase")]
+    AddLiquidityAmounts {
+        symbol: String,
+        symbol_0: String,
+        amount_0: Nat,
+        symbol_1: String,
+        amount_1: Nat,
+        add_lp_token_amount: Nat,
+    },
+    #[serde(rename_all = "camelCase")]
+    AddToken {
+        canister_id: String,
+        symbol: String,
+        decimals: u8,
+    },
+    #[serde(rename_all = "camelCase")]
+    UpdateToken {
+        canister_id: String,
+        symbol: String,
+        decimals: u8,
+    },
+    #[serde(rename_all = "camelCase")]
+    Tokens { tokens: Vec<ParsedKongToken> },
+    #[serde(rename_all = "camelCase")]
+    Pools { pools: Vec<ParsedKongPool> },
+    #[serde(rename_all = "camelCase")]
+    RemoveLiquidityAmounts {
+        symbol: String,
+        symbol_0: String,
+        amount_0: Nat,
+        symbol_1: String,
+        amount_1: Nat,
+        remove_lp_token_amount: Nat,
+    },
+    #[serde(rename_all = "camelCase")]
+    UserBalances {
+        balances: Vec<ParsedKongUserBalance>,
+    },
+}
 
-// ----------------- begin:add_liquidity_amounts -----------------
-pub fn kong_lp_balance_to_decimals(lp_balance: f64) -> Result<Nat, String> {
-    // Check that lp_balance is valid before conversion
-    if !lp_balance.is_finite() || lp_balance < 0.0 {
-        return Err("Invalid LP balance value".to_string());
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParsedKongToken {
+    pub chain: String,
+    pub canister_id: String,
+    pub symbol: String,
+}
+
+impl From<&TokensReply> for ParsedKongToken {
+    fn from(reply: &TokensReply) -> Self {
+        match reply {
+            TokensReply::IC(ICReply {
+                chain,
+                canister_id,
+                symbol,
+                ..
+            }) => Self {
+                chain: chain.clone(),
+                canister_id: canister_id.clone(),
+                symbol: symbol.clone(),
+            },
+            TokensReply::LP(LPReply {
+                chain,
+                address,
+                symbol,
+                ..
+            }) => Self {
+                chain: chain.clone(),
+                canister_id: address.clone(),
+                symbol: symbol.clone(),
+            },
+        }
+    }
+}
+
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParsedKongPool {
+    pub symbol: String,
+    pub chain_0: String,
+    pub symbol_0: String,
+    pub balance_0: Nat,
+    pub chain_1: String,
+    pub symbol_1: String,
+    pub balance_1: Nat,
+}
+
+impl From<&PoolReply> for ParsedKongPool {
+    fn from(reply: &PoolReply) -> Self {
+        let PoolReply {
+            symbol,
+            chain_0,
+            symbol_0,
+            balance_0,
+            chain_1,
+            symbol_1,
+            balance_1,
+            ..
+        } = reply;
+
+        Self {
+            symbol: symbol.clone(),
+            chain_0: chain_0.clone(),
+            symbol_0: symbol_0.clone(),
+            balance_0: balance_0.clone(),
+            chain_1: chain_1.clone(),
+            symbol_1: symbol_1.clone(),
+            balance_1: balance_1.clone(),
+        }
+    }
+}
+
+/// `UserBalanceLPReply` doesn't report the per-asset `decimals` the way `tokens()`'s `LPReply`
+/// does, so its `f64` amounts are parsed at Kong's de facto standard precision for LP-pooled
+/// assets rather than a value we actually looked up.
+const DEFAULT_KONG_ASSET_DECIMALS: u8 = 8;
+
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParsedKongUserBalance {
+    pub symbol: String,
+    pub chain_0: String,
+    pub symbol_0: String,
+    pub amount_0: KongTokenAmount,
+    pub chain_1: String,
+    pub symbol_1: String,
+    pub amount_1: KongTokenAmount,
+}
+
+impl TryFrom<&UserBalanceLPReply> for ParsedKongUserBalance {
+    type Error = String;
+
+    fn try_from(reply: &UserBalanceLPReply) -> Result<Self, Self::Error> {
+        let UserBalanceLPReply {
+            symbol,
+            chain_0,
+            symbol_0,
+            amount_0,
+            chain_1,
+            symbol_1,
+            amount_1,
+            ..
+        } = reply;
+
+        Ok(Self {
+            symbol: symbol.clone(),
+            chain_0: chain_0.clone(),
+            symbol_0: symbol_0.clone(),
+            amount_0: KongTokenAmount::from_f64(*amount_0, DEFAULT_KONG_ASSET_DECIMALS)?,
+            chain_1: chain_1.clone(),
+            symbol_1: symbol_1.clone(),
+            amount_1: KongTokenAmount::from_f64(*amount_1, DEFAULT_KONG_ASSET_DECIMALS)?,
+        })
+    }
+}
+
+fn parsed_kong_witness(parsed: &ParsedKongWitness) -> TransactionWitness {
+    let json = serde_json::to_string(parsed)
+        .unwrap_or_else(|err| format!("failed to serialize ParsedKongWitness: {}", err));
+
+    TransactionWitness::NonLedger(json)
+}
+// ----------------- end:parsed_kong_witness -----------------
+
+// ----------------- begin:kong_token_amount -----------------
+/// A Kong token amount recorded as raw base units, mirroring the representation Solana's
+/// `UiTokenAmount` uses (a raw integer amount, a `decimals` field, and a human-readable string)
+/// rather than a bare `f64`. Named distinctly from the crate's accounting
+/// [`TokenAmount`](crate::token_amount::TokenAmount) -- this type exists only to carry Kong's
+/// `f64`-reported amounts without losing precision to floating-point multiplication, not to
+/// participate in the checked treasury arithmetic the other type guarantees.
+#[derive(CandidType, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KongTokenAmount {
+    pub amount: Nat,
+    pub decimals: u8,
+    pub ui_amount_string: String,
+}
+
+impl KongTokenAmount {
+    /// Parses a decimal string such as `"1.23"` into raw base units at `decimals` digits of
+    /// precision, using only integer arithmetic: split on the decimal point, then right-pad or
+    /// truncate the fractional part to `decimals` digits before concatenating and parsing as a
+    /// [`Nat`]. This avoids the rounding a float multiplication (e.g. `1.23 * 1e8`) can introduce.
+    pub fn parse_decimal(ui_amount_string: &str, decimals: u8) -> Result<Self, String> {
+        let (whole, fractional) = ui_amount_string
+            .split_once('.')
+            .unwrap_or((ui_amount_string, ""));
+
+        let mut fractional = fractional.to_string();
+        fractional.truncate(decimals as usize);
+        while fractional.len() < decimals as usize {
+            fractional.push('0');
+        }
+
+        let digits = format!("{whole}{fractional}");
+        let digits = if digits.is_empty() { "0" } else { digits.as_str() };
+
+        let amount = digits.parse::<Nat>().map_err(|err| {
+            format!(
+                "Failed to parse Kong token amount {:?} as {} decimals: {}",
+                ui_amount_string, decimals, err
+            )
+        })?;
+
+        Ok(Self {
+            amount,
+            decimals,
+            ui_amount_string: ui_amount_string.to_string(),
+        })
     }
 
-    // Calculate with overflow checking
-    let e8_value = E8 as f64;
-    let result_f64 = lp_balance * e8_value;
+    /// Like [`Self::parse_decimal`], but takes the `f64` Kong itself returns. The float is only
+    /// ever used to produce a decimal string (via `Display`) -- the base-unit amount is still
+    /// derived through [`Self::parse_decimal`]'s integer arithmetic, so it is never off by the
+    /// ULP a `lp_balance * 10^decimals` float multiplication could introduce.
+    pub fn from_f64(ui_amount: f64, decimals: u8) -> Result<Self, String> {
+        if !ui_amount.is_finite() || ui_amount < 0.0 {
+            return Err(format!("Invalid Kong token amount: {}", ui_amount));
+        }
 
-    // Ensure the result fits in u64 range
-    if result_f64 > u64::MAX as f64 {
-        return Err("LP balance conversion exceeds u64 maximum".to_string());
+        Self::parse_decimal(&format!("{ui_amount}"), decimals)
     }
+}
+// ----------------- end:kong_token_amount -----------------
 
-    // Convert to Nat (safe because we've checked the bounds)
-    Ok(Nat::from(result_f64.round() as u64))
+// ----------------- begin:add_liquidity_amounts -----------------
+/// Converts a Kong LP balance (an `f64`, as Kong reports it) to raw base units at `decimals`
+/// digits of precision -- the real decimals Kong's `tokens()` reports for the LP token, not a
+/// hard-coded assumption -- via [`KongTokenAmount::from_f64`]'s integer-arithmetic parsing, so
+/// the conversion is never off by the ULP a `lp_balance * 10^decimals` float multiplication could
+/// introduce.
+pub fn kong_lp_balance_to_decimals(lp_balance: f64, decimals: u8) -> Result<Nat, String> {
+    KongTokenAmount::from_f64(lp_balance, decimals).map(|token_amount| token_amount.amount)
 }
 
 #[derive(CandidType, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -79,8 +303,14 @@ impl Request for AddLiquidityAmountsArgs {
     ) -> Result<(TransactionWitness, Self::Ok), String> {
         let reply = response?;
 
-        // TODO: Use serde_json::to_string
-        let witness = TransactionWitness::NonLedger(format!("{:?}", reply));
+        let witness = parsed_kong_witness(&ParsedKongWitness::AddLiquidityAmounts {
+            symbol: reply.symbol.clone(),
+            symbol_0: reply.symbol_0.clone(),
+            amount_0: reply.amount_0.clone(),
+            symbol_1: reply.symbol_1.clone(),
+            amount_1: reply.amount_1.clone(),
+            add_lp_token_amount: reply.add_lp_token_amount.clone(),
+        });
 
         Ok((witness, reply))
     }
@@ -112,9 +342,7 @@ impl Request for AddLiquidityArgs {
     ) -> Result<(TransactionWitness, Self::Ok), String> {
         let reply = response?;
 
-        let transfers = reply.transfer_ids.iter().map(Transfer::from).collect();
-
-        let witness = TransactionWitness::Ledger(transfers);
+        let witness = transfer_witness(&reply.transfer_ids);
 
         Ok((witness, reply))
     }
@@ -134,6 +362,10 @@ pub struct AddLiquidityArgs {
     pub token_1: String,
     pub amount_1: Nat,
     pub tx_id_1: Option<TxId>,
+    /// Caller-supplied correlation memo, echoed back on [`ICTransferReply::memo`] so the transfers
+    /// this call produces can be tied back to the treasury-manager operation that caused them.
+    #[serde(default)]
+    pub memo: Option<Vec<u8>>,
 }
 
 #[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
@@ -165,6 +397,7 @@ pub struct TransferIdReply {
 #[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
 pub enum TransferReply {
     IC(ICTransferReply),
+    Solana(SolanaTransferReply),
 }
 
 #[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
@@ -175,6 +408,23 @@ pub struct ICTransferReply {
     pub amount: Nat,
     pub canister_id: String,
     pub block_index: Nat,
+    /// The memo the underlying ledger transfer carried, if Kong reports one. `Option` so a Kong
+    /// canister that doesn't yet populate this field still decodes under Candid's record
+    /// subtyping (an absent field decodes as `None`).
+    #[serde(default)]
+    pub memo: Option<Vec<u8>>,
+}
+
+/// A transfer settled on a chain that, unlike the IC, has no integer block index to key on —
+/// Solana's transaction-status types identify a settled transfer by `Signature` instead, so this
+/// mirrors that rather than forcing the signature into `ICTransferReply::block_index`.
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
+pub struct SolanaTransferReply {
+    pub chain: String,
+    pub symbol: String,
+    pub is_send: bool,
+    pub amount: Nat,
+    pub signature: String,
 }
 // ----------------- end:add_liquidity -----------------
 
@@ -203,8 +453,18 @@ impl Request for AddTokenArgs {
     ) -> Result<(TransactionWitness, Self::Ok), String> {
         let reply = response?;
 
-        // TODO: Use serde_json::to_string
-        let witness = TransactionWitness::NonLedger(format!("{:?}", self));
+        let AddTokenReply::IC(ICReply {
+            canister_id,
+            symbol,
+            decimals,
+            ..
+        }) = &reply;
+
+        let witness = parsed_kong_witness(&ParsedKongWitness::AddToken {
+            canister_id: canister_id.clone(),
+            symbol: symbol.clone(),
+            decimals: *decimals,
+        });
 
         Ok((witness, reply))
     }
@@ -272,8 +532,18 @@ impl Request for UpdateTokenArgs {
     ) -> Result<(TransactionWitness, Self::Ok), String> {
         let reply = response?;
 
-        // TODO: Use serde_json::to_string
-        let witness = TransactionWitness::NonLedger(format!("{:?}", self));
+        let UpdateTokenReply::IC(ICReply {
+            canister_id,
+            symbol,
+            decimals,
+            ..
+        }) = &reply;
+
+        let witness = parsed_kong_witness(&ParsedKongWitness::UpdateToken {
+            canister_id: canister_id.clone(),
+            symbol: symbol.clone(),
+            decimals: *decimals,
+        });
 
         Ok((witness, reply))
     }
@@ -305,9 +575,7 @@ impl Request for AddPoolArgs {
     ) -> Result<(TransactionWitness, Self::Ok), String> {
         let reply = response?;
 
-        let transfers = reply.transfer_ids.iter().map(Transfer::from).collect();
-
-        let witness = TransactionWitness::Ledger(transfers);
+        let witness = transfer_witness(&reply.transfer_ids);
 
         Ok((witness, reply))
     }
@@ -340,31 +608,53 @@ pub struct AddPoolReply {
     pub ts: u64,
 }
 
-impl From<&TransferIdReply> for Transfer {
-    fn from(transfer_id_reply: &TransferIdReply) -> Self {
+// `sns_treasury_manager::Transfer` has no `memo` field to carry `ICTransferReply::memo` through,
+// so it's dropped here along with `chain`/`symbol`/`is_send`, exactly like those already are.
+//
+// `Transfer` also has no field for a Solana-style signature, so this is fallible: a
+// `TransferReply::Solana` leg has no `Transfer` representation and is reported back as an error
+// instead of being mis-mapped onto `block_index`.
+impl TryFrom<&TransferIdReply> for Transfer {
+    type Error = SolanaTransferReply;
+
+    fn try_from(transfer_id_reply: &TransferIdReply) -> Result<Self, Self::Error> {
         let TransferIdReply {
             transfer_id: _,
-            transfer:
-                TransferReply::IC(ICTransferReply {
-                    amount,
-                    canister_id,
-                    block_index,
-                    ..
-                }),
+            transfer,
         } = transfer_id_reply;
 
-        let ledger_canister_id = canister_id.clone();
-        let amount_deimals = amount.clone();
-        let block_index = block_index.clone();
-
-        Self {
-            ledger_canister_id,
-            amount_decimals: amount_deimals,
-            block_index,
+        match transfer {
+            TransferReply::IC(ICTransferReply {
+                amount,
+                canister_id,
+                block_index,
+                ..
+            }) => Ok(Self {
+                ledger_canister_id: canister_id.clone(),
+                amount_decimals: amount.clone(),
+                block_index: block_index.clone(),
+            }),
+            TransferReply::Solana(solana_transfer_reply) => Err(solana_transfer_reply.clone()),
         }
     }
 }
 
+/// Builds the witness for a Kong call whose reply carries `transfer_ids`. When every leg settled
+/// on an IC ledger, this is the usual [`TransactionWitness::Ledger`]; when any leg settled on a
+/// chain with no ledger block index to key on (see [`SolanaTransferReply`]), there's no `Transfer`
+/// to hold it, so the whole reply falls back to [`TransactionWitness::NonLedger`] rather than
+/// dropping or mis-mapping the non-IC leg.
+fn transfer_witness(transfer_ids: &[TransferIdReply]) -> TransactionWitness {
+    match transfer_ids
+        .iter()
+        .map(Transfer::try_from)
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(transfers) => TransactionWitness::Ledger(transfers),
+        Err(_) => TransactionWitness::NonLedger(format!("{:?}", transfer_ids)),
+    }
+}
+
 #[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
 pub struct AddPoolArgs {
     pub token_0: String,
@@ -374,6 +664,10 @@ pub struct AddPoolArgs {
     pub amount_1: Nat,
     pub tx_id_1: Option<TxId>,
     pub lp_fee_bps: Option<u8>,
+    /// Caller-supplied correlation memo, echoed back on [`ICTransferReply::memo`] so the transfers
+    /// this call produces can be tied back to the treasury-manager operation that caused them.
+    #[serde(default)]
+    pub memo: Option<Vec<u8>>,
 }
 // ----------------- end:add_pool -----------------
 
@@ -402,14 +696,16 @@ impl Request for TokensArgs {
     ) -> Result<(TransactionWitness, Self::Ok), String> {
         let reply = response?;
 
-        // TODO: Use serde_json::to_string
-        let witness = TransactionWitness::NonLedger(format!("{:?}", reply));
+        let witness = parsed_kong_witness(&ParsedKongWitness::Tokens {
+            tokens: reply.iter().map(ParsedKongToken::from).collect(),
+        });
 
         Ok((witness, reply))
     }
 }
 
-struct TokensArgs {
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
+pub struct TokensArgs {
     pub symbol: Option<String>,
 }
 
@@ -459,14 +755,16 @@ impl Request for PoolsArgs {
     ) -> Result<(TransactionWitness, Self::Ok), String> {
         let reply = response?;
 
-        // TODO: Use serde_json::to_string
-        let witness = TransactionWitness::NonLedger(format!("{:?}", reply));
+        let witness = parsed_kong_witness(&ParsedKongWitness::Pools {
+            pools: reply.iter().map(ParsedKongPool::from).collect(),
+        });
 
         Ok((witness, reply))
     }
 }
 
-struct PoolsArgs {
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
+pub struct PoolsArgs {
     pub symbol: Option<String>,
 }
 
@@ -523,8 +821,14 @@ impl Request for RemoveLiquidityAmountsArgs {
     ) -> Result<(TransactionWitness, Self::Ok), String> {
         let reply = response?;
 
-        // TODO: Use serde_json::to_string
-        let witness = TransactionWitness::NonLedger(format!("{:?}", reply));
+        let witness = parsed_kong_witness(&ParsedKongWitness::RemoveLiquidityAmounts {
+            symbol: reply.symbol.clone(),
+            symbol_0: reply.symbol_0.clone(),
+            amount_0: reply.amount_0.clone(),
+            symbol_1: reply.symbol_1.clone(),
+            amount_1: reply.amount_1.clone(),
+            remove_lp_token_amount: reply.remove_lp_token_amount.clone(),
+        });
 
         Ok((witness, reply))
     }
@@ -579,9 +883,7 @@ impl Request for RemoveLiquidityArgs {
     ) -> Result<(TransactionWitness, Self::Ok), String> {
         let reply = response?;
 
-        let transfers = reply.transfer_ids.iter().map(Transfer::from).collect();
-
-        let witness = TransactionWitness::Ledger(transfers);
+        let witness = transfer_witness(&reply.transfer_ids);
 
         Ok((witness, reply))
     }
@@ -614,6 +916,10 @@ pub struct RemoveLiquidityArgs {
     pub token_0: String,
     pub token_1: String,
     pub remove_lp_token_amount: Nat,
+    /// Caller-supplied correlation memo, echoed back on [`ICTransferReply::memo`] so the transfers
+    /// this call produces can be tied back to the treasury-manager operation that caused them.
+    #[serde(default)]
+    pub memo: Option<Vec<u8>>,
 }
 // ----------------- end:liquidity_amounts -----------------
 
@@ -642,15 +948,14 @@ impl Request for UserBalancesArgs {
     ) -> Result<(TransactionWitness, Self::Ok), String> {
         let replies = response?;
 
-        let witnesses = replies
+        let balances = replies
             .iter()
             .map(|UserBalancesReply::LP(user_balance_lp_reply)| {
-                // TODO: Use serde_json::to_string
-                format!("{:?}", user_balance_lp_reply)
+                ParsedKongUserBalance::try_from(user_balance_lp_reply)
             })
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>, String>>()?;
 
-        let witness = TransactionWitness::NonLedger(witnesses.join(", "));
+        let witness = parsed_kong_witness(&ParsedKongWitness::UserBalances { balances });
 
         Ok((witness, replies))
     }
@@ -687,3 +992,124 @@ pub struct UserBalanceLPReply {
 }
 
 // ----------------- end:user_balances -----------------
+
+// ----------------- begin:swap_amounts -----------------
+impl Request for SwapAmountsArgs {
+    fn method(&self) -> &'static str {
+        "swap_amounts"
+    }
+
+    fn update(&self) -> bool {
+        false
+    }
+
+    fn payload(&self) -> Result<Vec<u8>, candid::Error> {
+        let Self {
+            pay_token,
+            pay_amount,
+            receive_token,
+        } = self;
+
+        candid::encode_args((pay_token, pay_amount, receive_token))
+    }
+
+    type Response = Result<SwapAmountsReply, String>;
+
+    type Ok = SwapAmountsReply;
+
+    fn transaction_witness(
+        &self,
+        _canister_id: candid::Principal,
+        response: Self::Response,
+    ) -> Result<(TransactionWitness, Self::Ok), String> {
+        let reply = response?;
+
+        // TODO: Use serde_json::to_string
+        let witness = TransactionWitness::NonLedger(format!("{:?}", reply));
+
+        Ok((witness, reply))
+    }
+}
+
+#[derive(CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct SwapAmountsArgs {
+    pub pay_token: String,
+    pub pay_amount: Nat,
+    pub receive_token: String,
+}
+
+#[derive(CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct SwapAmountsReply {
+    pub pay_chain: String,
+    pub pay_symbol: String,
+    pub pay_amount: Nat,
+    pub receive_chain: String,
+    pub receive_symbol: String,
+    pub receive_amount: Nat,
+    pub price: f64,
+    pub mid_price: f64,
+    pub slippage: f64,
+}
+// ----------------- end:swap_amounts -----------------
+
+// ----------------- begin:swap -----------------
+impl Request for SwapArgs {
+    fn method(&self) -> &'static str {
+        "swap"
+    }
+
+    fn update(&self) -> bool {
+        true
+    }
+
+    fn payload(&self) -> Result<Vec<u8>, candid::Error> {
+        candid::encode_one(self)
+    }
+
+    type Response = Result<SwapReply, String>;
+
+    type Ok = SwapReply;
+
+    fn transaction_witness(
+        &self,
+        _canister_id: candid::Principal,
+        response: Self::Response,
+    ) -> Result<(TransactionWitness, Self::Ok), String> {
+        let reply = response?;
+
+        let witness = transfer_witness(&reply.transfer_ids);
+
+        Ok((witness, reply))
+    }
+}
+
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
+pub struct SwapArgs {
+    pub pay_token: String,
+    pub pay_amount: Nat,
+    pub pay_tx_id: Option<TxId>,
+    pub receive_token: String,
+    pub receive_amount: Option<Nat>,
+    pub receive_address: Option<String>,
+    pub max_slippage: Option<f64>,
+}
+
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
+pub struct SwapReply {
+    pub tx_id: u64,
+    pub request_id: u64,
+    pub status: String,
+    pub pay_chain: String,
+    pub pay_symbol: String,
+    pub pay_amount: Nat,
+    pub receive_chain: String,
+    pub receive_symbol: String,
+    pub receive_amount: Nat,
+    pub mid_price: f64,
+    pub price: f64,
+    pub slippage: f64,
+    pub transfer_ids: Vec<TransferIdReply>,
+    pub claim_ids: Vec<u64>,
+    pub ts: u64,
+}
+// ----------------- end:swap -----------------
@@ -1,47 +1,207 @@
 use crate::{
     balances::{Party, ValidatedBalances},
-    state::KongSwapAdaptor,
+    log_err,
+    state::{
+        storage::{StableTransaction, GENESIS_PREV_HASH},
+        KongSwapAdaptor,
+    },
     tx_error_codes::TransactionErrorCodes,
-    validation::{decode_nat_to_u64, ValidatedAsset},
+    validation::{decode_nat_to_u64, LedgerProtocol, ValidatedAsset},
+};
+use candid::{CandidType, Nat, Principal};
+use ic_ledger_types::{
+    AccountBalanceArgs, AccountIdentifier, Memo as LegacyMemo, Subaccount, TimeStamp, Tokens,
+    TransferArgs, DEFAULT_SUBACCOUNT,
 };
-use candid::Nat;
 use icrc_ledger_types::icrc1::{
     account::Account,
     transfer::{Memo, TransferArg},
 };
-use kongswap_adaptor::agent::AbstractAgent;
-use sns_treasury_manager::{Error, ErrorKind, TreasuryManager, TreasuryManagerOperation};
+use kongswap_adaptor::{
+    agent::{AbstractAgent, Request},
+    requests::CommitStateRequest,
+};
+use sha2::{Digest, Sha256};
+use sns_treasury_manager::{
+    Error, ErrorKind, TransactionWitness, TreasuryManager, TreasuryManagerOperation,
+};
+use std::fmt::Debug;
+
+/// Converts an ICRC-1 [`Account`] into the classic ledger's [`AccountIdentifier`], for ledgers
+/// that only expose the legacy `transfer`/`account_balance` interface (see
+/// [`LedgerProtocol::Legacy`]). ICRC-1's `None` subaccount and the legacy ledger's all-zero
+/// `DEFAULT_SUBACCOUNT` both denote the same default account, so they map onto each other.
+pub(crate) fn account_to_account_identifier(account: &Account) -> AccountIdentifier {
+    let subaccount = account
+        .subaccount
+        .map(Subaccount)
+        .unwrap_or(DEFAULT_SUBACCOUNT);
+
+    AccountIdentifier::new(&account.owner, &subaccount)
+}
+
+/// Derives a deterministic classic-ledger `Memo` from `operation`, mirroring the ICRC-1 path's
+/// `Memo::from(Vec::<u8>::from(operation))`: the classic ledger's `Memo` is a bare `u64` rather
+/// than an arbitrary byte vector, so this hashes the same `Vec<u8>` encoding down to 8 bytes
+/// instead of reusing it directly.
+fn legacy_memo_for(operation: TreasuryManagerOperation) -> LegacyMemo {
+    let encoded = Vec::<u8>::from(operation);
+
+    let mut hasher = Sha256::new();
+    hasher.update(&encoded);
+    let digest = hasher.finalize();
+
+    LegacyMemo(u64::from_le_bytes(
+        digest[0..8].try_into().expect("a SHA-256 digest is at least 8 bytes"),
+    ))
+}
 
 impl<A: AbstractAgent> KongSwapAdaptor<A> {
-    async fn get_ledger_balance_decimals(
+    /// The network-calling half of a ledger balance query: makes the inter-canister call and
+    /// decodes its `TransactionWitness`/reply, without touching the audit trail. Takes `&self`
+    /// (rather than `&mut self`, like [`Self::emit_transaction`]) specifically so that two of
+    /// these can be issued as concurrent futures -- see [`Self::issue_ledger_balance_query`] --
+    /// with [`Self::record_ledger_query`] applying each one's audit-trail side effect afterward,
+    /// one at a time, once both have resolved.
+    async fn call_ledger_query<R>(
+        &self,
+        canister_id: Principal,
+        request: R,
+    ) -> (Result<TransactionWitness, Error>, Result<R::Ok, Error>)
+    where
+        R: Request + Clone + CandidType + Debug,
+    {
+        let call_result = unsafe {
+            let agent = self.agent.0.get();
+            (*agent)
+                .call(canister_id, request.clone())
+                .await
+                .map_err(|error| {
+                    Error::new_call(request.method().to_string(), canister_id, error.to_string())
+                })
+        };
+
+        match call_result {
+            Err(err) => (Err(err.clone()), Err(err)),
+            Ok(response) => {
+                let res = request
+                    .transaction_witness(canister_id, response)
+                    .map_err(|err| Error::new_backend(err.to_string()));
+
+                match res {
+                    Err(err) => (Err(err.clone()), Err(err)),
+                    Ok((witness, response)) => (Ok(witness), Ok(response)),
+                }
+            }
+        }
+    }
+
+    /// Applies [`Self::call_ledger_query`]'s deferred audit-trail bookkeeping: records the
+    /// sub-transaction and fires the state-commit self-call, exactly as
+    /// [`Self::emit_transaction`] does for its own (sequential) calls.
+    async fn record_ledger_query(
         &mut self,
+        canister_id: Principal,
         operation: TreasuryManagerOperation,
+        human_readable: String,
+        result: Result<TransactionWitness, Error>,
+    ) {
+        let transaction = StableTransaction {
+            timestamp_ns: self.time_ns(),
+            canister_id,
+            result,
+            human_readable,
+            operation,
+            prev_hash: GENESIS_PREV_HASH,
+            hash: GENESIS_PREV_HASH,
+            locked_ledgers: Vec::new(),
+        };
+
+        self.push_audit_trail_transaction(transaction);
+
+        unsafe {
+            let agent = self.agent.0.get();
+            if let Err(err) = (*agent).call(self.id, CommitStateRequest {}).await {
+                log_err(&format!(
+                    "Failed to commit state after a ledger balance query: {}",
+                    err
+                ));
+            }
+        };
+    }
+
+    /// Issues `asset`'s balance query via [`Self::call_ledger_query`] and decodes the reply down
+    /// to decimals, returning everything [`Self::record_ledger_query`] needs to record it
+    /// afterward. Kept on `&self` so [`Self::get_ledger_balances`] can run one of these per asset
+    /// as concurrent futures instead of awaiting them one after another.
+    async fn issue_ledger_balance_query(
+        &self,
         asset: ValidatedAsset,
-    ) -> Result<u64, Error> {
+    ) -> (
+        Principal,
+        String,
+        Result<TransactionWitness, Error>,
+        Result<u64, Error>,
+    ) {
         let ledger_canister_id = asset.ledger_canister_id();
 
-        let request = Account {
-            owner: self.id,
-            subaccount: None,
-        };
+        match asset.ledger_protocol() {
+            LedgerProtocol::Icrc => {
+                let request = Account {
+                    owner: self.id,
+                    subaccount: None,
+                };
 
-        let human_readable = format!(
-            "Calling {}.icrc1_balance_of to get the remaining balance of {}.",
-            ledger_canister_id,
-            asset.symbol(),
-        );
+                let human_readable = format!(
+                    "Calling {}.icrc1_balance_of to get the remaining balance of {}.",
+                    ledger_canister_id,
+                    asset.symbol(),
+                );
+
+                let (witness, balance_decimals) =
+                    self.call_ledger_query(ledger_canister_id, request).await;
+
+                let balance_decimals = balance_decimals.and_then(|balance_decimals| {
+                    decode_nat_to_u64(balance_decimals).map_err(|error| Error {
+                        code: u64::from(TransactionErrorCodes::PostConditionCode),
+                        message: error.clone(),
+                        kind: ErrorKind::Postcondition {},
+                    })
+                });
+
+                (
+                    ledger_canister_id,
+                    human_readable,
+                    witness,
+                    balance_decimals,
+                )
+            }
+            LedgerProtocol::Legacy => {
+                let request = AccountBalanceArgs {
+                    account: account_to_account_identifier(&Account {
+                        owner: self.id,
+                        subaccount: None,
+                    }),
+                };
+
+                let human_readable = format!(
+                    "Calling {}.account_balance to get the remaining balance of {}.",
+                    ledger_canister_id,
+                    asset.symbol(),
+                );
 
-        let balance_decimals = self
-            .emit_transaction(ledger_canister_id, request, operation, human_readable)
-            .await?;
+                let (witness, tokens) = self.call_ledger_query(ledger_canister_id, request).await;
 
-        let balance_decimals = decode_nat_to_u64(balance_decimals).map_err(|error| Error {
-            code: u64::from(TransactionErrorCodes::PostConditionCode),
-            message: error.clone(),
-            kind: ErrorKind::Postcondition {},
-        })?;
+                let balance_decimals = tokens.map(|tokens| tokens.e8s());
 
-        Ok(balance_decimals)
+                (
+                    ledger_canister_id,
+                    human_readable,
+                    witness,
+                    balance_decimals,
+                )
+            }
+        }
     }
 
     pub(crate) async fn get_ledger_balances(
@@ -50,10 +210,22 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
     ) -> Result<(u64, u64), Vec<Error>> {
         let (asset_0, asset_1) = self.assets();
 
-        // TODO: These calls could be parallelized.
-        let balance_0_decimals = self.get_ledger_balance_decimals(operation, asset_0).await;
+        // Issued as two concurrent futures against `&self` (`issue_ledger_balance_query` doesn't
+        // need exclusive access), then applied one at a time via `record_ledger_query`, which
+        // does -- so the recorded transaction order stays deterministic (asset_0 before asset_1)
+        // no matter which ledger actually answers first.
+        let (
+            (canister_id_0, human_readable_0, witness_0, balance_0_decimals),
+            (canister_id_1, human_readable_1, witness_1, balance_1_decimals),
+        ) = futures::join!(
+            self.issue_ledger_balance_query(asset_0),
+            self.issue_ledger_balance_query(asset_1),
+        );
 
-        let balance_1_decimals = self.get_ledger_balance_decimals(operation, asset_1).await;
+        self.record_ledger_query(canister_id_0, operation, human_readable_0, witness_0)
+            .await;
+        self.record_ledger_query(canister_id_1, operation, human_readable_1, witness_1)
+            .await;
 
         match (balance_0_decimals, balance_1_decimals) {
             (Ok(balance_0), Ok(balance_1)) => Ok((balance_0, balance_1)),
@@ -95,37 +267,165 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
                 continue;
             }
 
-            let ledger_canister_id = asset.ledger_canister_id();
+            // Held back by the DAO's withdrawal timelock (see `ValidatedBalances::
+            // withdrawal_timelock_remaining_ns`): the whole manager balance is gated on its most
+            // recent credit, so this asset isn't withdrawable yet even though other steps of this
+            // same withdraw (e.g. pulling liquidity off the DEX) already went through.
+            match self.withdrawal_timelock_remaining_ns(asset) {
+                Ok(Some(remaining_ns)) => {
+                    withdraw_errors.push(Error::new_temporarily_unavailable(format!(
+                        "{} withdrawal is locked by the DAO's withdrawal timelock for another {} \
+                         seconds.",
+                        asset.symbol(),
+                        remaining_ns / 1_000_000_000,
+                    )));
+                    continue;
+                }
+                Ok(None) => (),
+                Err(err) => {
+                    withdraw_errors.push(err);
+                    continue;
+                }
+            }
+
+            // Clamped (rather than rejected outright) down to whatever headroom remains in the
+            // DAO-configured per-window withdrawal limit -- see `ValidatedBalances::
+            // check_withdrawal_limit`. A clamp still lets the rest of this withdrawal (e.g. the
+            // other asset, or what already cleared the DEX) go through instead of failing it
+            // wholesale.
+            let amount_decimals = match self.check_withdrawal_limit(asset, amount_decimals) {
+                Ok(allowed_amount_decimals) => {
+                    if allowed_amount_decimals < amount_decimals {
+                        let human_readable = if allowed_amount_decimals == 0 {
+                            format!(
+                                "{} withdrawal of {} rejected: the per-window withdrawal limit has \
+                                 no remaining headroom.",
+                                asset.symbol(),
+                                amount_decimals,
+                            )
+                        } else {
+                            format!(
+                                "{} withdrawal clamped from {} to {} to stay within the per-window \
+                                 withdrawal limit.",
+                                asset.symbol(),
+                                amount_decimals,
+                                allowed_amount_decimals,
+                            )
+                        };
 
-            let human_readable = format!(
-                "Calling {}.icrc1_transfer to withdraw {} {} from KongSwapAdaptor to {}.",
-                ledger_canister_id,
-                amount_decimals,
-                asset.symbol(),
-                withdraw_account,
-            );
-
-            let request = TransferArg {
-                from_subaccount: None,
-                to: withdraw_account,
-                fee: Some(Nat::from(asset.ledger_fee_decimals())),
-                created_at_time: Some(ic_cdk::api::time()),
-                memo: Some(Memo::from(Vec::<u8>::from(operation))),
-                amount: Nat::from(amount_decimals),
+                        self.push_audit_trail_transaction(StableTransaction {
+                            timestamp_ns: self.time_ns(),
+                            canister_id: self.id,
+                            result: Ok(TransactionWitness::NonLedger(human_readable.clone())),
+                            human_readable,
+                            operation,
+                            prev_hash: GENESIS_PREV_HASH,
+                            hash: GENESIS_PREV_HASH,
+                            locked_ledgers: Vec::new(),
+                        });
+                    }
+
+                    allowed_amount_decimals
+                }
+                Err(err) => {
+                    withdraw_errors.push(err);
+                    continue;
+                }
             };
 
-            let result = self
-                .emit_transaction(ledger_canister_id, request, operation, human_readable)
-                .await;
+            if amount_decimals == 0 {
+                continue;
+            }
+
+            let ledger_canister_id = asset.ledger_canister_id();
+
+            let result = match asset.ledger_protocol() {
+                LedgerProtocol::Icrc => {
+                    let human_readable = format!(
+                        "Calling {}.icrc1_transfer to withdraw {} {} from KongSwapAdaptor to {}.",
+                        ledger_canister_id,
+                        amount_decimals,
+                        asset.symbol(),
+                        withdraw_account,
+                    );
+
+                    // Reused verbatim on a retry of this same (operation, ledger) pair -- e.g. if
+                    // the canister traps right after this transfer settles and the top-level
+                    // withdraw is resumed from scratch -- so the retried call carries the exact
+                    // same `created_at_time` + `memo` as the original and the ledger's own dedup
+                    // window recognizes it as a duplicate instead of moving the funds twice.
+                    let created_at_time_ns =
+                        self.reserve_transfer_created_at_time(operation, ledger_canister_id);
+
+                    let request = TransferArg {
+                        from_subaccount: None,
+                        to: withdraw_account,
+                        fee: Some(Nat::from(asset.ledger_fee_decimals())),
+                        created_at_time: Some(created_at_time_ns),
+                        memo: Some(Memo::from(Vec::<u8>::from(operation))),
+                        amount: Nat::from(amount_decimals),
+                    };
+
+                    let result = self
+                        .emit_transaction(ledger_canister_id, request, operation, human_readable)
+                        .await
+                        .map(|_| ());
+                    // Only clear the intent once the transfer has actually settled: a hard
+                    // failure here (e.g. a transient call error) must leave `created_at_time_ns`
+                    // in place so the next retry of this same step still lines up with the
+                    // ledger's dedup window instead of minting a fresh timestamp.
+                    if result.is_ok() {
+                        self.clear_transfer_intent(operation, ledger_canister_id);
+                    }
+                    result
+                }
+                LedgerProtocol::Legacy => {
+                    let human_readable = format!(
+                        "Calling {}.transfer to withdraw {} {} from KongSwapAdaptor to {}.",
+                        ledger_canister_id,
+                        amount_decimals,
+                        asset.symbol(),
+                        withdraw_account,
+                    );
+
+                    // See the ICRC-1 branch above: reused verbatim across a trap-and-resume so the
+                    // classic ledger's own `created_at_time` + `memo` dedup window catches a
+                    // retried transfer instead of double-spending it.
+                    let created_at_time_ns =
+                        self.reserve_transfer_created_at_time(operation, ledger_canister_id);
+
+                    let request = TransferArgs {
+                        memo: legacy_memo_for(operation),
+                        amount: Tokens::from_e8s(amount_decimals),
+                        fee: Tokens::from_e8s(asset.ledger_fee_decimals()),
+                        from_subaccount: None,
+                        to: account_to_account_identifier(&withdraw_account),
+                        created_at_time: Some(TimeStamp {
+                            timestamp_nanos: created_at_time_ns,
+                        }),
+                    };
+
+                    let result = self
+                        .emit_transaction(ledger_canister_id, request, operation, human_readable)
+                        .await
+                        .map(|_| ());
+                    if result.is_ok() {
+                        self.clear_transfer_intent(operation, ledger_canister_id);
+                    }
+                    result
+                }
+            };
 
             match result {
                 Ok(_) => {
-                    self.move_asset(
+                    if let Err(err) = self.move_asset(
                         &asset,
                         amount_decimals,
                         Party::TreasuryManager,
                         Party::TreasuryOwner,
-                    );
+                    ) {
+                        withdraw_errors.push(err);
+                    }
                 }
                 Err(err) => withdraw_errors.push(err),
             }
@@ -0,0 +1,120 @@
+//! Realized profit-and-loss accounting using the `Spendings` and `Earnings` parties:
+//! `spendings` accrues every ledger fee that [`crate::balances::ValidatedBalances::move_asset`]/
+//! [`crate::balances::ValidatedBalances::charge_fee`] route to `fee_collector` (the cost of
+//! operating the position), and `earnings` accrues whenever a balance refresh observes the
+//! DEX-reported external balance grow beyond what this adaptor last recorded as held there (LP
+//! trading fees / yield accrued since the last refresh). Net realized P&L for an asset is
+//! `earnings - spendings`.
+
+use crate::{
+    balances::ValidatedBalanceBook, price_history::value_in_reference, validation::ValidatedAsset,
+    KongSwapAdaptor,
+};
+use candid::CandidType;
+use kongswap_adaptor::agent::AbstractAgent;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AssetPnl {
+    pub symbol: String,
+    /// `earnings - spendings` for this asset, formatted to the asset's decimal precision (e.g.
+    /// `"-0.00010000 ICP"`), negative meaning the ledger fees paid so far exceed the LP trading
+    /// fees / yield earned.
+    pub net_realized: String,
+}
+
+/// Splits `earnings - spendings` into an absolute magnitude and a negative flag, since
+/// [`ValidatedAsset::format_amount_decimals`] only formats unsigned amounts.
+fn net_realized_decimals(book: &ValidatedBalanceBook) -> (u64, bool) {
+    if book.earnings >= book.spendings {
+        (book.earnings - book.spendings, false)
+    } else {
+        (book.spendings - book.earnings, true)
+    }
+}
+
+/// Formats `earnings - spendings`, prefixing a `-` when the net is negative.
+fn format_net_realized(asset: &ValidatedAsset, book: &ValidatedBalanceBook) -> String {
+    let (abs_decimals, is_negative) = net_realized_decimals(book);
+    let formatted = asset.format_amount_decimals(abs_decimals);
+
+    if is_negative {
+        format!("-{}", formatted)
+    } else {
+        formatted
+    }
+}
+
+/// Values `earnings - spendings` in the reference denomination, reapplying the sign after
+/// [`value_in_reference`] (which only accepts unsigned amounts). Returns `None` on overflow.
+fn signed_value_in_reference(
+    book: &ValidatedBalanceBook,
+    asset_decimals: u8,
+    rate_decimals: u64,
+) -> Option<Decimal> {
+    let (abs_decimals, is_negative) = net_realized_decimals(book);
+    let value = value_in_reference(abs_decimals, asset_decimals, rate_decimals)?;
+
+    Some(if is_negative { -value } else { value })
+}
+
+impl<A: AbstractAgent> KongSwapAdaptor<A> {
+    /// Returns the net realized P&L (`earnings - spendings`) for each managed asset.
+    pub fn get_realized_pnl(&self) -> (AssetPnl, AssetPnl) {
+        let balances = self.get_cached_balances();
+
+        let asset_pnl = |asset: ValidatedAsset| {
+            let book = balances
+                .asset_to_balances
+                .get(&asset)
+                .expect("a registered asset always has a balance book");
+            AssetPnl {
+                symbol: asset.symbol(),
+                net_realized: format_net_realized(&asset, book),
+            }
+        };
+
+        (asset_pnl(balances.asset_0), asset_pnl(balances.asset_1))
+    }
+
+    /// Returns net realized P&L across both managed assets, combined into the DAO-chosen
+    /// reference denomination via the rates set through `set_conversion_rates`.
+    ///
+    /// Errs if a rate hasn't been set for one of the assets yet, or if the conversion overflows.
+    pub fn get_realized_pnl_in_reference(&self) -> Result<String, String> {
+        let balances = self.get_cached_balances();
+
+        let asset_0_rate_decimals = balances
+            .asset_0_rate_decimals
+            .ok_or_else(|| "No conversion rate has been set for asset_0.".to_string())?;
+        let asset_1_rate_decimals = balances
+            .asset_1_rate_decimals
+            .ok_or_else(|| "No conversion rate has been set for asset_1.".to_string())?;
+
+        let asset_0_book = balances
+            .asset_to_balances
+            .get(&balances.asset_0)
+            .expect("a registered asset always has a balance book");
+        let asset_1_book = balances
+            .asset_to_balances
+            .get(&balances.asset_1)
+            .expect("a registered asset always has a balance book");
+
+        let value_0 = signed_value_in_reference(
+            asset_0_book,
+            balances.asset_0.decimals(),
+            asset_0_rate_decimals,
+        )
+        .ok_or_else(|| "Overflow while valuing asset_0's realized P&L.".to_string())?;
+
+        let value_1 = signed_value_in_reference(
+            asset_1_book,
+            balances.asset_1.decimals(),
+            asset_1_rate_decimals,
+        )
+        .ok_or_else(|| "Overflow while valuing asset_1's realized P&L.".to_string())?;
+
+        Ok((value_0 + value_1).to_string())
+    }
+}
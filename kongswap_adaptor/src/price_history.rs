@@ -0,0 +1,465 @@
+//! Spot-price tracking for the LP position, so the DAO can assess impermanent loss relative to
+//! simply holding the two assets instead of providing liquidity.
+//!
+//! Every deposit/withdraw/balance-refresh that derives fresh pool reserve amounts from KongSwap
+//! records the implied spot price (`amount_0 / amount_1`) in [`crate::StablePriceHistory`]. A
+//! later query can then compare that entry price against the current price to compute
+//! impermanent loss via `IL = 2*sqrt(P1/P0)/(1 + P1/P0) - 1`.
+
+use crate::{
+    balances::{ValidatedBalanceBook, RATE_DECIMALS_SCALE},
+    state::storage::PriceHistoryEntry,
+    validation::decode_nat_to_u64,
+    KongSwapAdaptor,
+};
+use kongswap_adaptor::agent::AbstractAgent;
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+/// Caps the number of price observations retained in stable memory. Once full, the oldest
+/// observation (the entry with the smallest `timestamp_ns` key) is evicted to make room for the
+/// next one.
+const MAX_PRICE_HISTORY_ENTRIES: u64 = 1_000;
+
+/// Ten thousand basis points, i.e. 100%.
+const BPS_DENOMINATOR: u16 = 10_000;
+
+/// Which source backed a particular [`value_in_reference`] rate. A pool-derived rate is only ever
+/// computable when the DAO's chosen [`crate::state::KongSwapAdaptor::valuation_quote_asset_symbol`]
+/// happens to equal the *other* managed asset's own symbol (the pool can only ever price `asset_0`
+/// against `asset_1` and vice versa), so `ExternalQuote` remains the only option for a reference
+/// denomination like `"USD"` that isn't one of the two managed assets.
+#[derive(candid::CandidType, candid::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RateSource {
+    /// Derived from the most recent entry in [`crate::StablePriceHistory`].
+    Pool,
+    /// Fetched via [`crate::exchange_rate::KongSwapAdaptor::refresh_exchange_rates`].
+    ExternalQuote,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Clone, Debug)]
+pub(crate) struct AssetValuation {
+    /// This asset's value in the reference denomination, `None` if neither the pool nor the
+    /// external quote canister has a fresh-enough rate for it.
+    pub value_decimals: Option<String>,
+    /// Which source backed `value_decimals`, `None` alongside a `None` value.
+    pub rate_source: Option<RateSource>,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Clone, Debug)]
+pub(crate) struct BalancesValuation {
+    /// The forex/crypto symbol this valuation is denominated in, see
+    /// [`crate::state::KongSwapAdaptor::valuation_quote_asset_symbol`].
+    pub quote_asset_symbol: String,
+    /// `asset_0`'s own breakdown of the total below, see [`AssetValuation`].
+    pub asset_0: AssetValuation,
+    /// Like [`Self::asset_0`], but for `asset_1`.
+    pub asset_1: AssetValuation,
+    /// The total treasury value in `quote_asset_symbol`, as reported by
+    /// [`KongSwapAdaptor::total_value_in_reference`]. `None` if either asset's rate -- pool-
+    /// derived or externally quoted -- is currently available.
+    pub total_value_decimals: Option<String>,
+    /// Whether either asset's underlying rate is missing or has gone stale per
+    /// [`crate::state::KongSwapAdaptor::rate_staleness_bound_ns`]. `true` whenever either
+    /// `asset_0`/`asset_1` valuation above is `None`.
+    pub is_stale: bool,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Clone, Debug)]
+pub(crate) struct ValuationSummary {
+    pub entry_price_ratio: String,
+    pub current_price_ratio: String,
+    /// Impermanent loss in basis points. Mathematically never positive: `0` means the price ratio
+    /// hasn't moved since the entry observation, negative means the LP position is worth less
+    /// than simply holding the two assets would have been.
+    pub impermanent_loss_bps: i64,
+    /// The current position value (the sum of both assets currently held externally by the DEX),
+    /// denominated in `asset_1`'s base units.
+    pub position_value_asset_1_decimals: u64,
+}
+
+/// Computes the checked `Decimal` ratio `amount_0 / amount_1`, returning `None` for an empty pool
+/// (`amount_1 == 0`) rather than dividing by zero.
+fn price_ratio(amount_0: u64, amount_1: u64) -> Option<Decimal> {
+    Decimal::from(amount_0).checked_div(Decimal::from(amount_1))
+}
+
+/// Approximates `value.sqrt()` by round-tripping through `f64`. `Decimal` has no native checked
+/// square root outside of rust_decimal's optional "maths" feature, and this computation only
+/// feeds a best-effort risk report, not a settlement amount, so `f64` precision is acceptable.
+fn decimal_sqrt(value: Decimal) -> Option<Decimal> {
+    if value.is_sign_negative() {
+        return None;
+    }
+
+    let value_f64 = value.to_f64()?;
+
+    Decimal::from_f64_retain(value_f64.sqrt())
+}
+
+/// Computes impermanent loss in basis points for a position whose pool price moved from
+/// `entry_price_ratio` to `current_price_ratio`, using `IL = 2*sqrt(P1/P0)/(1 + P1/P0) - 1`.
+fn compute_impermanent_loss_bps(
+    entry_price_ratio: Decimal,
+    current_price_ratio: Decimal,
+) -> Option<i64> {
+    if entry_price_ratio.is_zero() {
+        return None;
+    }
+
+    let price_relative = current_price_ratio.checked_div(entry_price_ratio)?;
+    let sqrt_price_relative = decimal_sqrt(price_relative)?;
+
+    let denominator = Decimal::ONE + price_relative;
+    if denominator.is_zero() {
+        return None;
+    }
+
+    let impermanent_loss =
+        (Decimal::from(2u8) * sqrt_price_relative).checked_div(denominator)? - Decimal::ONE;
+
+    (impermanent_loss * Decimal::from(BPS_DENOMINATOR))
+        .round()
+        .to_string()
+        .parse::<i64>()
+        .ok()
+}
+
+/// Sums every party's holding of a single asset (owner, manager, external, fee collector,
+/// spendings, earnings, suspense) into one total, in that asset's base units.
+fn total_balance_decimals(book: &ValidatedBalanceBook) -> u64 {
+    book.treasury_owner
+        .amount_decimals
+        .saturating_add(book.treasury_manager.amount_decimals)
+        .saturating_add(book.external)
+        .saturating_add(book.fee_collector)
+        .saturating_add(book.spendings)
+        .saturating_add(book.earnings)
+        .saturating_add(book.suspense)
+}
+
+/// The price of one whole unit of an asset in the reference denomination (fixed-point, scaled by
+/// [`RATE_DECIMALS_SCALE`]), bundled with that same asset's own decimal places so the two can't
+/// drift apart across a call chain the way passing them as two loose `u64`/`u8` arguments invites.
+/// Every conversion goes through checked arithmetic and yields `None` rather than panicking on
+/// overflow or an out-of-range `asset_decimals`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Rate {
+    rate_decimals: u64,
+    asset_decimals: u8,
+}
+
+impl Rate {
+    pub(crate) fn new(rate_decimals: u64, asset_decimals: u8) -> Self {
+        Self {
+            rate_decimals,
+            asset_decimals,
+        }
+    }
+
+    /// Converts `total_decimals` base units of this rate's asset into the reference denomination.
+    /// Returns `None` on overflow or an out-of-range `asset_decimals`.
+    pub(crate) fn value_of(&self, total_decimals: u64) -> Option<Decimal> {
+        let scale = Decimal::from(10u64.checked_pow(u32::from(self.asset_decimals))?);
+        let total = Decimal::from(total_decimals).checked_div(scale)?;
+        let rate =
+            Decimal::from(self.rate_decimals).checked_div(Decimal::from(RATE_DECIMALS_SCALE))?;
+        total.checked_mul(rate)
+    }
+}
+
+/// Converts `total_decimals` base units of an asset with `asset_decimals` decimal places into the
+/// reference denomination, using `rate_decimals` (fixed-point scaled by [`RATE_DECIMALS_SCALE`])
+/// as the price of one whole unit of that asset. Returns `None` on overflow or an out-of-range
+/// `asset_decimals`. A thin wrapper over [`Rate::value_of`], kept as a free function since most
+/// call sites only ever use a rate once and don't otherwise benefit from naming it.
+pub(crate) fn value_in_reference(
+    total_decimals: u64,
+    asset_decimals: u8,
+    rate_decimals: u64,
+) -> Option<Decimal> {
+    Rate::new(rate_decimals, asset_decimals).value_of(total_decimals)
+}
+
+impl<A: AbstractAgent> KongSwapAdaptor<A> {
+    /// Derives `asset_id`'s (`0` or `1`) rate in the reference denomination from the most recent
+    /// pool price observation, scaled to [`RATE_DECIMALS_SCALE`]. Only meaningful -- and so only
+    /// returns `Some` -- when `quote_asset_symbol` names the *other* managed asset, since that's
+    /// the only rate the pool's own reserves can imply; also requires that observation to be no
+    /// older than `rate_staleness_bound_ns` (reusing the same bound
+    /// [`crate::exchange_rate::KongSwapAdaptor::refresh_exchange_rates`]'s rate is judged against,
+    /// `0` disabling staleness checking for this source too, same as that one).
+    fn pool_derived_rate_decimals(
+        &self,
+        asset_id: usize,
+        quote_asset_symbol: &str,
+        rate_staleness_bound_ns: u64,
+    ) -> Option<u64> {
+        let (asset_0, asset_1) = self.assets();
+        let other_symbol = match asset_id {
+            0 => asset_1.symbol(),
+            1 => asset_0.symbol(),
+            _ => return None,
+        };
+        if quote_asset_symbol != other_symbol.as_str() {
+            return None;
+        }
+
+        let (timestamp_ns, price_ratio) = self.with_price_history(|price_history| {
+            price_history
+                .iter()
+                .next_back()
+                .map(|(timestamp_ns, entry)| (timestamp_ns, entry.price_ratio))
+        })?;
+
+        if rate_staleness_bound_ns != 0
+            && self.time_ns().saturating_sub(timestamp_ns) > rate_staleness_bound_ns
+        {
+            return None;
+        }
+
+        // `price_ratio` is `amount_0 / amount_1`, i.e. the price of one whole `asset_1` in
+        // `asset_0` terms. Valuing `asset_0` in `asset_1` terms (or vice versa) is therefore its
+        // reciprocal.
+        let rate = match asset_id {
+            0 => Decimal::ONE.checked_div(price_ratio)?,
+            _ => price_ratio,
+        };
+
+        (rate * Decimal::from(RATE_DECIMALS_SCALE))
+            .round()
+            .to_string()
+            .parse::<u64>()
+            .ok()
+    }
+
+    /// Resolves `asset_id`'s rate in the reference denomination, trying KongSwap's own pool price
+    /// first (see [`Self::pool_derived_rate_decimals`]) and falling back to the externally quoted
+    /// rate [`crate::exchange_rate::KongSwapAdaptor::refresh_exchange_rates`] last recorded, if
+    /// that one is itself fresh. Returns `None` -- rather than a stale or fabricated rate -- if
+    /// neither source currently has one.
+    fn reference_rate_decimals(&self, asset_id: usize) -> Option<(u64, RateSource)> {
+        let balances = self.get_cached_balances();
+        let quote_asset_symbol = self.valuation_quote_asset_symbol();
+        let rate_staleness_bound_ns = balances.rate_staleness_bound_ns;
+
+        if let Some(rate_decimals) =
+            self.pool_derived_rate_decimals(asset_id, &quote_asset_symbol, rate_staleness_bound_ns)
+        {
+            return Some((rate_decimals, RateSource::Pool));
+        }
+
+        let (rate_decimals, rate_timestamp_ns) = match asset_id {
+            0 => (balances.asset_0_rate_decimals, balances.asset_0_rate_timestamp_ns),
+            _ => (balances.asset_1_rate_decimals, balances.asset_1_rate_timestamp_ns),
+        };
+        let rate_decimals = rate_decimals?;
+        let rate_timestamp_ns = rate_timestamp_ns?;
+        if rate_staleness_bound_ns != 0
+            && self.time_ns().saturating_sub(rate_timestamp_ns) > rate_staleness_bound_ns
+        {
+            return None;
+        }
+
+        Some((rate_decimals, RateSource::ExternalQuote))
+    }
+
+    /// Records a pool price observation, evicting the oldest entry first if the history is
+    /// already at [`MAX_PRICE_HISTORY_ENTRIES`].
+    pub(crate) fn record_price_observation(&self, timestamp_ns: u64, amount_0: u64, amount_1: u64) {
+        let Some(price_ratio) = price_ratio(amount_0, amount_1) else {
+            // An empty pool has no meaningful spot price; skip recording rather than storing a
+            // misleading zero.
+            return;
+        };
+
+        self.with_price_history_mut(|price_history| {
+            price_history.insert(
+                timestamp_ns,
+                PriceHistoryEntry {
+                    timestamp_ns,
+                    price_ratio,
+                },
+            );
+
+            while price_history.len() > MAX_PRICE_HISTORY_ENTRIES {
+                let Some((oldest_timestamp_ns, _)) = price_history.iter().next() else {
+                    break;
+                };
+                price_history.remove(&oldest_timestamp_ns);
+            }
+        });
+    }
+
+    /// Convenience wrapper around [`Self::record_price_observation`] for call sites that already
+    /// have the reserve amounts as candid `Nat`s.
+    pub(crate) fn record_price_observation_from_nat(
+        &self,
+        amount_0: candid::Nat,
+        amount_1: candid::Nat,
+    ) {
+        let amount_0 = decode_nat_to_u64(amount_0).unwrap_or_default();
+        let amount_1 = decode_nat_to_u64(amount_1).unwrap_or_default();
+
+        self.record_price_observation(self.time_ns(), amount_0, amount_1);
+    }
+
+    /// Returns all recorded price observations, oldest first, as `(timestamp_ns, price_ratio)`
+    /// pairs with `price_ratio` formatted as a decimal string.
+    pub fn get_price_history(&self) -> Vec<(u64, String)> {
+        self.with_price_history(|price_history| {
+            price_history
+                .iter()
+                .map(|(timestamp_ns, entry)| (timestamp_ns, entry.price_ratio.to_string()))
+                .collect()
+        })
+    }
+
+    /// Reports impermanent loss relative to `entry_price_ratio` (a decimal string, typically taken
+    /// from a prior [`Self::get_price_history`] observation), using the most recent recorded price
+    /// as the current price. Also reports the current position value in `asset_1` terms.
+    pub fn get_valuation_summary(
+        &self,
+        entry_price_ratio: &str,
+    ) -> Result<ValuationSummary, String> {
+        let entry_price_ratio = Decimal::from_str(entry_price_ratio)
+            .map_err(|err| format!("Invalid entry_price_ratio `{}`: {}", entry_price_ratio, err))?;
+
+        let current_price_ratio = self
+            .with_price_history(|price_history| {
+                price_history
+                    .iter()
+                    .next_back()
+                    .map(|(_, entry)| entry.price_ratio)
+            })
+            .ok_or_else(|| "No price observations have been recorded yet.".to_string())?;
+
+        let impermanent_loss_bps =
+            compute_impermanent_loss_bps(entry_price_ratio, current_price_ratio).ok_or_else(
+                || {
+                    format!(
+                "Could not compute impermanent loss for entry price {} and current price {}.",
+                entry_price_ratio, current_price_ratio,
+            )
+                },
+            )?;
+
+        let balances = self.get_cached_balances();
+        let external_0 = Decimal::from(
+            balances
+                .asset_to_balances
+                .get(&balances.asset_0)
+                .expect("a registered asset always has a balance book")
+                .external,
+        );
+        let external_1 = Decimal::from(
+            balances
+                .asset_to_balances
+                .get(&balances.asset_1)
+                .expect("a registered asset always has a balance book")
+                .external,
+        );
+
+        let external_0_in_asset_1 = external_0
+            .checked_div(current_price_ratio)
+            .ok_or_else(|| "Current price ratio is zero; cannot value asset_0.".to_string())?;
+
+        let position_value_asset_1_decimals = (external_0_in_asset_1 + external_1)
+            .round()
+            .to_string()
+            .parse::<u64>()
+            .map_err(|err| format!("Failed to compute position value: {}", err))?;
+
+        Ok(ValuationSummary {
+            entry_price_ratio: entry_price_ratio.to_string(),
+            current_price_ratio: current_price_ratio.to_string(),
+            impermanent_loss_bps,
+            position_value_asset_1_decimals,
+        })
+    }
+
+    /// Values `asset_id`'s (`0` or `1`) conserved total in the reference denomination, trying
+    /// KongSwap's own pool price first and falling back to the externally quoted rate -- see
+    /// [`Self::reference_rate_decimals`]. Errs if neither source currently has a usable rate, or
+    /// if the conversion overflows.
+    fn asset_value_in_reference(&self, asset_id: usize) -> Result<(Decimal, RateSource), String> {
+        let balances = self.get_cached_balances();
+        let asset = if asset_id == 0 { &balances.asset_0 } else { &balances.asset_1 };
+        let book = balances
+            .asset_to_balances
+            .get(asset)
+            .expect("a registered asset always has a balance book");
+
+        let (rate_decimals, rate_source) =
+            self.reference_rate_decimals(asset_id).ok_or_else(|| {
+                format!(
+                    "No usable rate (pool-derived or externally quoted) for asset_{}.",
+                    asset_id
+                )
+            })?;
+
+        let total_decimals = total_balance_decimals(book);
+        let value = value_in_reference(total_decimals, asset.decimals(), rate_decimals)
+            .ok_or_else(|| {
+                format!("Overflow while valuing asset_{} in the reference denomination.", asset_id)
+            })?;
+
+        Ok((value, rate_source))
+    }
+
+    /// Returns the total portfolio value across both managed assets and every party tracked in
+    /// their [`ValidatedBalanceBook`]s, converted into a DAO-chosen reference denomination --
+    /// preferring KongSwap's own pool price, and falling back to the conversion rates
+    /// `refresh_exchange_rates` keeps fresh otherwise (see [`Self::reference_rate_decimals`]).
+    /// Unlike [`Self::get_valuation_summary`] (which always values the position in `asset_1`'s own
+    /// terms, derived purely from the pool's spot price), this can be denominated in any
+    /// configured reference symbol.
+    ///
+    /// Errs if neither source has a usable rate for one of the assets, or if the conversion
+    /// overflows.
+    pub fn total_value_in_reference(&self) -> Result<String, String> {
+        let (value_0, _) = self.asset_value_in_reference(0)?;
+        let (value_1, _) = self.asset_value_in_reference(1)?;
+
+        let total = value_0
+            .checked_add(value_1)
+            .ok_or_else(|| "Overflow while summing total portfolio value.".to_string())?;
+
+        Ok(total.to_string())
+    }
+
+    /// Reports the treasury's total value in [`Self::valuation_quote_asset_symbol`], broken down
+    /// per asset, alongside which rate source backed each (see [`Self::reference_rate_decimals`])
+    /// and whether the whole report should be considered stale. A per-asset `value_decimals` is
+    /// `None` -- rather than zero -- whenever neither the pool nor the external quote canister
+    /// currently has a usable rate for it, so a caller can distinguish "worthless" from "unknown".
+    pub fn get_balances_valuation(&self) -> BalancesValuation {
+        let quote_asset_symbol = self.valuation_quote_asset_symbol();
+
+        let asset_0 = match self.asset_value_in_reference(0) {
+            Ok((value, rate_source)) => AssetValuation {
+                value_decimals: Some(value.to_string()),
+                rate_source: Some(rate_source),
+            },
+            Err(_) => AssetValuation { value_decimals: None, rate_source: None },
+        };
+        let asset_1 = match self.asset_value_in_reference(1) {
+            Ok((value, rate_source)) => AssetValuation {
+                value_decimals: Some(value.to_string()),
+                rate_source: Some(rate_source),
+            },
+            Err(_) => AssetValuation { value_decimals: None, rate_source: None },
+        };
+
+        let total_value_decimals = self.total_value_in_reference().ok();
+        let is_stale = asset_0.value_decimals.is_none() || asset_1.value_decimals.is_none();
+
+        BalancesValuation {
+            quote_asset_symbol,
+            asset_0,
+            asset_1,
+            total_value_decimals,
+            is_stale,
+        }
+    }
+}
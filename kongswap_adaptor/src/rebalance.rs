@@ -0,0 +1,314 @@
+//! A slippage-bounded swap primitive, plus a `Rebalance` mode built on top of it that moves the
+//! two-asset treasury position back toward a target ratio. Modeled on the liquidity-swap and repay
+//! adapters used with Aave (e.g. ParaSwap's): a swap is previewed before it is submitted, aborts
+//! without moving any balance if the preview already falls short of the caller's floor, and is
+//! checked again against the DAO-configured [`crate::slippage::check_slippage_bps`] tolerance once
+//! it settles, so a price move between preview and submission can't silently cost more than either
+//! guard allows.
+
+use crate::{
+    balances::Party,
+    kong_types::{
+        ICTransferReply, SwapAmountsArgs, SwapAmountsReply, SwapArgs, SwapReply, TransferReply,
+    },
+    slippage::{check_price_deviation_bps, check_slippage_bps, BPS_DENOMINATOR},
+    transfer_verification::TransferVerificationStatus,
+    tx_error_codes::TransactionErrorCodes,
+    validation::{decode_nat_to_u64, ValidatedAsset},
+    KongSwapAdaptor, KONG_BACKEND_CANISTER_ID,
+};
+use candid::Nat;
+use icrc_ledger_types::icrc1::account::Account;
+use kongswap_adaptor::{agent::AbstractAgent, audit::OperationContext};
+use sns_treasury_manager::{Error, ErrorKind, Transfer};
+
+impl<A: AbstractAgent> KongSwapAdaptor<A> {
+    /// Swaps `amount_in` of `asset_in` for `asset_out` on KongSwap, returning the realized
+    /// `asset_out` amount.
+    ///
+    /// Aborts without moving any balance if the previewed output is already below
+    /// `min_amount_out` -- the caller-specified slippage floor for this particular swap, distinct
+    /// from the DAO-configured `max_slippage_bps` (see [`KongSwapAdaptor::pool_risk_params`]) used
+    /// below to catch a price move between the preview and the submitted swap. Both the preview and
+    /// the submitted swap are recorded into the audit trail via `context`, so a caller inspecting
+    /// the audit trail after an abort can see exactly what was quoted and why it was rejected.
+    pub async fn swap(
+        &mut self,
+        context: &mut OperationContext,
+        asset_in: ValidatedAsset,
+        asset_out: ValidatedAsset,
+        amount_in: u64,
+        min_amount_out: u64,
+    ) -> Result<u64, Vec<Error>> {
+        let preview_human_readable = format!(
+            "Calling KongSwapBackend.swap_amounts to preview swapping {} {} for {}.",
+            amount_in,
+            asset_in.symbol(),
+            asset_out.symbol(),
+        );
+
+        let SwapAmountsReply {
+            receive_amount: expected_amount_out,
+            ..
+        } = self
+            .emit_transaction(
+                context,
+                *KONG_BACKEND_CANISTER_ID,
+                SwapAmountsArgs {
+                    pay_token: asset_in.symbol(),
+                    pay_amount: Nat::from(amount_in),
+                    receive_token: asset_out.symbol(),
+                },
+                preview_human_readable,
+            )
+            .await
+            .map_err(|err| vec![err])?;
+        let expected_amount_out = decode_nat_to_u64(expected_amount_out).unwrap_or_default();
+
+        if expected_amount_out < min_amount_out {
+            return Err(vec![Error::new_precondition(format!(
+                "Swap aborted before submission: quoted output {} {} is below the caller's \
+                 minimum {} {}.",
+                expected_amount_out,
+                asset_out.symbol(),
+                min_amount_out,
+                asset_out.symbol(),
+            ))]);
+        }
+
+        let human_readable = format!(
+            "Calling KongSwapBackend.swap to swap {} {} for at least {} {}.",
+            amount_in,
+            asset_in.symbol(),
+            min_amount_out,
+            asset_out.symbol(),
+        );
+
+        let request = SwapArgs {
+            pay_token: asset_in.symbol(),
+            pay_amount: Nat::from(amount_in),
+            pay_tx_id: None,
+            receive_token: asset_out.symbol(),
+            receive_amount: None,
+            receive_address: None,
+            max_slippage: None,
+        };
+
+        let SwapReply {
+            receive_amount: amount_out,
+            mid_price,
+            price,
+            transfer_ids,
+            ..
+        } = self
+            .emit_transaction(context, *KONG_BACKEND_CANISTER_ID, request, human_readable)
+            .await
+            .map_err(|err| vec![err])?;
+        let amount_out = decode_nat_to_u64(amount_out).unwrap_or_default();
+
+        // Kong's `swap` above already executed irreversibly -- the pay leg is gone and the
+        // receive leg has settled on Kong's side regardless of what the checks below find. Record
+        // what it actually moved right away, so a guard failure below can only flag the trade
+        // (via `mark_state_corrupt`) instead of leaving the books silently unaware a swap
+        // happened at all.
+        self.move_asset(asset_in, amount_in, Party::TreasuryManager, Party::External)
+            .map_err(|err| vec![err])?;
+        self.move_asset(
+            asset_out,
+            amount_out,
+            Party::External,
+            Party::TreasuryManager,
+        )
+        .map_err(|err| vec![err])?;
+
+        if amount_out < min_amount_out {
+            self.mark_state_corrupt(&format!(
+                "Swap realized only {} {}, below the caller's minimum {} {}.",
+                amount_out,
+                asset_out.symbol(),
+                min_amount_out,
+                asset_out.symbol(),
+            ));
+        }
+
+        let (max_slippage_bps, _lp_fee_bps) = self.pool_risk_params();
+        if let Err(err) = check_slippage_bps(
+            expected_amount_out,
+            amount_out,
+            max_slippage_bps,
+            &format!("swap receive_amount ({})", asset_out.symbol()),
+        ) {
+            self.mark_state_corrupt(&err.message);
+        }
+
+        // `check_slippage_bps` above already compares the previewed and realized amounts;
+        // KongSwap's own reported `price`/`mid_price` is an independent source of the same
+        // fact, so cross-check it too rather than trusting our amount-ratio computation alone.
+        if let Err(err) = check_price_deviation_bps(
+            mid_price,
+            price,
+            max_slippage_bps,
+            &format!(
+                "swap price ({} -> {})",
+                asset_in.symbol(),
+                asset_out.symbol()
+            ),
+        ) {
+            self.mark_state_corrupt(&err.message);
+        }
+
+        // Kong's own `transfer_ids` is the only on-ledger-settlement claim this reply makes; the
+        // slippage/price checks above only compare Kong-reported amounts against each other, not
+        // against what actually settled. Confirm the incoming leg against the ledger the same way
+        // `withdraw`/`rewards` already confirm their own incoming transfers via reconciliation --
+        // a non-IC (e.g. Solana) receive leg has no ledger block to query, so there's nothing
+        // further to check for it here.
+        let receive_transfer = transfer_ids.iter().find(|transfer_id_reply| {
+            matches!(
+                transfer_id_reply.transfer,
+                TransferReply::IC(ICTransferReply { is_send: false, .. })
+            )
+        });
+
+        if let Some(transfer_id_reply) = receive_transfer {
+            if let Ok(transfer) = Transfer::try_from(transfer_id_reply) {
+                let manager_account = Account {
+                    owner: self.id,
+                    subaccount: None,
+                };
+
+                // Both the call itself and a non-`Confirmed` status are reported via
+                // `mark_state_corrupt` rather than propagated, for the same reason as the guards
+                // above: `move_asset` already committed this swap to the books, so from here on
+                // there's nothing left to abort -- only something to flag.
+                match self
+                    .verify_transfer(
+                        context,
+                        &transfer,
+                        manager_account,
+                        asset_out.ledger_fee_decimals(),
+                    )
+                    .await
+                {
+                    Ok(verified) if verified.status != TransferVerificationStatus::Confirmed => {
+                        self.mark_state_corrupt(&format!(
+                            "Swap receive transfer for {} could not be confirmed on-ledger: {:?}.",
+                            asset_out.symbol(),
+                            verified.status,
+                        ));
+                    }
+                    Ok(_) => (),
+                    Err(err) => self.mark_state_corrupt(&format!(
+                        "Could not verify swap receive transfer for {} on-ledger: {}",
+                        asset_out.symbol(),
+                        err.message,
+                    )),
+                }
+            }
+        }
+
+        Ok(amount_out)
+    }
+
+    /// Computes the `(asset_in, asset_out, amount_in)` that would move the treasury manager's
+    /// current two-asset position towards holding `target_ratio_bps` of its total value in
+    /// `asset_0`, or `None` if the position is already exactly on target (nothing to swap) or the
+    /// current total is `0` (nothing to rebalance).
+    pub fn target_swap(
+        &self,
+        target_ratio_bps: u16,
+    ) -> Option<(ValidatedAsset, ValidatedAsset, u64)> {
+        let (asset_0, asset_1) = self.assets();
+        let balances = self.get_cached_balances();
+
+        let amount_0 = balances
+            .asset_to_balances
+            .get(&asset_0)?
+            .treasury_manager
+            .amount_decimals;
+        let amount_1 = balances
+            .asset_to_balances
+            .get(&asset_1)?
+            .treasury_manager
+            .amount_decimals;
+
+        let total = amount_0.checked_add(amount_1)?;
+        if total == 0 {
+            return None;
+        }
+
+        let target_amount_0 = total
+            .checked_mul(u64::from(target_ratio_bps))?
+            .checked_div(u64::from(BPS_DENOMINATOR))?;
+
+        // Swapping the whole gap is what lands exactly on `target_ratio_bps` afterwards: the
+        // swap moves `asset_0` and `asset_1` by the same amount in opposite directions, so
+        // `total` is unchanged and `asset_0` ends at `target_amount_0`.
+        if target_amount_0 > amount_0 {
+            let amount_in = target_amount_0 - amount_0;
+            (amount_in > 0).then_some((asset_1, asset_0, amount_in))
+        } else {
+            let amount_in = amount_0 - target_amount_0;
+            (amount_in > 0).then_some((asset_0, asset_1, amount_in))
+        }
+    }
+
+    /// Rebalances the treasury manager's position toward `target_ratio_bps` (of its total value,
+    /// in `asset_0`) by computing the swap [`Self::target_swap`] calls for, then submitting it
+    /// through [`Self::swap`]. `min_amount_out` is derived from the position's current balance
+    /// ratio (a spot-price proxy, since the two held amounts are themselves a quote of the pool's
+    /// price) discounted by `max_slippage_bps`, so this doesn't need its own preview call:
+    /// [`Self::swap`]'s own `swap_amounts` preview is what actually protects the trade. Returns
+    /// `Ok` with no swap performed if the position is already on target.
+    ///
+    /// `amount_in` is clamped to [`crate::balances::ValidatedBalances::max_rebalance_amount_decimals`]
+    /// if configured, so a single call can't move more than that bound regardless of how far off
+    /// target the position is -- closing a larger gap then takes more than one call, each bounded
+    /// and audited the same way a deposit is.
+    pub async fn rebalance_to_target_ratio(
+        &mut self,
+        context: &mut OperationContext,
+        target_ratio_bps: u16,
+    ) -> Result<(), Vec<Error>> {
+        let Some((asset_in, asset_out, amount_in)) = self.target_swap(target_ratio_bps) else {
+            return Ok(());
+        };
+
+        let max_rebalance_amount_decimals =
+            self.get_cached_balances().max_rebalance_amount_decimals;
+        let amount_in = if max_rebalance_amount_decimals == 0 {
+            amount_in
+        } else {
+            amount_in.min(max_rebalance_amount_decimals)
+        };
+
+        let balances = self.get_cached_balances();
+        let amount_in_side = balances
+            .asset_to_balances
+            .get(&asset_in)
+            .expect("a registered asset always has a balance book")
+            .treasury_manager
+            .amount_decimals;
+        let amount_out_side = balances
+            .asset_to_balances
+            .get(&asset_out)
+            .expect("a registered asset always has a balance book")
+            .treasury_manager
+            .amount_decimals;
+
+        // `amount_in`/`amount_in_side` times `amount_out_side` is the spot-price-implied output,
+        // before the DAO-configured slippage tolerance is subtracted to get a safe floor.
+        let spot_amount_out =
+            (amount_in as u128 * amount_out_side as u128 / amount_in_side.max(1) as u128) as u64;
+
+        let (max_slippage_bps, _lp_fee_bps) = self.pool_risk_params();
+        let slippage_allowance =
+            spot_amount_out * u64::from(max_slippage_bps) / u64::from(BPS_DENOMINATOR);
+        let min_amount_out = spot_amount_out.saturating_sub(slippage_allowance);
+
+        self.swap(context, asset_in, asset_out, amount_in, min_amount_out)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,358 @@
+//! Deterministic reconciliation of ledger-side token movements via ICRC-3 block queries, used in
+//! place of diffing `icrc1_balance_of` snapshots taken before and after a call. A balance diff is
+//! racy: any concurrent transfer touching the manager account corrupts it. Reading the ledger's
+//! own blocks and filtering by account and memo is authoritative instead.
+
+use crate::{
+    validation::{decode_nat_to_u64, ValidatedAsset},
+    KongSwapAdaptor,
+};
+use candid::{CandidType, Nat, Principal};
+use icrc_ledger_types::{icrc::generic_value::ICRC3Value, icrc1::account::Account};
+use kongswap_adaptor::{
+    agent::{icrc3_requests::Icrc3GetBlocksRequest, AbstractAgent},
+    audit::OperationContext,
+};
+use serde::Deserialize;
+use sns_treasury_manager::{Error, Operation, Transfer, TreasuryManagerOperation};
+
+#[derive(Debug, Clone)]
+pub(crate) struct DecodedTransfer {
+    pub(crate) to: Option<Account>,
+    pub(crate) amount_decimals: candid::Nat,
+    pub(crate) fee_decimals: Option<candid::Nat>,
+    pub(crate) memo: Option<Vec<u8>>,
+}
+
+pub(crate) fn decode_account(value: &ICRC3Value) -> Option<Account> {
+    let ICRC3Value::Array(parts) = value else {
+        return None;
+    };
+
+    let ICRC3Value::Blob(owner_bytes) = parts.first()? else {
+        return None;
+    };
+    let owner = candid::Principal::try_from_slice(owner_bytes).ok()?;
+
+    let subaccount = match parts.get(1) {
+        Some(ICRC3Value::Blob(bytes)) => {
+            let mut subaccount = [0u8; 32];
+            let len = bytes.len().min(32);
+            subaccount[..len].copy_from_slice(&bytes[..len]);
+            Some(subaccount)
+        }
+        _ => None,
+    };
+
+    Some(Account { owner, subaccount })
+}
+
+/// Decodes an ICRC-3 generic block (`{ tx: { op, from, to, amt, memo, fee }, ts }`, the schema
+/// used by `ic-icrc1-ledger`-compatible ledgers) into the fields needed for reconciliation.
+/// Returns `None` for blocks that aren't transfers (mints, burns, approvals) or that don't match
+/// the expected schema.
+pub(crate) fn decode_transfer_block(block: &ICRC3Value) -> Option<DecodedTransfer> {
+    let ICRC3Value::Map(fields) = block else {
+        return None;
+    };
+    let ICRC3Value::Map(tx) = fields.get("tx")? else {
+        return None;
+    };
+
+    let ICRC3Value::Text(op) = tx.get("op")? else {
+        return None;
+    };
+    if op != "xfer" {
+        return None;
+    }
+
+    let ICRC3Value::Nat(amount_decimals) = tx.get("amt")? else {
+        return None;
+    };
+
+    let to = tx.get("to").and_then(decode_account);
+    let memo = match tx.get("memo") {
+        Some(ICRC3Value::Blob(bytes)) => Some(bytes.to_vec()),
+        _ => None,
+    };
+    let fee_decimals = match tx.get("fee") {
+        Some(ICRC3Value::Nat(fee)) => Some(fee.clone()),
+        _ => None,
+    };
+
+    Some(DecodedTransfer {
+        to,
+        amount_decimals: amount_decimals.clone(),
+        fee_decimals,
+        memo,
+    })
+}
+
+impl<A: AbstractAgent> KongSwapAdaptor<A> {
+    /// Returns the ledger's current block count (`log_length`), used to establish the start of a
+    /// block range to reconcile an upcoming operation against.
+    pub(crate) async fn get_chain_length(
+        &mut self,
+        context: &mut OperationContext,
+        asset: ValidatedAsset,
+    ) -> Result<u64, Error> {
+        let ledger_canister_id = asset.ledger_canister_id();
+
+        let human_readable = format!(
+            "Calling {}.icrc3_get_blocks to read the current chain length.",
+            ledger_canister_id,
+        );
+
+        let result = self
+            .emit_transaction(
+                context,
+                ledger_canister_id,
+                Icrc3GetBlocksRequest::new(0, 0),
+                human_readable,
+            )
+            .await?;
+
+        decode_nat_to_u64(result.log_length).map_err(|err| {
+            Error::new_postcondition(format!(
+                "Ledger {} reported a log_length that doesn't fit in a u64: {}",
+                ledger_canister_id, err
+            ))
+        })
+    }
+
+    /// Reconciles the amount credited to `account` by `operation`, by summing the `amt` of every
+    /// ICRC-3 transfer block in `[start, end)` on `asset`'s ledger whose `to` is `account` and
+    /// whose memo matches `operation`'s id (the same id already stamped on transfers made via
+    /// `make_transfer_request`/`make_transfer_from_request`).
+    pub(crate) async fn reconcile_via_icrc3(
+        &mut self,
+        context: &mut OperationContext,
+        asset: ValidatedAsset,
+        account: Account,
+        operation: TreasuryManagerOperation,
+        start: u64,
+        end: u64,
+    ) -> Result<u64, Error> {
+        let ledger_canister_id = asset.ledger_canister_id();
+
+        let length = end.checked_sub(start).ok_or_else(|| {
+            Error::new_postcondition(format!(
+                "Invalid block range [{}, {}) while reconciling ledger {}.",
+                start, end, ledger_canister_id
+            ))
+        })?;
+
+        let memo = Vec::<u8>::from(operation);
+
+        let human_readable = format!(
+            "Calling {}.icrc3_get_blocks to reconcile blocks [{}, {}).",
+            ledger_canister_id, start, end,
+        );
+
+        let result = self
+            .emit_transaction(
+                context,
+                ledger_canister_id,
+                Icrc3GetBlocksRequest::new(start, length),
+                human_readable,
+            )
+            .await?;
+
+        // Unlike the rest of this function, a non-empty `archived_blocks` is treated as a hard
+        // failure rather than a best-effort warning: an archived block this adaptor can't follow
+        // means the reconciled amount below could silently be missing a matching transfer, which
+        // is indistinguishable from that transfer never having happened.
+        if !result.archived_blocks.is_empty() {
+            return Err(Error::new_postcondition(format!(
+                "Ledger {} reconciliation range [{}, {}) spans {} archived block(s), which this \
+                 adaptor does not yet follow; cannot confirm settlement.",
+                ledger_canister_id,
+                start,
+                end,
+                result.archived_blocks.len(),
+            )));
+        }
+
+        let mut reconciled_amount_decimals = 0_u64;
+
+        for block_with_id in result.blocks {
+            let Some(transfer) = decode_transfer_block(&block_with_id.block) else {
+                continue;
+            };
+
+            if transfer.to.as_ref() != Some(&account) {
+                continue;
+            }
+
+            if transfer.memo.as_deref() != Some(memo.as_slice()) {
+                continue;
+            }
+
+            if let Some(fee_decimals) = transfer.fee_decimals {
+                let fee_decimals = decode_nat_to_u64(fee_decimals).map_err(|err| {
+                    Error::new_postcondition(format!(
+                        "Ledger {} block {} has a fee that doesn't fit in a u64: {}",
+                        ledger_canister_id, block_with_id.id, err
+                    ))
+                })?;
+
+                if fee_decimals != asset.ledger_fee_decimals() {
+                    return Err(Error::new_postcondition(format!(
+                        "Ledger {} block {} charged fee {}, but the adaptor's cached ledger fee \
+                         is {}.",
+                        ledger_canister_id,
+                        block_with_id.id,
+                        fee_decimals,
+                        asset.ledger_fee_decimals(),
+                    )));
+                }
+            }
+
+            let amount_decimals = decode_nat_to_u64(transfer.amount_decimals).map_err(|err| {
+                Error::new_postcondition(format!(
+                    "Ledger {} block {} has an amount that doesn't fit in a u64: {}",
+                    ledger_canister_id, block_with_id.id, err
+                ))
+            })?;
+
+            reconciled_amount_decimals = reconciled_amount_decimals.saturating_add(amount_decimals);
+        }
+
+        Ok(reconciled_amount_decimals)
+    }
+
+    /// Confirms a single claimed transfer's block still exists on its ledger with a matching
+    /// amount, without asserting a counterparty -- unlike [`Self::verify_transfer`], which checks
+    /// a `Transfer` Kong just reported against a recipient the call site knows, this is used to
+    /// re-check a `Transfer` read back out of the audit trail, which carries no recipient of its
+    /// own to compare against (see [`Self::reconcile_audit_trail_against_ledgers`]).
+    async fn confirm_transfer_block(
+        &mut self,
+        context: &mut OperationContext,
+        ledger_canister_id: Principal,
+        block_index: u64,
+        expected_amount_decimals: &Nat,
+    ) -> Result<AuditTrailBlockStatus, Error> {
+        let human_readable = format!(
+            "Calling {}.icrc3_get_blocks to confirm audit-trail block {}.",
+            ledger_canister_id, block_index,
+        );
+
+        let result = self
+            .emit_transaction(
+                context,
+                ledger_canister_id,
+                Icrc3GetBlocksRequest::new(block_index, 1),
+                human_readable,
+            )
+            .await?;
+
+        // Same reasoning as `reconcile_via_icrc3`/`verify_transfer`: an archived block this
+        // adaptor doesn't follow is indistinguishable from a block that isn't there.
+        if !result.archived_blocks.is_empty() || result.blocks.is_empty() {
+            return Ok(AuditTrailBlockStatus::BlockNotFound);
+        }
+
+        Ok(match decode_transfer_block(&result.blocks[0].block) {
+            Some(decoded) if &decoded.amount_decimals != expected_amount_decimals => {
+                AuditTrailBlockStatus::AmountMismatch {
+                    on_chain_decimals: decoded.amount_decimals,
+                }
+            }
+            Some(_) => AuditTrailBlockStatus::Confirmed,
+            None => AuditTrailBlockStatus::BlockNotFound,
+        })
+    }
+
+    /// Retrospectively confirms that every ledger transfer recorded in `StableAuditTrail` still
+    /// has a matching on-chain block, by querying each transfer's ledger for the exact block it
+    /// claims via [`Self::confirm_transfer_block`] -- the audit-trail-wide counterpart to
+    /// [`Self::reconcile_via_icrc3`], which only checks the block range belonging to one
+    /// in-flight operation.
+    ///
+    /// `sns_treasury_manager::Transfer` carries only `ledger_canister_id`/`amount_decimals`/
+    /// `block_index` (no memo, no counterparty -- see the comment on `impl TryFrom<&
+    /// TransferIdReply> for Transfer` in `kong_types.rs`), so unlike a live `verify_transfer` call
+    /// this can only confirm that the claimed block exists and its amount agrees; it cannot also
+    /// confirm who it was sent to or what memo it carried, since neither survives into the audit
+    /// trail's own `Transfer` representation.
+    pub(crate) async fn reconcile_audit_trail_against_ledgers(
+        &mut self,
+    ) -> Vec<AuditTrailBlockDiscrepancy> {
+        let mut context = self.new_operation_context(Operation::Balances);
+
+        let mut discrepancies = Vec::new();
+
+        for (audit_trail_index, (_timestamp_ns, transfers)) in
+            self.get_ledger_block_log().into_iter().enumerate()
+        {
+            for transfer in transfers {
+                let Ok(ledger_canister_id) = Principal::from_text(&transfer.ledger_canister_id)
+                else {
+                    discrepancies.push(AuditTrailBlockDiscrepancy {
+                        audit_trail_index: audit_trail_index as u64,
+                        transfer: transfer.clone(),
+                        status: AuditTrailBlockStatus::BlockNotFound,
+                    });
+                    continue;
+                };
+
+                let Ok(block_index) = decode_nat_to_u64(transfer.block_index.clone()) else {
+                    discrepancies.push(AuditTrailBlockDiscrepancy {
+                        audit_trail_index: audit_trail_index as u64,
+                        transfer: transfer.clone(),
+                        status: AuditTrailBlockStatus::BlockNotFound,
+                    });
+                    continue;
+                };
+
+                let status = match self
+                    .confirm_transfer_block(
+                        &mut context,
+                        ledger_canister_id,
+                        block_index,
+                        &transfer.amount_decimals,
+                    )
+                    .await
+                {
+                    Ok(status) => status,
+                    Err(err) => AuditTrailBlockStatus::QueryFailed(err.message),
+                };
+
+                if status != AuditTrailBlockStatus::Confirmed {
+                    discrepancies.push(AuditTrailBlockDiscrepancy {
+                        audit_trail_index: audit_trail_index as u64,
+                        transfer: transfer.clone(),
+                        status,
+                    });
+                }
+            }
+        }
+
+        self.finalize_audit_trail_transaction(context);
+
+        discrepancies
+    }
+}
+
+/// The outcome of [`KongSwapAdaptor::confirm_transfer_block`] for one audit-trail [`Transfer`].
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum AuditTrailBlockStatus {
+    /// The claimed block exists and its amount agrees with what the audit trail recorded.
+    Confirmed,
+    /// The claimed block exists, but its amount disagrees with what the audit trail recorded.
+    AmountMismatch { on_chain_decimals: Nat },
+    /// The claimed block doesn't exist, is still archived, or isn't a transfer.
+    BlockNotFound,
+    /// The `icrc3_get_blocks` call itself failed, so this transfer could not be checked.
+    QueryFailed(String),
+}
+
+/// One audit-trail transfer [`KongSwapAdaptor::reconcile_audit_trail_against_ledgers`] could not
+/// confirm against its ledger's on-chain blocks.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct AuditTrailBlockDiscrepancy {
+    pub(crate) audit_trail_index: u64,
+    pub(crate) transfer: Transfer,
+    pub(crate) status: AuditTrailBlockStatus,
+}
@@ -20,9 +20,21 @@ impl Request for DepositRequest {
     fn transaction_witness(
         &self,
         _canister_id: candid::Principal,
-        _response: Self::Response,
+        response: Self::Response,
     ) -> Result<(sns_treasury_manager::TransactionWitness, Self::Ok), String> {
-        unimplemented!()
+        // Every ledger transfer a deposit makes is already recorded as its own locked
+        // audit-trail entry by `crate::emit_transaction` as the deposit runs (see
+        // `KongSwapAdaptor::deposit_with_max_price_deviation_bps`), each with its own
+        // ledger-level `TransactionWitness::Ledger`. This top-level call has no ledger
+        // movement of its own to witness, only the outcome it reports back to the caller.
+        let response_str = match &response {
+            Ok(balances) => format!("Deposit succeeded; resulting balances: {:?}", balances),
+            Err(errors) => format!("Deposit failed: {:?}", errors),
+        };
+        Ok((
+            sns_treasury_manager::TransactionWitness::NonLedger(response_str),
+            response,
+        ))
     }
 }
 
@@ -42,9 +54,19 @@ impl Request for WithdrawRequest {
     fn transaction_witness(
         &self,
         _canister_id: candid::Principal,
-        _response: Self::Response,
+        response: Self::Response,
     ) -> Result<(sns_treasury_manager::TransactionWitness, Self::Ok), String> {
-        unimplemented!()
+        // Same reasoning as `DepositRequest`'s impl above: the ledger transfers a withdrawal
+        // makes are already witnessed individually as they're recorded, so this top-level
+        // call's own witness is just a summary of the reported outcome.
+        let response_str = match &response {
+            Ok(balances) => format!("Withdraw succeeded; resulting balances: {:?}", balances),
+            Err(errors) => format!("Withdraw failed: {:?}", errors),
+        };
+        Ok((
+            sns_treasury_manager::TransactionWitness::NonLedger(response_str),
+            response,
+        ))
     }
 }
 
@@ -64,9 +86,15 @@ impl Request for BalancesRequest {
     fn transaction_witness(
         &self,
         _canister_id: candid::Principal,
-        _response: Self::Response,
+        response: Self::Response,
     ) -> Result<(sns_treasury_manager::TransactionWitness, Self::Ok), String> {
-        unimplemented!()
+        // A balances query never moves funds, so it never has a ledger-level witness of its
+        // own -- only the returned snapshot itself.
+        let response_str = format!("{:?}", response);
+        Ok((
+            sns_treasury_manager::TransactionWitness::NonLedger(response_str),
+            response,
+        ))
     }
 }
 
@@ -92,6 +120,7 @@ impl Request for AuditTrailRequest {
     }
 }
 
+#[derive(Clone, Debug)]
 pub struct CommitStateRequest {}
 
 impl Request for CommitStateRequest {
@@ -1,13 +1,226 @@
-use crate::state::KongSwapAdaptor;
+use crate::{
+    balances::Party,
+    kong_types::{
+        RemoveLiquidityAmountsArgs, RemoveLiquidityAmountsReply, RemoveLiquidityArgs,
+        RemoveLiquidityReply,
+    },
+    slippage::check_slippage_bps,
+    tx_error_codes::TransactionErrorCodes,
+    validation::decode_nat_to_u64,
+    KongSwapAdaptor, KONG_BACKEND_CANISTER_ID,
+};
+use candid::Nat;
+use icrc_ledger_types::icrc1::account::Account;
 use kongswap_adaptor::{agent::AbstractAgent, audit::OperationContext};
-use sns_treasury_manager::Error;
+use sns_treasury_manager::{Error, ErrorKind};
 
 impl<A: AbstractAgent> KongSwapAdaptor<A> {
+    /// Removes only the LP-fee portion accrued since the last harvest (or the last deposit/
+    /// withdraw, if more recent -- see [`crate::balances::ValidatedBalances::
+    /// last_harvested_lp_balance_decimals`]) instead of the whole position, so the principal
+    /// keeps earning fees in the pool between `issue_rewards` calls.
+    ///
+    /// A deposit or withdrawal that changes the LP balance between two `issue_rewards` calls
+    /// isn't distinguished from organic fee growth: the next harvest will fold that change into
+    /// its delta. This is considered acceptable since `issue_rewards` is expected to run on a
+    /// tight periodic schedule relative to how often the DAO manually deposits or withdraws.
+    async fn harvest_lp_fees(&mut self, context: &mut OperationContext) -> Result<(), Vec<Error>> {
+        let current_lp_balance = self.lp_balance(context).await;
+        let current_lp_balance_decimals =
+            decode_nat_to_u64(current_lp_balance).unwrap_or_default();
+        let last_harvested_lp_balance_decimals =
+            self.get_cached_balances().last_harvested_lp_balance_decimals;
+
+        if current_lp_balance_decimals <= last_harvested_lp_balance_decimals {
+            // Nothing accrued since the last harvest; a withdrawal since then would also land
+            // here, so re-peg the baseline down to match rather than leaving it stale.
+            self.with_balances_mut(|validated_balances| {
+                validated_balances
+                    .set_last_harvested_lp_balance_decimals(current_lp_balance_decimals)
+            });
+            return Ok(());
+        }
+
+        let accrued_lp_token_amount =
+            Nat::from(current_lp_balance_decimals - last_harvested_lp_balance_decimals);
+
+        let (asset_0, asset_1) = self.assets();
+
+        let preview_human_readable = format!(
+            "Calling KongSwapBackend.remove_liquidity_amounts to preview harvesting the accrued \
+             LP-fee portion ({}) of the position.",
+            accrued_lp_token_amount
+        );
+        let RemoveLiquidityAmountsReply {
+            amount_0: expected_amount_0,
+            amount_1: expected_amount_1,
+            ..
+        } = self
+            .emit_transaction(
+                context,
+                *KONG_BACKEND_CANISTER_ID,
+                RemoveLiquidityAmountsArgs {
+                    token_0: asset_0.symbol(),
+                    token_1: asset_1.symbol(),
+                    remove_lp_token_amount: accrued_lp_token_amount.clone(),
+                },
+                preview_human_readable,
+            )
+            .await
+            .map_err(|err| vec![err])?;
+        let expected_amount_0 = decode_nat_to_u64(expected_amount_0).unwrap_or_default();
+        let expected_amount_1 = decode_nat_to_u64(expected_amount_1).unwrap_or_default();
+
+        let human_readable = format!(
+            "Calling KongSwapBackend.remove_liquidity to harvest the accrued LP-fee portion ({}) \
+             of the position.",
+            accrued_lp_token_amount
+        );
+        let request = RemoveLiquidityArgs {
+            token_0: asset_0.symbol(),
+            token_1: asset_1.symbol(),
+            remove_lp_token_amount: accrued_lp_token_amount.clone(),
+
+            // See the equivalent comment in `withdraw.rs`: this call's `emit_transaction`
+            // assigns the `TreasuryManagerOperation` internally, so there's nothing to stamp yet.
+            memo: None,
+        };
+
+        let chain_length_before_0 = self.get_chain_length(context, asset_0).await?;
+        let chain_length_before_1 = self.get_chain_length(context, asset_1).await?;
+
+        let RemoveLiquidityReply {
+            claim_ids,
+            amount_0,
+            lp_fee_0,
+            amount_1,
+            lp_fee_1,
+            ..
+        } = self
+            .emit_transaction(context, *KONG_BACKEND_CANISTER_ID, request, human_readable)
+            .await
+            .map_err(|err| vec![err])?;
+
+        // The same operation `emit_transaction` just assigned, reused below to match this
+        // remove_liquidity call's transfers during ICRC-3 reconciliation.
+        let operation = context
+            .last_operation()
+            .expect("emit_transaction always assigns one via next_operation");
+
+        let (max_slippage_bps, _lp_fee_bps) = self.pool_risk_params();
+
+        check_slippage_bps(
+            expected_amount_0,
+            decode_nat_to_u64(amount_0.clone()).unwrap_or_default(),
+            max_slippage_bps,
+            &format!("harvest remove_liquidity amount_0 ({})", asset_0.symbol()),
+        )
+        .map_err(|err| vec![err])?;
+        check_slippage_bps(
+            expected_amount_1,
+            decode_nat_to_u64(amount_1.clone()).unwrap_or_default(),
+            max_slippage_bps,
+            &format!("harvest remove_liquidity amount_1 ({})", asset_1.symbol()),
+        )
+        .map_err(|err| vec![err])?;
+
+        if !claim_ids.is_empty() {
+            let claim_ids = claim_ids
+                .iter()
+                .map(|claim_id| claim_id.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(vec![Error {
+                code: u64::from(TransactionErrorCodes::BackendCode),
+                message: format!(
+                    "Harvesting LP fees might not be complete, returned claims: {}.",
+                    claim_ids
+                ),
+                kind: ErrorKind::Backend {},
+            }]);
+        }
+
+        self.record_price_observation_from_nat(amount_0.clone(), amount_1.clone());
+
+        let manager_account = Account {
+            owner: self.id,
+            subaccount: None,
+        };
+
+        let chain_length_after_0 = self.get_chain_length(context, asset_0).await?;
+        let chain_length_after_1 = self.get_chain_length(context, asset_1).await?;
+
+        let reconciled_amount_0 = self
+            .reconcile_via_icrc3(
+                context,
+                asset_0,
+                manager_account,
+                operation,
+                chain_length_before_0,
+                chain_length_after_0,
+            )
+            .await?;
+        let reconciled_amount_1 = self
+            .reconcile_via_icrc3(
+                context,
+                asset_1,
+                manager_account,
+                operation,
+                chain_length_before_1,
+                chain_length_after_1,
+            )
+            .await?;
+
+        let expected_total_0 = decode_nat_to_u64(amount_0 + lp_fee_0).unwrap_or_default();
+        let expected_total_1 = decode_nat_to_u64(amount_1 + lp_fee_1).unwrap_or_default();
+
+        if reconciled_amount_0 != expected_total_0 {
+            return Err(vec![Error::new_postcondition(format!(
+                "Ledger {} blocks reconciled to {}, but KongSwap reported amount_0 + lp_fee_0 = \
+                 {}.",
+                asset_0.ledger_canister_id(),
+                reconciled_amount_0,
+                expected_total_0,
+            ))]);
+        }
+        if reconciled_amount_1 != expected_total_1 {
+            return Err(vec![Error::new_postcondition(format!(
+                "Ledger {} blocks reconciled to {}, but KongSwap reported amount_1 + lp_fee_1 = \
+                 {}.",
+                asset_1.ledger_canister_id(),
+                reconciled_amount_1,
+                expected_total_1,
+            ))]);
+        }
+
+        self.move_asset(
+            asset_0,
+            reconciled_amount_0,
+            Party::External,
+            Party::TreasuryManager,
+        )
+        .map_err(|err| vec![err])?;
+        self.move_asset(
+            asset_1,
+            reconciled_amount_1,
+            Party::External,
+            Party::TreasuryManager,
+        )
+        .map_err(|err| vec![err])?;
+
+        self.with_balances_mut(|validated_balances| {
+            validated_balances
+                .set_last_harvested_lp_balance_decimals(current_lp_balance_decimals)
+        });
+
+        Ok(())
+    }
+
     pub async fn issue_rewards_impl(
         &mut self,
         context: &mut OperationContext,
     ) -> Result<(), Vec<Error>> {
-        // TODO: Ask DEX to send our rewards back.
+        self.harvest_lp_fees(context).await?;
 
         let (withdraw_account_0, withdraw_account_1) = self.owner_accounts();
 
@@ -15,3 +228,6 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
             .await
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,520 @@
+use super::*;
+use crate::kong_types::{UserBalanceLPReply, UserBalancesArgs, UserBalancesReply};
+use crate::{
+    state::storage::{
+        ConfigState, ContractStatus, IntegrityStatus, OperationLock, PendingDepositState,
+        TaskStatuses,
+    },
+    validation::ValidatedTreasuryManagerInit,
+    StableAuditTrail, StableBalances, StableContractStatus, StableExchangeRateHistory,
+    StableIdempotencyKeys, StableIntegrityStatus, StablePendingDepositStateCell,
+    StableOperationLockCell, StableTaskStatusCell,
+    StableTransferIntents,
+    StableWithdrawStateCell, AUDIT_TRAIL_MEMORY_ID,
+    BALANCES_MEMORY_ID, CONTRACT_STATUS_MEMORY_ID, EXCHANGE_RATE_HISTORY_MEMORY_ID,
+    IDEMPOTENCY_KEYS_MEMORY_ID, INTEGRITY_STATUS_MEMORY_ID, PENDING_DEPOSIT_STATE_MEMORY_ID,
+    OPERATION_LOCK_MEMORY_ID, TASK_STATUS_MEMORY_ID,
+    TRANSFER_INTENTS_MEMORY_ID,
+    WITHDRAW_STATE_MEMORY_ID,
+};
+use candid::{Nat, Principal};
+use ic_stable_structures::memory_manager::MemoryManager;
+use ic_stable_structures::{
+    BTreeMap as StableBTreeMap, Cell as StableCell, DefaultMemoryImpl, Vec as StableVec,
+};
+use icrc_ledger_types::{
+    icrc::generic_value::ICRC3Value,
+    icrc3::blocks::{BlockWithId, GetBlocksResult},
+};
+use kongswap_adaptor::{
+    agent::{icrc3_requests::Icrc3GetBlocksRequest, mock_agent::MockAgent},
+    audit::{OperationContext, RecordDecision},
+};
+use sns_treasury_manager::{
+    Allowance, Asset, Operation, Step, TreasuryManagerInit, TreasuryManagerOperation,
+};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use lazy_static::lazy_static;
+
+const E8: u64 = 100_000_000;
+
+lazy_static! {
+    static ref SELF_CANISTER_ID: Principal =
+        Principal::from_text("jexlm-gaaaa-aaaar-qalmq-cai").unwrap();
+}
+
+fn make_lp_balance_request() -> UserBalancesArgs {
+    UserBalancesArgs {
+        principal_id: SELF_CANISTER_ID.to_string(),
+    }
+}
+
+fn make_lp_balance_reply(symbol: String, balance: f64) -> UserBalancesReply {
+    UserBalancesReply::LP(UserBalanceLPReply {
+        symbol,
+        name: String::default(),
+        lp_token_id: 0,
+        balance,
+        usd_balance: 0.0,
+        chain_0: String::default(),
+        symbol_0: String::default(),
+        address_0: String::default(),
+        amount_0: 0.0,
+        usd_amount_0: 0.0,
+        chain_1: String::default(),
+        symbol_1: String::default(),
+        address_1: String::default(),
+        amount_1: 0.0,
+        usd_amount_1: 0.0,
+        ts: 0,
+    })
+}
+
+fn make_remove_liquidity_amounts_request(
+    token_0: String,
+    token_1: String,
+    remove_lp_token_amount: u64,
+) -> RemoveLiquidityAmountsArgs {
+    RemoveLiquidityAmountsArgs {
+        token_0,
+        token_1,
+        remove_lp_token_amount: Nat::from(remove_lp_token_amount),
+    }
+}
+
+fn make_remove_liquidity_amounts_reply(
+    token_0: String,
+    token_1: String,
+    amount_0: u64,
+    amount_1: u64,
+) -> RemoveLiquidityAmountsReply {
+    RemoveLiquidityAmountsReply {
+        symbol: format!("{}_{}", token_0, token_1),
+        chain_0: String::default(),
+        address_0: String::default(),
+        symbol_0: token_0.clone(),
+        amount_0: Nat::from(amount_0),
+        lp_fee_0: Nat::from(0_u8),
+        chain_1: String::default(),
+        address_1: String::default(),
+        symbol_1: token_1.clone(),
+        amount_1: Nat::from(amount_1),
+        lp_fee_1: Nat::from(0_u8),
+        remove_lp_token_amount: Nat::from(0_u8),
+    }
+}
+
+fn make_remove_liquidity_request(
+    token_0: String,
+    token_1: String,
+    remove_lp_token_amount: u64,
+) -> RemoveLiquidityArgs {
+    RemoveLiquidityArgs {
+        token_0,
+        token_1,
+        remove_lp_token_amount: Nat::from(remove_lp_token_amount),
+        memo: None,
+    }
+}
+
+fn make_remove_liquidity_reply(
+    token_0: String,
+    token_1: String,
+    amount_0: u64,
+    amount_1: u64,
+) -> RemoveLiquidityReply {
+    RemoveLiquidityReply {
+        tx_id: 0,
+        request_id: 0,
+        status: "Success".to_string(),
+        symbol: format!("{}_{}", token_0, token_1),
+        chain_0: String::default(),
+        address_0: String::default(),
+        symbol_0: token_0.clone(),
+        amount_0: Nat::from(amount_0),
+        lp_fee_0: Nat::from(0_u8),
+        chain_1: String::default(),
+        address_1: String::default(),
+        symbol_1: token_1.clone(),
+        amount_1: Nat::from(amount_1),
+        lp_fee_1: Nat::from(0_u8),
+        remove_lp_token_amount: Nat::from(0_u8),
+        transfer_ids: vec![],
+        claim_ids: vec![],
+        ts: 0,
+    }
+}
+
+fn make_get_blocks_request(start: u64, length: u64) -> Icrc3GetBlocksRequest {
+    Icrc3GetBlocksRequest::new(start, length)
+}
+
+/// A minimal ICRC-3 transfer block crediting `amount_decimals` to `to`, tagged with `memo` --
+/// just enough of the schema for [`crate::reconciliation`]'s `decode_transfer_block` to recognize
+/// it. `fee` is deliberately omitted, since `decode_transfer_block` treats a missing fee as
+/// nothing to cross-check.
+fn make_transfer_block(to: Account, amount_decimals: u64, memo: Vec<u8>) -> ICRC3Value {
+    let mut tx = BTreeMap::new();
+    tx.insert("op".to_string(), ICRC3Value::Text("xfer".to_string()));
+    tx.insert(
+        "to".to_string(),
+        ICRC3Value::Array(vec![ICRC3Value::Blob(to.owner.as_slice().to_vec().into())]),
+    );
+    tx.insert(
+        "amt".to_string(),
+        ICRC3Value::Nat(Nat::from(amount_decimals)),
+    );
+    tx.insert("memo".to_string(), ICRC3Value::Blob(memo.into()));
+
+    let mut block = BTreeMap::new();
+    block.insert("tx".to_string(), ICRC3Value::Map(tx));
+    ICRC3Value::Map(block)
+}
+
+fn empty_blocks_reply(log_length: u64) -> GetBlocksResult {
+    GetBlocksResult {
+        log_length: Nat::from(log_length),
+        blocks: vec![],
+        archived_blocks: vec![],
+    }
+}
+
+fn transfer_blocks_reply(block: ICRC3Value) -> GetBlocksResult {
+    GetBlocksResult {
+        log_length: Nat::from(1_u64),
+        blocks: vec![BlockWithId {
+            id: Nat::from(0_u8),
+            block,
+        }],
+        archived_blocks: vec![],
+    }
+}
+
+/// Covers the growth path explicitly called out by this method's contract: a pool whose
+/// [`UserBalanceLPReply::balance`] has grown since the last harvest (standing in here for "since
+/// deposit", since the baseline this method diffs against is re-pegged identically by both) is
+/// harvested for just the accrued portion, leaving the rest of the position in the pool.
+#[tokio::test]
+async fn test_harvest_lp_fees_harvests_accrued_portion() {
+    const FEE_SNS: u64 = 10_500u64;
+    const FEE_ICP: u64 = 9_500u64;
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    let token_0 = format!("IC.{}", sns_ledger);
+    let token_1 = format!("IC.{}", icp_ledger);
+    let symbol_0 = "DAO".to_string();
+    let symbol_1 = "ICP".to_string();
+    let lp_symbol = format!("{}_{}", symbol_0, symbol_1);
+
+    let asset_0 = Asset::Token {
+        ledger_canister_id: sns_ledger,
+        symbol: symbol_0.clone(),
+        ledger_fee_decimals: Nat::from(FEE_SNS),
+    };
+    let asset_1 = Asset::Token {
+        ledger_canister_id: icp_ledger,
+        symbol: symbol_1.clone(),
+        ledger_fee_decimals: Nat::from(FEE_ICP),
+    };
+
+    let owner_account = sns_treasury_manager::Account {
+        owner: Principal::from_text("2vxsx-fae").unwrap(),
+        subaccount: None,
+    };
+
+    thread_local! {
+        static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+            RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+        static BALANCES: RefCell<StableBalances> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(BALANCES_MEMORY_ID),
+                        ConfigState::default()
+                    )
+                    .expect("BALANCES init should not cause errors")
+                )
+            );
+
+        static AUDIT_TRAIL: RefCell<StableAuditTrail> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableVec::init(
+                        memory_manager.borrow().get(AUDIT_TRAIL_MEMORY_ID)
+                    )
+                    .expect("AUDIT_TRAIL init should not cause errors")
+                )
+            );
+
+        static WITHDRAW_STATE: RefCell<StableWithdrawStateCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(WITHDRAW_STATE_MEMORY_ID),
+                        Default::default()
+                    )
+                    .expect("WITHDRAW_STATE init should not cause errors")
+                )
+            );
+
+        static PRICE_HISTORY: RefCell<StablePriceHistory> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(PRICE_HISTORY_MEMORY_ID)
+                    )
+                )
+            );
+
+        static IDEMPOTENCY_KEYS: RefCell<StableIdempotencyKeys> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(IDEMPOTENCY_KEYS_MEMORY_ID)
+                    )
+                )
+            );
+
+        static CONTRACT_STATUS: RefCell<StableContractStatus> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(CONTRACT_STATUS_MEMORY_ID),
+                        ContractStatus::default()
+                    )
+                    .expect("CONTRACT_STATUS init should not cause errors")
+                )
+            );
+
+        static PENDING_DEPOSIT_STATE: RefCell<StablePendingDepositStateCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(PENDING_DEPOSIT_STATE_MEMORY_ID),
+                        PendingDepositState::default()
+                    )
+                    .expect("PENDING_DEPOSIT_STATE init should not cause errors")
+                )
+            );
+
+        static INTEGRITY_STATUS: RefCell<StableIntegrityStatus> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(INTEGRITY_STATUS_MEMORY_ID),
+                        IntegrityStatus::default()
+                    )
+                    .expect("INTEGRITY_STATUS init should not cause errors")
+                )
+            );
+
+        static EXCHANGE_RATE_HISTORY: RefCell<StableExchangeRateHistory> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(EXCHANGE_RATE_HISTORY_MEMORY_ID)
+                    )
+                )
+            );
+
+        static TASK_STATUS: RefCell<StableTaskStatusCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(TASK_STATUS_MEMORY_ID),
+                        TaskStatuses::default()
+                    )
+                    .expect("TASK_STATUS init should not cause errors")
+                )
+            );
+
+        static TRANSFER_INTENTS: RefCell<StableTransferIntents> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(TRANSFER_INTENTS_MEMORY_ID)
+                    )
+                )
+            );
+        static OPERATION_LOCK: RefCell<StableOperationLockCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(OPERATION_LOCK_MEMORY_ID),
+                        OperationLock::default()
+                    )
+                    .expect("OPERATION_LOCK init should not cause errors")
+                )
+            );
+    }
+
+    let baseline_lp_balance_decimals = 100 * E8;
+    let grown_lp_balance = 105.0; // +5 LP tokens' worth of accrued fees since the last harvest.
+    let accrued_lp_token_amount_decimals = 5 * E8;
+    let amount_0 = 6_000_000u64;
+    let amount_1 = 5_000_000u64;
+
+    // The operation `harvest_lp_fees`'s `remove_liquidity` call is assigned: the 5th
+    // `emit_transaction` call in its sequence (lp_balance, preview, 2 chain-length-before,
+    // remove_liquidity), so `next_operation`'s 0-based index lands on 4.
+    let operation = TreasuryManagerOperation {
+        operation: Operation::IssueReward,
+        step: Step {
+            index: 4,
+            is_final: false,
+        },
+    };
+    let memo = Vec::<u8>::from(operation);
+
+    let manager_account = Account {
+        owner: *SELF_CANISTER_ID,
+        subaccount: None,
+    };
+
+    let mock_agent = MockAgent::new(*SELF_CANISTER_ID)
+        .add_call(
+            *KONG_BACKEND_CANISTER_ID,
+            make_lp_balance_request(),
+            Ok(vec![make_lp_balance_reply(
+                lp_symbol.clone(),
+                grown_lp_balance,
+            )]),
+        )
+        .add_call(
+            *KONG_BACKEND_CANISTER_ID,
+            make_remove_liquidity_amounts_request(
+                symbol_0.clone(),
+                symbol_1.clone(),
+                accrued_lp_token_amount_decimals,
+            ),
+            Ok(make_remove_liquidity_amounts_reply(
+                symbol_0.clone(),
+                symbol_1.clone(),
+                amount_0,
+                amount_1,
+            )),
+        )
+        .add_call(
+            sns_ledger,
+            make_get_blocks_request(0, 0),
+            empty_blocks_reply(0),
+        )
+        .add_call(
+            icp_ledger,
+            make_get_blocks_request(0, 0),
+            empty_blocks_reply(0),
+        )
+        .add_call(
+            *KONG_BACKEND_CANISTER_ID,
+            make_remove_liquidity_request(
+                symbol_0.clone(),
+                symbol_1.clone(),
+                accrued_lp_token_amount_decimals,
+            ),
+            Ok(make_remove_liquidity_reply(
+                symbol_0.clone(),
+                symbol_1.clone(),
+                amount_0,
+                amount_1,
+            )),
+        )
+        .add_call(
+            sns_ledger,
+            make_get_blocks_request(0, 0),
+            empty_blocks_reply(1),
+        )
+        .add_call(
+            icp_ledger,
+            make_get_blocks_request(0, 0),
+            empty_blocks_reply(1),
+        )
+        .add_call(
+            sns_ledger,
+            make_get_blocks_request(0, 1),
+            transfer_blocks_reply(make_transfer_block(
+                manager_account,
+                amount_0,
+                memo.clone(),
+            )),
+        )
+        .add_call(
+            icp_ledger,
+            make_get_blocks_request(0, 1),
+            transfer_blocks_reply(make_transfer_block(manager_account, amount_1, memo)),
+        );
+
+    let mut kong_adaptor = KongSwapAdaptor::new(
+        || 0, // Mock time function
+        mock_agent,
+        *SELF_CANISTER_ID,
+        &BALANCES,
+        &AUDIT_TRAIL,
+        &WITHDRAW_STATE,
+        &PRICE_HISTORY,
+        &IDEMPOTENCY_KEYS,
+        &CONTRACT_STATUS,
+        &PENDING_DEPOSIT_STATE,
+        &INTEGRITY_STATUS,
+        &EXCHANGE_RATE_HISTORY,
+        &TASK_STATUS,
+        &TRANSFER_INTENTS,
+        &OPERATION_LOCK,
+    );
+
+    let init = TreasuryManagerInit {
+        allowances: vec![
+            Allowance {
+                asset: asset_0,
+                owner_account,
+                amount_decimals: Nat::from(baseline_lp_balance_decimals),
+            },
+            Allowance {
+                asset: asset_1,
+                owner_account,
+                amount_decimals: Nat::from(baseline_lp_balance_decimals),
+            },
+        ],
+    };
+    let ValidatedTreasuryManagerInit {
+        allowance_0,
+        allowance_1,
+    } = init.try_into().unwrap();
+
+    kong_adaptor.initialize(
+        allowance_0.asset,
+        allowance_1.asset,
+        allowance_0.owner_account,
+        allowance_1.owner_account,
+    );
+
+    // Simulate having already deposited and harvested once, pegging the baseline to the LP
+    // balance as of that harvest.
+    kong_adaptor.with_balances_mut(|validated_balances| {
+        validated_balances.set_last_harvested_lp_balance_decimals(baseline_lp_balance_decimals)
+    });
+
+    let mut context =
+        OperationContext::new(Operation::IssueReward).with_record_decision(RecordDecision::Full);
+
+    let result = kong_adaptor.harvest_lp_fees(&mut context).await;
+    assert!(result.is_ok(), "harvest_lp_fees failed: {:?}", result);
+
+    let expected_baseline_decimals =
+        baseline_lp_balance_decimals + accrued_lp_token_amount_decimals;
+    assert_eq!(
+        kong_adaptor
+            .get_cached_balances()
+            .last_harvested_lp_balance_decimals,
+        expected_baseline_decimals,
+        "the harvested baseline should be re-pegged to the new LP balance"
+    );
+
+    assert!(
+        kong_adaptor.agent.finished_calls(),
+        "There are still some calls remaining"
+    );
+}
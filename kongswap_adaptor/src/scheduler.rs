@@ -0,0 +1,51 @@
+//! Pure backoff math behind `refresh_balances`'s and `issue_rewards`' independently-scheduled
+//! timers (see `crate::canister::run_refresh_balances_task`/`run_issue_rewards_task`). Splitting
+//! them onto their own timers -- instead of both running back-to-back off a single fixed-interval
+//! timer, as `run_periodic_tasks` used to -- means a slow or failing one no longer delays the
+//! other, and a persistently failing one backs off instead of retrying every tick against a
+//! downstream dependency that's down.
+
+/// Which of the two independently-scheduled periodic tasks a backoff decision is for. Distinct
+/// from [`crate::state::storage::PeriodicTask`] (which also covers `init_async`, a one-shot
+/// self-call with no timer of its own to back off).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ScheduledTask {
+    RefreshBalances,
+    IssueRewards,
+}
+
+/// How many consecutive failures [`next_delay_ns`] keeps doubling the delay for, before holding at
+/// the resulting ceiling (`base_interval_ns * 2^MAX_BACKOFF_SHIFT`) instead of growing further.
+pub(crate) const MAX_BACKOFF_SHIFT: u32 = 6;
+
+/// The delay (in nanoseconds) before a periodic task's timer should next be armed:
+/// `base_interval_ns` once `consecutive_failures` is back to `0` (i.e. the last attempt
+/// succeeded, or none has run yet), doubling per consecutive failure up to [`MAX_BACKOFF_SHIFT`].
+pub(crate) fn next_delay_ns(base_interval_ns: u64, consecutive_failures: u32) -> u64 {
+    let shift = consecutive_failures.min(MAX_BACKOFF_SHIFT);
+    base_interval_ns.saturating_mul(1u64 << shift)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_delay_ns_holds_base_interval_on_success() {
+        assert_eq!(next_delay_ns(1_000, 0), 1_000);
+    }
+
+    #[test]
+    fn test_next_delay_ns_doubles_per_consecutive_failure() {
+        assert_eq!(next_delay_ns(1_000, 1), 2_000);
+        assert_eq!(next_delay_ns(1_000, 2), 4_000);
+        assert_eq!(next_delay_ns(1_000, 3), 8_000);
+    }
+
+    #[test]
+    fn test_next_delay_ns_caps_at_max_backoff_shift() {
+        let capped = next_delay_ns(1_000, MAX_BACKOFF_SHIFT);
+        assert_eq!(next_delay_ns(1_000, MAX_BACKOFF_SHIFT + 1), capped);
+        assert_eq!(next_delay_ns(1_000, u32::MAX), capped);
+    }
+}
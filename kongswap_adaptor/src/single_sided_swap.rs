@@ -0,0 +1,82 @@
+//! Pure math for folding a deposit's unproportional remainder into deployed liquidity instead of
+//! refunding it (see `crate::deposit::KongSwapAdaptor::redeploy_remainder`). Splitting this out of
+//! `deposit.rs` keeps the formula -- and the reasoning behind it -- independently readable and
+//! testable, the same way `crate::scheduler`'s backoff math is split out of `canister.rs`.
+
+use rust_decimal::Decimal;
+
+/// Given an excess `amount` of one pool asset (i.e. more of it than the other side of a deposit
+/// needed) and that asset's current pool `reserve`, returns how much of `amount` should be
+/// swapped for the other asset before the rest is contributed as liquidity, so that both pieces
+/// land on the pool's current ratio: the standard single-sided constant-product formula
+/// `s = (sqrt(reserve * (reserve + amount * (1 - f))) - reserve) / (1 - f)`, where `f` is the
+/// pool's fee fraction (`lp_fee_bps / 10_000`).
+///
+/// Returns `None` if `reserve` or `amount` is `0` (nothing to single-side into or out of),
+/// `lp_fee_bps` is `>= 10_000` (a degenerate, non-positive fee complement), or the computation
+/// overflows or doesn't fit back into a `u64`.
+pub(crate) fn single_sided_swap_in_amount(
+    reserve: u64,
+    amount: u64,
+    lp_fee_bps: u8,
+) -> Option<u64> {
+    if reserve == 0 || amount == 0 {
+        return None;
+    }
+
+    let reserve = Decimal::from(reserve);
+    let amount = Decimal::from(amount);
+    let fee_complement = Decimal::ONE - Decimal::from(lp_fee_bps) / Decimal::from(10_000u16);
+    if fee_complement <= Decimal::ZERO {
+        return None;
+    }
+
+    let discriminant = reserve.checked_mul(reserve + amount.checked_mul(fee_complement)?)?;
+    let swap_in_amount = discriminant.sqrt()?.checked_sub(reserve)?.checked_div(fee_complement)?;
+
+    if swap_in_amount.is_sign_negative() {
+        return None;
+    }
+
+    u64::try_from(swap_in_amount.round()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_sided_swap_in_amount_splits_below_the_full_excess() {
+        // With a non-zero fee, swapping strictly less than the whole excess is always what
+        // lands back on the pool's ratio -- if it swapped everything, there'd be nothing left
+        // to contribute as the residual side.
+        let swap_in_amount = single_sided_swap_in_amount(1_000_000, 100_000, 30).unwrap();
+        assert!(swap_in_amount > 0);
+        assert!(swap_in_amount < 100_000);
+    }
+
+    #[test]
+    fn test_single_sided_swap_in_amount_zero_reserve_or_amount_is_none() {
+        assert_eq!(single_sided_swap_in_amount(0, 100_000, 30), None);
+        assert_eq!(single_sided_swap_in_amount(1_000_000, 0, 30), None);
+    }
+
+    #[test]
+    fn test_single_sided_swap_in_amount_max_u8_fee_is_not_degenerate() {
+        // `lp_fee_bps` is a `u8`, so even its maximum (255 bps) leaves a positive fee complement
+        // (0.9745): (sqrt(1e6 * (1e6 + 1e5 * 0.9745)) - 1e6) / 0.9745 = 48_837.84, rounded.
+        assert_eq!(
+            single_sided_swap_in_amount(1_000_000, 100_000, u8::MAX),
+            Some(48_838)
+        );
+    }
+
+    #[test]
+    fn test_single_sided_swap_in_amount_scales_with_reserve() {
+        // A much deeper pool absorbs the same excess with proportionally less of it swapped
+        // away, since the excess moves the pool's price by less.
+        let shallow = single_sided_swap_in_amount(1_000_000, 100_000, 30).unwrap();
+        let deep = single_sided_swap_in_amount(100_000_000, 100_000, 30).unwrap();
+        assert!(deep < shallow);
+    }
+}
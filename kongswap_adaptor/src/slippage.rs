@@ -0,0 +1,259 @@
+//! Slippage guards for DEX operations whose realized amounts can differ from what KongSwap's
+//! `*_amounts` preview endpoints reported, e.g. due to a price move between the preview call and
+//! the call it previews. Comparing previewed and realized amounts as a checked `Decimal` ratio
+//! avoids the overflow a naive `u64` multiplication could hit on large amounts.
+
+use rust_decimal::Decimal;
+use sns_treasury_manager::Error;
+
+/// Ten thousand basis points, i.e. 100%.
+pub(crate) const BPS_DENOMINATOR: u16 = 10_000;
+
+/// Returns `Err` if `actual` deviates from `expected` by more than `max_slippage_bps`
+/// (basis points), and `Ok(())` otherwise. `expected` is typically a KongSwap `*_amounts` preview
+/// reply, and `actual` the amount reported by the `add_liquidity`/`remove_liquidity` call that
+/// followed it.
+pub(crate) fn check_slippage_bps(
+    expected: u64,
+    actual: u64,
+    max_slippage_bps: u16,
+    description: &str,
+) -> Result<(), Error> {
+    if expected == 0 {
+        if actual == 0 {
+            return Ok(());
+        }
+        return Err(Error::new_postcondition(format!(
+            "Slippage check for {} failed: expected 0, but got {}.",
+            description, actual,
+        )));
+    }
+
+    let expected_decimal = Decimal::from(expected);
+    let actual_decimal = Decimal::from(actual);
+
+    // The implied realized-to-expected price ratio, computed as a checked division so that a
+    // pathological (e.g. zero) denominator yields a typed error instead of a panic.
+    let ratio = actual_decimal
+        .checked_div(expected_decimal)
+        .ok_or_else(|| {
+            Error::new_postcondition(format!(
+                "Slippage check for {} failed: could not compute actual/expected ratio \
+             ({} / {}).",
+                description, actual, expected,
+            ))
+        })?;
+
+    let deviation = (ratio - Decimal::ONE).abs();
+
+    let max_slippage = Decimal::from(max_slippage_bps) / Decimal::from(BPS_DENOMINATOR);
+
+    if deviation > max_slippage {
+        return Err(Error::new_postcondition(format!(
+            "Slippage check for {} failed: expected {}, got {} ({} bps deviation, max allowed {} \
+             bps).",
+            description,
+            expected,
+            actual,
+            (deviation * Decimal::from(BPS_DENOMINATOR)).round(),
+            max_slippage_bps,
+        )));
+    }
+
+    Ok(())
+}
+
+/// Returns `Err` if a deposit's implied price (`amount_1 / amount_0`) deviates from an
+/// already-existing pool's own reserve ratio (`reserve_1 / reserve_0`) by more than
+/// `max_deviation_bps`. Unlike [`check_slippage_bps`] (preview vs. realized, after the fact), this
+/// runs *before* `add_pool`/`add_liquidity` is called, comparing the caller's chosen amounts
+/// against the pool's current reserves so a deposit can't be drawn into a pool whose price has
+/// moved (or been manipulated) since the caller chose those amounts.
+pub(crate) fn check_deposit_price_deviation_bps(
+    reserve_0: u64,
+    reserve_1: u64,
+    amount_0: u64,
+    amount_1: u64,
+    max_deviation_bps: u16,
+    description: &str,
+) -> Result<(), Error> {
+    let reserve_0_decimal = Decimal::from(reserve_0);
+    let reserve_1_decimal = Decimal::from(reserve_1);
+    let amount_0_decimal = Decimal::from(amount_0);
+    let amount_1_decimal = Decimal::from(amount_1);
+
+    // The pool's current reserve ratio and the deposit's implied ratio, both computed as checked
+    // divisions so a pathological (e.g. zero) denominator yields a typed error instead of a panic.
+    let reserve_ratio = reserve_1_decimal
+        .checked_div(reserve_0_decimal)
+        .ok_or_else(|| {
+            Error::new_postcondition(format!(
+                "Price deviation check for {} failed: could not compute the pool's reserve ratio \
+                 ({} / {}).",
+                description, reserve_1, reserve_0,
+            ))
+        })?;
+    let deposit_ratio = amount_1_decimal
+        .checked_div(amount_0_decimal)
+        .ok_or_else(|| {
+            Error::new_postcondition(format!(
+                "Price deviation check for {} failed: could not compute the deposit's implied \
+                 ratio ({} / {}).",
+                description, amount_1, amount_0,
+            ))
+        })?;
+
+    let ratio = deposit_ratio.checked_div(reserve_ratio).ok_or_else(|| {
+        Error::new_postcondition(format!(
+            "Price deviation check for {} failed: could not compare the deposit's implied ratio \
+             to the pool's reserve ratio.",
+            description,
+        ))
+    })?;
+
+    let deviation = (ratio - Decimal::ONE).abs();
+
+    let max_deviation = Decimal::from(max_deviation_bps) / Decimal::from(BPS_DENOMINATOR);
+
+    if deviation > max_deviation {
+        return Err(Error::new_postcondition(format!(
+            "Price deviation check for {} failed: pool reserve ratio {}, deposit implied ratio \
+             {} ({} bps deviation, max allowed {} bps).",
+            description,
+            reserve_ratio,
+            deposit_ratio,
+            (deviation * Decimal::from(BPS_DENOMINATOR)).round(),
+            max_deviation_bps,
+        )));
+    }
+
+    Ok(())
+}
+
+/// Returns `Err` if a top-up's realized implied price (`amount_1 / amount_0`, from KongSwap's
+/// `add_liquidity_amounts` preview) deviates from the ratio the caller's own allowances imply
+/// (`intended_amount_1 / intended_amount_0`) by more than `max_deviation_bps`. Unlike
+/// [`check_deposit_price_deviation_bps`] (realized vs. the pool's own reserves), this compares
+/// against what the caller asked for in the first place, so a deposit is rejected if KongSwap's
+/// proportional split leaves it contributing at a ratio the caller never intended -- e.g. because
+/// the pool's reserves had already drifted from the price the caller planned around.
+pub(crate) fn check_deposit_intended_ratio_bps(
+    intended_amount_0: u64,
+    intended_amount_1: u64,
+    amount_0: u64,
+    amount_1: u64,
+    max_deviation_bps: u16,
+    description: &str,
+) -> Result<(), Error> {
+    let intended_amount_0_decimal = Decimal::from(intended_amount_0);
+    let intended_amount_1_decimal = Decimal::from(intended_amount_1);
+    let amount_0_decimal = Decimal::from(amount_0);
+    let amount_1_decimal = Decimal::from(amount_1);
+
+    // The caller's intended ratio and the deposit's realized implied ratio, both computed as
+    // checked divisions so a pathological (e.g. zero) denominator yields a typed error instead of
+    // a panic.
+    let intended_ratio = intended_amount_1_decimal
+        .checked_div(intended_amount_0_decimal)
+        .ok_or_else(|| {
+            Error::new_postcondition(format!(
+                "Price deviation check for {} failed: could not compute the caller's intended \
+                 ratio ({} / {}).",
+                description, intended_amount_1, intended_amount_0,
+            ))
+        })?;
+    let realized_ratio = amount_1_decimal.checked_div(amount_0_decimal).ok_or_else(|| {
+        Error::new_postcondition(format!(
+            "Price deviation check for {} failed: could not compute the deposit's realized \
+             ratio ({} / {}).",
+            description, amount_1, amount_0,
+        ))
+    })?;
+
+    let ratio = realized_ratio.checked_div(intended_ratio).ok_or_else(|| {
+        Error::new_postcondition(format!(
+            "Price deviation check for {} failed: could not compare the deposit's realized ratio \
+             to the caller's intended ratio.",
+            description,
+        ))
+    })?;
+
+    let deviation = (ratio - Decimal::ONE).abs();
+
+    let max_deviation = Decimal::from(max_deviation_bps) / Decimal::from(BPS_DENOMINATOR);
+
+    if deviation > max_deviation {
+        return Err(Error::new_postcondition(format!(
+            "Price deviation check for {} failed: caller's intended ratio {}, deposit realized \
+             ratio {} ({} bps deviation, max allowed {} bps).",
+            description,
+            intended_ratio,
+            realized_ratio,
+            (deviation * Decimal::from(BPS_DENOMINATOR)).round(),
+            max_deviation_bps,
+        )));
+    }
+
+    Ok(())
+}
+
+/// Returns `Err` if KongSwap's self-reported realized `price` deviates from the quoted
+/// `mid_price` by more than `max_slippage_bps`. Both are KongSwap-reported `f64` spot prices
+/// (`SwapReply::{price, mid_price}`), converted to `Decimal` via a checked `TryFrom` so that a
+/// degenerate reply (non-finite, or a `mid_price` of `0.0`) yields a typed error instead of a
+/// NaN/Inf comparison that could silently evaluate to `false`. This is a cross-check against
+/// KongSwap's own notion of slippage, independent of [`check_slippage_bps`]'s comparison of the
+/// previewed and realized amounts.
+pub(crate) fn check_price_deviation_bps(
+    mid_price: f64,
+    price: f64,
+    max_slippage_bps: u16,
+    description: &str,
+) -> Result<(), Error> {
+    let mid_price_decimal = Decimal::try_from(mid_price).map_err(|err| {
+        Error::new_postcondition(format!(
+            "Slippage check for {} failed: mid_price {} is not a valid decimal: {}.",
+            description, mid_price, err,
+        ))
+    })?;
+    let price_decimal = Decimal::try_from(price).map_err(|err| {
+        Error::new_postcondition(format!(
+            "Slippage check for {} failed: price {} is not a valid decimal: {}.",
+            description, price, err,
+        ))
+    })?;
+
+    if mid_price_decimal.is_zero() {
+        return Err(Error::new_postcondition(format!(
+            "Slippage check for {} failed: quoted mid_price is 0.",
+            description,
+        )));
+    }
+
+    // The implied realized-to-quoted price ratio, computed as a checked division so that a
+    // pathological (e.g. zero) denominator yields a typed error instead of a panic.
+    let ratio = price_decimal.checked_div(mid_price_decimal).ok_or_else(|| {
+        Error::new_postcondition(format!(
+            "Slippage check for {} failed: could not compute price/mid_price ratio ({} / {}).",
+            description, price, mid_price,
+        ))
+    })?;
+
+    let deviation = (ratio - Decimal::ONE).abs();
+
+    let max_slippage = Decimal::from(max_slippage_bps) / Decimal::from(BPS_DENOMINATOR);
+
+    if deviation > max_slippage {
+        return Err(Error::new_postcondition(format!(
+            "Slippage check for {} failed: quoted mid_price {}, realized price {} ({} bps \
+             deviation, max allowed {} bps).",
+            description,
+            mid_price,
+            price,
+            (deviation * Decimal::from(BPS_DENOMINATOR)).round(),
+            max_slippage_bps,
+        )));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,321 @@
+//! Upgrade/downgrade persistence harness, in the spirit of the ICRC-1 ledger's own
+//! upgrade/downgrade integration test: rather than round-tripping a [`Storable`] impl in isolation
+//! (see [`crate::state::storage::tests`], which already locks [`ValidatedBalances`]'s and
+//! [`StableTransaction`]'s encodings), this re-attaches a fresh [`MemoryManager`] and fresh
+//! `StableCell`/`StableVec` instances to the *same* backing memory a previous instance wrote to --
+//! exactly what happens to `BALANCES`/`AUDIT_TRAIL` (see `canister.rs`) across a real canister
+//! upgrade, where the `thread_local!` instances are dropped and reconstructed from whatever bytes
+//! are already sitting in stable memory.
+//!
+//! This does not drive the actual `#[pre_upgrade]`/`#[post_upgrade]` hooks, and does not check in
+//! a stable-memory image produced by a prior schema version -- there is only one schema version
+//! of [`ConfigState`] today, so there is no "prior layout" fixture to diff against, and no
+//! `post_upgrade`-equivalent decoding path to run it through. What this harness does cover for
+//! real: the audit trail (`StableVec<StableTransaction, _>`), the `BALANCES` cell
+//! (`StableCell<ConfigState, _>`), and the other scalar `StableCell`s
+//! ([`ContractStatus`], [`IntegrityStatus`], [`PendingDepositState`]) genuinely survive being
+//! dropped and re-read from the same backing memory, both forward (more writes after the first
+//! re-attach) and in reverse (re-attaching a second time and finding only what was actually
+//! persisted, nothing a stale in-memory copy might have papered over).
+use candid::Principal;
+use ic_stable_structures::{
+    memory_manager::{MemoryId, MemoryManager},
+    Cell as StableCell, DefaultMemoryImpl, Vec as StableVec,
+};
+use icrc_ledger_types::icrc1::account::Account;
+use sns_treasury_manager::{Operation, Step, TransactionWitness, TreasuryManagerOperation};
+
+use crate::{
+    balances::ValidatedBalances,
+    state::storage::{
+        compute_transaction_hash, ConfigState, ContractStatus, IntegrityStatus,
+        PendingDepositState, StableTransaction, GENESIS_PREV_HASH,
+    },
+    validation::ValidatedAsset,
+};
+
+const GOLDEN_AUDIT_TRAIL_MEMORY_ID: MemoryId = MemoryId::new(0);
+const GOLDEN_CONTRACT_STATUS_MEMORY_ID: MemoryId = MemoryId::new(1);
+const GOLDEN_INTEGRITY_STATUS_MEMORY_ID: MemoryId = MemoryId::new(2);
+const GOLDEN_PENDING_DEPOSIT_STATE_MEMORY_ID: MemoryId = MemoryId::new(3);
+const GOLDEN_BALANCES_MEMORY_ID: MemoryId = MemoryId::new(4);
+
+/// A minimal but fully-initialized [`ValidatedBalances`], just enough to tell an `Uninitialized`
+/// [`ConfigState`] apart from an `Initialized` one across a re-attach -- the encoding of
+/// [`ValidatedBalances`] itself is already locked field-by-field by
+/// [`crate::state::storage::tests::test_validated_balances_storable_round_trips_unchanged`].
+fn golden_validated_balances() -> ValidatedBalances {
+    let asset_0 = ValidatedAsset::try_from(sns_treasury_manager::Asset::Token {
+        ledger_canister_id: Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap(),
+        symbol: "ICP".to_string(),
+        ledger_fee_decimals: candid::Nat::from(10_000u64),
+    })
+    .unwrap();
+    let asset_1 = ValidatedAsset::try_from(sns_treasury_manager::Asset::Token {
+        ledger_canister_id: Principal::from_text("mxzaz-hqaaa-aaaar-qaada-cai").unwrap(),
+        symbol: "SNS".to_string(),
+        ledger_fee_decimals: candid::Nat::from(10_000u64),
+    })
+    .unwrap();
+    let account = |principal_index: u8| Account {
+        owner: Principal::from_slice(&[principal_index]),
+        subaccount: None,
+    };
+
+    ValidatedBalances::new(
+        1_700_000_000_000_000_000,
+        asset_0,
+        asset_1,
+        "owner".to_string(),
+        account(1),
+        account(2),
+        "manager".to_string(),
+        account(3),
+        account(4),
+    )
+}
+
+/// A snapshot of the raw stable memory backing a [`MemoryManager`], taken by cloning the shared
+/// [`DefaultMemoryImpl`] handle rather than copying bytes -- `DefaultMemoryImpl` is reference
+/// counted, so the clone and the original observe the same underlying pages. Re-running
+/// [`MemoryManager::init`] against this snapshot is what simulates "restart the canister and let
+/// it re-attach to whatever is already in stable memory" across the rest of this module.
+fn snapshot_stable_memory(memory: &DefaultMemoryImpl) -> DefaultMemoryImpl {
+    memory.clone()
+}
+
+fn golden_audit_entry(index: u64, prev_hash: [u8; 32]) -> StableTransaction {
+    let operation = TreasuryManagerOperation {
+        operation: Operation::Deposit,
+        step: Step {
+            index,
+            is_final: true,
+        },
+    };
+    let canister_id = Principal::from_text("2vxsx-fae").unwrap();
+    let timestamp_ns = 1_700_000_000_000_000_000 + index;
+    let result = Ok(TransactionWitness::NonLedger(format!("golden-{index}")));
+    let human_readable = format!("Golden audit trail entry {index}.");
+
+    let hash = compute_transaction_hash(
+        &prev_hash,
+        timestamp_ns,
+        &operation,
+        canister_id,
+        &result,
+        &human_readable,
+    );
+
+    StableTransaction {
+        timestamp_ns,
+        canister_id,
+        result,
+        human_readable,
+        operation,
+        prev_hash,
+        hash,
+        locked_ledgers: Vec::new(),
+    }
+}
+
+/// Asserts two [`StableTransaction`]s agree field-by-field -- it has no `PartialEq` of its own,
+/// since `result`/`operation` come from the unmodifiable `sns_treasury_manager` crate.
+fn assert_transaction_eq(restored: &StableTransaction, golden: &StableTransaction) {
+    assert_eq!(restored.timestamp_ns, golden.timestamp_ns);
+    assert_eq!(restored.canister_id, golden.canister_id);
+    assert_eq!(
+        format!("{:?}", restored.result),
+        format!("{:?}", golden.result)
+    );
+    assert_eq!(restored.human_readable, golden.human_readable);
+    assert_eq!(restored.operation.operation, golden.operation.operation);
+    assert_eq!(restored.operation.step.index, golden.operation.step.index);
+    assert_eq!(
+        restored.operation.step.is_final,
+        golden.operation.step.is_final
+    );
+    assert_eq!(restored.prev_hash, golden.prev_hash);
+    assert_eq!(restored.hash, golden.hash);
+    assert_eq!(restored.locked_ledgers, golden.locked_ledgers);
+}
+
+#[test]
+fn test_audit_trail_and_scalar_cells_survive_upgrade() {
+    let backing_memory = DefaultMemoryImpl::default();
+
+    // "Before the upgrade": write golden values through a first generation of stable structures.
+    let first_entry = golden_audit_entry(0, GENESIS_PREV_HASH);
+    let second_entry = golden_audit_entry(1, first_entry.hash);
+    {
+        let memory_manager = MemoryManager::init(snapshot_stable_memory(&backing_memory));
+
+        let audit_trail: StableVec<StableTransaction, _> =
+            StableVec::init(memory_manager.get(GOLDEN_AUDIT_TRAIL_MEMORY_ID)).unwrap();
+        audit_trail.push(&first_entry).unwrap();
+        audit_trail.push(&second_entry).unwrap();
+
+        let contract_status: StableCell<ContractStatus, _> = StableCell::init(
+            memory_manager.get(GOLDEN_CONTRACT_STATUS_MEMORY_ID),
+            ContractStatus::default(),
+        )
+        .unwrap();
+        contract_status.set(ContractStatus::Halted).unwrap();
+
+        let integrity_status: StableCell<IntegrityStatus, _> = StableCell::init(
+            memory_manager.get(GOLDEN_INTEGRITY_STATUS_MEMORY_ID),
+            IntegrityStatus::default(),
+        )
+        .unwrap();
+        integrity_status.set(IntegrityStatus::Corrupt).unwrap();
+
+        let pending_deposit_state: StableCell<PendingDepositState, _> = StableCell::init(
+            memory_manager.get(GOLDEN_PENDING_DEPOSIT_STATE_MEMORY_ID),
+            PendingDepositState::default(),
+        )
+        .unwrap();
+        pending_deposit_state
+            .set(PendingDepositState::PendingPoolAdd)
+            .unwrap();
+
+        let balances: StableCell<ConfigState, _> = StableCell::init(
+            memory_manager.get(GOLDEN_BALANCES_MEMORY_ID),
+            ConfigState::default(),
+        )
+        .unwrap();
+        balances
+            .set(ConfigState::Initialized(golden_validated_balances()))
+            .unwrap();
+
+        // Every stable structure above, and `memory_manager` itself, is dropped here, the same as
+        // a canister's `thread_local!`s are torn down when the old Wasm module is discarded.
+    }
+
+    // "After the upgrade": re-attach fresh stable structures to the same backing memory and
+    // confirm every value written above is still there, exactly as `post_upgrade` relies on.
+    {
+        let memory_manager = MemoryManager::init(snapshot_stable_memory(&backing_memory));
+
+        let audit_trail: StableVec<StableTransaction, _> =
+            StableVec::init(memory_manager.get(GOLDEN_AUDIT_TRAIL_MEMORY_ID)).unwrap();
+        assert_eq!(audit_trail.len(), 2);
+        assert_transaction_eq(&audit_trail.get(0).unwrap(), &first_entry);
+        assert_transaction_eq(&audit_trail.get(1).unwrap(), &second_entry);
+
+        let contract_status: StableCell<ContractStatus, _> = StableCell::init(
+            memory_manager.get(GOLDEN_CONTRACT_STATUS_MEMORY_ID),
+            ContractStatus::default(),
+        )
+        .unwrap();
+        assert_eq!(contract_status.get(), &ContractStatus::Halted);
+
+        let integrity_status: StableCell<IntegrityStatus, _> = StableCell::init(
+            memory_manager.get(GOLDEN_INTEGRITY_STATUS_MEMORY_ID),
+            IntegrityStatus::default(),
+        )
+        .unwrap();
+        assert_eq!(integrity_status.get(), &IntegrityStatus::Corrupt);
+
+        let pending_deposit_state: StableCell<PendingDepositState, _> = StableCell::init(
+            memory_manager.get(GOLDEN_PENDING_DEPOSIT_STATE_MEMORY_ID),
+            PendingDepositState::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            pending_deposit_state.get(),
+            &PendingDepositState::PendingPoolAdd
+        );
+
+        let balances: StableCell<ConfigState, _> = StableCell::init(
+            memory_manager.get(GOLDEN_BALANCES_MEMORY_ID),
+            ConfigState::default(),
+        )
+        .unwrap();
+        match balances.get() {
+            ConfigState::Initialized(restored) => {
+                assert_eq!(restored, &golden_validated_balances())
+            }
+            ConfigState::Uninitialized => panic!("balances should have survived the re-attach"),
+        }
+    }
+}
+
+/// The reverse direction: writes made *after* the first re-attach (i.e. on the new version) must
+/// still be exactly what a second re-attach (i.e. a downgrade back, or a second restart) observes
+/// -- nothing from the first generation leaks back in, and nothing is silently dropped.
+#[test]
+fn test_writes_after_upgrade_survive_a_second_reattach() {
+    let backing_memory = DefaultMemoryImpl::default();
+
+    {
+        let memory_manager = MemoryManager::init(snapshot_stable_memory(&backing_memory));
+        let contract_status: StableCell<ContractStatus, _> = StableCell::init(
+            memory_manager.get(GOLDEN_CONTRACT_STATUS_MEMORY_ID),
+            ContractStatus::default(),
+        )
+        .unwrap();
+        contract_status.set(ContractStatus::DepositsPaused).unwrap();
+    }
+
+    let second_entry = {
+        let memory_manager = MemoryManager::init(snapshot_stable_memory(&backing_memory));
+
+        let audit_trail: StableVec<StableTransaction, _> =
+            StableVec::init(memory_manager.get(GOLDEN_AUDIT_TRAIL_MEMORY_ID)).unwrap();
+        let entry = golden_audit_entry(0, GENESIS_PREV_HASH);
+        audit_trail.push(&entry).unwrap();
+
+        let contract_status: StableCell<ContractStatus, _> = StableCell::init(
+            memory_manager.get(GOLDEN_CONTRACT_STATUS_MEMORY_ID),
+            ContractStatus::default(),
+        )
+        .unwrap();
+        assert_eq!(contract_status.get(), &ContractStatus::DepositsPaused);
+        contract_status.set(ContractStatus::Operational).unwrap();
+
+        entry
+    };
+
+    {
+        let memory_manager = MemoryManager::init(snapshot_stable_memory(&backing_memory));
+
+        let audit_trail: StableVec<StableTransaction, _> =
+            StableVec::init(memory_manager.get(GOLDEN_AUDIT_TRAIL_MEMORY_ID)).unwrap();
+        assert_eq!(audit_trail.len(), 1);
+        assert_transaction_eq(&audit_trail.get(0).unwrap(), &second_entry);
+
+        let contract_status: StableCell<ContractStatus, _> = StableCell::init(
+            memory_manager.get(GOLDEN_CONTRACT_STATUS_MEMORY_ID),
+            ContractStatus::default(),
+        )
+        .unwrap();
+        assert_eq!(contract_status.get(), &ContractStatus::Operational);
+    }
+}
+
+/// `snapshot_stable_memory` is itself exercised above (every re-attach in this module goes
+/// through it), but this spells out what it actually guarantees in isolation: two handles taken
+/// from the same backing memory observe each other's writes, the precondition the rest of this
+/// harness depends on to mean anything.
+#[test]
+fn test_snapshot_stable_memory_shares_the_same_backing_pages() {
+    let backing_memory = DefaultMemoryImpl::default();
+    let handle_a = snapshot_stable_memory(&backing_memory);
+    let handle_b = snapshot_stable_memory(&backing_memory);
+
+    let memory_manager_a = MemoryManager::init(handle_a);
+    let cell_a: StableCell<ContractStatus, _> = StableCell::init(
+        memory_manager_a.get(GOLDEN_CONTRACT_STATUS_MEMORY_ID),
+        ContractStatus::default(),
+    )
+    .unwrap();
+    cell_a.set(ContractStatus::Halted).unwrap();
+    drop(memory_manager_a);
+
+    let memory_manager_b = MemoryManager::init(handle_b);
+    let cell_b: StableCell<ContractStatus, _> = StableCell::init(
+        memory_manager_b.get(GOLDEN_CONTRACT_STATUS_MEMORY_ID),
+        ContractStatus::default(),
+    )
+    .unwrap();
+    assert_eq!(cell_b.get(), &ContractStatus::Halted);
+}
@@ -0,0 +1,404 @@
+//! A property-based generalization of the old single-hard-coded-switch lock-interleaving test:
+//! instead of forcing one specific context switch with a two-party `Barrier`, this drives two
+//! concurrent simulated `Deposit` operations through a [`CooperativeScheduler`], sweeping every
+//! legitimate point at which the second one's lock check could land after the first's lock is
+//! recorded. The invariant under test is exactly [`KongSwapAdaptor::check_state_lock`]'s contract:
+//! at most one operation ever progresses past lock acquisition on a given `(Operation, ledger)`
+//! key, and every later one is rejected with [`ErrorKind::TemporarilyUnavailable`].
+//!
+//! This deliberately drives [`KongSwapAdaptor::check_state_lock`]/
+//! [`KongSwapAdaptor::push_audit_trail_transaction`] directly, the same pair of primitives
+//! [`crate::emit_transaction`] calls around its single real `.await`, rather than going through
+//! the full `deposit` entry point -- the invariant being tested lives entirely in that pair, and
+//! isolating it keeps this test from being entangled with (and broken by) unrelated changes
+//! elsewhere in the deposit flow.
+
+use super::*;
+use crate::{
+    state::storage::OperationLock, validation::ValidatedTreasuryManagerInit, StableAuditTrail,
+    StableBalances, StableContractStatus, StableIdempotencyKeys, StableOperationLockCell,
+    StablePriceHistory, StableTransferIntents, StableWithdrawStateCell, AUDIT_TRAIL_MEMORY_ID,
+    BALANCES_MEMORY_ID, CONTRACT_STATUS_MEMORY_ID, IDEMPOTENCY_KEYS_MEMORY_ID,
+    OPERATION_LOCK_MEMORY_ID, PRICE_HISTORY_MEMORY_ID, TRANSFER_INTENTS_MEMORY_ID,
+    WITHDRAW_STATE_MEMORY_ID,
+};
+use candid::{Nat, Principal};
+use ic_stable_structures::memory_manager::MemoryManager;
+use ic_stable_structures::{
+    BTreeMap as StableBTreeMap, Cell as StableCell, DefaultMemoryImpl, Vec as StableVec,
+};
+use kongswap_adaptor::{
+    agent::mock_agent::{CooperativeScheduler, MockAgent, SteppedAgent},
+    requests::CommitStateRequest,
+};
+use lazy_static::lazy_static;
+use sns_treasury_manager::{Allowance, Asset, ErrorKind, TreasuryManagerInit};
+use std::{cell::RefCell, sync::Arc};
+
+const E8: u64 = 100_000_000;
+
+/// How many scheduler-gated sub-transactions the lock-holding stack steps through after
+/// acquiring the lock, purely to give the fuzzer a range of post-acquisition switch points to
+/// place the contending stack's attempt at.
+const NUM_SUB_TRANSACTIONS: usize = 5;
+
+lazy_static! {
+    static ref SELF_CANISTER_ID: Principal =
+        Principal::from_text("jexlm-gaaaa-aaaar-qalmq-cai").unwrap();
+    static ref SNS_LEDGER: Principal = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    static ref ICP_LEDGER: Principal = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+}
+
+/// Builds a fresh [`KongSwapAdaptor`] over `mock_agent`, wrapped in a [`SteppedAgent`] so its
+/// calls are gated by `scheduler`, sharing the given stable-storage cells with any other adaptor
+/// built over the same cells -- i.e. a second view onto the one logical canister state, the same
+/// way `canister_state()` hands every update call its own short-lived [`KongSwapAdaptor`] value
+/// over storage that actually persists across calls.
+#[allow(clippy::too_many_arguments)]
+fn make_adaptor(
+    mock_agent: MockAgent,
+    balances: &'static std::thread::LocalKey<RefCell<StableBalances>>,
+    audit_trail: &'static std::thread::LocalKey<RefCell<StableAuditTrail>>,
+    withdraw_state: &'static std::thread::LocalKey<RefCell<StableWithdrawStateCell>>,
+    price_history: &'static std::thread::LocalKey<RefCell<StablePriceHistory>>,
+    idempotency_keys: &'static std::thread::LocalKey<RefCell<StableIdempotencyKeys>>,
+    contract_status: &'static std::thread::LocalKey<RefCell<StableContractStatus>>,
+    transfer_intents: &'static std::thread::LocalKey<RefCell<StableTransferIntents>>,
+    operation_lock: &'static std::thread::LocalKey<RefCell<StableOperationLockCell>>,
+    scheduler: Arc<CooperativeScheduler>,
+    stack_id: usize,
+) -> KongSwapAdaptor<SteppedAgent<MockAgent>> {
+    let agent = SteppedAgent::new(mock_agent, scheduler, stack_id);
+
+    KongSwapAdaptor::new(
+        || 0, // a constant mock clock keeps `check_state_lock`'s reported remaining duration
+        // deterministic across every seed.
+        agent,
+        *SELF_CANISTER_ID,
+        balances,
+        audit_trail,
+        withdraw_state,
+        price_history,
+        idempotency_keys,
+        contract_status,
+        transfer_intents,
+        operation_lock,
+    )
+}
+
+/// Runs the lock-holding stack: acquires the `Deposit` lock, then steps through
+/// [`NUM_SUB_TRANSACTIONS`] scheduler-gated calls, recording the lock-holding audit-trail entry
+/// after the first one -- mirroring exactly what [`crate::emit_transaction`] does around its first
+/// sub-transaction.
+async fn run_lock_holder(
+    kong_adaptor: Arc<KongSwapAdaptor<SteppedAgent<MockAgent>>>,
+    scheduler: Arc<CooperativeScheduler>,
+    stack_id: usize,
+) -> Result<(), Vec<Error>> {
+    scheduler.wait_turn(stack_id).await;
+
+    let keys = kong_adaptor.lock_keys(&[Operation::Deposit]);
+    kong_adaptor.check_state_lock(&keys)?;
+
+    let mut context = kong_adaptor.new_operation_context(Operation::Deposit);
+
+    for step in 0..NUM_SUB_TRANSACTIONS {
+        // `SteppedAgent::call` already waits its own turn on `scheduler` before delegating to
+        // `MockAgent`, so this is the one checkpoint per sub-transaction -- no separate
+        // `wait_turn` call is needed here.
+        kong_adaptor
+            .agent
+            .call(*SELF_CANISTER_ID, CommitStateRequest {})
+            .await
+            .expect("the scripted commit_state call should always succeed");
+
+        let operation = context.next_operation();
+        if step == 0 {
+            // The operation's first sub-transaction is always recorded -- it's the anchor entry
+            // that holds the lock (see `KongSwapAdaptor::check_state_lock`).
+            kong_adaptor.push_audit_trail_transaction(StableTransaction {
+                timestamp_ns: kong_adaptor.time_ns(),
+                canister_id: *SELF_CANISTER_ID,
+                result: Ok(TransactionWitness::NonLedger(
+                    "simulated lock-acquiring sub-transaction".to_string(),
+                )),
+                human_readable: "Simulates emit_transaction's first sub-transaction of a \
+                                  concurrent deposit."
+                    .to_string(),
+                operation,
+                prev_hash: GENESIS_PREV_HASH,
+                hash: GENESIS_PREV_HASH,
+                locked_ledgers: keys.iter().map(|(_, ledger)| *ledger).collect(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the contending stack: a single `Deposit` lock check, with no calls scripted at all --
+/// every schedule this test builds places it after the lock holder has already recorded its
+/// lock, so it's expected to be rejected before ever reaching an agent call.
+async fn run_contender(
+    kong_adaptor: Arc<KongSwapAdaptor<SteppedAgent<MockAgent>>>,
+    scheduler: Arc<CooperativeScheduler>,
+    stack_id: usize,
+) -> Result<(), Vec<Error>> {
+    scheduler.wait_turn(stack_id).await;
+
+    let keys = kong_adaptor.lock_keys(&[Operation::Deposit]);
+    kong_adaptor.check_state_lock(&keys)
+}
+
+/// Schedules the lock holder (`stack 0`) through `switch_at` of its checkpoints (its virtual
+/// start plus however many of its [`NUM_SUB_TRANSACTIONS`] calls), then lets the contender
+/// (`stack 1`) take its one checkpoint, then lets the lock holder run to completion.
+///
+/// `switch_at` must be at least `2`, so the contender never runs before the lock holder's virtual
+/// start *and* its first call (the one whose completion records the lock) have both passed --
+/// anything earlier would race the lock holder's own synchronous prefix, a real but separate bug
+/// in the adaptor's locking window that this test isn't scoped to cover.
+fn build_schedule(switch_at: usize) -> Vec<usize> {
+    let total_lock_holder_checkpoints = 1 + NUM_SUB_TRANSACTIONS;
+    let switch_at = switch_at.clamp(2, total_lock_holder_checkpoints);
+
+    let mut schedule = vec![0; switch_at];
+    schedule.push(1);
+    schedule.resize(schedule.len() + (total_lock_holder_checkpoints - switch_at), 0);
+    schedule
+}
+
+async fn run_schedule(switch_at: usize) {
+    thread_local! {
+        static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+            RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+        static BALANCES: RefCell<StableBalances> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(BALANCES_MEMORY_ID),
+                        ConfigState::default()
+                    )
+                    .expect("BALANCES init should not cause errors")
+                )
+            );
+
+        static AUDIT_TRAIL: RefCell<StableAuditTrail> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableVec::init(
+                        memory_manager.borrow().get(AUDIT_TRAIL_MEMORY_ID)
+                    )
+                    .expect("AUDIT_TRAIL init should not cause errors")
+                )
+            );
+
+        static WITHDRAW_STATE: RefCell<StableWithdrawStateCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(WITHDRAW_STATE_MEMORY_ID),
+                        Default::default()
+                    )
+                    .expect("WITHDRAW_STATE init should not cause errors")
+                )
+            );
+
+        static PRICE_HISTORY: RefCell<StablePriceHistory> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(PRICE_HISTORY_MEMORY_ID)
+                    )
+                )
+            );
+
+        static IDEMPOTENCY_KEYS: RefCell<StableIdempotencyKeys> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(IDEMPOTENCY_KEYS_MEMORY_ID)
+                    )
+                )
+            );
+
+        static CONTRACT_STATUS: RefCell<StableContractStatus> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(CONTRACT_STATUS_MEMORY_ID),
+                        ContractStatus::default()
+                    )
+                    .expect("CONTRACT_STATUS init should not cause errors")
+                )
+            );
+
+        static TRANSFER_INTENTS: RefCell<StableTransferIntents> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(TRANSFER_INTENTS_MEMORY_ID)
+                    )
+                )
+            );
+
+        static OPERATION_LOCK: RefCell<StableOperationLockCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(OPERATION_LOCK_MEMORY_ID),
+                        OperationLock::default()
+                    )
+                    .expect("OPERATION_LOCK init should not cause errors")
+                )
+            );
+    }
+
+    let scheduler = CooperativeScheduler::new(build_schedule(switch_at));
+
+    // One scripted `commit_state` self-call per sub-transaction the lock holder steps through --
+    // `add_call` auto-appends a second, identical `commit_state` entry after each one (mirroring
+    // the self-call every real `emit_transaction` issues), which is simply never consumed here.
+    let mut lock_holder_agent = MockAgent::new(*SELF_CANISTER_ID);
+    for _ in 0..NUM_SUB_TRANSACTIONS {
+        lock_holder_agent =
+            lock_holder_agent.add_call(*SELF_CANISTER_ID, CommitStateRequest {}, ());
+    }
+
+    let lock_holder = Arc::new(make_adaptor(
+        lock_holder_agent,
+        &BALANCES,
+        &AUDIT_TRAIL,
+        &WITHDRAW_STATE,
+        &PRICE_HISTORY,
+        &IDEMPOTENCY_KEYS,
+        &CONTRACT_STATUS,
+        &TRANSFER_INTENTS,
+        &OPERATION_LOCK,
+        Arc::clone(&scheduler),
+        0,
+    ));
+
+    let owner_account = sns_treasury_manager::Account {
+        owner: Principal::from_text("2vxsx-fae").unwrap(),
+        subaccount: None,
+    };
+    let init = TreasuryManagerInit {
+        allowances: vec![
+            Allowance {
+                asset: Asset::Token {
+                    ledger_canister_id: *SNS_LEDGER,
+                    symbol: "DAO".to_string(),
+                    ledger_fee_decimals: Nat::from(10_000u64),
+                },
+                owner_account,
+                amount_decimals: Nat::from(100 * E8),
+            },
+            Allowance {
+                asset: Asset::Token {
+                    ledger_canister_id: *ICP_LEDGER,
+                    symbol: "ICP".to_string(),
+                    ledger_fee_decimals: Nat::from(10_000u64),
+                },
+                owner_account,
+                amount_decimals: Nat::from(100 * E8),
+            },
+        ],
+    };
+    let ValidatedTreasuryManagerInit {
+        allowance_0,
+        allowance_1,
+    } = init.try_into().unwrap();
+
+    lock_holder.initialize(
+        allowance_0.asset,
+        allowance_1.asset,
+        allowance_0.owner_account,
+        allowance_1.owner_account,
+    );
+
+    // A second view onto the same stable storage `lock_holder` just initialized -- modeling the
+    // second of two concurrent update calls, each given its own short-lived `KongSwapAdaptor`
+    // value the way `canister_state()` does in production.
+    let contender = Arc::new(make_adaptor(
+        MockAgent::new(*SELF_CANISTER_ID),
+        &BALANCES,
+        &AUDIT_TRAIL,
+        &WITHDRAW_STATE,
+        &PRICE_HISTORY,
+        &IDEMPOTENCY_KEYS,
+        &CONTRACT_STATUS,
+        &TRANSFER_INTENTS,
+        &OPERATION_LOCK,
+        Arc::clone(&scheduler),
+        1,
+    ));
+
+    let lock_holder_task = tokio::spawn(run_lock_holder(
+        Arc::clone(&lock_holder),
+        Arc::clone(&scheduler),
+        0,
+    ));
+    let contender_task = tokio::spawn(run_contender(
+        Arc::clone(&contender),
+        Arc::clone(&scheduler),
+        1,
+    ));
+
+    let (lock_holder_result, contender_result) = tokio::join!(lock_holder_task, contender_task);
+    let lock_holder_result = lock_holder_result.expect("the lock holder task should not panic");
+    let contender_result = contender_result.expect("the contender task should not panic");
+
+    assert!(
+        lock_holder_result.is_ok(),
+        "switch_at {switch_at}: the stack that already holds the lock should run uninterrupted, \
+         got {lock_holder_result:?}"
+    );
+
+    match contender_result {
+        Err(errors) => {
+            assert_eq!(
+                errors.len(),
+                1,
+                "switch_at {switch_at}: expected exactly one error, got {errors:?}"
+            );
+            assert!(
+                matches!(errors[0].kind, ErrorKind::TemporarilyUnavailable {}),
+                "switch_at {switch_at}: expected TemporarilyUnavailable, got {:?}",
+                errors[0]
+            );
+        }
+        Ok(()) => panic!(
+            "switch_at {switch_at}: a concurrent deposit attempted after the lock was already \
+             recorded should never be allowed to also acquire it"
+        ),
+    }
+}
+
+/// Sweeps every `switch_at` in the safe range (at or after the lock holder's own lock-recording
+/// checkpoint), rather than hand-coding the single switch point the old
+/// `test_lock_interleaving_should_not_pass` used -- systematic coverage of the same invariant
+/// across every legitimate interleaving, instead of just the one the original author happened to
+/// pick.
+///
+/// Each `switch_at` is run on its own freshly spawned OS thread, with its own single-threaded
+/// Tokio runtime, rather than simply `.await`ed in a loop on this test's own runtime -- the
+/// `thread_local!` stable-storage cells `run_schedule` declares are real `static`s keyed per OS
+/// thread, so reusing this test's thread across iterations would leak one iteration's audit trail
+/// (and its still-held lock) into the next. A fresh thread gives each `switch_at` the same
+/// pristine storage a fresh canister instance would have.
+#[tokio::test]
+async fn property_test_at_most_one_concurrent_deposit_acquires_the_lock() {
+    for switch_at in 2..=(1 + NUM_SUB_TRANSACTIONS) {
+        std::thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("building a current-thread runtime should not fail")
+                .block_on(run_schedule(switch_at));
+        })
+        .join()
+        .expect("the run_schedule thread should not panic");
+    }
+}
@@ -1,13 +1,15 @@
 use candid::{CandidType, Nat, Principal};
 use ic_stable_structures::memory_manager::MemoryManager;
-use ic_stable_structures::{Cell as StableCell, DefaultMemoryImpl, Vec as StableVec};
+use ic_stable_structures::{
+    BTreeMap as StableBTreeMap, Cell as StableCell, DefaultMemoryImpl, Vec as StableVec,
+};
 use icrc_ledger_types::icrc::generic_metadata_value::MetadataValue;
 use icrc_ledger_types::icrc2::approve::ApproveArgs;
 use kongswap_adaptor::agent::icrc_requests::Icrc1MetadataRequest;
 use kongswap_adaptor::{agent::Request, requests::CommitStateRequest};
 use pretty_assertions::assert_eq;
 use sns_treasury_manager::{
-    Allowance, Asset, DepositRequest, TreasuryManager, TreasuryManagerInit,
+    Allowance, Asset, DepositRequest, TreasuryManager, TreasuryManagerInit, TreasuryManagerOperation,
 };
 use std::cell::UnsafeCell;
 use std::collections::HashMap;
@@ -23,8 +25,10 @@ use crate::kong_types::{
 };
 use crate::KONG_BACKEND_CANISTER_ID;
 use crate::{
-    state::storage::ConfigState, validation::ValidatedTreasuryManagerInit, StableAuditTrail,
-    StableBalances, AUDIT_TRAIL_MEMORY_ID, BALANCES_MEMORY_ID,
+    state::storage::{ConfigState, StableWithdrawState},
+    validation::ValidatedTreasuryManagerInit,
+    StableAuditTrail, StableBalances, StablePriceHistory, StableWithdrawStateCell,
+    AUDIT_TRAIL_MEMORY_ID, BALANCES_MEMORY_ID, PRICE_HISTORY_MEMORY_ID, WITHDRAW_STATE_MEMORY_ID,
 };
 use std::fmt::Debug;
 
@@ -376,6 +380,7 @@ fn make_add_pool_request(
         amount_1: Nat::from(amount_1),
         tx_id_1: None,
         lp_fee_bps: Some(30),
+        memo: Some(Vec::<u8>::from(TreasuryManagerOperation::Deposit)),
     }
 }
 
@@ -634,6 +639,26 @@ async fn test_lock_interleaving_should_not_pass() {
                     .expect("AUDIT_TRAIL init should not cause errors")
                 )
             );
+
+        static WITHDRAW_STATE: RefCell<StableWithdrawStateCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(WITHDRAW_STATE_MEMORY_ID),
+                        StableWithdrawState::default()
+                    )
+                    .expect("WITHDRAW_STATE init should not cause errors")
+                )
+            );
+
+        static PRICE_HISTORY: RefCell<StablePriceHistory> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(PRICE_HISTORY_MEMORY_ID)
+                    )
+                )
+            );
     }
 
     let amount_0_decimals = 500 * E8;
@@ -673,6 +698,8 @@ async fn test_lock_interleaving_should_not_pass() {
         *SELF_CANISTER_ID,
         &BALANCES,
         &AUDIT_TRAIL,
+        &WITHDRAW_STATE,
+        &PRICE_HISTORY,
     );
 
     let init = TreasuryManagerInit {
@@ -1,6 +1,8 @@
 use candid::{CandidType, Nat, Principal};
 use ic_stable_structures::memory_manager::MemoryManager;
-use ic_stable_structures::{Cell as StableCell, DefaultMemoryImpl, Vec as StableVec};
+use ic_stable_structures::{
+    BTreeMap as StableBTreeMap, Cell as StableCell, DefaultMemoryImpl, Vec as StableVec,
+};
 use icrc_ledger_types::icrc::generic_metadata_value::MetadataValue;
 use icrc_ledger_types::icrc2::approve::ApproveArgs;
 use kongswap_adaptor::agent::icrc_requests::Icrc1MetadataRequest;
@@ -9,7 +11,7 @@ use maplit::btreemap;
 use pretty_assertions::assert_eq;
 use sns_treasury_manager::{
     Allowance, Asset, Balance, BalanceBook, Balances, DepositRequest, TreasuryManager,
-    TreasuryManagerInit,
+    TreasuryManagerInit, TreasuryManagerOperation,
 };
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
@@ -23,8 +25,10 @@ use crate::kong_types::{
 };
 use crate::KONG_BACKEND_CANISTER_ID;
 use crate::{
-    state::storage::ConfigState, validation::ValidatedTreasuryManagerInit, StableAuditTrail,
-    StableBalances, AUDIT_TRAIL_MEMORY_ID, BALANCES_MEMORY_ID,
+    state::storage::{ConfigState, StableWithdrawState},
+    validation::ValidatedTreasuryManagerInit,
+    StableAuditTrail, StableBalances, StablePriceHistory, StableWithdrawStateCell,
+    AUDIT_TRAIL_MEMORY_ID, BALANCES_MEMORY_ID, PRICE_HISTORY_MEMORY_ID, WITHDRAW_STATE_MEMORY_ID,
 };
 use std::fmt::Debug;
 
@@ -343,6 +347,7 @@ fn make_add_pool_request(
         amount_1: Nat::from(amount_1),
         tx_id_1: None,
         lp_fee_bps: Some(30),
+        memo: Some(Vec::<u8>::from(TreasuryManagerOperation::Deposit)),
     }
 }
 
@@ -598,6 +603,26 @@ async fn test_lock() {
                     .expect("AUDIT_TRAIL init should not cause errors")
                 )
             );
+
+        static WITHDRAW_STATE: RefCell<StableWithdrawStateCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(WITHDRAW_STATE_MEMORY_ID),
+                        StableWithdrawState::default()
+                    )
+                    .expect("WITHDRAW_STATE init should not cause errors")
+                )
+            );
+
+        static PRICE_HISTORY: RefCell<StablePriceHistory> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(PRICE_HISTORY_MEMORY_ID)
+                    )
+                )
+            );
     }
 
     let amount_0_decimals = 500 * E8;
@@ -631,6 +656,8 @@ async fn test_lock() {
         *SELF_CANISTER_ID,
         &BALANCES,
         &AUDIT_TRAIL,
+        &WITHDRAW_STATE,
+        &PRICE_HISTORY,
     );
 
     unsafe {
@@ -131,6 +131,7 @@ pub(crate) fn make_add_pool_request(
         amount_1: Nat::from(amount_1),
         tx_id_1: None,
         lp_fee_bps: Some(30),
+        memo: Some(Vec::<u8>::from(TreasuryManagerOperation::Deposit)),
     }
 }
 
@@ -223,6 +224,7 @@ pub(crate) fn make_remove_liquidity_request(
         token_0,
         token_1,
         remove_lp_token_amount: Nat::from(remove_lp_token_amount),
+        memo: None,
     }
 }
 
@@ -347,6 +349,9 @@ pub(crate) fn make_add_liquidity_request(
         token_1: token_1.to_string(),
         amount_1: Nat::from(amount_1),
         tx_id_1: None,
+        memo: Some(Vec::<u8>::from(TreasuryManagerOperation::new(
+            sns_treasury_manager::Operation::Deposit,
+        ))),
     }
 }
 
@@ -0,0 +1,112 @@
+//! A type-safe wrapper around a raw token amount (in the asset's base units, e.g. e8s), so that
+//! accounting arithmetic can never silently wrap around. An amount is only ever constructed
+//! through [`TokenAmount::try_from`], which rejects values above [`MAX_TOKEN_AMOUNT_DECIMALS`],
+//! and every subsequent operation is checked, surfacing overflow as `None` instead of wrapping.
+
+use candid::{CandidType, Nat};
+use serde::Deserialize;
+use std::iter::Sum;
+
+/// The largest base-unit amount a [`TokenAmount`] may represent. Set to half of `u64::MAX` so
+/// that the sum of any two valid amounts is still representable in a `u64` (and therefore still
+/// round-trips through `Nat`/the `sns_treasury_manager` wire types) without itself overflowing.
+pub const MAX_TOKEN_AMOUNT_DECIMALS: u64 = u64::MAX / 2;
+
+#[derive(CandidType, Clone, Copy, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct TokenAmount(u64);
+
+impl TokenAmount {
+    pub const ZERO: TokenAmount = TokenAmount(0);
+
+    /// Returns the raw base-unit amount.
+    pub fn get(self) -> u64 {
+        self.0
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let sum = self.0.checked_add(other.0)?;
+        Self::try_from(sum).ok()
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        let difference = self.0.checked_sub(other.0)?;
+        Some(Self(difference))
+    }
+
+    pub fn checked_mul(self, factor: u64) -> Option<Self> {
+        let product = self.0.checked_mul(factor)?;
+        Self::try_from(product).ok()
+    }
+
+    /// Like [`Self::checked_sub`], but floors at zero instead of returning `None` on underflow.
+    /// Subsumes the crate's former free-standing `saturating_sub` helper for `Nat` operands.
+    pub fn saturating_sub(self, other: Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+}
+
+impl TryFrom<u64> for TokenAmount {
+    type Error = String;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        if value > MAX_TOKEN_AMOUNT_DECIMALS {
+            return Err(format!(
+                "Token amount {} exceeds the maximum supported amount of {}.",
+                value, MAX_TOKEN_AMOUNT_DECIMALS
+            ));
+        }
+
+        Ok(Self(value))
+    }
+}
+
+impl TryFrom<Nat> for TokenAmount {
+    type Error = String;
+
+    fn try_from(value: Nat) -> Result<Self, Self::Error> {
+        let u64_digit_components = value.0.to_u64_digits();
+
+        let amount = match &u64_digit_components[..] {
+            [] => 0,
+            [val] => *val,
+            vals => {
+                return Err(format!(
+                    "Error parsing a Nat value `{:?}` to a token amount: expected a unique u64 \
+                     value, got {:?}.",
+                    &value,
+                    vals.len(),
+                ))
+            }
+        };
+
+        Self::try_from(amount)
+    }
+}
+
+impl From<TokenAmount> for u64 {
+    fn from(value: TokenAmount) -> Self {
+        value.0
+    }
+}
+
+impl From<TokenAmount> for Nat {
+    fn from(value: TokenAmount) -> Self {
+        Nat::from(value.0)
+    }
+}
+
+impl Sum for TokenAmount {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, |total, amount| {
+            total
+                .checked_add(amount)
+                .expect("Sum of token amounts overflowed the monetary range.")
+        })
+    }
+}
+
+impl std::fmt::Display for TokenAmount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
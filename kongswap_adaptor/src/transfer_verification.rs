@@ -0,0 +1,140 @@
+//! On-ledger verification that a Kong-reported [`Transfer`] witness (built from
+//! `ICTransferReply`'s `amount`/`canister_id`/`block_index` by `From<&TransferIdReply> for
+//! Transfer`, see `kong_types.rs`) actually happened, instead of trusting Kong's self-report.
+//! Reuses the same ICRC-3 `icrc3_get_blocks` query [`crate::reconciliation`] relies on, but fetches
+//! exactly the one block a `Transfer` claims to be in, rather than scanning a range.
+
+use crate::{reconciliation::decode_transfer_block, validation::decode_nat_to_u64, KongSwapAdaptor};
+use candid::{Nat, Principal};
+use icrc_ledger_types::icrc1::account::Account;
+use kongswap_adaptor::{
+    agent::{icrc3_requests::Icrc3GetBlocksRequest, AbstractAgent},
+    audit::OperationContext,
+};
+use sns_treasury_manager::{Error, Transfer};
+
+/// The outcome of checking a Kong-reported [`Transfer`] against the ledger block it claims.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TransferVerificationStatus {
+    /// The claimed block is a transfer to the expected account whose amount and fee agree with
+    /// what Kong reported.
+    Confirmed,
+    /// The claimed block exists and is a transfer to the expected account, but its amount or fee
+    /// disagrees with what Kong reported.
+    AmountMismatch { expected_decimals: Nat, actual_decimals: Nat },
+    /// The claimed block doesn't exist, is still archived, isn't a transfer, or isn't a transfer
+    /// to the expected account -- in every one of these cases, the transfer Kong reported cannot
+    /// be confirmed to actually be there.
+    BlockNotFound,
+}
+
+/// A Kong-reported [`Transfer`] paired with the verdict [`KongSwapAdaptor::verify_transfer`]
+/// reached after querying the ledger for the block it claims to be in.
+#[derive(Debug, Clone)]
+pub(crate) struct VerifiedTransfer {
+    pub transfer: Transfer,
+    pub status: TransferVerificationStatus,
+}
+
+impl<A: AbstractAgent> KongSwapAdaptor<A> {
+    /// Confirms a single Kong-reported [`Transfer`] by querying its ledger's `icrc3_get_blocks`
+    /// for the block at `transfer.block_index`, checking that it is a transfer to `expected_to`
+    /// whose amount and fee agree with what Kong claimed.
+    pub(crate) async fn verify_transfer(
+        &mut self,
+        context: &mut OperationContext,
+        transfer: &Transfer,
+        expected_to: Account,
+        expected_fee_decimals: u64,
+    ) -> Result<VerifiedTransfer, Error> {
+        let ledger_canister_id = Principal::from_text(&transfer.ledger_canister_id)
+            .map_err(|err| {
+                Error::new_postcondition(format!(
+                    "Transfer reported an invalid ledger canister id {:?}: {}",
+                    transfer.ledger_canister_id, err
+                ))
+            })?;
+
+        let block_index = decode_nat_to_u64(transfer.block_index.clone()).map_err(|err| {
+            Error::new_postcondition(format!(
+                "Transfer reported a block_index that doesn't fit in a u64: {}",
+                err
+            ))
+        })?;
+
+        let human_readable = format!(
+            "Calling {}.icrc3_get_blocks to verify the transfer Kong reported at block {}.",
+            ledger_canister_id, block_index,
+        );
+
+        let result = self
+            .emit_transaction(
+                context,
+                ledger_canister_id,
+                Icrc3GetBlocksRequest::new(block_index, 1),
+                human_readable,
+            )
+            .await?;
+
+        // An archived block this adaptor doesn't follow is indistinguishable from a block that
+        // isn't there at all: either way, the transfer Kong reported cannot be confirmed.
+        if !result.archived_blocks.is_empty() || result.blocks.is_empty() {
+            return Ok(VerifiedTransfer {
+                transfer: transfer.clone(),
+                status: TransferVerificationStatus::BlockNotFound,
+            });
+        }
+
+        let status = match decode_transfer_block(&result.blocks[0].block) {
+            Some(decoded) if decoded.to.as_ref() != Some(&expected_to) => {
+                TransferVerificationStatus::BlockNotFound
+            }
+            Some(decoded) if decoded.amount_decimals != transfer.amount_decimals => {
+                TransferVerificationStatus::AmountMismatch {
+                    expected_decimals: transfer.amount_decimals.clone(),
+                    actual_decimals: decoded.amount_decimals,
+                }
+            }
+            Some(decoded) => match decoded.fee_decimals {
+                Some(fee_decimals)
+                    if decode_nat_to_u64(fee_decimals.clone()).unwrap_or_default()
+                        != expected_fee_decimals =>
+                {
+                    TransferVerificationStatus::AmountMismatch {
+                        expected_decimals: Nat::from(expected_fee_decimals),
+                        actual_decimals: fee_decimals,
+                    }
+                }
+                _ => TransferVerificationStatus::Confirmed,
+            },
+            None => TransferVerificationStatus::BlockNotFound,
+        };
+
+        Ok(VerifiedTransfer {
+            transfer: transfer.clone(),
+            status,
+        })
+    }
+
+    /// Verifies every [`Transfer`] in a `TransactionWitness::Ledger` batch (e.g. the transfers
+    /// reported by `add_liquidity`/`add_pool`/`remove_liquidity`/`swap`), so a treasury operation
+    /// can fail loudly when Kong reports a transfer the ledger does not actually contain.
+    pub(crate) async fn verify_transfers(
+        &mut self,
+        context: &mut OperationContext,
+        transfers: &[Transfer],
+        expected_to: Account,
+        expected_fee_decimals: u64,
+    ) -> Result<Vec<VerifiedTransfer>, Error> {
+        let mut verified = Vec::with_capacity(transfers.len());
+
+        for transfer in transfers {
+            verified.push(
+                self.verify_transfer(context, transfer, expected_to, expected_fee_decimals)
+                    .await?,
+            );
+        }
+
+        Ok(verified)
+    }
+}
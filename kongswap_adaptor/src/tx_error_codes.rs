@@ -0,0 +1,47 @@
+/// Machine-readable codes attached to [`sns_treasury_manager::Error::code`], distinguishing the
+/// many call sites across this crate that report errors of the same
+/// [`sns_treasury_manager::ErrorKind`] but for otherwise unrelated reasons. Callers compare
+/// against these symbolically (`u64::from(TransactionErrorCodes::X)`), never against a raw
+/// literal, so the exact numeric values only need to stay distinct from one another, not stable
+/// across releases.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TransactionErrorCodes {
+    /// A downstream canister (ledger, DEX backend, exchange rate) reported a failure.
+    BackendCode,
+    /// A balance computation over-/under-flowed, or otherwise failed to reconcile.
+    BalanceArithmeticCode,
+    /// The exchange rate canister's quote was missing, stale, or otherwise unusable.
+    ExchangeRateCode,
+    /// A post-condition that should hold once an operation has completed did not.
+    PostConditionCode,
+    /// A pre-condition that should hold before an operation starts did not.
+    PreConditionCode,
+    /// A dependency is temporarily unavailable; the caller may retry later.
+    TemporaryUnavailableCode,
+    /// An asset referenced by the request is not one this adaptor manages.
+    UnknownAssetCode,
+    /// A state view (pool reserves, LP balance, operation sequence) snapshotted earlier in an
+    /// operation no longer matches what was re-queried immediately before committing to it.
+    StaleStateCode,
+    /// The canister's stable state has been marked corrupt (see
+    /// [`crate::state::KongSwapAdaptor::mark_state_corrupt`]) and is quarantined: every
+    /// deposit/withdraw/rebalance call is rejected until
+    /// [`crate::state::KongSwapAdaptor::repair_state`] succeeds.
+    IntegrityViolationCode,
+}
+
+impl From<TransactionErrorCodes> for u64 {
+    fn from(code: TransactionErrorCodes) -> Self {
+        match code {
+            TransactionErrorCodes::BackendCode => 1,
+            TransactionErrorCodes::BalanceArithmeticCode => 2,
+            TransactionErrorCodes::ExchangeRateCode => 3,
+            TransactionErrorCodes::PostConditionCode => 4,
+            TransactionErrorCodes::PreConditionCode => 5,
+            TransactionErrorCodes::TemporaryUnavailableCode => 6,
+            TransactionErrorCodes::UnknownAssetCode => 7,
+            TransactionErrorCodes::StaleStateCode => 8,
+            TransactionErrorCodes::IntegrityViolationCode => 9,
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::{
     accounting::{ValidatedBalances, ValidatedBalancesForAsset},
+    token_amount::TokenAmount,
     ICP_LEDGER_CANISTER_ID,
 };
 use candid::{CandidType, Nat, Principal};
@@ -13,6 +14,16 @@ use std::str::FromStr;
 
 pub const MAX_SYMBOL_BYTES: usize = 10;
 
+/// Whether `validate_assets` requires `asset_1` to be quoted in ICP (i.e. `asset_1` must be the
+/// ICP ledger, and `asset_0` must not be).
+///
+/// This is a crate-level policy switch rather than a field on `TreasuryManagerInit`, because
+/// `sns_treasury_manager::TreasuryManagerInit` is defined upstream and does not (yet) carry a
+/// per-instance pairing policy. Flipping this to `false` lets a build of this canister manage a
+/// stable-pair or token-token KongSwap pool; the default of `true` preserves the adaptor's
+/// original ICP-quoted behavior.
+pub(crate) const REQUIRE_ICP_QUOTE_ASSET: bool = true;
+
 pub(crate) struct ValidatedTreasuryManagerInit {
     pub allowance_0: ValidatedAllowance,
     pub allowance_1: ValidatedAllowance,
@@ -45,20 +56,31 @@ pub(crate) fn validate_assets(
         ));
     }
 
-    if asset_0.symbol() == "ICP" {
-        problems.push("asset_0 must NOT represent ICP tokens.".to_string());
+    if asset_0.ledger_canister_id() == asset_1.ledger_canister_id() {
+        problems.push("asset_0 and asset_1 must be distinct ledgers.".to_string());
     }
 
-    if asset_1.symbol() != "ICP" {
-        problems.push("asset_1 must represent ICP tokens.".to_string());
-    }
+    if REQUIRE_ICP_QUOTE_ASSET {
+        if asset_0.symbol() == "ICP" {
+            problems.push("asset_0 must NOT represent ICP tokens.".to_string());
+        }
 
-    if asset_0.ledger_canister_id() == *ICP_LEDGER_CANISTER_ID {
-        problems.push("asset_0 ledger must NOT be the ICP ledger.".to_string());
-    }
+        if asset_1.symbol() != "ICP" {
+            problems.push("asset_1 must represent ICP tokens.".to_string());
+        }
 
-    if asset_1.ledger_canister_id() != *ICP_LEDGER_CANISTER_ID {
-        problems.push("asset_1 ledger must be the ICP ledger.".to_string());
+        if asset_0.ledger_canister_id() == *ICP_LEDGER_CANISTER_ID {
+            problems.push("asset_0 ledger must NOT be the ICP ledger.".to_string());
+        }
+
+        if asset_1.ledger_canister_id() != *ICP_LEDGER_CANISTER_ID {
+            problems.push(
+                "asset_1 ledger must be the ICP ledger (the pool's quote asset). Set \
+                            `REQUIRE_ICP_QUOTE_ASSET` to `false` to manage stable-pair or \
+                            token-token pools instead."
+                    .to_string(),
+            );
+        }
     }
 
     if !problems.is_empty() {
@@ -139,7 +161,7 @@ impl TryFrom<Allowance> for ValidatedAllowance {
             }
         };
 
-        let amount_decimals = match decode_nat_to_u64(amount_decimals) {
+        let amount_decimals = match TokenAmount::try_from(amount_decimals) {
             Ok(amount_decimals) => Some(amount_decimals),
             Err(err) => {
                 problems.push(err);
@@ -180,10 +202,22 @@ impl TryFrom<Asset> for ValidatedAsset {
         let ledger_fee_decimals = decode_nat_to_u64(ledger_fee_decimals)
             .map_err(|err| format!("Failed to validate asset ledger fee_decimals: {}", err))?;
 
+        // The mainnet ICP ledger predates ICRC-2 and is the only ledger this adaptor is known to
+        // talk to that might still be missing `icrc2_approve`/`icrc2_transfer_from`, so it's the
+        // only one defaulted to the legacy `AccountIdentifier`-based protocol. Every other ledger
+        // is assumed ICRC-2-capable, matching the adaptor's original (ICRC-only) behavior.
+        let ledger_protocol = if ledger_canister_id == *ICP_LEDGER_CANISTER_ID {
+            LedgerProtocol::Legacy
+        } else {
+            LedgerProtocol::Icrc
+        };
+
         Ok(Self::Token {
             symbol,
             ledger_canister_id,
             ledger_fee_decimals,
+            decimals: DEFAULT_DECIMALS,
+            ledger_protocol,
         })
     }
 }
@@ -204,19 +238,37 @@ impl TryFrom<TreasuryManagerInit> for ValidatedTreasuryManagerInit {
     }
 }
 
+/// The ledger's `icrc1:decimals` metadata value is not part of `sns_treasury_manager::Asset`, so
+/// it is not known until the first `refresh_ledger_metadata` call. Until then, assets default to
+/// this value, matching ICP and the overwhelming majority of SNS ledgers.
+const DEFAULT_DECIMALS: u8 = 8;
+
+/// Which ledger interface a [`ValidatedAsset`] is reachable through -- see
+/// [`ValidatedAsset::ledger_protocol`].
+#[derive(CandidType, Clone, Copy, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum LedgerProtocol {
+    /// `icrc1_transfer`/`icrc2_approve`/`icrc2_transfer_from`/`icrc1_balance_of`.
+    Icrc,
+    /// The classic ICP ledger interface: `transfer`/`account_balance`, addressed by
+    /// `AccountIdentifier` rather than an ICRC-1 `Account`.
+    Legacy,
+}
+
 #[derive(CandidType, Clone, Copy, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub(crate) enum ValidatedAsset {
     Token {
         symbol: ValidatedSymbol,
         ledger_canister_id: Principal,
         ledger_fee_decimals: u64,
+        decimals: u8,
+        ledger_protocol: LedgerProtocol,
     },
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub(crate) struct ValidatedAllowance {
     pub asset: ValidatedAsset,
-    pub amount_decimals: u64,
+    pub amount_decimals: TokenAmount,
     pub owner_account: Account,
 }
 
@@ -320,10 +372,18 @@ impl TryFrom<(String, String, u64)> for ValidatedAsset {
             )
         })?;
 
+        let ledger_protocol = if ledger_canister_id == *ICP_LEDGER_CANISTER_ID {
+            LedgerProtocol::Legacy
+        } else {
+            LedgerProtocol::Icrc
+        };
+
         Ok(Self::Token {
             symbol,
             ledger_canister_id,
             ledger_fee_decimals,
+            decimals: DEFAULT_DECIMALS,
+            ledger_protocol,
         })
     }
 }
@@ -465,6 +525,53 @@ impl ValidatedAsset {
             } => *ledger_canister_id,
         }
     }
+
+    pub fn decimals(&self) -> u8 {
+        match self {
+            Self::Token { decimals, .. } => *decimals,
+        }
+    }
+
+    pub fn set_decimals(&mut self, new_decimals: u8) -> bool {
+        match self {
+            Self::Token {
+                ref mut decimals, ..
+            } => {
+                if decimals == &new_decimals {
+                    false
+                } else {
+                    *decimals = new_decimals;
+                    true
+                }
+            }
+        }
+    }
+
+    pub fn ledger_protocol(&self) -> LedgerProtocol {
+        match self {
+            Self::Token {
+                ledger_protocol, ..
+            } => *ledger_protocol,
+        }
+    }
+
+    /// Renders `amount_decimals` (a raw base-unit amount, e.g. e8s) as a human-readable string
+    /// scaled by this asset's `decimals`, followed by the asset's symbol, e.g. `"1.23456789 ICP"`.
+    pub fn format_amount_decimals(&self, amount_decimals: u64) -> String {
+        let decimals = self.decimals() as u32;
+        let scale = 10_u64.checked_pow(decimals).unwrap_or(u64::MAX);
+
+        let whole = amount_decimals / scale;
+        let fraction = amount_decimals % scale;
+
+        format!(
+            "{}.{:0width$} {}",
+            whole,
+            fraction,
+            self.symbol(),
+            width = decimals as usize
+        )
+    }
 }
 
 pub(crate) fn decode_nat_to_u64(value: Nat) -> Result<u64, String> {
@@ -495,6 +602,8 @@ impl From<ValidatedAsset> for Asset {
             symbol,
             ledger_canister_id,
             ledger_fee_decimals,
+            decimals: _,
+            ledger_protocol: _,
         } = value;
 
         let symbol = symbol.to_string();
@@ -0,0 +1,570 @@
+//! A pre-commit solvency check for deposit/withdraw operations, modeled on the "this instruction
+//! must not leave the account's health below a floor" guard concentrated-liquidity venues run
+//! before letting a position-mutating instruction land. Unlike [`crate::slippage::check_slippage_bps`]
+//! (which compares a preview against what a call actually realized, after the fact), this runs
+//! *before* anything is submitted: it quotes what the DEX would hand back for the whole current LP
+//! position right now, values that quote in the DAO's reference denomination, and aborts if it's
+//! already below a configured floor -- catching a manipulated or broken pool before a
+//! deposit/withdraw is allowed to touch it.
+
+use crate::{
+    kong_types::{RemoveLiquidityAmountsArgs, RemoveLiquidityAmountsReply},
+    price_history::value_in_reference,
+    slippage::{check_deposit_price_deviation_bps, check_slippage_bps, BPS_DENOMINATOR},
+    tx_error_codes::TransactionErrorCodes,
+    validation::decode_nat_to_u64,
+    KongSwapAdaptor, KONG_BACKEND_CANISTER_ID,
+};
+use candid::Nat;
+use kongswap_adaptor::{agent::AbstractAgent, audit::OperationContext};
+use rust_decimal::Decimal;
+use sns_treasury_manager::{Asset, Error, ErrorKind};
+use std::collections::BTreeMap;
+
+impl<A: AbstractAgent> KongSwapAdaptor<A> {
+    /// Aborts with a structured [`Error`] if the treasury's current position, valued in the
+    /// reference denomination at the pool's current price, is already below
+    /// [`crate::state::KongSwapAdaptor::min_treasury_value_in_reference_decimals`]'s configured
+    /// floor. A floor of `0` (the default) disables the guard, as does a fresh position with no LP
+    /// balance yet -- there's nothing at risk to protect.
+    ///
+    /// The position is valued by quoting `remove_liquidity_amounts` for the whole current LP
+    /// balance rather than trusting the balance book's own `external` bookkeeping, so a pool whose
+    /// quoted removal value has been depressed by manipulation or a broken invariant is caught even
+    /// though the bookkeeping itself still looks healthy.
+    pub(crate) async fn assert_value_preserved(
+        &mut self,
+        context: &mut OperationContext,
+    ) -> Result<(), Vec<Error>> {
+        let min_value_in_reference_decimals = self.min_treasury_value_in_reference_decimals();
+        if min_value_in_reference_decimals == 0 {
+            return Ok(());
+        }
+
+        let lp_balance = self.lp_balance(context).await;
+        if lp_balance == Nat::from(0u8) {
+            return Ok(());
+        }
+
+        // Abort rather than valuing the position against conversion rates that have gone stale
+        // (see `ValidatedBalances::exchange_rate_is_stale`) -- proceeding blind on an expired
+        // rate would make the floor check below meaningless.
+        if self
+            .get_cached_balances()
+            .exchange_rate_is_stale(self.time_ns())
+        {
+            return Err(vec![Error::new_precondition(
+                "The configured conversion rates have gone stale; cannot evaluate the \
+                 value-preservation guard."
+                    .to_string(),
+            )]);
+        }
+
+        let (asset_0, asset_1) = self.assets();
+
+        let human_readable = format!(
+            "Calling KongSwapBackend.remove_liquidity_amounts to value the current position ({} \
+             LP tokens) against the configured value-preservation floor before committing this \
+             operation.",
+            lp_balance
+        );
+
+        let RemoveLiquidityAmountsReply {
+            amount_0, amount_1, ..
+        } = self
+            .emit_transaction(
+                context,
+                *KONG_BACKEND_CANISTER_ID,
+                RemoveLiquidityAmountsArgs {
+                    token_0: asset_0.symbol(),
+                    token_1: asset_1.symbol(),
+                    remove_lp_token_amount: lp_balance,
+                },
+                human_readable,
+            )
+            .await
+            .map_err(|err| vec![err])?;
+
+        let amount_0 = decode_nat_to_u64(amount_0).unwrap_or_default();
+        let amount_1 = decode_nat_to_u64(amount_1).unwrap_or_default();
+
+        let balances = self.get_cached_balances();
+        let asset_0_rate_decimals = balances.asset_0_rate_decimals.ok_or_else(|| {
+            vec![Error::new_precondition(
+                "No conversion rate has been set for asset_0; cannot evaluate the \
+                 value-preservation guard."
+                    .to_string(),
+            )]
+        })?;
+        let asset_1_rate_decimals = balances.asset_1_rate_decimals.ok_or_else(|| {
+            vec![Error::new_precondition(
+                "No conversion rate has been set for asset_1; cannot evaluate the \
+                 value-preservation guard."
+                    .to_string(),
+            )]
+        })?;
+
+        let value_0 = value_in_reference(amount_0, asset_0.decimals(), asset_0_rate_decimals)
+            .ok_or_else(|| {
+                vec![Error::new_precondition(
+                    "Overflow while valuing asset_0 for the value-preservation guard.".to_string(),
+                )]
+            })?;
+        let value_1 = value_in_reference(amount_1, asset_1.decimals(), asset_1_rate_decimals)
+            .ok_or_else(|| {
+                vec![Error::new_precondition(
+                    "Overflow while valuing asset_1 for the value-preservation guard.".to_string(),
+                )]
+            })?;
+
+        let estimated_value = value_0 + value_1;
+        let min_value = rust_decimal::Decimal::from(min_value_in_reference_decimals);
+
+        if estimated_value < min_value {
+            return Err(vec![Error {
+                code: u64::from(TransactionErrorCodes::PreConditionCode),
+                message: format!(
+                    "Value-preservation guard tripped: removing the current position would \
+                     realize an estimated {} in the reference denomination, below the configured \
+                     floor of {}.",
+                    estimated_value, min_value,
+                ),
+                kind: ErrorKind::Precondition {},
+            }]);
+        }
+
+        Ok(())
+    }
+
+    /// Checks a `remove_liquidity_amounts` preview against the DAO-configured conversion rates
+    /// (see [`crate::state::KongSwapAdaptor::set_conversion_rates`]) before a withdrawal commits
+    /// to removing liquidity at that quote. This adaptor has no built-in price oracle, so those
+    /// DAO-supplied rates are the only price source it has independent of the pool itself; in a
+    /// correctly-priced constant-product pool, a proportional removal always pulls out equal
+    /// reference-denominated value of each asset, so a deviation beyond the configured
+    /// `max_slippage_bps` means the pool quote and the DAO's rates disagree enough that the pool
+    /// is plausibly illiquid or manipulated.
+    ///
+    /// Skips the check entirely if conversion rates haven't been configured yet, the same way
+    /// [`Self::assert_value_preserved`] treats a `0` floor as "disabled". Once rates are
+    /// configured, though, a stale read (see [`crate::balances::ValidatedBalances::exchange_rate_is_stale`])
+    /// is a hard abort rather than a silent skip -- an expired rate is worse than no rate at all,
+    /// since it can make a manipulated pool look like it agrees with a price that's no longer
+    /// current.
+    pub(crate) fn assert_withdrawal_price_within_oracle_bounds(
+        &self,
+        amount_0: u64,
+        amount_1: u64,
+    ) -> Result<(), Vec<Error>> {
+        let balances = self.get_cached_balances();
+
+        let (Some(asset_0_rate_decimals), Some(asset_1_rate_decimals)) = (
+            balances.asset_0_rate_decimals,
+            balances.asset_1_rate_decimals,
+        ) else {
+            return Ok(());
+        };
+
+        if balances.exchange_rate_is_stale(self.time_ns()) {
+            return Err(vec![Error::new_precondition(
+                "The configured conversion rates have gone stale; cannot evaluate the oracle \
+                 price-sanity guard."
+                    .to_string(),
+            )]);
+        }
+
+        let (asset_0, asset_1) = self.assets();
+        let (max_slippage_bps, _lp_fee_bps) = self.pool_risk_params();
+
+        let value_0 = value_in_reference(amount_0, asset_0.decimals(), asset_0_rate_decimals)
+            .ok_or_else(|| {
+                vec![Error::new_precondition(
+                    "Overflow while valuing amount_0 against the DAO-configured conversion rate."
+                        .to_string(),
+                )]
+            })?;
+        let value_1 = value_in_reference(amount_1, asset_1.decimals(), asset_1_rate_decimals)
+            .ok_or_else(|| {
+                vec![Error::new_precondition(
+                    "Overflow while valuing amount_1 against the DAO-configured conversion rate."
+                        .to_string(),
+                )]
+            })?;
+
+        let total_value = value_0 + value_1;
+        if total_value.is_zero() {
+            // Nothing is being withdrawn; there is no implied price to sanity-check.
+            return Ok(());
+        }
+
+        let deviation = (value_0 - value_1).abs();
+        let max_deviation =
+            total_value * Decimal::from(max_slippage_bps) / Decimal::from(BPS_DENOMINATOR);
+
+        if deviation > max_deviation {
+            return Err(vec![Error {
+                code: u64::from(TransactionErrorCodes::PreConditionCode),
+                message: format!(
+                    "Oracle price-sanity guard tripped: remove_liquidity_amounts quoted {} {} \
+                     against {} {} (an estimated {} vs {} in the reference denomination), which \
+                     disagrees with the DAO-configured conversion rates by more than the \
+                     configured {} bps.",
+                    amount_0,
+                    asset_0.symbol(),
+                    amount_1,
+                    asset_1.symbol(),
+                    value_0,
+                    value_1,
+                    max_slippage_bps,
+                ),
+                kind: ErrorKind::Precondition {},
+            }]);
+        }
+
+        Ok(())
+    }
+
+    /// Aborts before `add_pool`/`add_liquidity` is called if this deposit's implied price
+    /// (`amount_1 / amount_0`) deviates from an already-existing pool's own reserve ratio by more
+    /// than [`crate::state::KongSwapAdaptor::deposit_guard_params`]'s configured
+    /// `max_deposit_price_deviation_bps` -- see [`check_deposit_price_deviation_bps`]. There's
+    /// nothing to compare against yet for a brand-new pool (no reserves), so the check is skipped
+    /// in that case; [`Self::assert_deposit_price_within_oracle_bounds`] still covers that case
+    /// independently of the pool's own reserves. `0` (the default) disables the check entirely.
+    pub(crate) async fn assert_deposit_price_within_reserve_tolerance(
+        &mut self,
+        context: &mut OperationContext,
+        amount_0: u64,
+        amount_1: u64,
+    ) -> Result<(), Vec<Error>> {
+        let (max_deposit_price_deviation_bps, _min_deposit_lp_decimals) =
+            self.deposit_guard_params();
+        if max_deposit_price_deviation_bps == 0 {
+            return Ok(());
+        }
+
+        let Some((reserve_0, reserve_1)) = self.pool_reserves(context).await else {
+            return Ok(());
+        };
+        let reserve_0 = decode_nat_to_u64(reserve_0).unwrap_or_default();
+        let reserve_1 = decode_nat_to_u64(reserve_1).unwrap_or_default();
+
+        if reserve_0 == 0 || reserve_1 == 0 {
+            return Ok(());
+        }
+
+        check_deposit_price_deviation_bps(
+            reserve_0,
+            reserve_1,
+            amount_0,
+            amount_1,
+            max_deposit_price_deviation_bps,
+            &format!("deposit into pool {}", self.lp_token()),
+        )
+        .map_err(|err| vec![err])
+    }
+
+    /// Checks a deposit's amounts against the DAO-configured conversion rates (see
+    /// [`crate::state::KongSwapAdaptor::set_conversion_rates`]) before `add_pool`/`add_liquidity`
+    /// commits to them -- the deposit-side counterpart of
+    /// [`Self::assert_withdrawal_price_within_oracle_bounds`]. `deposit.rs`'s
+    /// `check_deposit_price_deviation_bps` already compares a deposit's implied ratio against the
+    /// pool's own live reserves, but a pool that's itself been manipulated to a bad price would
+    /// pass that check regardless -- the DAO's conversion rates are this adaptor's only price
+    /// source independent of the pool, so disagreeing with them beyond `max_slippage_bps` is
+    /// cause to abort even when the reserve-ratio check already passed.
+    ///
+    /// Skips the check entirely if conversion rates haven't been configured yet, the same way
+    /// [`Self::assert_withdrawal_price_within_oracle_bounds`] does; a stale read is a hard abort
+    /// rather than a silent skip, for the same reason.
+    pub(crate) fn assert_deposit_price_within_oracle_bounds(
+        &self,
+        amount_0: u64,
+        amount_1: u64,
+    ) -> Result<(), Vec<Error>> {
+        let balances = self.get_cached_balances();
+
+        let (Some(asset_0_rate_decimals), Some(asset_1_rate_decimals)) = (
+            balances.asset_0_rate_decimals,
+            balances.asset_1_rate_decimals,
+        ) else {
+            return Ok(());
+        };
+
+        if balances.exchange_rate_is_stale(self.time_ns()) {
+            return Err(vec![Error::new_precondition(
+                "The configured conversion rates have gone stale; cannot evaluate the oracle \
+                 price-sanity guard."
+                    .to_string(),
+            )]);
+        }
+
+        let (asset_0, asset_1) = self.assets();
+        let (max_slippage_bps, _lp_fee_bps) = self.pool_risk_params();
+
+        let value_0 = value_in_reference(amount_0, asset_0.decimals(), asset_0_rate_decimals)
+            .ok_or_else(|| {
+                vec![Error::new_precondition(
+                    "Overflow while valuing amount_0 against the DAO-configured conversion rate."
+                        .to_string(),
+                )]
+            })?;
+        let value_1 = value_in_reference(amount_1, asset_1.decimals(), asset_1_rate_decimals)
+            .ok_or_else(|| {
+                vec![Error::new_precondition(
+                    "Overflow while valuing amount_1 against the DAO-configured conversion rate."
+                        .to_string(),
+                )]
+            })?;
+
+        let total_value = value_0 + value_1;
+        if total_value.is_zero() {
+            // Nothing is being deposited; there is no implied price to sanity-check.
+            return Ok(());
+        }
+
+        let deviation = (value_0 - value_1).abs();
+        let max_deviation =
+            total_value * Decimal::from(max_slippage_bps) / Decimal::from(BPS_DENOMINATOR);
+
+        if deviation > max_deviation {
+            return Err(vec![Error {
+                code: u64::from(TransactionErrorCodes::PreConditionCode),
+                message: format!(
+                    "Oracle price-sanity guard tripped: depositing {} {} against {} {} (an \
+                     estimated {} vs {} in the reference denomination) disagrees with the \
+                     DAO-configured conversion rates by more than the configured {} bps.",
+                    amount_0,
+                    asset_0.symbol(),
+                    amount_1,
+                    asset_1.symbol(),
+                    value_0,
+                    value_1,
+                    max_slippage_bps,
+                ),
+                kind: ErrorKind::Precondition {},
+            }]);
+        }
+
+        Ok(())
+    }
+
+    /// Aborts with a structured `MinHoldingsViolation` [`Error`] if, for any `(asset, floor)` pair
+    /// in `expected`, this adaptor's current balance book for that asset -- summing
+    /// [`crate::balances::ValidatedBalanceBook::external`] (funds at the DEX),
+    /// [`crate::balances::ValidatedBalanceBook::treasury_owner`]'s `amount_decimals` (funds
+    /// already returned to the DAO) and [`crate::balances::ValidatedBalanceBook::fee_collector`]
+    /// -- is already below `floor`. Meant to be called right before the final
+    /// `add_pool`/`add_liquidity`/`remove_liquidity` call of a deposit or withdraw, the same way
+    /// [`Self::assert_value_preserved`] is: both read a snapshot of the balance book taken
+    /// immediately before that call commits, rather than simulating the call's own effect on it,
+    /// so a caller-specified floor that a mis-sized deposit/withdraw would breach aborts the
+    /// operation before anything is submitted. An `expected` entry naming an asset this adaptor
+    /// doesn't manage is reported as its own violation rather than silently ignored.
+    pub(crate) fn assert_min_holdings(
+        &self,
+        expected: &BTreeMap<Asset, Nat>,
+    ) -> Result<(), Vec<Error>> {
+        let balances = self.get_cached_balances();
+        let mut errors = Vec::new();
+
+        for (asset, floor) in expected {
+            let Some((validated_asset, book)) = balances
+                .asset_to_balances
+                .iter()
+                .find(|(validated_asset, _)| Asset::from(**validated_asset) == *asset)
+            else {
+                errors.push(Error::new_precondition(format!(
+                    "Min-holdings guard tripped (MinHoldingsViolation): {:?} is not registered \
+                     with this adaptor.",
+                    asset,
+                )));
+                continue;
+            };
+
+            let projected = book
+                .external
+                .saturating_add(book.treasury_owner.amount_decimals)
+                .saturating_add(book.fee_collector);
+
+            let floor_decimals = decode_nat_to_u64(floor.clone()).unwrap_or(u64::MAX);
+
+            if projected < floor_decimals {
+                errors.push(Error {
+                    code: u64::from(TransactionErrorCodes::PreConditionCode),
+                    message: format!(
+                        "Min-holdings guard tripped (MinHoldingsViolation): projected holdings \
+                         for {} would be {}, below the configured floor of {}.",
+                        validated_asset.symbol(),
+                        projected,
+                        floor_decimals,
+                    ),
+                    kind: ErrorKind::Precondition {},
+                });
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Aborts if the LP balance and pool reserves re-queried right now have drifted from
+    /// `snapshot_lp_balance_decimals`/`snapshot_reserves` -- captured at the start of a withdrawal,
+    /// before its `Step`-sequenced sub-calls began -- by more than
+    /// [`crate::state::KongSwapAdaptor::max_withdraw_reserve_drift_bps`], or if
+    /// `snapshot_operation_sequence` no longer matches
+    /// [`crate::state::KongSwapAdaptor::operation_sequence`] -- i.e. some other state-mutating
+    /// operation committed in between, the same staleness the `expected_sequence` parameter on
+    /// [`crate::canister::RebalanceRequest`] guards against across calls, checked here within a
+    /// single one. Meant to be called immediately before the `remove_liquidity` call that commits
+    /// to the plan, so the withdrawal can't act on reserves that shifted after the plan was
+    /// formed. `0` (the default) disables the drift half of the check; a snapshot of `None` for
+    /// the reserves (no pool yet) skips only the reserve half of the comparison, since there's
+    /// nothing to compare it against.
+    pub(crate) async fn assert_reserve_sequence_unchanged(
+        &mut self,
+        context: &mut OperationContext,
+        snapshot_lp_balance_decimals: u64,
+        snapshot_reserves: Option<(Nat, Nat)>,
+        snapshot_operation_sequence: u64,
+    ) -> Result<(), Vec<Error>> {
+        if self.operation_sequence() != snapshot_operation_sequence {
+            return Err(vec![Error {
+                code: u64::from(TransactionErrorCodes::StaleStateCode),
+                message: format!(
+                    "Withdraw sequence check failed: operation_sequence moved from {} to {} \
+                     since this withdrawal's plan was formed.",
+                    snapshot_operation_sequence,
+                    self.operation_sequence(),
+                ),
+                kind: ErrorKind::Precondition {},
+            }]);
+        }
+
+        let max_drift_bps = self.max_withdraw_reserve_drift_bps();
+        if max_drift_bps == 0 {
+            return Ok(());
+        }
+
+        let current_lp_balance_decimals =
+            decode_nat_to_u64(self.lp_balance(context).await).unwrap_or_default();
+
+        check_reserve_drift_bps(
+            snapshot_lp_balance_decimals,
+            current_lp_balance_decimals,
+            max_drift_bps,
+            "withdraw sequence check (LP balance)",
+        )?;
+
+        let Some((snapshot_reserve_0, snapshot_reserve_1)) = snapshot_reserves else {
+            return Ok(());
+        };
+        let snapshot_reserve_0 = decode_nat_to_u64(snapshot_reserve_0).unwrap_or_default();
+        let snapshot_reserve_1 = decode_nat_to_u64(snapshot_reserve_1).unwrap_or_default();
+
+        let Some((current_reserve_0, current_reserve_1)) = self.pool_reserves(context).await else {
+            return Ok(());
+        };
+        let current_reserve_0 = decode_nat_to_u64(current_reserve_0).unwrap_or_default();
+        let current_reserve_1 = decode_nat_to_u64(current_reserve_1).unwrap_or_default();
+
+        check_reserve_drift_bps(
+            snapshot_reserve_0,
+            current_reserve_0,
+            max_drift_bps,
+            "withdraw sequence check (pool reserve_0)",
+        )?;
+        check_reserve_drift_bps(
+            snapshot_reserve_1,
+            current_reserve_1,
+            max_drift_bps,
+            "withdraw sequence check (pool reserve_1)",
+        )?;
+
+        Ok(())
+    }
+
+    /// Like [`Self::assert_reserve_sequence_unchanged`], but for the deposit side: aborts if the
+    /// pool reserves snapshotted at the start of a deposit have drifted, by the time
+    /// `add_pool`/`add_liquidity` is about to commit, by more than
+    /// [`crate::state::KongSwapAdaptor::max_deposit_reserve_drift_bps`], or if
+    /// `snapshot_operation_sequence` no longer matches
+    /// [`crate::state::KongSwapAdaptor::operation_sequence`]. `0` (the default) disables the
+    /// drift half of the check; a snapshot of `None` for the reserves (no pool yet) skips only
+    /// the reserve half of the comparison, since there's nothing to compare it against.
+    pub(crate) async fn assert_deposit_reserve_sequence_unchanged(
+        &mut self,
+        context: &mut OperationContext,
+        snapshot_reserves: Option<(Nat, Nat)>,
+        snapshot_operation_sequence: u64,
+    ) -> Result<(), Vec<Error>> {
+        if self.operation_sequence() != snapshot_operation_sequence {
+            return Err(vec![Error {
+                code: u64::from(TransactionErrorCodes::StaleStateCode),
+                message: format!(
+                    "Deposit sequence check failed: operation_sequence moved from {} to {} \
+                     since this deposit's plan was formed.",
+                    snapshot_operation_sequence,
+                    self.operation_sequence(),
+                ),
+                kind: ErrorKind::Precondition {},
+            }]);
+        }
+
+        let max_drift_bps = self.max_deposit_reserve_drift_bps();
+        if max_drift_bps == 0 {
+            return Ok(());
+        }
+
+        let Some((snapshot_reserve_0, snapshot_reserve_1)) = snapshot_reserves else {
+            return Ok(());
+        };
+        let snapshot_reserve_0 = decode_nat_to_u64(snapshot_reserve_0).unwrap_or_default();
+        let snapshot_reserve_1 = decode_nat_to_u64(snapshot_reserve_1).unwrap_or_default();
+
+        let Some((current_reserve_0, current_reserve_1)) = self.pool_reserves(context).await else {
+            return Ok(());
+        };
+        let current_reserve_0 = decode_nat_to_u64(current_reserve_0).unwrap_or_default();
+        let current_reserve_1 = decode_nat_to_u64(current_reserve_1).unwrap_or_default();
+
+        check_reserve_drift_bps(
+            snapshot_reserve_0,
+            current_reserve_0,
+            max_drift_bps,
+            "deposit sequence check (pool reserve_0)",
+        )?;
+        check_reserve_drift_bps(
+            snapshot_reserve_1,
+            current_reserve_1,
+            max_drift_bps,
+            "deposit sequence check (pool reserve_1)",
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Like [`check_slippage_bps`], but re-codes a failure as [`TransactionErrorCodes::StaleStateCode`]
+/// instead of the generic postcondition code `check_slippage_bps` defaults to -- so a caller
+/// filtering on `Error::code` can tell a staleness abort apart from an ordinary slippage failure.
+fn check_reserve_drift_bps(
+    expected: u64,
+    actual: u64,
+    max_drift_bps: u16,
+    description: &str,
+) -> Result<(), Vec<Error>> {
+    check_slippage_bps(expected, actual, max_drift_bps, description).map_err(|err| {
+        vec![Error {
+            code: u64::from(TransactionErrorCodes::StaleStateCode),
+            message: err.message,
+            kind: err.kind,
+        }]
+    })
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,844 @@
+use super::*;
+use crate::kong_types::{
+    PoolReply, PoolsArgs, UserBalanceLPReply, UserBalancesArgs, UserBalancesReply,
+};
+use crate::{
+    balances::RATE_DECIMALS_SCALE,
+    state::storage::{
+        ConfigState, ContractStatus, IntegrityStatus, OperationLock, PendingDepositState,
+        TaskStatuses,
+    },
+    validation::ValidatedTreasuryManagerInit,
+    StableAuditTrail, StableBalances, StableContractStatus, StableExchangeRateHistory,
+    StableIdempotencyKeys, StableIntegrityStatus, StablePendingDepositStateCell,
+    StableOperationLockCell, StableTaskStatusCell,
+    StableTransferIntents,
+    StableWithdrawStateCell, AUDIT_TRAIL_MEMORY_ID,
+    BALANCES_MEMORY_ID, CONTRACT_STATUS_MEMORY_ID, EXCHANGE_RATE_HISTORY_MEMORY_ID,
+    IDEMPOTENCY_KEYS_MEMORY_ID, INTEGRITY_STATUS_MEMORY_ID, PENDING_DEPOSIT_STATE_MEMORY_ID,
+    OPERATION_LOCK_MEMORY_ID, TASK_STATUS_MEMORY_ID,
+    TRANSFER_INTENTS_MEMORY_ID,
+    WITHDRAW_STATE_MEMORY_ID,
+};
+use candid::{Nat, Principal};
+use ic_stable_structures::memory_manager::MemoryManager;
+use ic_stable_structures::{
+    BTreeMap as StableBTreeMap, Cell as StableCell, DefaultMemoryImpl, Vec as StableVec,
+};
+use kongswap_adaptor::{
+    agent::mock_agent::MockAgent,
+    audit::{OperationContext, RecordDecision},
+};
+use sns_treasury_manager::{Allowance, Asset, Operation, TreasuryManagerInit};
+use std::cell::RefCell;
+
+use lazy_static::lazy_static;
+
+const E8: u64 = 100_000_000;
+
+lazy_static! {
+    static ref SELF_CANISTER_ID: Principal =
+        Principal::from_text("jexlm-gaaaa-aaaar-qalmq-cai").unwrap();
+}
+
+fn make_lp_balance_request() -> UserBalancesArgs {
+    UserBalancesArgs {
+        principal_id: SELF_CANISTER_ID.to_string(),
+    }
+}
+
+fn make_lp_balance_reply(symbol: String, balance: f64) -> UserBalancesReply {
+    UserBalancesReply::LP(UserBalanceLPReply {
+        symbol,
+        name: String::default(),
+        lp_token_id: 0,
+        balance,
+        usd_balance: 0.0,
+        chain_0: String::default(),
+        symbol_0: String::default(),
+        address_0: String::default(),
+        amount_0: 0.0,
+        usd_amount_0: 0.0,
+        chain_1: String::default(),
+        symbol_1: String::default(),
+        address_1: String::default(),
+        amount_1: 0.0,
+        usd_amount_1: 0.0,
+        ts: 0,
+    })
+}
+
+fn make_remove_liquidity_amounts_request(
+    token_0: String,
+    token_1: String,
+    remove_lp_token_amount: u64,
+) -> RemoveLiquidityAmountsArgs {
+    RemoveLiquidityAmountsArgs {
+        token_0,
+        token_1,
+        remove_lp_token_amount: Nat::from(remove_lp_token_amount),
+    }
+}
+
+fn make_remove_liquidity_amounts_reply(
+    token_0: String,
+    token_1: String,
+    amount_0: u64,
+    amount_1: u64,
+) -> RemoveLiquidityAmountsReply {
+    RemoveLiquidityAmountsReply {
+        symbol: format!("{}_{}", token_0, token_1),
+        chain_0: String::default(),
+        address_0: String::default(),
+        symbol_0: token_0.clone(),
+        amount_0: Nat::from(amount_0),
+        lp_fee_0: Nat::from(0_u8),
+        chain_1: String::default(),
+        address_1: String::default(),
+        symbol_1: token_1.clone(),
+        amount_1: Nat::from(amount_1),
+        lp_fee_1: Nat::from(0_u8),
+        remove_lp_token_amount: Nat::from(0_u8),
+    }
+}
+
+fn make_pool_reserves_request(symbol: String) -> PoolsArgs {
+    PoolsArgs {
+        symbol: Some(symbol),
+    }
+}
+
+fn make_pool_reserves_reply(
+    token_0: String,
+    token_1: String,
+    balance_0: u64,
+    balance_1: u64,
+) -> Vec<PoolReply> {
+    vec![PoolReply {
+        pool_id: 0,
+        name: String::default(),
+        symbol: format!("{}_{}", token_0, token_1),
+        chain_0: String::default(),
+        symbol_0: token_0,
+        address_0: String::default(),
+        balance_0: Nat::from(balance_0),
+        lp_fee_0: Nat::from(0_u8),
+        chain_1: String::default(),
+        symbol_1: token_1,
+        address_1: String::default(),
+        balance_1: Nat::from(balance_1),
+        lp_fee_1: Nat::from(0_u8),
+        price: 1.0,
+        lp_fee_bps: 30,
+        lp_token_symbol: String::default(),
+        is_removed: false,
+    }]
+}
+
+/// Builds a fresh, initialized [`KongSwapAdaptor`] over `mock_agent` with conversion rates of 1:1
+/// already set, and the value-preservation floor set to `min_treasury_value_in_reference_decimals`.
+fn make_adaptor(
+    mock_agent: MockAgent,
+    sns_ledger: Principal,
+    icp_ledger: Principal,
+    min_treasury_value_in_reference_decimals: u64,
+) -> KongSwapAdaptor<MockAgent> {
+    thread_local! {
+        static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+            RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+        static BALANCES: RefCell<StableBalances> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(BALANCES_MEMORY_ID),
+                        ConfigState::default()
+                    )
+                    .expect("BALANCES init should not cause errors")
+                )
+            );
+
+        static AUDIT_TRAIL: RefCell<StableAuditTrail> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableVec::init(
+                        memory_manager.borrow().get(AUDIT_TRAIL_MEMORY_ID)
+                    )
+                    .expect("AUDIT_TRAIL init should not cause errors")
+                )
+            );
+
+        static WITHDRAW_STATE: RefCell<StableWithdrawStateCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(WITHDRAW_STATE_MEMORY_ID),
+                        Default::default()
+                    )
+                    .expect("WITHDRAW_STATE init should not cause errors")
+                )
+            );
+
+        static PRICE_HISTORY: RefCell<StablePriceHistory> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(PRICE_HISTORY_MEMORY_ID)
+                    )
+                )
+            );
+
+        static IDEMPOTENCY_KEYS: RefCell<StableIdempotencyKeys> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(IDEMPOTENCY_KEYS_MEMORY_ID)
+                    )
+                )
+            );
+
+        static CONTRACT_STATUS: RefCell<StableContractStatus> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(CONTRACT_STATUS_MEMORY_ID),
+                        ContractStatus::default()
+                    )
+                    .expect("CONTRACT_STATUS init should not cause errors")
+                )
+            );
+
+        static PENDING_DEPOSIT_STATE: RefCell<StablePendingDepositStateCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(PENDING_DEPOSIT_STATE_MEMORY_ID),
+                        PendingDepositState::default()
+                    )
+                    .expect("PENDING_DEPOSIT_STATE init should not cause errors")
+                )
+            );
+
+        static INTEGRITY_STATUS: RefCell<StableIntegrityStatus> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(INTEGRITY_STATUS_MEMORY_ID),
+                        IntegrityStatus::default()
+                    )
+                    .expect("INTEGRITY_STATUS init should not cause errors")
+                )
+            );
+
+        static EXCHANGE_RATE_HISTORY: RefCell<StableExchangeRateHistory> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(EXCHANGE_RATE_HISTORY_MEMORY_ID)
+                    )
+                )
+            );
+
+        static TASK_STATUS: RefCell<StableTaskStatusCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(TASK_STATUS_MEMORY_ID),
+                        TaskStatuses::default()
+                    )
+                    .expect("TASK_STATUS init should not cause errors")
+                )
+            );
+
+        static TRANSFER_INTENTS: RefCell<StableTransferIntents> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(TRANSFER_INTENTS_MEMORY_ID)
+                    )
+                )
+            );
+        static OPERATION_LOCK: RefCell<StableOperationLockCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(OPERATION_LOCK_MEMORY_ID),
+                        OperationLock::default()
+                    )
+                    .expect("OPERATION_LOCK init should not cause errors")
+                )
+            );
+    }
+
+    let symbol_0 = "DAO".to_string();
+    let symbol_1 = "ICP".to_string();
+
+    let asset_0 = Asset::Token {
+        ledger_canister_id: sns_ledger,
+        symbol: symbol_0,
+        ledger_fee_decimals: Nat::from(10_000u64),
+    };
+    let asset_1 = Asset::Token {
+        ledger_canister_id: icp_ledger,
+        symbol: symbol_1,
+        ledger_fee_decimals: Nat::from(10_000u64),
+    };
+
+    let owner_account = sns_treasury_manager::Account {
+        owner: Principal::from_text("2vxsx-fae").unwrap(),
+        subaccount: None,
+    };
+
+    let mut kong_adaptor = KongSwapAdaptor::new(
+        || 0, // Mock time function
+        mock_agent,
+        *SELF_CANISTER_ID,
+        &BALANCES,
+        &AUDIT_TRAIL,
+        &WITHDRAW_STATE,
+        &PRICE_HISTORY,
+        &IDEMPOTENCY_KEYS,
+        &CONTRACT_STATUS,
+        &PENDING_DEPOSIT_STATE,
+        &INTEGRITY_STATUS,
+        &EXCHANGE_RATE_HISTORY,
+        &TASK_STATUS,
+        &TRANSFER_INTENTS,
+        &OPERATION_LOCK,
+    );
+
+    let init = TreasuryManagerInit {
+        allowances: vec![
+            Allowance {
+                asset: asset_0,
+                owner_account,
+                amount_decimals: Nat::from(100 * E8),
+            },
+            Allowance {
+                asset: asset_1,
+                owner_account,
+                amount_decimals: Nat::from(100 * E8),
+            },
+        ],
+    };
+    let ValidatedTreasuryManagerInit {
+        allowance_0,
+        allowance_1,
+    } = init.try_into().unwrap();
+
+    kong_adaptor.initialize(
+        allowance_0.asset,
+        allowance_1.asset,
+        allowance_0.owner_account,
+        allowance_1.owner_account,
+    );
+
+    kong_adaptor.set_conversion_rates(RATE_DECIMALS_SCALE, RATE_DECIMALS_SCALE);
+    kong_adaptor
+        .set_min_treasury_value_in_reference_decimals(min_treasury_value_in_reference_decimals);
+
+    kong_adaptor
+}
+
+/// Covers the guard's contract: a position whose `remove_liquidity_amounts` quote implies a value
+/// below the configured floor aborts the operation with a structured `Error`, before anything else
+/// is submitted.
+#[tokio::test]
+async fn test_assert_value_preserved_rejects_below_floor() {
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    let lp_balance = 10.0;
+    let lp_balance_decimals = 10 * E8;
+    // A manipulated/broken pool: removing the whole position only quotes for 1 whole unit of
+    // each asset, well short of the 50-unit floor configured below.
+    let amount_0 = E8;
+    let amount_1 = E8;
+
+    let mock_agent = MockAgent::new(*SELF_CANISTER_ID)
+        .add_call(
+            *KONG_BACKEND_CANISTER_ID,
+            make_lp_balance_request(),
+            Ok(vec![make_lp_balance_reply("DAO_ICP".to_string(), lp_balance)]),
+        )
+        .add_call(
+            *KONG_BACKEND_CANISTER_ID,
+            make_remove_liquidity_amounts_request(
+                "DAO".to_string(),
+                "ICP".to_string(),
+                lp_balance_decimals,
+            ),
+            Ok(make_remove_liquidity_amounts_reply(
+                "DAO".to_string(),
+                "ICP".to_string(),
+                amount_0,
+                amount_1,
+            )),
+        );
+
+    let mut kong_adaptor = make_adaptor(mock_agent, sns_ledger, icp_ledger, 50 * E8);
+
+    let mut context =
+        OperationContext::new(Operation::Withdraw).with_record_decision(RecordDecision::Full);
+
+    let result = kong_adaptor.assert_value_preserved(&mut context).await;
+
+    assert!(
+        result.is_err(),
+        "the guard should reject a position quoting below its configured floor"
+    );
+
+    assert!(
+        kong_adaptor.agent.finished_calls(),
+        "There are still some calls remaining"
+    );
+}
+
+/// A floor of `0` (the default) disables the guard entirely, so no calls are made at all.
+#[tokio::test]
+async fn test_assert_value_preserved_disabled_by_default() {
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    let mock_agent = MockAgent::new(*SELF_CANISTER_ID);
+
+    let mut kong_adaptor = make_adaptor(mock_agent, sns_ledger, icp_ledger, 0);
+
+    let mut context =
+        OperationContext::new(Operation::Withdraw).with_record_decision(RecordDecision::Full);
+
+    let result = kong_adaptor.assert_value_preserved(&mut context).await;
+
+    assert!(result.is_ok(), "a floor of 0 should disable the guard");
+    assert!(
+        kong_adaptor.agent.finished_calls(),
+        "no calls should have been made while the guard is disabled"
+    );
+}
+
+/// Once a nonzero staleness bound is configured, an untimestamped conversion rate (as left by
+/// [`crate::state::KongSwapAdaptor::set_conversion_rates`], which never backdates a timestamp)
+/// counts as stale, and the guard aborts before quoting `remove_liquidity_amounts` at all.
+#[tokio::test]
+async fn test_assert_value_preserved_aborts_on_stale_rates() {
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    let lp_balance = 10.0;
+
+    let mock_agent = MockAgent::new(*SELF_CANISTER_ID).add_call(
+        *KONG_BACKEND_CANISTER_ID,
+        make_lp_balance_request(),
+        Ok(vec![make_lp_balance_reply(
+            "DAO_ICP".to_string(),
+            lp_balance,
+        )]),
+    );
+
+    let mut kong_adaptor = make_adaptor(mock_agent, sns_ledger, icp_ledger, 50 * E8);
+    kong_adaptor.set_rate_staleness_bound_ns(1);
+
+    let mut context =
+        OperationContext::new(Operation::Withdraw).with_record_decision(RecordDecision::Full);
+
+    let result = kong_adaptor.assert_value_preserved(&mut context).await;
+
+    assert!(
+        result.is_err(),
+        "a stale conversion rate should hard-abort the guard rather than being evaluated"
+    );
+    assert!(
+        kong_adaptor.agent.finished_calls(),
+        "the guard should abort on staleness before quoting remove_liquidity_amounts"
+    );
+}
+
+/// The oracle price-sanity guard hard-aborts on a stale rate too, rather than letting a
+/// withdrawal proceed against an expired price.
+#[test]
+fn test_assert_withdrawal_price_within_oracle_bounds_aborts_on_stale_rates() {
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    let mock_agent = MockAgent::new(*SELF_CANISTER_ID);
+
+    let mut kong_adaptor = make_adaptor(mock_agent, sns_ledger, icp_ledger, 0);
+    kong_adaptor.set_rate_staleness_bound_ns(1);
+
+    let result = kong_adaptor.assert_withdrawal_price_within_oracle_bounds(E8, E8);
+
+    assert!(
+        result.is_err(),
+        "a stale conversion rate should hard-abort the oracle price-sanity guard"
+    );
+    assert!(
+        kong_adaptor.agent.finished_calls(),
+        "no calls should have been made before the staleness check aborts"
+    );
+}
+
+/// A drift tolerance of `0` (the default) disables the reserve-sequence drift check entirely, so
+/// the re-query it would otherwise make is skipped.
+#[tokio::test]
+async fn test_assert_reserve_sequence_unchanged_disabled_by_default() {
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    let mock_agent = MockAgent::new(*SELF_CANISTER_ID);
+
+    let mut kong_adaptor = make_adaptor(mock_agent, sns_ledger, icp_ledger, 0);
+
+    let mut context =
+        OperationContext::new(Operation::Withdraw).with_record_decision(RecordDecision::Full);
+
+    let result = kong_adaptor
+        .assert_reserve_sequence_unchanged(
+            &mut context,
+            10 * E8,
+            Some((Nat::from(0u8), Nat::from(0u8))),
+            kong_adaptor.operation_sequence(),
+        )
+        .await;
+
+    assert!(
+        result.is_ok(),
+        "a drift tolerance of 0 should disable the check"
+    );
+    assert!(
+        kong_adaptor.agent.finished_calls(),
+        "no calls should have been made while the check is disabled"
+    );
+}
+
+/// Once a nonzero drift tolerance is configured, an LP balance re-queried immediately before
+/// `remove_liquidity` that has moved away from the start-of-operation snapshot by more than that
+/// tolerance aborts the withdrawal.
+#[tokio::test]
+async fn test_assert_reserve_sequence_unchanged_aborts_on_lp_balance_drift() {
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    // The snapshot taken at the start of the withdrawal implied 10 LP tokens; by the time this
+    // guard re-queries, the pool has moved enough that only half remain -- well beyond any
+    // reasonable drift tolerance.
+    let snapshot_lp_balance_decimals = 10 * E8;
+    let drifted_lp_balance = 5.0;
+
+    let mock_agent = MockAgent::new(*SELF_CANISTER_ID).add_call(
+        *KONG_BACKEND_CANISTER_ID,
+        make_lp_balance_request(),
+        Ok(vec![make_lp_balance_reply(
+            "DAO_ICP".to_string(),
+            drifted_lp_balance,
+        )]),
+    );
+
+    let mut kong_adaptor = make_adaptor(mock_agent, sns_ledger, icp_ledger, 0);
+    kong_adaptor.set_max_withdraw_reserve_drift_bps(100); // 1%
+
+    let mut context =
+        OperationContext::new(Operation::Withdraw).with_record_decision(RecordDecision::Full);
+
+    let result = kong_adaptor
+        .assert_reserve_sequence_unchanged(
+            &mut context,
+            snapshot_lp_balance_decimals,
+            None,
+            kong_adaptor.operation_sequence(),
+        )
+        .await;
+
+    assert!(
+        result.is_err(),
+        "an LP balance that drifted past the configured tolerance should abort the withdrawal"
+    );
+    assert!(
+        kong_adaptor.agent.finished_calls(),
+        "the guard should abort on the LP-balance re-query before touching pool reserves"
+    );
+}
+
+/// The same drift tolerance is enforced against the pool reserves snapshot, re-queried via
+/// `pools()` once the LP balance re-query above it has passed.
+#[tokio::test]
+async fn test_assert_reserve_sequence_unchanged_aborts_on_reserve_drift() {
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    let snapshot_lp_balance_decimals = 10 * E8;
+    let snapshot_reserve_0 = 100 * E8;
+    let snapshot_reserve_1 = 100 * E8;
+    // Reserves re-queried right before `remove_liquidity` have drifted far past tolerance, even
+    // though the LP balance itself hasn't moved.
+    let drifted_reserve_0 = 50 * E8;
+    let drifted_reserve_1 = 50 * E8;
+
+    let mock_agent = MockAgent::new(*SELF_CANISTER_ID)
+        .add_call(
+            *KONG_BACKEND_CANISTER_ID,
+            make_lp_balance_request(),
+            Ok(vec![make_lp_balance_reply("DAO_ICP".to_string(), 10.0)]),
+        )
+        .add_call(
+            *KONG_BACKEND_CANISTER_ID,
+            make_pool_reserves_request("DAO_ICP".to_string()),
+            Ok(make_pool_reserves_reply(
+                "DAO".to_string(),
+                "ICP".to_string(),
+                drifted_reserve_0,
+                drifted_reserve_1,
+            )),
+        );
+
+    let mut kong_adaptor = make_adaptor(mock_agent, sns_ledger, icp_ledger, 0);
+    kong_adaptor.set_max_withdraw_reserve_drift_bps(100); // 1%
+
+    let mut context =
+        OperationContext::new(Operation::Withdraw).with_record_decision(RecordDecision::Full);
+
+    let result = kong_adaptor
+        .assert_reserve_sequence_unchanged(
+            &mut context,
+            snapshot_lp_balance_decimals,
+            Some((Nat::from(snapshot_reserve_0), Nat::from(snapshot_reserve_1))),
+            kong_adaptor.operation_sequence(),
+        )
+        .await;
+
+    assert!(
+        result.is_err(),
+        "pool reserves that drifted past the configured tolerance should abort the withdrawal"
+    );
+    assert!(
+        kong_adaptor.agent.finished_calls(),
+        "There are still some calls remaining"
+    );
+}
+
+/// The `operation_sequence` comparison runs before the drift checks, and aborts even with the
+/// drift tolerance left at its disabled default -- a different operation having committed in the
+/// meantime is its own staleness signal, independent of whether reserves happen to have moved.
+#[tokio::test]
+async fn test_assert_reserve_sequence_unchanged_aborts_on_operation_sequence_mismatch() {
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    let mock_agent = MockAgent::new(*SELF_CANISTER_ID);
+    let mut kong_adaptor = make_adaptor(mock_agent, sns_ledger, icp_ledger, 0);
+
+    let snapshot_operation_sequence = kong_adaptor.operation_sequence();
+    kong_adaptor.advance_operation_sequence();
+
+    let mut context =
+        OperationContext::new(Operation::Withdraw).with_record_decision(RecordDecision::Full);
+
+    let result = kong_adaptor
+        .assert_reserve_sequence_unchanged(
+            &mut context,
+            10 * E8,
+            None,
+            snapshot_operation_sequence,
+        )
+        .await;
+
+    let errors = result.expect_err("operation_sequence moving should abort the withdrawal");
+    assert_eq!(
+        errors[0].code,
+        u64::from(TransactionErrorCodes::StaleStateCode)
+    );
+    assert!(
+        kong_adaptor.agent.finished_calls(),
+        "no calls should have been made before the operation_sequence check"
+    );
+}
+
+/// A deviation tolerance of 0 (the default) disables the reserve-ratio comparison entirely, so no
+/// `pool_reserves` call is even made.
+#[tokio::test]
+async fn test_assert_deposit_price_within_reserve_tolerance_disabled_by_default() {
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    let mock_agent = MockAgent::new(*SELF_CANISTER_ID);
+    let mut kong_adaptor = make_adaptor(mock_agent, sns_ledger, icp_ledger, 0);
+
+    let mut context =
+        OperationContext::new(Operation::Deposit).with_record_decision(RecordDecision::Full);
+
+    let result = kong_adaptor
+        .assert_deposit_price_within_reserve_tolerance(&mut context, 100 * E8, 100 * E8)
+        .await;
+
+    assert!(
+        result.is_ok(),
+        "a deviation tolerance of 0 should disable the check"
+    );
+    assert!(
+        kong_adaptor.agent.finished_calls(),
+        "no calls should have been made while the check is disabled"
+    );
+}
+
+/// A fresh pool (no reserves yet) has nothing to compare the deposit's implied ratio against, so
+/// the check is skipped even with a nonzero tolerance configured -- the oracle-based guard still
+/// covers that case independently.
+#[tokio::test]
+async fn test_assert_deposit_price_within_reserve_tolerance_skips_fresh_pool() {
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    let mock_agent = MockAgent::new(*SELF_CANISTER_ID).add_call(
+        *KONG_BACKEND_CANISTER_ID,
+        make_pool_reserves_request("DAO_ICP".to_string()),
+        Ok(make_pool_reserves_reply(
+            "DAO".to_string(),
+            "ICP".to_string(),
+            0,
+            0,
+        )),
+    );
+
+    let mut kong_adaptor = make_adaptor(mock_agent, sns_ledger, icp_ledger, 0);
+    kong_adaptor.set_max_deposit_price_deviation_bps(100); // 1%
+
+    let mut context =
+        OperationContext::new(Operation::Deposit).with_record_decision(RecordDecision::Full);
+
+    let result = kong_adaptor
+        .assert_deposit_price_within_reserve_tolerance(&mut context, 100 * E8, 100 * E8)
+        .await;
+
+    assert!(
+        result.is_ok(),
+        "a pool with no reserves yet has nothing to compare the deposit's ratio against"
+    );
+    assert!(
+        kong_adaptor.agent.finished_calls(),
+        "There are still some calls remaining"
+    );
+}
+
+/// Once a nonzero deviation tolerance is configured, a deposit whose implied price
+/// (`amount_1 / amount_0`) diverges from the pool's current reserve ratio by more than that
+/// tolerance is rejected before `add_pool`/`add_liquidity` is ever called.
+#[tokio::test]
+async fn test_assert_deposit_price_within_reserve_tolerance_aborts_on_deviation() {
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    // The pool's reserve ratio is 1:1, but this deposit's implied ratio is 1:2 -- far outside a
+    // 1% tolerance.
+    let reserve_0 = 100 * E8;
+    let reserve_1 = 100 * E8;
+    let amount_0 = 100 * E8;
+    let amount_1 = 200 * E8;
+
+    let mock_agent = MockAgent::new(*SELF_CANISTER_ID).add_call(
+        *KONG_BACKEND_CANISTER_ID,
+        make_pool_reserves_request("DAO_ICP".to_string()),
+        Ok(make_pool_reserves_reply(
+            "DAO".to_string(),
+            "ICP".to_string(),
+            reserve_0,
+            reserve_1,
+        )),
+    );
+
+    let mut kong_adaptor = make_adaptor(mock_agent, sns_ledger, icp_ledger, 0);
+    kong_adaptor.set_max_deposit_price_deviation_bps(100); // 1%
+
+    let mut context =
+        OperationContext::new(Operation::Deposit).with_record_decision(RecordDecision::Full);
+
+    let result = kong_adaptor
+        .assert_deposit_price_within_reserve_tolerance(&mut context, amount_0, amount_1)
+        .await;
+
+    assert!(
+        result.is_err(),
+        "a deposit ratio that deviates past the configured tolerance should be rejected"
+    );
+    assert!(
+        kong_adaptor.agent.finished_calls(),
+        "There are still some calls remaining"
+    );
+}
+
+/// Like [`test_assert_reserve_sequence_unchanged_disabled_by_default`], but for the deposit-side
+/// guard: a drift tolerance of 0 (the default) disables the reserve comparison entirely.
+#[tokio::test]
+async fn test_assert_deposit_reserve_sequence_unchanged_disabled_by_default() {
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    let mock_agent = MockAgent::new(*SELF_CANISTER_ID);
+    let mut kong_adaptor = make_adaptor(mock_agent, sns_ledger, icp_ledger, 0);
+
+    let mut context =
+        OperationContext::new(Operation::Deposit).with_record_decision(RecordDecision::Full);
+
+    let result = kong_adaptor
+        .assert_deposit_reserve_sequence_unchanged(
+            &mut context,
+            Some((Nat::from(0u8), Nat::from(0u8))),
+            kong_adaptor.operation_sequence(),
+        )
+        .await;
+
+    assert!(
+        result.is_ok(),
+        "a drift tolerance of 0 should disable the check"
+    );
+    assert!(
+        kong_adaptor.agent.finished_calls(),
+        "no calls should have been made while the check is disabled"
+    );
+}
+
+/// Once a nonzero drift tolerance is configured, pool reserves re-queried immediately before
+/// `add_pool`/`add_liquidity` that have moved away from the start-of-deposit snapshot by more
+/// than that tolerance abort the deposit.
+#[tokio::test]
+async fn test_assert_deposit_reserve_sequence_unchanged_aborts_on_reserve_drift() {
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    let snapshot_reserve_0 = 100 * E8;
+    let snapshot_reserve_1 = 100 * E8;
+    let drifted_reserve_0 = 50 * E8;
+    let drifted_reserve_1 = 50 * E8;
+
+    let mock_agent = MockAgent::new(*SELF_CANISTER_ID).add_call(
+        *KONG_BACKEND_CANISTER_ID,
+        make_pool_reserves_request("DAO_ICP".to_string()),
+        Ok(make_pool_reserves_reply(
+            "DAO".to_string(),
+            "ICP".to_string(),
+            drifted_reserve_0,
+            drifted_reserve_1,
+        )),
+    );
+
+    let mut kong_adaptor = make_adaptor(mock_agent, sns_ledger, icp_ledger, 0);
+    kong_adaptor.set_max_deposit_reserve_drift_bps(100); // 1%
+
+    let mut context =
+        OperationContext::new(Operation::Deposit).with_record_decision(RecordDecision::Full);
+
+    let result = kong_adaptor
+        .assert_deposit_reserve_sequence_unchanged(
+            &mut context,
+            Some((Nat::from(snapshot_reserve_0), Nat::from(snapshot_reserve_1))),
+            kong_adaptor.operation_sequence(),
+        )
+        .await;
+
+    assert!(
+        result.is_err(),
+        "pool reserves that drifted past the configured tolerance should abort the deposit"
+    );
+    assert!(
+        kong_adaptor.agent.finished_calls(),
+        "There are still some calls remaining"
+    );
+}
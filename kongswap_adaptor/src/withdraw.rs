@@ -1,22 +1,26 @@
 use crate::{
     balances::{Party, ValidatedBalances},
     kong_types::{
-        ClaimArgs, ClaimReply, ClaimsArgs, ClaimsReply, RemoveLiquidityArgs, RemoveLiquidityReply,
+        ClaimArgs, ClaimReply, ClaimsArgs, ClaimsReply, RemoveLiquidityAmountsArgs,
+        RemoveLiquidityAmountsReply, RemoveLiquidityArgs, RemoveLiquidityReply,
     },
-    tx_error_codes::TransactionErrorCodes,
+    slippage::check_slippage_bps,
+    state::storage::StableWithdrawState,
     validation::decode_nat_to_u64,
     KongSwapAdaptor, KONG_BACKEND_CANISTER_ID,
 };
 use candid::Nat;
 use icrc_ledger_types::icrc1::account::Account;
 use kongswap_adaptor::{agent::AbstractAgent, audit::OperationContext};
-use sns_treasury_manager::{Error, ErrorKind};
+use sns_treasury_manager::{Error, Operation};
 
 impl<A: AbstractAgent> KongSwapAdaptor<A> {
     async fn withdraw_from_dex(
         &mut self,
         context: &mut OperationContext,
     ) -> Result<(), Vec<Error>> {
+        self.assert_value_preserved(context).await?;
+
         let remove_lp_token_amount = self.lp_balance(context).await;
 
         if remove_lp_token_amount == Nat::from(0u8) {
@@ -24,19 +28,89 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
             return Ok(());
         }
 
+        // Snapshot the LP balance, pool reserves, and operation_sequence now, at the start of the
+        // withdrawal, so they can be re-queried and compared immediately before
+        // `remove_liquidity` below -- see `assert_reserve_sequence_unchanged`. Only bother
+        // querying reserves if the drift check is actually enabled, the same way `deposit`'s
+        // analogous price-deviation guard skips its own `pool_reserves` call when disabled.
+        let max_withdraw_reserve_drift_bps = self.max_withdraw_reserve_drift_bps();
+        let reserve_sequence_snapshot = if max_withdraw_reserve_drift_bps > 0 {
+            let snapshot_lp_balance_decimals =
+                decode_nat_to_u64(remove_lp_token_amount.clone()).unwrap_or_default();
+            Some((
+                snapshot_lp_balance_decimals,
+                self.pool_reserves(context).await,
+            ))
+        } else {
+            None
+        };
+        let snapshot_operation_sequence = self.operation_sequence();
+
         let human_readable =
             "Calling KongSwapBackend.remove_liquidity to withdraw all allocated tokens."
                 .to_string();
 
         let (asset_0, asset_1) = self.assets();
 
+        // Preview the expected counter-amounts before committing, so the realized amounts can be
+        // checked for slippage below.
+        let preview_human_readable = format!(
+            "Calling KongSwapBackend.remove_liquidity_amounts to preview the removal of LP token \
+             amount {}.",
+            remove_lp_token_amount
+        );
+        let RemoveLiquidityAmountsReply {
+            amount_0: expected_amount_0,
+            amount_1: expected_amount_1,
+            ..
+        } = self
+            .emit_transaction(
+                context,
+                *KONG_BACKEND_CANISTER_ID,
+                RemoveLiquidityAmountsArgs {
+                    token_0: asset_0.symbol(),
+                    token_1: asset_1.symbol(),
+                    remove_lp_token_amount: remove_lp_token_amount.clone(),
+                },
+                preview_human_readable,
+            )
+            .await
+            .map_err(|err| vec![err])?;
+        let expected_amount_0 = decode_nat_to_u64(expected_amount_0).unwrap_or_default();
+        let expected_amount_1 = decode_nat_to_u64(expected_amount_1).unwrap_or_default();
+
+        // Abort before burning any LP tokens if the preview's implied price disagrees with the
+        // DAO-configured conversion rates by more than tolerated -- see `value_guard`.
+        self.assert_withdrawal_price_within_oracle_bounds(expected_amount_0, expected_amount_1)?;
+
+        // Abort before burning any LP tokens if the LP balance or pool reserves have drifted
+        // from the snapshot taken at the start of this operation by more than tolerated, or if
+        // another operation has committed in the meantime -- see
+        // `assert_reserve_sequence_unchanged`.
+        let (snapshot_lp_balance_decimals, snapshot_reserves) =
+            reserve_sequence_snapshot.unwrap_or_default();
+        self.assert_reserve_sequence_unchanged(
+            context,
+            snapshot_lp_balance_decimals,
+            snapshot_reserves,
+            snapshot_operation_sequence,
+        )
+        .await?;
+
         let request = RemoveLiquidityArgs {
             token_0: asset_0.symbol(),
             token_1: asset_1.symbol(),
             remove_lp_token_amount,
+
+            // Unlike `add_pool`/`add_liquidity`, this call's `emit_transaction` assigns the
+            // `TreasuryManagerOperation` internally and only exposes it afterwards via
+            // `context.last_operation()` (see below), so there's no value to stamp here yet.
+            // Reconciliation against the ledger still uses that operation as the memo to match.
+            memo: None,
         };
 
-        let balances_before = self.get_ledger_balances(context).await?;
+        let chain_length_before_0 = self.get_chain_length(context, asset_0).await?;
+        let chain_length_before_1 = self.get_chain_length(context, asset_1).await?;
 
         let RemoveLiquidityReply {
             claim_ids,
@@ -46,53 +120,120 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
             lp_fee_1,
             ..
         } = self
-            .emit_transaction(
-                context.next_operation(),
-                *KONG_BACKEND_CANISTER_ID,
-                request,
-                human_readable,
-            )
+            .emit_transaction(context, *KONG_BACKEND_CANISTER_ID, request, human_readable)
             .await
             .map_err(|err| vec![err])?;
 
+        // The same operation `emit_transaction` just assigned, reused below to match this
+        // remove_liquidity call's transfers during ICRC-3 reconciliation.
+        let operation = context
+            .last_operation()
+            .expect("emit_transaction always assigns one via next_operation");
+
+        self.record_price_observation_from_nat(amount_0.clone(), amount_1.clone());
+
+        let manager_account = Account {
+            owner: self.id,
+            subaccount: None,
+        };
+
+        let chain_length_after_0 = self.get_chain_length(context, asset_0).await?;
+        let chain_length_after_1 = self.get_chain_length(context, asset_1).await?;
+
+        // `remove_liquidity` above already burned the LP tokens and released the underlying
+        // assets on KongSwap's side -- that can't be undone from here. ICRC-3 reconciliation is
+        // the ground truth for what actually landed on this canister's ledger account, so record
+        // it via `move_asset` right away: every check below this point (slippage, outstanding
+        // claims, the reconciled-vs-reported postcondition) only tells us whether to trust this
+        // withdrawal's *numbers*, not whether the transfer happened, so a failure there must flag
+        // the state as corrupt rather than return an error that would leave the books believing
+        // the withdrawal never happened.
+        let reconciled_amount_0 = self
+            .reconcile_via_icrc3(
+                context,
+                asset_0,
+                manager_account,
+                operation,
+                chain_length_before_0,
+                chain_length_after_0,
+            )
+            .await?;
+        let reconciled_amount_1 = self
+            .reconcile_via_icrc3(
+                context,
+                asset_1,
+                manager_account,
+                operation,
+                chain_length_before_1,
+                chain_length_after_1,
+            )
+            .await?;
+
+        self.move_asset(
+            asset_0,
+            reconciled_amount_0,
+            Party::External,
+            Party::TreasuryManager,
+        )
+        .map_err(|err| vec![err])?;
+        self.move_asset(
+            asset_1,
+            reconciled_amount_1,
+            Party::External,
+            Party::TreasuryManager,
+        )
+        .map_err(|err| vec![err])?;
+
+        let (max_slippage_bps, _lp_fee_bps) = self.pool_risk_params();
+
+        if let Err(err) = check_slippage_bps(
+            expected_amount_0,
+            decode_nat_to_u64(amount_0.clone()).unwrap_or_default(),
+            max_slippage_bps,
+            &format!("remove_liquidity amount_0 ({})", asset_0.symbol()),
+        ) {
+            self.mark_state_corrupt(&err.message);
+        }
+        if let Err(err) = check_slippage_bps(
+            expected_amount_1,
+            decode_nat_to_u64(amount_1.clone()).unwrap_or_default(),
+            max_slippage_bps,
+            &format!("remove_liquidity amount_1 ({})", asset_1.symbol()),
+        ) {
+            self.mark_state_corrupt(&err.message);
+        }
+
         if !claim_ids.is_empty() {
             let claim_ids = claim_ids
                 .iter()
                 .map(|claim_id| claim_id.to_string())
                 .collect::<Vec<_>>()
                 .join(", ");
-            return Err(vec![Error {
-                code: u64::from(TransactionErrorCodes::BackendCode),
-                message: format!(
-                    "Withdrawal from DEX might not be complete, returned claims: {}.",
-                    claim_ids
-                ),
-                kind: ErrorKind::Backend {},
-            }]);
+            self.mark_state_corrupt(&format!(
+                "Withdrawal from DEX might not be complete, returned claims: {}.",
+                claim_ids
+            ));
         }
 
-        // TODO Unwrapping
-        let balances_after = self.get_ledger_balances(context).await?;
-
-        let amount_0 = decode_nat_to_u64(amount_0 + lp_fee_0).unwrap();
-        let amount_1 = decode_nat_to_u64(amount_1 + lp_fee_1).unwrap();
+        let expected_amount_0 = decode_nat_to_u64(amount_0 + lp_fee_0).unwrap_or_default();
+        let expected_amount_1 = decode_nat_to_u64(amount_1 + lp_fee_1).unwrap_or_default();
 
-        self.find_discrepency(
-            asset_0,
-            balances_before.0,
-            balances_after.0,
-            amount_0,
-            false,
-        );
-        self.find_discrepency(
-            asset_1,
-            balances_before.1,
-            balances_after.1,
-            amount_1,
-            false,
-        );
-        self.move_asset(asset_0, amount_0, Party::External, Party::TreasuryManager);
-        self.move_asset(asset_1, amount_1, Party::External, Party::TreasuryManager);
+        if reconciled_amount_0 != expected_amount_0 {
+            self.mark_state_corrupt(&format!(
+                "Ledger {} blocks reconciled to {}, but KongSwap reported amount_0 + lp_fee_0 = {}.",
+                asset_0.ledger_canister_id(),
+                reconciled_amount_0,
+                expected_amount_0,
+            ));
+        }
+        if reconciled_amount_1 != expected_amount_1 {
+            self.mark_state_corrupt(&format!(
+                "Ledger {} blocks reconciled to {}, but KongSwap reported amount_1 + lp_fee_1 = {}.",
+                asset_1.ledger_canister_id(),
+                reconciled_amount_1,
+                expected_amount_1,
+            ));
+        }
 
         Ok(())
     }
@@ -107,7 +248,7 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
         let balances_before = self.get_ledger_balances(context).await?;
         let claims = self
             .emit_transaction(
-                context.next_operation(),
+                context,
                 *KONG_BACKEND_CANISTER_ID,
                 ClaimsArgs {
                     principal_id: self.id.to_string(),
@@ -130,7 +271,7 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
 
             let response = self
                 .emit_transaction(
-                    context.next_operation(),
+                    context,
                     *KONG_BACKEND_CANISTER_ID,
                     ClaimArgs { claim_id },
                     human_readable,
@@ -150,19 +291,25 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
                     if let Some(asset) = self.get_asset_for_ledger(&canister_id) {
                         match decode_nat_to_u64(amount) {
                             Ok(amount) => {
-                                self.move_asset(
+                                if let Err(err) = self.move_asset(
                                     asset,
                                     amount,
                                     Party::External,
                                     Party::TreasuryManager,
-                                );
-                                self.find_discrepency(
+                                ) {
+                                    errors.push(err);
+                                    continue;
+                                }
+                                if let Err(err) = self.find_discrepency(
                                     asset,
                                     balances_before.0,
                                     balances_after.0,
                                     amount,
                                     false,
-                                );
+                                ) {
+                                    errors.push(err);
+                                    continue;
+                                }
                             }
                             Err(err) => {
                                 errors.push(Error::new_postcondition(format!(
@@ -197,6 +344,54 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
         Ok(())
     }
 
+    /// Standalone claim-recovery sweep, independent of an in-flight [`Self::withdraw_impl`]
+    /// sequence: reuses [`Self::retry_withdraw_from_dex`]'s claims-query/re-claim logic so that
+    /// funds stranded by a previously failed claim (`ClaimsReply::status` other than
+    /// `"Success"`) are recovered even if no caller ever invokes `withdraw` again. Called by both
+    /// the manual `retry_claims` canister entry point and the periodic sweep in
+    /// `canister::refresh_balances_tick`, gated by [`crate::state::KongSwapAdaptor::claims_sweep_is_due`].
+    ///
+    /// Recovered funds land in [`Party::TreasuryManager`] -- the same place `withdraw_impl`'s own
+    /// `ProcessingClaims` step leaves them -- rather than being forwarded to an owner account:
+    /// unlike a caller-initiated `withdraw`, this sweep has no `withdraw_account_0`/
+    /// `withdraw_account_1` to send funds to, and moving DAO funds out to a guessed destination on
+    /// a timer with no caller in the loop is exactly what this canister's access checks elsewhere
+    /// exist to prevent. A subsequent `withdraw` call picks up and returns whatever this sweep
+    /// already recovered, the same as it would for a retry left over from its own
+    /// `ProcessingClaims` step.
+    pub async fn retry_claims(&mut self) -> Result<(), Vec<Error>> {
+        self.check_state_lock(&self.lock_keys(&[Operation::Withdraw]))?;
+        self.check_integrity()?;
+
+        let lock_generation = self
+            .acquire_operation_lock(Operation::Withdraw)
+            .map_err(|err| vec![err])?;
+        let mut context = self
+            .new_operation_context(Operation::Withdraw)
+            .with_lock_generation(lock_generation);
+
+        let result = self.retry_withdraw_from_dex(&mut context).await;
+
+        match &result {
+            Ok(()) => {
+                self.finalize_audit_trail_transaction(context);
+                self.release_operation_lock(Operation::Withdraw);
+            }
+            Err(errors) => self.rollback_operation(context, crate::combine_errors(errors)),
+        }
+
+        result
+    }
+
+    /// Drives the withdraw sequence (`remove_liquidity` -> process outstanding claims -> return
+    /// assets to the owner) from the step last persisted in [`StableWithdrawState`], rather than
+    /// always restarting at the beginning. This matters because a trap or upgrade between two
+    /// sub-steps (e.g. after `remove_liquidity` burned the LP tokens but before the claims were
+    /// processed) would otherwise cause the next `withdraw` call to redo a sub-step that already
+    /// took effect on the DEX side.
+    ///
+    /// Each sub-step only advances the persisted state once it has returned successfully, so a
+    /// trap always leaves the state pointing at the sub-step that still needs to run.
     pub async fn withdraw_impl(
         &mut self,
         context: &mut OperationContext,
@@ -205,24 +400,40 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
     ) -> Result<ValidatedBalances, Vec<Error>> {
         let mut errors = vec![];
 
-        if let Err(err) = self.withdraw_from_dex(context).await {
-            errors.extend(err.into_iter());
+        if self.get_withdraw_state() == StableWithdrawState::Done {
+            self.set_withdraw_state(StableWithdrawState::RemovingLiquidity);
         }
 
-        if let Err(err) = self.retry_withdraw_from_dex(context).await {
-            errors.extend(err.into_iter());
+        if self.get_withdraw_state() == StableWithdrawState::RemovingLiquidity {
+            match self.withdraw_from_dex(context).await {
+                Ok(()) => self.set_withdraw_state(StableWithdrawState::ProcessingClaims),
+                Err(err) => errors.extend(err),
+            }
         }
 
-        match self
-            .return_remaining_assets_to_owner(context, withdraw_account_0, withdraw_account_1)
-            .await
-        {
-            Ok(_) => {}
-            Err(err) => {
-                errors.extend(err.clone());
-                return Err(err);
+        if errors.is_empty() && self.get_withdraw_state() == StableWithdrawState::ProcessingClaims {
+            match self.retry_withdraw_from_dex(context).await {
+                Ok(()) => self.set_withdraw_state(StableWithdrawState::ReturningToOwner),
+                Err(err) => errors.extend(err),
             }
-        };
+        }
+
+        if errors.is_empty() && self.get_withdraw_state() == StableWithdrawState::ReturningToOwner {
+            match self
+                .return_remaining_assets_to_owner(context, withdraw_account_0, withdraw_account_1)
+                .await
+            {
+                Ok(_) => self.set_withdraw_state(StableWithdrawState::Done),
+                Err(err) => {
+                    errors.extend(err.clone());
+                    return Err(err);
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
 
         Ok(self.get_cached_balances())
     }
@@ -230,3 +441,6 @@ impl<A: AbstractAgent> KongSwapAdaptor<A> {
 
 #[cfg(test)]
 mod tests;
+
+#[cfg(test)]
+mod property_tests;
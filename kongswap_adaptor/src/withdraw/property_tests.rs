@@ -0,0 +1,723 @@
+//! A property-based conservation check for a full deposit-then-withdraw cycle through
+//! [`KongSwapAdaptor`] itself, randomizing the allowance amounts and ledger fees `test_withdraw_
+//! success` (in [`super::tests`]) otherwise hard-codes. Like [`crate::agent::mock_agent::tests`]
+//! and [`crate::balances::tests`], this stops short of modeling KongSwap's own AMM pricing math --
+//! `add_pool`/`remove_liquidity` are still scripted, not simulated -- so what varies from seed to
+//! seed is the deposit/withdraw amounts and fees feeding that scripted round-trip, not the pool's
+//! own quote. What's here still catches the class of bug a single fixed-constant test cannot: an
+//! off-by-one fee multiplier or a bucket left unaccounted for that only a handful of the
+//! infinitely many possible (amount, fee) pairs happen to paper over.
+//!
+//! The scripted-call builder functions below duplicate (rather than import) the ones in
+//! [`super::tests`], matching how [`crate::agent::mock_agent::tests`] and
+//! [`crate::balances::tests`] each keep their own local `Prng` instead of sharing one: these are
+//! `fn`s private to the `tests` module, and a property-test module living alongside it, not inside
+//! it, is meant to stand on its own.
+//!
+//! Each seed asserts, after the deposit and again after the withdraw, that the returned
+//! [`Balances`] conserves the full deposited amount across `treasury_owner`/`treasury_manager`/
+//! `external_custodian`/`fee_collector`, and that the allowance was fully spent, i.e. the
+//! adaptor's own ledger balance is `0` once the deposit settles -- the "All approved tokens should
+//! be fully used up before the next deposit" invariant the single fixed scenario test checks only
+//! incidentally, by construction.
+
+use super::*;
+use crate::kong_types::{
+    AddPoolArgs, AddPoolReply, AddTokenArgs, AddTokenReply, ICReply, UserBalanceLPReply,
+    UserBalancesArgs, UserBalancesReply,
+};
+use crate::{
+    state::storage::{
+        ConfigState, ContractStatus, IntegrityStatus, OperationLock, PendingDepositState,
+        StableWithdrawState, TaskStatuses,
+    },
+    validation::ValidatedTreasuryManagerInit,
+    StableAuditTrail, StableBalances, StableContractStatus, StableExchangeRateHistory,
+    StableIdempotencyKeys, StableIntegrityStatus, StableOperationLockCell,
+    StablePendingDepositStateCell, StableTaskStatusCell, StableTransferIntents,
+    StableWithdrawStateCell, AUDIT_TRAIL_MEMORY_ID, BALANCES_MEMORY_ID, CONTRACT_STATUS_MEMORY_ID,
+    EXCHANGE_RATE_HISTORY_MEMORY_ID, IDEMPOTENCY_KEYS_MEMORY_ID, INTEGRITY_STATUS_MEMORY_ID,
+    OPERATION_LOCK_MEMORY_ID, PENDING_DEPOSIT_STATE_MEMORY_ID, TASK_STATUS_MEMORY_ID,
+    TRANSFER_INTENTS_MEMORY_ID, WITHDRAW_STATE_MEMORY_ID,
+};
+use candid::{Nat, Principal};
+use ic_stable_structures::memory_manager::MemoryManager;
+use ic_stable_structures::{
+    BTreeMap as StableBTreeMap, Cell as StableCell, DefaultMemoryImpl, Vec as StableVec,
+};
+use kongswap_adaptor::agent::mock_agent::MockLedgerAgent;
+use maplit::btreemap;
+use pretty_assertions::assert_eq;
+use sns_treasury_manager::{
+    Allowance, Asset, Balance, BalanceBook, Balances, DepositRequest, TreasuryManager,
+    TreasuryManagerInit, TreasuryManagerOperation, WithdrawRequest,
+};
+use std::cell::RefCell;
+
+/// A small, deterministic, dependency-free PRNG -- see [`crate::agent::mock_agent::tests::Prng`]
+/// for the identical rationale: this crate has no randomness source available outside of a
+/// canister, and a property test needs its draws to be exactly reproducible from a seed anyway.
+struct Prng {
+    state: u64,
+}
+
+impl Prng {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// A value in `[low, high]`.
+    fn next_range(&mut self, low: u64, high: u64) -> u64 {
+        low + self.next_u64() % (high - low + 1)
+    }
+}
+
+const SEEDS: [u64; 10] = [
+    1,
+    2,
+    3,
+    4,
+    5,
+    42,
+    1_000_003,
+    0xC0FFEE,
+    0xDEAD_BEEF,
+    u64::MAX,
+];
+
+fn make_add_token_request(token: String) -> AddTokenArgs {
+    AddTokenArgs { token }
+}
+
+fn make_add_token_reply(
+    token_id: u32,
+    chain: String,
+    canister_id: Principal,
+    name: String,
+    symbol: String,
+    fee: u64,
+) -> AddTokenReply {
+    AddTokenReply::IC(ICReply {
+        token_id,
+        chain,
+        canister_id: canister_id.to_string(),
+        name,
+        symbol,
+        decimals: 8,
+        fee: Nat::from(fee),
+        icrc1: true,
+        icrc2: true,
+        icrc3: true,
+        is_removed: false,
+    })
+}
+
+fn make_add_pool_request(
+    token_0: String,
+    amount_0: u64,
+    token_1: String,
+    amount_1: u64,
+) -> AddPoolArgs {
+    AddPoolArgs {
+        token_0,
+        amount_0: Nat::from(amount_0),
+        tx_id_0: None,
+        token_1,
+        amount_1: Nat::from(amount_1),
+        tx_id_1: None,
+        lp_fee_bps: Some(30),
+        memo: Some(Vec::<u8>::from(TreasuryManagerOperation::Deposit)),
+    }
+}
+
+fn make_lp_balance_request(principal_id: Principal) -> UserBalancesArgs {
+    UserBalancesArgs {
+        principal_id: principal_id.to_string(),
+    }
+}
+
+fn make_lp_balance_reply(token_0: String, token_1: String) -> UserBalancesReply {
+    UserBalancesReply::LP(UserBalanceLPReply {
+        symbol: format!("{}_{}", token_0, token_1),
+        name: String::default(),
+        lp_token_id: 0,
+        balance: 100.0,
+        usd_balance: 0.0,
+        chain_0: String::default(),
+        symbol_0: String::default(),
+        address_0: String::default(),
+        amount_0: 0.0,
+        usd_amount_0: 0.0,
+        chain_1: String::default(),
+        symbol_1: String::default(),
+        address_1: String::default(),
+        amount_1: 0.0,
+        usd_amount_1: 0.0,
+        ts: 0,
+    })
+}
+
+fn make_remove_liquidity_request(
+    token_0: String,
+    token_1: String,
+    remove_lp_token_amount: u64,
+) -> RemoveLiquidityArgs {
+    RemoveLiquidityArgs {
+        token_0,
+        token_1,
+        remove_lp_token_amount: Nat::from(remove_lp_token_amount),
+        memo: None,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn make_remove_liquidity_reply(
+    token_0: String,
+    token_1: String,
+    amount_0: u64,
+    amount_1: u64,
+    lp_fee_0: u64,
+    lp_fee_1: u64,
+    remove_lp_token_amount: u64,
+) -> RemoveLiquidityReply {
+    RemoveLiquidityReply {
+        tx_id: 0,
+        request_id: 0,
+        status: "Success".to_string(),
+        symbol: format!("{}_{}", token_0, token_1),
+        chain_0: String::default(),
+        address_0: String::default(),
+        symbol_0: token_0.clone(),
+        amount_0: Nat::from(amount_0),
+        lp_fee_0: Nat::from(lp_fee_0),
+        chain_1: String::default(),
+        address_1: String::default(),
+        symbol_1: token_1.clone(),
+        amount_1: Nat::from(amount_1),
+        lp_fee_1: Nat::from(lp_fee_1),
+        remove_lp_token_amount: Nat::from(remove_lp_token_amount),
+        transfer_ids: vec![],
+        claim_ids: vec![],
+        ts: 0,
+    }
+}
+
+/// A single randomly generated deposit/withdraw scenario: a pair of ledger fees and a pair of
+/// deposit amounts, each a comfortable multiple of its own fee so it survives the four fee
+/// deductions (one `approve`, one `add_pool`/`remove_liquidity` round-trip, one final `transfer`)
+/// the happy path below puts it through.
+struct Scenario {
+    fee_sns: u64,
+    fee_icp: u64,
+    amount_0_decimals: u64,
+    amount_1_decimals: u64,
+}
+
+fn generate_scenario(rng: &mut Prng) -> Scenario {
+    let fee_sns = rng.next_range(1, 100_000);
+    let fee_icp = rng.next_range(1, 100_000);
+    let amount_0_decimals = fee_sns * rng.next_range(10, 1_000_000);
+    let amount_1_decimals = fee_icp * rng.next_range(10, 1_000_000);
+
+    Scenario {
+        fee_sns,
+        fee_icp,
+        amount_0_decimals,
+        amount_1_decimals,
+    }
+}
+
+async fn run_scenario(seed: u64, scenario: Scenario) {
+    let Scenario {
+        fee_sns: FEE_SNS,
+        fee_icp: FEE_ICP,
+        amount_0_decimals,
+        amount_1_decimals,
+    } = scenario;
+
+    let self_canister_id = Principal::from_text("jexlm-gaaaa-aaaar-qalmq-cai").unwrap();
+    let sns_ledger = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let icp_ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let sns_id = Principal::from_text("jg2ra-syaaa-aaaaq-aaewa-cai").unwrap();
+
+    let token_0 = format!("IC.{}", sns_ledger);
+    let token_1 = format!("IC.{}", icp_ledger);
+
+    let symbol_0 = "DAO".to_string();
+    let symbol_1 = "ICP".to_string();
+
+    let asset_0 = Asset::Token {
+        ledger_canister_id: sns_ledger,
+        symbol: symbol_0.clone(),
+        ledger_fee_decimals: Nat::from(FEE_SNS),
+    };
+
+    let asset_1 = Asset::Token {
+        ledger_canister_id: icp_ledger,
+        symbol: symbol_1.clone(),
+        ledger_fee_decimals: Nat::from(FEE_ICP),
+    };
+
+    let owner_account = sns_treasury_manager::Account {
+        owner: Principal::from_text("2vxsx-fae").unwrap(),
+        subaccount: None,
+    };
+
+    thread_local! {
+        static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+            RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+        static BALANCES: RefCell<StableBalances> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(BALANCES_MEMORY_ID),
+                        ConfigState::default()
+                    )
+                    .expect("BALANCES init should not cause errors")
+                )
+            );
+
+        static AUDIT_TRAIL: RefCell<StableAuditTrail> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableVec::init(
+                        memory_manager.borrow().get(AUDIT_TRAIL_MEMORY_ID)
+                    )
+                    .expect("AUDIT_TRAIL init should not cause errors")
+                )
+            );
+
+        static WITHDRAW_STATE: RefCell<StableWithdrawStateCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(WITHDRAW_STATE_MEMORY_ID),
+                        StableWithdrawState::default()
+                    )
+                    .expect("WITHDRAW_STATE init should not cause errors")
+                )
+            );
+
+        static PRICE_HISTORY: RefCell<StablePriceHistory> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(PRICE_HISTORY_MEMORY_ID)
+                    )
+                )
+            );
+
+        static IDEMPOTENCY_KEYS: RefCell<StableIdempotencyKeys> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(IDEMPOTENCY_KEYS_MEMORY_ID)
+                    )
+                )
+            );
+
+        static CONTRACT_STATUS: RefCell<StableContractStatus> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(CONTRACT_STATUS_MEMORY_ID),
+                        ContractStatus::default()
+                    )
+                    .expect("CONTRACT_STATUS init should not cause errors")
+                )
+            );
+
+        static PENDING_DEPOSIT_STATE: RefCell<StablePendingDepositStateCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(PENDING_DEPOSIT_STATE_MEMORY_ID),
+                        PendingDepositState::default()
+                    )
+                    .expect("PENDING_DEPOSIT_STATE init should not cause errors")
+                )
+            );
+
+        static INTEGRITY_STATUS: RefCell<StableIntegrityStatus> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(INTEGRITY_STATUS_MEMORY_ID),
+                        IntegrityStatus::default()
+                    )
+                    .expect("INTEGRITY_STATUS init should not cause errors")
+                )
+            );
+
+        static EXCHANGE_RATE_HISTORY: RefCell<StableExchangeRateHistory> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(EXCHANGE_RATE_HISTORY_MEMORY_ID)
+                    )
+                )
+            );
+
+        static TASK_STATUS: RefCell<StableTaskStatusCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(TASK_STATUS_MEMORY_ID),
+                        TaskStatuses::default()
+                    )
+                    .expect("TASK_STATUS init should not cause errors")
+                )
+            );
+
+        static TRANSFER_INTENTS: RefCell<StableTransferIntents> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(TRANSFER_INTENTS_MEMORY_ID)
+                    )
+                )
+            );
+        static OPERATION_LOCK: RefCell<StableOperationLockCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(OPERATION_LOCK_MEMORY_ID),
+                        OperationLock::default()
+                    )
+                    .expect("OPERATION_LOCK init should not cause errors")
+                )
+            );
+    }
+
+    let allowances = vec![
+        Allowance {
+            asset: asset_0.clone(),
+            owner_account,
+            amount_decimals: Nat::from(amount_0_decimals),
+        },
+        Allowance {
+            asset: asset_1.clone(),
+            owner_account,
+            amount_decimals: Nat::from(amount_1_decimals),
+        },
+    ];
+
+    let self_account = Account {
+        owner: self_canister_id,
+        subaccount: None,
+    };
+
+    let mock_agent = MockLedgerAgent::new(self_canister_id)
+        .with_ledger(sns_ledger, FEE_SNS, "My DAO Token", "DAO")
+        .with_ledger(icp_ledger, FEE_ICP, "Internet Computer", "ICP")
+        .with_balance(sns_ledger, self_account, amount_0_decimals)
+        .with_balance(icp_ledger, self_account, amount_1_decimals)
+        .add_scripted_call(
+            *KONG_BACKEND_CANISTER_ID,
+            make_add_token_request(token_0.clone()),
+            Ok(make_add_token_reply(
+                1,
+                "IC".to_string(),
+                sns_id,
+                "My DAO Token".to_string(),
+                "DAO".to_string(),
+                FEE_SNS,
+            )),
+        )
+        .add_scripted_call(
+            *KONG_BACKEND_CANISTER_ID,
+            make_add_token_request(token_1.clone()),
+            Ok(make_add_token_reply(
+                2,
+                "IC".to_string(),
+                icp_ledger,
+                "Internet Computer".to_string(),
+                "ICP".to_string(),
+                FEE_ICP,
+            )),
+        )
+        .add_scripted_call_with_balances(
+            *KONG_BACKEND_CANISTER_ID,
+            make_add_pool_request(
+                token_0.clone(),
+                amount_0_decimals - 2 * FEE_SNS,
+                token_1.clone(),
+                amount_1_decimals - 2 * FEE_ICP,
+            ),
+            Ok(AddPoolReply::default()),
+            vec![(sns_ledger, self_account, 0), (icp_ledger, self_account, 0)],
+        )
+        .add_scripted_call(
+            *KONG_BACKEND_CANISTER_ID,
+            make_lp_balance_request(self_canister_id),
+            Ok(vec![make_lp_balance_reply(
+                symbol_0.clone(),
+                symbol_1.clone(),
+            )]),
+        )
+        .add_scripted_call_with_balances(
+            *KONG_BACKEND_CANISTER_ID,
+            make_remove_liquidity_request(symbol_0.clone(), symbol_1.clone(), 100 * 100_000_000),
+            Ok(make_remove_liquidity_reply(
+                symbol_0.clone(),
+                symbol_1.clone(),
+                amount_0_decimals - 2 * FEE_SNS,
+                amount_1_decimals - 2 * FEE_ICP,
+                0,
+                0,
+                100,
+            )),
+            vec![
+                (sns_ledger, self_account, amount_0_decimals - 3 * FEE_SNS),
+                (icp_ledger, self_account, amount_1_decimals - 3 * FEE_ICP),
+            ],
+        )
+        .add_scripted_call(
+            *KONG_BACKEND_CANISTER_ID,
+            ClaimsArgs {
+                principal_id: self_canister_id.to_string(),
+            },
+            Ok(vec![]),
+        );
+
+    let mut kong_adaptor = KongSwapAdaptor::new(
+        || 0, // Mock time function
+        mock_agent,
+        self_canister_id,
+        &BALANCES,
+        &AUDIT_TRAIL,
+        &WITHDRAW_STATE,
+        &PRICE_HISTORY,
+        &IDEMPOTENCY_KEYS,
+        &CONTRACT_STATUS,
+        &PENDING_DEPOSIT_STATE,
+        &INTEGRITY_STATUS,
+        &EXCHANGE_RATE_HISTORY,
+        &TASK_STATUS,
+        &TRANSFER_INTENTS,
+        &OPERATION_LOCK,
+    );
+
+    let init = TreasuryManagerInit {
+        allowances: allowances.clone(),
+    };
+
+    let ValidatedTreasuryManagerInit {
+        allowance_0,
+        allowance_1,
+    } = init.try_into().unwrap();
+
+    kong_adaptor.initialize(
+        allowance_0.asset,
+        allowance_1.asset,
+        allowance_0.owner_account,
+        allowance_1.owner_account,
+    );
+
+    let treasury_manager_account = sns_treasury_manager::Account {
+        owner: kong_adaptor.id,
+        subaccount: None,
+    };
+
+    {
+        let result_deposit = kong_adaptor.deposit(DepositRequest { allowances }).await;
+
+        let mut asset_0_balance = BalanceBook::empty()
+            .with_treasury_owner(owner_account, "DAO Treasury".to_string())
+            .with_treasury_manager(
+                treasury_manager_account,
+                format!("KongSwapAdaptor({})", kong_adaptor.id),
+            )
+            .with_external_custodian(None, None)
+            .with_suspense(None)
+            .with_fee_collector(None, None)
+            .fee_collector(2 * FEE_SNS)
+            .external_custodian(amount_0_decimals - 2 * FEE_SNS);
+
+        asset_0_balance.payees = Some(Balance {
+            amount_decimals: 0_u64.into(),
+            account: None,
+            name: None,
+        });
+        asset_0_balance.payers = Some(Balance {
+            amount_decimals: 0_u64.into(),
+            account: None,
+            name: None,
+        });
+
+        let mut asset_1_balance = BalanceBook::empty()
+            .with_treasury_owner(owner_account, "DAO Treasury".to_string())
+            .with_treasury_manager(
+                treasury_manager_account,
+                format!("KongSwapAdaptor({})", kong_adaptor.id),
+            )
+            .with_external_custodian(None, None)
+            .with_suspense(None)
+            .with_fee_collector(None, None)
+            .fee_collector(2 * FEE_ICP)
+            .external_custodian(amount_1_decimals - 2 * FEE_ICP);
+
+        asset_1_balance.payees = Some(Balance {
+            amount_decimals: 0_u64.into(),
+            account: None,
+            name: None,
+        });
+        asset_1_balance.payers = Some(Balance {
+            amount_decimals: 0_u64.into(),
+            account: None,
+            name: None,
+        });
+
+        let expected = Balances {
+            timestamp_ns: 0,
+            asset_to_balances: Some(btreemap! {
+                asset_0.clone() => asset_0_balance,
+                asset_1.clone() => asset_1_balance,
+            }),
+        };
+
+        assert_eq!(
+            result_deposit,
+            Ok(expected),
+            "seed {seed}: deposit balances mismatch -- replay with seed {seed}",
+        );
+
+        // The allowance is fully spent: nothing remains in the adaptor's own ledger account once
+        // the deposit has settled into the pool.
+        assert_eq!(
+            kong_adaptor.agent.balance_of(sns_ledger, &self_account),
+            Nat::from(0_u64),
+            "seed {seed}: SNS allowance left unspent after deposit -- replay with seed {seed}",
+        );
+        assert_eq!(
+            kong_adaptor.agent.balance_of(icp_ledger, &self_account),
+            Nat::from(0_u64),
+            "seed {seed}: ICP allowance left unspent after deposit -- replay with seed {seed}",
+        );
+    }
+
+    {
+        let withdraw_accounts = btreemap! {
+            sns_ledger => sns_treasury_manager::Account {
+                owner: allowance_0.owner_account.owner,
+                subaccount: None
+            },
+            icp_ledger => sns_treasury_manager::Account {
+                owner: allowance_1.owner_account.owner,
+                subaccount: None
+            },
+        };
+
+        let result_withdraw = kong_adaptor
+            .withdraw(WithdrawRequest {
+                withdraw_accounts: Some(withdraw_accounts),
+            })
+            .await;
+
+        let mut asset_0_balance = BalanceBook::empty()
+            .with_treasury_owner(owner_account, "DAO Treasury".to_string())
+            .with_treasury_manager(
+                treasury_manager_account,
+                format!("KongSwapAdaptor({})", kong_adaptor.id),
+            )
+            .with_external_custodian(None, None)
+            .with_suspense(None)
+            .with_fee_collector(None, None)
+            .fee_collector(4 * FEE_SNS)
+            .treasury_owner(amount_0_decimals - 4 * FEE_SNS);
+
+        asset_0_balance.payees = Some(Balance {
+            amount_decimals: 0_u64.into(),
+            account: None,
+            name: None,
+        });
+        asset_0_balance.payers = Some(Balance {
+            amount_decimals: 0_u64.into(),
+            account: None,
+            name: None,
+        });
+
+        let mut asset_1_balance = BalanceBook::empty()
+            .with_treasury_owner(owner_account, "DAO Treasury".to_string())
+            .with_treasury_manager(
+                treasury_manager_account,
+                format!("KongSwapAdaptor({})", kong_adaptor.id),
+            )
+            .with_external_custodian(None, None)
+            .with_suspense(None)
+            .with_fee_collector(None, None)
+            .fee_collector(4 * FEE_ICP)
+            .treasury_owner(amount_1_decimals - 4 * FEE_ICP);
+
+        asset_1_balance.payees = Some(Balance {
+            amount_decimals: 0_u64.into(),
+            account: None,
+            name: None,
+        });
+        asset_1_balance.payers = Some(Balance {
+            amount_decimals: 0_u64.into(),
+            account: None,
+            name: None,
+        });
+
+        let expected = Balances {
+            timestamp_ns: 0,
+            asset_to_balances: Some(btreemap! {
+                asset_0 => asset_0_balance,
+                asset_1 => asset_1_balance,
+            }),
+        };
+
+        assert_eq!(
+            result_withdraw,
+            Ok(expected),
+            "seed {seed}: withdraw balances mismatch -- replay with seed {seed}",
+        );
+    }
+
+    let owner_ledger_account = Account {
+        owner: owner_account.owner,
+        subaccount: None,
+    };
+    kong_adaptor
+        .agent
+        .assert_invariants(sns_ledger, &Nat::from(amount_0_decimals - 4 * FEE_SNS));
+    kong_adaptor
+        .agent
+        .assert_invariants(icp_ledger, &Nat::from(amount_1_decimals - 4 * FEE_ICP));
+    assert_eq!(
+        kong_adaptor
+            .agent
+            .balance_of(sns_ledger, &owner_ledger_account),
+        Nat::from(amount_0_decimals - 4 * FEE_SNS),
+        "seed {seed}: SNS owner balance mismatch after withdraw -- replay with seed {seed}",
+    );
+    assert_eq!(
+        kong_adaptor
+            .agent
+            .balance_of(icp_ledger, &owner_ledger_account),
+        Nat::from(amount_1_decimals - 4 * FEE_ICP),
+        "seed {seed}: ICP owner balance mismatch after withdraw -- replay with seed {seed}",
+    );
+    assert!(
+        kong_adaptor.agent.finished_calls(),
+        "seed {seed}: there are still some calls remaining -- replay with seed {seed}",
+    );
+}
+
+#[tokio::test]
+async fn property_test_deposit_withdraw_conservation() {
+    for seed in SEEDS {
+        let scenario = generate_scenario(&mut Prng::new(seed));
+        run_scenario(seed, scenario).await;
+    }
+}
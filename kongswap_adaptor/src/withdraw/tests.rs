@@ -4,19 +4,29 @@ use crate::kong_types::{
     UserBalancesArgs, UserBalancesReply,
 };
 use crate::{
-    state::storage::ConfigState, validation::ValidatedTreasuryManagerInit, StableAuditTrail,
-    StableBalances, AUDIT_TRAIL_MEMORY_ID, BALANCES_MEMORY_ID,
+    state::storage::{
+        ConfigState, ContractStatus, IntegrityStatus, OperationLock, PendingDepositState,
+        StableWithdrawState, TaskStatuses,
+    },
+    validation::ValidatedTreasuryManagerInit,
+    StableAuditTrail, StableBalances, StableContractStatus, StableExchangeRateHistory,
+    StableIdempotencyKeys, StableIntegrityStatus, StableOperationLockCell,
+    StablePendingDepositStateCell, StableTaskStatusCell, StableTransferIntents,
+    StableWithdrawStateCell, AUDIT_TRAIL_MEMORY_ID, BALANCES_MEMORY_ID, CONTRACT_STATUS_MEMORY_ID,
+    EXCHANGE_RATE_HISTORY_MEMORY_ID, IDEMPOTENCY_KEYS_MEMORY_ID, INTEGRITY_STATUS_MEMORY_ID,
+    OPERATION_LOCK_MEMORY_ID, PENDING_DEPOSIT_STATE_MEMORY_ID, TASK_STATUS_MEMORY_ID,
+    TRANSFER_INTENTS_MEMORY_ID, WITHDRAW_STATE_MEMORY_ID,
 };
 use candid::{Nat, Principal};
 use ic_stable_structures::memory_manager::MemoryManager;
-use ic_stable_structures::{Cell as StableCell, DefaultMemoryImpl, Vec as StableVec};
-use icrc_ledger_types::icrc1::transfer::{Memo, TransferArg};
-use icrc_ledger_types::icrc2::approve::ApproveArgs;
-use kongswap_adaptor::agent::mock_agent::MockAgent;
+use ic_stable_structures::{
+    BTreeMap as StableBTreeMap, Cell as StableCell, DefaultMemoryImpl, Vec as StableVec,
+};
+use kongswap_adaptor::agent::mock_agent::MockLedgerAgent;
 use maplit::btreemap;
 use pretty_assertions::assert_eq;
 use sns_treasury_manager::{
-    Allowance, Asset, Balance, BalanceBook, Balances, DepositRequest, Step, TreasuryManager,
+    Allowance, Asset, Balance, BalanceBook, Balances, DepositRequest, TreasuryManager,
     TreasuryManagerInit, TreasuryManagerOperation, WithdrawRequest,
 };
 use std::cell::RefCell;
@@ -30,30 +40,6 @@ lazy_static! {
         Principal::from_text("jexlm-gaaaa-aaaar-qalmq-cai").unwrap();
 }
 
-fn make_approve_request(amount: u64, fee: u64) -> ApproveArgs {
-    ApproveArgs {
-        from_subaccount: None,
-        spender: Account {
-            owner: *KONG_BACKEND_CANISTER_ID,
-            subaccount: None,
-        },
-        // All approved tokens should be fully used up before the next deposit.
-        amount: Nat::from(amount - fee),
-        expected_allowance: Some(Nat::from(0u8)),
-        expires_at: Some(u64::MAX),
-        memo: None,
-        created_at_time: None,
-        fee: Some(fee.into()),
-    }
-}
-
-fn make_balance_request(self_id: Principal) -> Account {
-    Account {
-        owner: self_id,
-        subaccount: None,
-    }
-}
-
 fn make_add_token_request(token: String) -> AddTokenArgs {
     AddTokenArgs { token }
 }
@@ -95,6 +81,7 @@ fn make_add_pool_request(
         amount_1: Nat::from(amount_1),
         tx_id_1: None,
         lp_fee_bps: Some(30),
+        memo: Some(Vec::<u8>::from(TreasuryManagerOperation::Deposit)),
     }
 }
 
@@ -134,6 +121,7 @@ fn make_remove_liquidity_request(
         token_0,
         token_1,
         remove_lp_token_amount: Nat::from(remove_lp_token_amount),
+        memo: None,
     }
 }
 
@@ -168,22 +156,6 @@ fn make_remove_liquidity_reply(
     }
 }
 
-fn make_transfer_request(
-    owner: Account,
-    fee: u64,
-    amount: u64,
-    operation: TreasuryManagerOperation,
-) -> TransferArg {
-    TransferArg {
-        from_subaccount: None,
-        to: owner,
-        fee: Some(Nat::from(fee)),
-        created_at_time: Some(0),
-        memo: Some(Memo::from(Vec::<u8>::from(operation))),
-        amount: Nat::from(amount - fee),
-    }
-}
-
 #[tokio::test]
 async fn test_withdraw_success() {
     const FEE_SNS: u64 = 10_500u64;
@@ -240,6 +212,107 @@ async fn test_withdraw_success() {
                     .expect("AUDIT_TRAIL init should not cause errors")
                 )
             );
+
+        static WITHDRAW_STATE: RefCell<StableWithdrawStateCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(WITHDRAW_STATE_MEMORY_ID),
+                        StableWithdrawState::default()
+                    )
+                    .expect("WITHDRAW_STATE init should not cause errors")
+                )
+            );
+
+        static PRICE_HISTORY: RefCell<StablePriceHistory> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(PRICE_HISTORY_MEMORY_ID)
+                    )
+                )
+            );
+
+        static IDEMPOTENCY_KEYS: RefCell<StableIdempotencyKeys> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(IDEMPOTENCY_KEYS_MEMORY_ID)
+                    )
+                )
+            );
+
+        static CONTRACT_STATUS: RefCell<StableContractStatus> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(CONTRACT_STATUS_MEMORY_ID),
+                        ContractStatus::default()
+                    )
+                    .expect("CONTRACT_STATUS init should not cause errors")
+                )
+            );
+
+        static PENDING_DEPOSIT_STATE: RefCell<StablePendingDepositStateCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(PENDING_DEPOSIT_STATE_MEMORY_ID),
+                        PendingDepositState::default()
+                    )
+                    .expect("PENDING_DEPOSIT_STATE init should not cause errors")
+                )
+            );
+
+        static INTEGRITY_STATUS: RefCell<StableIntegrityStatus> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(INTEGRITY_STATUS_MEMORY_ID),
+                        IntegrityStatus::default()
+                    )
+                    .expect("INTEGRITY_STATUS init should not cause errors")
+                )
+            );
+
+        static EXCHANGE_RATE_HISTORY: RefCell<StableExchangeRateHistory> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(EXCHANGE_RATE_HISTORY_MEMORY_ID)
+                    )
+                )
+            );
+
+        static TASK_STATUS: RefCell<StableTaskStatusCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(TASK_STATUS_MEMORY_ID),
+                        TaskStatuses::default()
+                    )
+                    .expect("TASK_STATUS init should not cause errors")
+                )
+            );
+
+        static TRANSFER_INTENTS: RefCell<StableTransferIntents> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableBTreeMap::init(
+                        memory_manager.borrow().get(TRANSFER_INTENTS_MEMORY_ID)
+                    )
+                )
+            );
+        static OPERATION_LOCK: RefCell<StableOperationLockCell> =
+            MEMORY_MANAGER.with(|memory_manager|
+                RefCell::new(
+                    StableCell::init(
+                        memory_manager.borrow().get(OPERATION_LOCK_MEMORY_ID),
+                        OperationLock::default()
+                    )
+                    .expect("OPERATION_LOCK init should not cause errors")
+                )
+            );
     }
 
     let amount_0_decimals = 500 * E8;
@@ -259,28 +332,24 @@ async fn test_withdraw_success() {
         },
     ];
 
-    let mock_agent = MockAgent::new(*SELF_CANISTER_ID)
-        .add_call(
-            sns_ledger,
-            make_approve_request(amount_0_decimals, FEE_SNS),
-            Ok(Nat::from(amount_0_decimals)),
-        )
-        .add_call(
-            icp_ledger,
-            make_approve_request(amount_1_decimals, FEE_ICP),
-            Ok(Nat::from(amount_1_decimals)),
-        )
-        .add_call(
-            sns_ledger,
-            make_balance_request(*SELF_CANISTER_ID),
-            Nat::from(amount_0_decimals - FEE_SNS),
-        )
-        .add_call(
-            icp_ledger,
-            make_balance_request(*SELF_CANISTER_ID),
-            Nat::from(amount_1_decimals - FEE_ICP),
-        )
-        .add_call(
+    let self_account = Account {
+        owner: *SELF_CANISTER_ID,
+        subaccount: None,
+    };
+
+    // `MockLedgerAgent` models every ledger-facing call (`icrc2_approve`/`icrc1_transfer`/
+    // `icrc1_balance_of`) against a live in-memory balance instead of replaying canned bytes for
+    // each one -- so, unlike the `MockAgent`-scripted version this replaced, adding or removing an
+    // internal balance refresh doesn't require re-scripting a chain of `make_balance_request`
+    // calls returning hardcoded amounts. Only the KongSwap backend calls, which this mock doesn't
+    // otherwise model, are scripted; the two that move funds by calling the ledger directly
+    // (`add_pool`, `remove_liquidity`) carry an explicit balance effect for the same reason.
+    let mock_agent = MockLedgerAgent::new(*SELF_CANISTER_ID)
+        .with_ledger(sns_ledger, FEE_SNS, "My DAO Token", "DAO")
+        .with_ledger(icp_ledger, FEE_ICP, "Internet Computer", "ICP")
+        .with_balance(sns_ledger, self_account, amount_0_decimals)
+        .with_balance(icp_ledger, self_account, amount_1_decimals)
+        .add_scripted_call(
             *KONG_BACKEND_CANISTER_ID,
             make_add_token_request(token_0.clone()),
             Ok(make_add_token_reply(
@@ -292,7 +361,7 @@ async fn test_withdraw_success() {
                 FEE_SNS,
             )),
         )
-        .add_call(
+        .add_scripted_call(
             *KONG_BACKEND_CANISTER_ID,
             make_add_token_request(token_1.clone()),
             Ok(make_add_token_reply(
@@ -304,7 +373,7 @@ async fn test_withdraw_success() {
                 FEE_ICP,
             )),
         )
-        .add_call(
+        .add_scripted_call_with_balances(
             *KONG_BACKEND_CANISTER_ID,
             make_add_pool_request(
                 token_0.clone(),
@@ -313,28 +382,9 @@ async fn test_withdraw_success() {
                 amount_1_decimals - 2 * FEE_ICP,
             ),
             Ok(AddPoolReply::default()),
+            vec![(sns_ledger, self_account, 0), (icp_ledger, self_account, 0)],
         )
-        .add_call(
-            sns_ledger,
-            make_balance_request(*SELF_CANISTER_ID),
-            Nat::from(0_u64),
-        )
-        .add_call(
-            icp_ledger, // @todo
-            make_balance_request(*SELF_CANISTER_ID),
-            Nat::from(0_u64),
-        )
-        .add_call(
-            sns_ledger,
-            make_balance_request(*SELF_CANISTER_ID),
-            Nat::from(0_u64),
-        )
-        .add_call(
-            icp_ledger,
-            make_balance_request(*SELF_CANISTER_ID),
-            Nat::from(0_u64),
-        )
-        .add_call(
+        .add_scripted_call(
             *KONG_BACKEND_CANISTER_ID,
             make_lp_balance_request(),
             Ok(vec![make_lp_balance_reply(
@@ -342,17 +392,7 @@ async fn test_withdraw_success() {
                 symbol_1.clone(),
             )]),
         )
-        .add_call(
-            sns_ledger,
-            make_balance_request(*SELF_CANISTER_ID),
-            Nat::from(0_u64),
-        )
-        .add_call(
-            icp_ledger,
-            make_balance_request(*SELF_CANISTER_ID),
-            Nat::from(0_u64),
-        )
-        .add_call(
+        .add_scripted_call_with_balances(
             *KONG_BACKEND_CANISTER_ID,
             make_remove_liquidity_request(symbol_0.clone(), symbol_1.clone(), 100 * E8),
             Ok(make_remove_liquidity_reply(
@@ -364,81 +404,17 @@ async fn test_withdraw_success() {
                 0,
                 100,
             )),
+            vec![
+                (sns_ledger, self_account, amount_0_decimals - 3 * FEE_SNS),
+                (icp_ledger, self_account, amount_1_decimals - 3 * FEE_ICP),
+            ],
         )
-        .add_call(
-            sns_ledger,
-            make_balance_request(*SELF_CANISTER_ID),
-            Nat::from(amount_0_decimals - 3 * FEE_SNS),
-        )
-        .add_call(
-            icp_ledger,
-            make_balance_request(*SELF_CANISTER_ID),
-            Nat::from(amount_1_decimals - 3 * FEE_ICP),
-        )
-        .add_call(
-            sns_ledger,
-            make_balance_request(*SELF_CANISTER_ID),
-            Nat::from(amount_0_decimals - 3 * FEE_SNS),
-        )
-        .add_call(
-            icp_ledger,
-            make_balance_request(*SELF_CANISTER_ID),
-            Nat::from(amount_1_decimals - 3 * FEE_ICP),
-        )
-        .add_call(
+        .add_scripted_call(
             *KONG_BACKEND_CANISTER_ID,
             ClaimsArgs {
                 principal_id: SELF_CANISTER_ID.to_string(),
             },
             Ok(vec![]),
-        )
-        .add_call(
-            sns_ledger,
-            make_balance_request(*SELF_CANISTER_ID),
-            Nat::from(amount_0_decimals - 3 * FEE_SNS),
-        )
-        .add_call(
-            icp_ledger,
-            make_balance_request(*SELF_CANISTER_ID),
-            Nat::from(amount_1_decimals - 3 * FEE_ICP),
-        )
-        .add_call(
-            sns_ledger,
-            make_transfer_request(
-                Account {
-                    owner: owner_account.owner,
-                    subaccount: None,
-                },
-                FEE_SNS,
-                amount_0_decimals - 3 * FEE_SNS,
-                TreasuryManagerOperation {
-                    operation: sns_treasury_manager::Operation::Withdraw,
-                    step: Step {
-                        index: 11,
-                        is_final: false,
-                    },
-                },
-            ),
-            Ok(Nat::from(amount_0_decimals - 3 * FEE_SNS)),
-        )
-        .add_call(
-            icp_ledger,
-            make_transfer_request(
-                Account {
-                    owner: owner_account.owner,
-                    subaccount: None,
-                },
-                FEE_ICP,
-                amount_1_decimals - 3 * FEE_ICP,
-                TreasuryManagerOperation {
-                    operation: sns_treasury_manager::Operation::Withdraw,
-                    step: Step {
-                        index: 12,
-                        is_final: false,
-                    },
-                },
-            ),
-            Ok(Nat::from(amount_1_decimals - 3 * FEE_ICP)),
         );
 
     let mut kong_adaptor = KongSwapAdaptor::new(
@@ -447,6 +423,16 @@ async fn test_withdraw_success() {
         *SELF_CANISTER_ID,
         &BALANCES,
         &AUDIT_TRAIL,
+        &WITHDRAW_STATE,
+        &PRICE_HISTORY,
+        &IDEMPOTENCY_KEYS,
+        &CONTRACT_STATUS,
+        &PENDING_DEPOSIT_STATE,
+        &INTEGRITY_STATUS,
+        &EXCHANGE_RATE_HISTORY,
+        &TASK_STATUS,
+        &TRANSFER_INTENTS,
+        &OPERATION_LOCK,
     );
 
     let init = TreasuryManagerInit {
@@ -615,6 +601,42 @@ async fn test_withdraw_success() {
 
         assert_eq!(result_withdraw, Ok(balances));
     }
+
+    // Cross-check the adaptor's own bookkeeping (asserted above) against the ledger state it was
+    // actually driving: the owner should hold everything back out minus the fees burned along the
+    // way (one `approve`, one final `transfer` per asset), and the adaptor's own account should be
+    // fully drained.
+    let owner_ledger_account = Account {
+        owner: owner_account.owner,
+        subaccount: None,
+    };
+    assert_eq!(
+        kong_adaptor
+            .agent
+            .balance_of(sns_ledger, &owner_ledger_account),
+        Nat::from(amount_0_decimals - 4 * FEE_SNS)
+    );
+    assert_eq!(
+        kong_adaptor
+            .agent
+            .balance_of(icp_ledger, &owner_ledger_account),
+        Nat::from(amount_1_decimals - 4 * FEE_ICP)
+    );
+    assert_eq!(
+        kong_adaptor.agent.balance_of(sns_ledger, &self_account),
+        Nat::from(0_u64)
+    );
+    assert_eq!(
+        kong_adaptor.agent.balance_of(icp_ledger, &self_account),
+        Nat::from(0_u64)
+    );
+    kong_adaptor
+        .agent
+        .assert_invariants(sns_ledger, &Nat::from(amount_0_decimals - 4 * FEE_SNS));
+    kong_adaptor
+        .agent
+        .assert_invariants(icp_ledger, &Nat::from(amount_1_decimals - 4 * FEE_ICP));
+
     assert!(
         kong_adaptor.agent.finished_calls(),
         "There are still some calls remaining"
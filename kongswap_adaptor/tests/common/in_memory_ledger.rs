@@ -0,0 +1,207 @@
+#![allow(dead_code)]
+//! A small in-memory model of an ICRC-1 / ICRC-2 ledger, used to cheaply exhaust-check every
+//! transfer/approve/transfer_from a test drives against a real ledger canister, instead of
+//! hand-asserting a single balance or allowance at the end.
+
+use candid::{Decode, Encode, Nat, Principal};
+use icrc_ledger_types::icrc1::account::Account;
+use icrc_ledger_types::icrc2::allowance::{Allowance, AllowanceArgs};
+use pocket_ic::nonblocking::PocketIc;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ModeledAllowance {
+    allowance: Nat,
+    expires_at: Option<u64>,
+}
+
+/// Tracks the same state a real ICRC-1/ICRC-2 ledger would, as seen through the subset of calls
+/// (`transfer`, `approve`, `transfer_from`) this test harness drives.
+#[derive(Clone, Debug)]
+pub struct InMemoryLedger {
+    fee_decimals: Nat,
+    balances: BTreeMap<Account, Nat>,
+    allowances: BTreeMap<(Account, Account), ModeledAllowance>,
+    touched_accounts: Vec<Account>,
+}
+
+impl InMemoryLedger {
+    pub fn new(fee_decimals: Nat) -> Self {
+        Self {
+            fee_decimals,
+            balances: BTreeMap::new(),
+            allowances: BTreeMap::new(),
+            touched_accounts: vec![],
+        }
+    }
+
+    fn touch(&mut self, account: Account) {
+        if !self.touched_accounts.contains(&account) {
+            self.touched_accounts.push(account);
+        }
+    }
+
+    fn balance_of(&self, account: &Account) -> Nat {
+        self.balances
+            .get(account)
+            .cloned()
+            .unwrap_or_else(|| Nat::from(0_u8))
+    }
+
+    /// Seeds a balance directly, e.g. to model a mint performed by the ledger's own minting
+    /// account (which is not itself charged a fee).
+    pub fn set_balance(&mut self, account: Account, amount: Nat) {
+        self.touch(account);
+        self.balances.insert(account, amount);
+    }
+
+    /// Applies a `transfer` from `from` to `to`. The fee is deducted from `from` in addition to
+    /// the transferred amount, matching ICRC-1 semantics (self-transfers still pay the fee).
+    pub fn record_transfer(&mut self, from: Account, to: Account, amount: Nat) {
+        self.touch(from);
+        self.touch(to);
+
+        let debit = amount.clone() + self.fee_decimals.clone();
+        let from_balance = self.balance_of(&from);
+        self.balances.insert(from, from_balance - debit);
+
+        let to_balance = self.balance_of(&to);
+        self.balances.insert(to, to_balance + amount);
+    }
+
+    /// Applies an `approve`. ICRC-2 approvals overwrite rather than add to the previous
+    /// allowance, and the approval fee is always charged to `owner`, even when it lowers an
+    /// existing allowance.
+    pub fn record_approve(
+        &mut self,
+        owner: Account,
+        spender: Account,
+        amount: Nat,
+        expires_at: Option<u64>,
+    ) {
+        self.touch(owner);
+        self.touch(spender);
+
+        let owner_balance = self.balance_of(&owner);
+        self.balances
+            .insert(owner, owner_balance - self.fee_decimals.clone());
+
+        self.allowances.insert(
+            (owner, spender),
+            ModeledAllowance {
+                allowance: amount,
+                expires_at,
+            },
+        );
+    }
+
+    /// Applies a `transfer_from`, decrementing both the allowance and the owner's balance.
+    /// Returns `Err` if the allowance is insufficient, expired (relative to `now_ns`), or absent
+    /// — mirroring the errors a real ledger would reject the call with.
+    pub fn record_transfer_from(
+        &mut self,
+        owner: Account,
+        spender: Account,
+        to: Account,
+        amount: Nat,
+        now_ns: u64,
+    ) -> Result<(), String> {
+        let key = (owner, spender);
+
+        let Some(modeled) = self.allowances.get(&key).cloned() else {
+            return Err(format!("No allowance from {} to {}", owner, spender));
+        };
+
+        if let Some(expires_at) = modeled.expires_at {
+            if expires_at <= now_ns {
+                return Err(format!(
+                    "Allowance from {} to {} has expired",
+                    owner, spender
+                ));
+            }
+        }
+
+        let debit = amount.clone() + self.fee_decimals.clone();
+
+        if modeled.allowance < debit {
+            return Err(format!(
+                "Allowance from {} to {} ({}) is insufficient to cover {} + fee",
+                owner, spender, modeled.allowance, amount
+            ));
+        }
+
+        self.touch(owner);
+        self.touch(to);
+
+        let owner_balance = self.balance_of(&owner);
+        self.balances.insert(owner, owner_balance - debit.clone());
+
+        let to_balance = self.balance_of(&to);
+        self.balances.insert(to, to_balance + amount);
+
+        self.allowances.insert(
+            key,
+            ModeledAllowance {
+                allowance: modeled.allowance - debit,
+                ..modeled
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Fetches `icrc1_balance_of`/`icrc2_allowance` for every touched account and allowance from
+    /// the live ledger canister, and asserts the result agrees with this model exactly.
+    pub async fn assert_matches_ledger(
+        &self,
+        pocket_ic: &PocketIc,
+        sender: Principal,
+        ledger_canister_id: Principal,
+    ) {
+        for account in &self.touched_accounts {
+            let modeled_balance = self.balance_of(account);
+
+            let reply = pocket_ic
+                .query_call(
+                    ledger_canister_id,
+                    sender,
+                    "icrc1_balance_of",
+                    Encode!(account).unwrap(),
+                )
+                .await
+                .unwrap();
+            let live_balance = Decode!(&reply, Nat).unwrap();
+
+            assert_eq!(
+                modeled_balance, live_balance,
+                "Balance mismatch for {} on ledger {}",
+                account, ledger_canister_id
+            );
+        }
+
+        for (owner, spender) in self.allowances.keys() {
+            let modeled = &self.allowances[&(*owner, *spender)];
+
+            let request = AllowanceArgs {
+                account: *owner,
+                spender: *spender,
+            };
+            let reply = pocket_ic
+                .query_call(
+                    ledger_canister_id,
+                    sender,
+                    "icrc2_allowance",
+                    Encode!(&request).unwrap(),
+                )
+                .await
+                .unwrap();
+            let live_allowance = Decode!(&reply, Allowance).unwrap();
+
+            assert_eq!(
+                modeled.allowance, live_allowance.allowance,
+                "Allowance mismatch for {} -> {} on ledger {}",
+                owner, spender, ledger_canister_id
+            );
+        }
+    }
+}